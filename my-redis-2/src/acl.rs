@@ -0,0 +1,688 @@
+//! Users and what they may run, checked before every command.
+//!
+//! Each user has passwords, the commands it may run and the key patterns it
+//! may access, set by rules as in `ACL SETUSER`:
+//!
+//! * `on` and `off` enable and disable the user
+//! * `>password` and `<password` add and remove a password, `#digest` adds
+//!   the password whose SHA-256 digest is given in lowercase hexadecimal,
+//!   `nopass` lets any password in and `resetpass` forgets them all
+//! * `+command` and `-command` allow and deny a command, `+@category` and
+//!   `-@category` every command of a category, `allcommands` and
+//!   `nocommands` standing for `+@all` and `-@all`
+//! * `~pattern` allows the keys matching a glob-style pattern, `allkeys`
+//!   standing for `~*`, and `resetkeys` forgets the patterns
+//! * `reset` takes the user back to the state of a new one
+//!
+//! Connections start out as the `default` user, which may run everything
+//! without a password unless configured otherwise. Until then, connections
+//! have to `AUTH` before running commands.
+//!
+//! Users are loaded from an ACL file, with a `user name rule ...` line per
+//! user. Only the SHA-256 digests of the passwords are kept, and listed as
+//! `#digest` rules.
+
+use crate::{glob, sha256, Db, Frame};
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The user of connections which did not authenticate.
+pub const DEFAULT_USER: &str = "default";
+
+/// Command categories, as listed by `ACL CAT`.
+const CATEGORIES: [&str; 15] = [
+    "keyspace",
+    "read",
+    "write",
+    "string",
+    "list",
+    "hash",
+    "set",
+    "sortedset",
+    "stream",
+    "pubsub",
+    "admin",
+    "dangerous",
+    "connection",
+    "transaction",
+    "blocking",
+];
+
+/// Every command, along with its categories and the position of its keys.
+const COMMANDS: &[(&str, &[&str], Keys)] = &[
+    ("get", &["read", "string"], Keys::First),
+    ("set", &["write", "string"], Keys::First),
+    ("setnx", &["write", "string"], Keys::First),
+    ("getset", &["write", "string"], Keys::First),
+    ("mget", &["read", "string"], Keys::All),
+    ("mset", &["write", "string"], Keys::EveryOther),
+    ("incr", &["write", "string"], Keys::First),
+    ("decr", &["write", "string"], Keys::First),
+    ("incrby", &["write", "string"], Keys::First),
+    ("decrby", &["write", "string"], Keys::First),
+    ("append", &["write", "string"], Keys::First),
+    ("strlen", &["read", "string"], Keys::First),
+    ("del", &["write", "keyspace"], Keys::All),
+    ("exists", &["read", "keyspace"], Keys::All),
+    ("keys", &["read", "keyspace", "dangerous"], Keys::None),
+    ("scan", &["read", "keyspace"], Keys::None),
+    ("expire", &["write", "keyspace"], Keys::First),
+    ("pexpire", &["write", "keyspace"], Keys::First),
+    ("expireat", &["write", "keyspace"], Keys::First),
+    ("pexpireat", &["write", "keyspace"], Keys::First),
+    ("ttl", &["read", "keyspace"], Keys::First),
+    ("pttl", &["read", "keyspace"], Keys::First),
+    ("persist", &["write", "keyspace"], Keys::First),
+    ("type", &["read", "keyspace"], Keys::First),
+    ("move", &["write", "keyspace"], Keys::First),
+    ("dbsize", &["read", "keyspace"], Keys::None),
+    ("flushdb", &["write", "keyspace", "dangerous"], Keys::None),
+    ("flushall", &["write", "keyspace", "dangerous"], Keys::None),
+    ("swapdb", &["write", "keyspace", "dangerous"], Keys::None),
+    ("lpush", &["write", "list"], Keys::First),
+    ("rpush", &["write", "list"], Keys::First),
+    ("lpop", &["write", "list"], Keys::First),
+    ("rpop", &["write", "list"], Keys::First),
+    ("lrange", &["read", "list"], Keys::First),
+    ("lmove", &["write", "list"], Keys::FirstTwo),
+    ("blpop", &["write", "list", "blocking"], Keys::AllButLast),
+    ("brpop", &["write", "list", "blocking"], Keys::AllButLast),
+    ("blmove", &["write", "list", "blocking"], Keys::FirstTwo),
+    ("hset", &["write", "hash"], Keys::First),
+    ("hget", &["read", "hash"], Keys::First),
+    ("hgetall", &["read", "hash"], Keys::First),
+    ("hdel", &["write", "hash"], Keys::First),
+    ("hscan", &["read", "hash"], Keys::First),
+    ("sadd", &["write", "set"], Keys::First),
+    ("smembers", &["read", "set"], Keys::First),
+    ("sinter", &["read", "set"], Keys::All),
+    ("sunion", &["read", "set"], Keys::All),
+    ("sscan", &["read", "set"], Keys::First),
+    ("zadd", &["write", "sortedset"], Keys::First),
+    ("zrange", &["read", "sortedset"], Keys::First),
+    ("zrank", &["read", "sortedset"], Keys::First),
+    ("zincrby", &["write", "sortedset"], Keys::First),
+    ("zscan", &["read", "sortedset"], Keys::First),
+    ("xadd", &["write", "stream"], Keys::First),
+    ("xlen", &["read", "stream"], Keys::First),
+    ("xrange", &["read", "stream"], Keys::First),
+    ("xrevrange", &["read", "stream"], Keys::First),
+    ("xread", &["read", "stream", "blocking"], Keys::Streams),
+    (
+        "xreadgroup",
+        &["write", "stream", "blocking"],
+        Keys::Streams,
+    ),
+    ("xgroup", &["write", "stream"], Keys::Second),
+    ("xack", &["write", "stream"], Keys::First),
+    ("xpending", &["read", "stream"], Keys::First),
+    ("xclaim", &["write", "stream"], Keys::First),
+    ("xsetid", &["write", "stream"], Keys::First),
+    ("publish", &["pubsub"], Keys::None),
+    ("subscribe", &["pubsub"], Keys::None),
+    ("psubscribe", &["pubsub"], Keys::None),
+    ("unsubscribe", &["pubsub"], Keys::None),
+    ("punsubscribe", &["pubsub"], Keys::None),
+    ("multi", &["transaction"], Keys::None),
+    ("exec", &["transaction"], Keys::None),
+    ("discard", &["transaction"], Keys::None),
+    ("watch", &["transaction"], Keys::All),
+    ("unwatch", &["transaction"], Keys::None),
+    ("ping", &["connection"], Keys::None),
+    ("echo", &["connection"], Keys::None),
+    ("hello", &["connection"], Keys::None),
+    ("auth", &["connection"], Keys::None),
+    ("select", &["connection"], Keys::None),
+    ("client", &["admin", "dangerous", "connection"], Keys::None),
+    ("bgrewriteaof", &["admin", "dangerous"], Keys::None),
+    ("save", &["admin", "dangerous"], Keys::None),
+    ("bgsave", &["admin", "dangerous"], Keys::None),
+    ("lastsave", &["dangerous"], Keys::None),
+    ("info", &["dangerous"], Keys::None),
+    ("slowlog", &["admin", "dangerous"], Keys::None),
+    ("replicaof", &["admin", "dangerous"], Keys::None),
+    ("psync", &["admin", "dangerous"], Keys::None),
+    ("monitor", &["admin", "dangerous"], Keys::None),
+    ("acl", &["admin", "dangerous"], Keys::None),
+    ("config", &["admin", "dangerous"], Keys::None),
+];
+
+/// Where the keys are among the arguments of a command.
+#[derive(Debug, Clone, Copy)]
+enum Keys {
+    None,
+    First,
+    Second,
+    FirstTwo,
+    All,
+    AllButLast,
+
+    /// Every other argument, starting with the first, e.g. `MSET`.
+    EveryOther,
+
+    /// The first half of the arguments following `STREAMS`.
+    Streams,
+}
+
+/// The users, see `Db::acl`.
+#[derive(Debug)]
+pub struct Acl {
+    users: BTreeMap<String, User>,
+
+    /// The ACL file, used by `ACL LOAD` and `ACL SAVE`.
+    path: Option<PathBuf>,
+}
+
+/// A user, as set by `ACL SETUSER`.
+#[derive(Debug, Clone)]
+pub struct User {
+    name: String,
+
+    /// Disabled users can't authenticate, and their connections can't run
+    /// commands anymore.
+    enabled: bool,
+
+    /// Set if any password is accepted.
+    nopass: bool,
+
+    /// SHA-256 digests of the passwords, in lowercase hexadecimal.
+    passwords: Vec<String>,
+
+    /// Names of the commands the user may run.
+    commands: BTreeSet<&'static str>,
+
+    /// The command rules which led to `commands`, for `ACL LIST`.
+    command_rules: Vec<String>,
+
+    /// Patterns of the keys the user may access.
+    key_patterns: Vec<String>,
+}
+
+impl Default for Acl {
+    fn default() -> Acl {
+        let mut users = BTreeMap::new();
+        users.insert(DEFAULT_USER.to_string(), User::default_user());
+
+        Acl { users, path: None }
+    }
+}
+
+/// Require `password` from the connections of the default user, or no
+/// password at all if it is empty.
+pub fn require_pass(db: &Db, password: &str) {
+    let mut acl = db.acl();
+    let user = acl.users.get_mut(DEFAULT_USER).unwrap();
+
+    user.nopass = password.is_empty();
+    user.passwords = match password {
+        "" => vec![],
+        password => vec![sha256::hex_digest(password.as_bytes())],
+    };
+}
+
+/// Load the users of the ACL file at `path`, replacing the current ones. The
+/// file is loaded again by `ACL LOAD` and written by `ACL SAVE`.
+pub fn load(db: &Db, path: impl AsRef<Path>) -> crate::Result<()> {
+    let path = path.as_ref().to_path_buf();
+    let users = read_file(&path)?;
+
+    let mut acl = db.acl();
+    acl.set_users(users);
+    acl.path = Some(path);
+
+    Ok(())
+}
+
+impl Acl {
+    /// Check that the session of `user`, unset if it did not authenticate,
+    /// may run the `name` command, returning the error reply if not.
+    ///
+    /// Unknown commands are let through, to fail as such.
+    pub(crate) fn check(
+        &self,
+        user: Option<&str>,
+        name: &str,
+        command: &Frame,
+    ) -> Result<(), String> {
+        let args = match command {
+            Frame::Array(args) => &args[..],
+            _ => &[],
+        };
+
+        // `AUTH`, or `HELLO` with `AUTH`, authenticates the session.
+        let authenticates = name == "auth"
+            || (name == "hello"
+                && Keys::All
+                    .of(args)
+                    .iter()
+                    .any(|arg| arg.eq_ignore_ascii_case(b"auth")));
+
+        let user = match self.user_of(user) {
+            Some(user) => user,
+            None if authenticates => return Ok(()),
+            None => return Err("NOAUTH Authentication required.".to_string()),
+        };
+
+        let keys = match COMMANDS.iter().find(|(command, ..)| *command == name) {
+            Some((.., keys)) => *keys,
+            None => return Ok(()),
+        };
+
+        if !authenticates && !user.commands.contains(name) {
+            return Err(format!(
+                "NOPERM User {} has no permissions to run the '{}' command",
+                user.name, name
+            ));
+        }
+
+        if !keys.of(args).iter().all(|key| user.can_access(key)) {
+            return Err("NOPERM No permissions to access a key".to_string());
+        }
+
+        Ok(())
+    }
+
+    /// The user of a session which authenticated as `user`, or did not
+    /// authenticate, if it may still run commands.
+    fn user_of(&self, user: Option<&str>) -> Option<&User> {
+        let found = self.users.get(user.unwrap_or(DEFAULT_USER))?;
+
+        match found.enabled && (user.is_some() || found.nopass) {
+            true => Some(found),
+            false => None,
+        }
+    }
+
+    /// Returns `true` if `password` is one of the passwords of `user`, which
+    /// is enabled.
+    pub(crate) fn authenticate(&self, user: &str, password: &str) -> bool {
+        match self.users.get(user) {
+            Some(user) => {
+                let digest = sha256::hex_digest(password.as_bytes());
+                user.enabled && (user.nopass || user.passwords.contains(&digest))
+            }
+            None => false,
+        }
+    }
+
+    /// Returns `true` if the default user needs no password, so `AUTH` with
+    /// only a password makes no sense.
+    pub(crate) fn default_nopass(&self) -> bool {
+        self.users[DEFAULT_USER].nopass
+    }
+
+    /// Apply `rules` to `name`, creating the user if needed. Either every
+    /// rule applies or none do.
+    pub(crate) fn set_user(&mut self, name: &str, rules: &[String]) -> Result<(), String> {
+        let mut user = match self.users.get(name) {
+            Some(user) => user.clone(),
+            None => User::new(name),
+        };
+
+        for rule in rules {
+            user.apply(rule)?;
+        }
+
+        self.users.insert(name.to_string(), user);
+        Ok(())
+    }
+
+    /// Remove the user `name`, returning `false` if there is none.
+    ///
+    /// The default user can't be removed.
+    pub(crate) fn remove_user(&mut self, name: &str) -> bool {
+        name != DEFAULT_USER && self.users.remove(name).is_some()
+    }
+
+    /// Names of the users, sorted.
+    pub fn users(&self) -> impl Iterator<Item = &str> {
+        self.users.keys().map(String::as_str)
+    }
+
+    /// A `user name rule ...` line per user, as in the ACL file.
+    pub fn list(&self) -> Vec<String> {
+        self.users.values().map(User::describe).collect()
+    }
+
+    /// Load the ACL file again, replacing the users.
+    pub(crate) fn reload(&mut self) -> Result<(), String> {
+        let path = self.path.as_ref().ok_or_else(no_file)?;
+        let users = read_file(path).map_err(|err| err.to_string())?;
+
+        self.set_users(users);
+        Ok(())
+    }
+
+    /// Write the users to the ACL file.
+    pub(crate) fn save(&self) -> Result<(), String> {
+        let path = self.path.as_ref().ok_or_else(no_file)?;
+
+        let mut contents = self.list().join("\n");
+        contents.push('\n');
+        fs::write(path, contents).map_err(|err| err.to_string())
+    }
+
+    /// Replace the users, keeping the default user unless it is replaced.
+    fn set_users(&mut self, mut users: BTreeMap<String, User>) {
+        if !users.contains_key(DEFAULT_USER) {
+            let default = self.users.remove(DEFAULT_USER).unwrap();
+            users.insert(DEFAULT_USER.to_string(), default);
+        }
+
+        self.users = users;
+    }
+}
+
+impl User {
+    /// A new user: disabled, without passwords, commands or keys.
+    fn new(name: &str) -> User {
+        User {
+            name: name.to_string(),
+            enabled: false,
+            nopass: false,
+            passwords: vec![],
+            commands: BTreeSet::new(),
+            command_rules: vec![],
+            key_patterns: vec![],
+        }
+    }
+
+    /// The default user: `on nopass ~* +@all`.
+    fn default_user() -> User {
+        let mut user = User::new(DEFAULT_USER);
+        for rule in ["on", "nopass", "~*", "+@all"] {
+            user.apply(rule).unwrap();
+        }
+        user
+    }
+
+    /// Apply a rule, see the module documentation.
+    fn apply(&mut self, rule: &str) -> Result<(), String> {
+        match rule.to_lowercase().as_str() {
+            "on" => self.enabled = true,
+            "off" => self.enabled = false,
+            "nopass" => {
+                self.nopass = true;
+                self.passwords.clear();
+            }
+            "resetpass" => {
+                self.nopass = false;
+                self.passwords.clear();
+            }
+            "allkeys" => self.key_patterns = vec!["*".to_string()],
+            "resetkeys" => self.key_patterns.clear(),
+            "allcommands" => return self.apply("+@all"),
+            "nocommands" => return self.apply("-@all"),
+            "reset" => *self = User::new(&self.name),
+            _ => match rule.split_at(rule.chars().next().map_or(0, char::len_utf8)) {
+                (">", password) => self.add_password(sha256::hex_digest(password.as_bytes())),
+                ("#", digest) => {
+                    let hex = |b: u8| b.is_ascii_digit() || (b'a'..=b'f').contains(&b);
+                    if digest.len() != 64 || !digest.bytes().all(hex) {
+                        return Err("The password hash must be exactly 64 characters \
+                            and contain only lowercase hexadecimal characters"
+                            .to_string());
+                    }
+                    self.add_password(digest.to_string());
+                }
+                ("<", password) => {
+                    let digest = sha256::hex_digest(password.as_bytes());
+                    let count = self.passwords.len();
+                    self.passwords.retain(|p| *p != digest);
+                    if self.passwords.len() == count {
+                        return Err("no such password to remove".to_string());
+                    }
+                }
+                ("~", pattern) => {
+                    if !self.key_patterns.iter().any(|p| p == "*" || p == pattern) {
+                        self.key_patterns.push(pattern.to_string());
+                    }
+                }
+                (sign @ ("+" | "-"), command) => self.allow(sign == "+", command)?,
+                _ => return Err(format!("Syntax error in rule '{}'", rule)),
+            },
+        }
+
+        Ok(())
+    }
+
+    /// Accept the password whose digest is `digest`.
+    fn add_password(&mut self, digest: String) {
+        self.nopass = false;
+        if !self.passwords.contains(&digest) {
+            self.passwords.push(digest);
+        }
+    }
+
+    /// Allow or deny `command`, a command name or `@category`.
+    fn allow(&mut self, allow: bool, command: &str) -> Result<(), String> {
+        let command = command.to_lowercase();
+
+        let names: Vec<&'static str> = match command.strip_prefix('@') {
+            Some("all") => COMMANDS.iter().map(|(name, ..)| *name).collect(),
+            Some(category) if CATEGORIES.contains(&category) => commands_in(category).collect(),
+            Some(category) => return Err(format!("Unknown command category '{}'", category)),
+            None => match COMMANDS.iter().find(|(name, ..)| *name == command) {
+                Some((name, ..)) => vec![*name],
+                None => return Err(format!("Unknown command '{}'", command)),
+            },
+        };
+
+        for name in names {
+            match allow {
+                true => self.commands.insert(name),
+                false => self.commands.remove(name),
+            };
+        }
+
+        // Rules for every command make the previous ones pointless.
+        if command == "@all" {
+            self.command_rules.clear();
+        }
+        let sign = if allow { '+' } else { '-' };
+        self.command_rules.push(format!("{}{}", sign, command));
+
+        Ok(())
+    }
+
+    fn can_access(&self, key: &[u8]) -> bool {
+        self.key_patterns
+            .iter()
+            .any(|pattern| glob::matches_bytes(pattern.as_bytes(), key))
+    }
+
+    /// The rules giving this user, as a line of the ACL file.
+    fn describe(&self) -> String {
+        let mut rules = vec![
+            "user".to_string(),
+            self.name.clone(),
+            if self.enabled { "on" } else { "off" }.to_string(),
+        ];
+
+        if self.nopass {
+            rules.push("nopass".to_string());
+        }
+        rules.extend(self.passwords.iter().map(|p| format!("#{}", p)));
+        rules.extend(self.key_patterns.iter().map(|p| format!("~{}", p)));
+
+        match self.command_rules.is_empty() {
+            true => rules.push("-@all".to_string()),
+            false => rules.extend(self.command_rules.iter().cloned()),
+        }
+
+        rules.join(" ")
+    }
+}
+
+impl Keys {
+    /// The keys among `args`, the command name being the first.
+    fn of(self, args: &[Frame]) -> Vec<&[u8]> {
+        let args: Vec<&[u8]> = args
+            .iter()
+            .skip(1)
+            .filter_map(|arg| match arg {
+                Frame::Bulk(data) => Some(&data[..]),
+                Frame::Simple(data) => Some(data.as_bytes()),
+                _ => None,
+            })
+            .collect();
+
+        match self {
+            Keys::None => vec![],
+            Keys::First => args.into_iter().take(1).collect(),
+            Keys::Second => args.into_iter().skip(1).take(1).collect(),
+            Keys::FirstTwo => args.into_iter().take(2).collect(),
+            Keys::All => args,
+            Keys::AllButLast => {
+                let count = args.len().saturating_sub(1);
+                args.into_iter().take(count).collect()
+            }
+            Keys::EveryOther => args.into_iter().step_by(2).collect(),
+            Keys::Streams => {
+                let streams = args
+                    .iter()
+                    .position(|arg| arg.eq_ignore_ascii_case(b"streams"))
+                    .map_or(&[][..], |i| &args[i + 1..]);
+                streams[..streams.len() / 2].to_vec()
+            }
+        }
+    }
+}
+
+/// Categories, as listed by `ACL CAT`.
+pub fn categories() -> impl Iterator<Item = &'static str> {
+    CATEGORIES.iter().copied()
+}
+
+/// Names of the commands of `category`.
+pub fn commands_in(category: &str) -> impl Iterator<Item = &'static str> + '_ {
+    COMMANDS
+        .iter()
+        .filter(move |(_, categories, _)| categories.contains(&category))
+        .map(|(name, ..)| *name)
+}
+
+/// Parse an ACL file, a `user name rule ...` line per user. Empty lines and
+/// lines starting with `#` are skipped.
+fn read_file(path: &Path) -> crate::Result<BTreeMap<String, User>> {
+    let contents = fs::read_to_string(path)?;
+    let mut users = BTreeMap::new();
+
+    for (i, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let error = |err: String| format!("{}:{}: {}", path.display(), i + 1, err);
+
+        let mut words = line.split_whitespace();
+        let name = match (words.next(), words.next()) {
+            (Some("user"), Some(name)) => name,
+            _ => return Err(error("expected `user name rule ...`".to_string()).into()),
+        };
+
+        let mut user = User::new(name);
+        for rule in words {
+            user.apply(rule).map_err(error)?;
+        }
+        users.insert(name.to_string(), user);
+    }
+
+    Ok(users)
+}
+
+fn no_file() -> String {
+    "This instance is not configured to use an ACL file.".to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::command;
+
+    fn rules(rules: &str) -> Vec<String> {
+        rules.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn users_run_the_commands_and_keys_they_are_allowed() {
+        let mut acl = Acl::default();
+        acl.set_user("alice", &rules("on >secret ~cache:* +@read -keys +set"))
+            .unwrap();
+
+        let alice = Some("alice");
+        let check = |acl: &Acl, user, args: &[&str]| acl.check(user, args[0], &command(args));
+
+        assert!(check(&acl, alice, &["get", "cache:1"]).is_ok());
+        assert!(check(&acl, alice, &["set", "cache:1", "x"]).is_ok());
+        assert!(check(&acl, alice, &["mget", "cache:1", "cache:2"]).is_ok());
+        assert_eq!(
+            check(&acl, alice, &["del", "cache:1"]),
+            Err("NOPERM User alice has no permissions to run the 'del' command".to_string())
+        );
+        assert!(check(&acl, alice, &["keys", "*"]).is_err());
+        assert_eq!(
+            check(&acl, alice, &["mget", "cache:1", "other"]),
+            Err("NOPERM No permissions to access a key".to_string())
+        );
+        assert!(check(&acl, alice, &["xread", "streams", "cache:s", "other"]).is_ok());
+
+        assert!(acl.authenticate("alice", "secret"));
+        assert!(!acl.authenticate("alice", "wrong"));
+        assert!(!acl.authenticate("bob", "secret"));
+
+        // Connections which did not authenticate run as the default user.
+        assert!(check(&acl, None, &["del", "a"]).is_ok());
+        acl.set_user(DEFAULT_USER, &rules(">pass")).unwrap();
+        assert_eq!(
+            check(&acl, None, &["get", "a"]),
+            Err("NOAUTH Authentication required.".to_string())
+        );
+        assert!(check(&acl, None, &["auth", "pass"]).is_ok());
+        assert!(check(&acl, Some(DEFAULT_USER), &["get", "a"]).is_ok());
+
+        acl.set_user("alice", &rules("off")).unwrap();
+        assert!(check(&acl, alice, &["get", "cache:1"]).is_err());
+    }
+
+    #[test]
+    fn invalid_rules_leave_the_user_unchanged() {
+        let mut acl = Acl::default();
+        acl.set_user("bob", &rules("on nopass ~* +@all")).unwrap();
+
+        assert_eq!(
+            acl.set_user("bob", &rules("-@all +nosuchcommand")),
+            Err("Unknown command 'nosuchcommand'".to_string())
+        );
+        assert_eq!(
+            acl.list(),
+            [
+                "user bob on nopass ~* +@all",
+                "user default on nopass ~* +@all"
+            ]
+        );
+
+        acl.set_user("bob", &rules("reset on >pw ~a:* +@all -@dangerous +info"))
+            .unwrap();
+        let pw = "30c952fab122c3f9759f02a6d95c3758b246b4fee239957b2d4fee46e26170c4";
+        assert_eq!(
+            acl.list()[0],
+            format!("user bob on #{} ~a:* +@all -@dangerous +info", pw)
+        );
+
+        // Users are saved with the digests of their passwords, and loaded
+        // back from them.
+        acl.set_user("carol", &rules(&format!("on #{}", pw)))
+            .unwrap();
+        assert!(acl.authenticate("carol", "pw"));
+        assert_eq!(acl.list()[1], format!("user carol on #{} -@all", pw));
+        assert!(acl.set_user("carol", &rules("#abc")).is_err());
+        let upper = format!("#{}", pw.to_uppercase());
+        assert!(acl.set_user("carol", &[upper]).is_err());
+        assert!(!acl.remove_user(DEFAULT_USER));
+        assert!(acl.remove_user("bob"));
+    }
+}
@@ -0,0 +1,895 @@
+//! Append-only file persistence.
+//!
+//! Every write command is appended to the file as a RESP array, the encoding
+//! clients use, and the file is replayed on startup to rebuild the keyspace.
+//! Relative expirations (`SET .. EX`, `EXPIRE`, ...) are logged as absolute
+//! `PEXPIREAT` commands, so replaying the file later does not extend them.
+//! A `SELECT` is logged whenever a command applies to another database than
+//! the previous one.
+//!
+//! The file grows with every write. A background rewrite compacts it into the
+//! minimal list of commands recreating the current keyspace, see
+//! `Aof::rewrite`.
+
+use crate::db::{unix_time, Pending, Stream, StreamId, Value};
+use crate::{cmd, frame, Db, Frame};
+
+use bytes::Bytes;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Cursor, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+use tokio::{task, time};
+
+/// The file is rewritten automatically once it has grown past this size and
+/// doubled since the last rewrite.
+const AUTO_REWRITE_MIN_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Most items of a collection recreated by a single command when rewriting.
+const REWRITE_BATCH: usize = 64;
+
+/// When the file is flushed to disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fsync {
+    /// After every write command. Nothing acknowledged is ever lost, at the
+    /// cost of a disk flush per write.
+    Always,
+
+    /// Once per second, from a background task. A crash loses at most the
+    /// last second of writes.
+    EverySec,
+
+    /// Never explicitly, the operating system flushes when it sees fit.
+    No,
+}
+
+impl FromStr for Fsync {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Fsync, String> {
+        match &s.to_lowercase()[..] {
+            "always" => Ok(Fsync::Always),
+            "everysec" => Ok(Fsync::EverySec),
+            "no" => Ok(Fsync::No),
+            _ => Err(format!(
+                "invalid fsync policy `{}`, expected always, everysec or no",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Fsync {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Fsync::Always => "always",
+            Fsync::EverySec => "everysec",
+            Fsync::No => "no",
+        }
+        .fmt(fmt)
+    }
+}
+
+/// An open append-only file, see `Db::aof`.
+#[derive(Debug)]
+pub struct Aof {
+    path: PathBuf,
+
+    /// The file being appended to. The everysec background task holds a weak
+    /// reference, so it stops once the file is replaced by a rewrite.
+    file: Arc<File>,
+
+    fsync: Fsync,
+
+    /// Current size of the file.
+    size: u64,
+
+    /// Size of the file when it was opened or last rewritten.
+    base_size: u64,
+
+    /// Index of the database last selected in the file, unset if the next
+    /// command has to select one.
+    selected: Option<usize>,
+
+    /// Commands logged while a rewrite is in progress. They are appended to
+    /// the rewritten file before it replaces the current one.
+    rewrite_buffer: Option<Vec<u8>>,
+}
+
+/// Replay the append-only file at `path` into `db`, then log every following
+/// write command to it.
+///
+/// A missing file is created. A file ending in the middle of a command, as
+/// left behind by a crash, is cut back to its last complete command.
+pub fn open(db: &Db, path: impl AsRef<Path>, fsync: Fsync) -> crate::Result<()> {
+    let path = path.as_ref().to_path_buf();
+
+    load(db, &path)?;
+
+    let file = OpenOptions::new().create(true).append(true).open(&path)?;
+    let size = file.metadata()?.len();
+
+    let mut logging = db.logging_mut();
+    *logging = true;
+    *db.aof() = Some(Aof::new(path, file, fsync, size));
+
+    Ok(())
+}
+
+/// Flush the append-only file of `db` to disk, if enabled.
+pub fn sync(db: &Db) -> io::Result<()> {
+    match &*db.aof() {
+        Some(aof) => aof.file.sync_data(),
+        None => Ok(()),
+    }
+}
+
+/// Start logging the write commands of `db` to a new append-only file at
+/// `path`, as `CONFIG SET appendonly yes` does. The file is first written
+/// with the current keyspace by a rewrite.
+pub fn enable(db: &Db, path: impl AsRef<Path>, fsync: Fsync) -> crate::Result<()> {
+    let path = path.as_ref().to_path_buf();
+
+    // Holding the logging lock keeps write commands out until the rewrite
+    // took its snapshot of the keyspace.
+    let mut logging = db.logging_mut();
+    let mut aof = db.aof();
+    if aof.is_some() {
+        return Ok(());
+    }
+    *logging = true;
+
+    let file = File::create(&path)?;
+    aof.insert(Aof::new(path, file, fsync, 0)).rewrite(db);
+
+    Ok(())
+}
+
+/// Stop logging write commands, flushing the append-only file to disk first.
+pub fn disable(db: &Db) -> io::Result<()> {
+    let mut logging = db.logging_mut();
+    let aof = db.aof().take();
+    *logging = db.replication().is_streaming();
+
+    match aof {
+        Some(aof) => aof.file.sync_data(),
+        None => Ok(()),
+    }
+}
+
+/// Flush the append-only file of `db`, if enabled, following `fsync` from now
+/// on.
+pub fn set_fsync(db: &Db, fsync: Fsync) -> io::Result<()> {
+    let mut aof = db.aof();
+    let aof = match aof.as_mut() {
+        Some(aof) => aof,
+        None => return Ok(()),
+    };
+
+    // The everysec task stops once its handle on the file is dropped, so the
+    // file is swapped for a new handle before a task is started for it.
+    aof.file = Arc::new(aof.file.try_clone()?);
+    aof.fsync = fsync;
+    if fsync == Fsync::EverySec {
+        tokio::spawn(sync_every_second(Arc::downgrade(&aof.file)));
+    }
+
+    Ok(())
+}
+
+/// Apply every command of the file at `path` to `db`.
+fn load(db: &Db, path: &Path) -> crate::Result<()> {
+    let data = match fs::read(path) {
+        Ok(data) => data,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err.into()),
+    };
+
+    let mut buf = Cursor::new(&data[..]);
+
+    // The database the commands apply to, switched by `SELECT`.
+    let mut selected = db.clone();
+
+    // Commands of a transaction are only applied once its `EXEC` is read,
+    // along with the offset of its `MULTI`.
+    let mut transaction: Option<(u64, Vec<Frame>)> = None;
+
+    while buf.position() < data.len() as u64 {
+        let start = buf.position();
+
+        match Frame::check(&mut buf) {
+            Ok(_) => {}
+            Err(frame::Error::Incomplete) => {
+                buf.set_position(start);
+                break;
+            }
+            Err(err) => return Err(err.into()),
+        }
+
+        buf.set_position(start);
+        let command = Frame::parse(&mut buf)?;
+
+        match (&cmd::name(&command)[..], &mut transaction) {
+            (b"multi", _) => transaction = Some((start, vec![])),
+            (b"exec", Some(_)) => {
+                let (_, commands) = transaction.take().unwrap();
+                for command in commands {
+                    replay(&mut selected, command)?;
+                }
+            }
+            // A rewrite which started within a transaction only keeps its end
+            (b"exec", None) => {}
+            (_, Some((_, commands))) => commands.push(command),
+            (_, None) => replay(&mut selected, command)?,
+        }
+    }
+
+    // Anything after the last complete command or transaction was cut short
+    // by a crash.
+    let end = match transaction {
+        Some((start, _)) => start,
+        None => buf.position(),
+    };
+
+    if end < data.len() as u64 {
+        eprintln!(
+            "append only file ends with a truncated command, discarding its last {} bytes",
+            data.len() as u64 - end
+        );
+        OpenOptions::new().write(true).open(path)?.set_len(end)?;
+    }
+
+    Ok(())
+}
+
+fn replay(db: &mut Db, command: Frame) -> crate::Result<()> {
+    if let Some(index) = cmd::select_index(&command) {
+        *db = db
+            .select(index)
+            .ok_or("append only file selects a missing database")?;
+        return Ok(());
+    }
+
+    // Only successful commands are logged, so an error means the file does
+    // not match the keyspace it was written from.
+    match cmd::apply(command, db) {
+        Frame::Error(err) => Err(format!("append only file replay failed: {}", err).into()),
+        _ => Ok(()),
+    }
+}
+
+impl Aof {
+    fn new(path: PathBuf, file: File, fsync: Fsync, size: u64) -> Aof {
+        let file = Arc::new(file);
+
+        if fsync == Fsync::EverySec {
+            tokio::spawn(sync_every_second(Arc::downgrade(&file)));
+        }
+
+        Aof {
+            path,
+            file,
+            fsync,
+            size,
+            base_size: size,
+            selected: None,
+            rewrite_buffer: None,
+        }
+    }
+
+    /// Log `buf`, a write command which was just applied to `db`, as encoded
+    /// by `encoded`.
+    pub(crate) fn append(&mut self, db: &Db, buf: &[u8]) -> io::Result<()> {
+        let index = db.index();
+        if self.selected != Some(index) {
+            self.write(&select(index))?;
+            self.selected = Some(index);
+        }
+        self.write(buf)?;
+
+        if self.fsync == Fsync::Always {
+            self.file.sync_data()?;
+        }
+
+        if self.rewrite_buffer.is_none()
+            && self.size >= AUTO_REWRITE_MIN_SIZE
+            && self.size >= self.base_size * 2
+        {
+            self.rewrite(db);
+        }
+
+        Ok(())
+    }
+
+    /// Write `buf` to the file, and to the rewrite buffer if a rewrite is in
+    /// progress.
+    fn write(&mut self, buf: &[u8]) -> io::Result<()> {
+        (&*self.file).write_all(buf)?;
+        self.size += buf.len() as u64;
+
+        if let Some(pending) = &mut self.rewrite_buffer {
+            pending.extend_from_slice(buf);
+        }
+
+        Ok(())
+    }
+
+    /// Start compacting the file in the background.
+    ///
+    /// The keyspace is snapshotted right away, which requires that no write
+    /// command runs concurrently: callers hold the `Db::aof` lock. A blocking
+    /// task writes the snapshot to a temporary file while newly logged
+    /// commands are buffered. Once done, the buffer is appended and the
+    /// temporary file atomically replaces the log.
+    ///
+    /// Returns `false` if a rewrite is already in progress.
+    pub(crate) fn rewrite(&mut self, db: &Db) -> bool {
+        if self.rewrite_buffer.is_some() {
+            return false;
+        }
+
+        let entries = db.dump();
+        let now = unix_time();
+        self.rewrite_buffer = Some(vec![]);
+
+        // The buffer is appended after the database last selected by the
+        // snapshot, so it starts with a `SELECT` of its own.
+        self.selected = None;
+
+        let db = db.clone();
+        let tmp = temp_path(&self.path);
+
+        tokio::spawn(async move {
+            let path = tmp.clone();
+            let file = task::spawn_blocking(move || write_snapshot(&path, entries, now))
+                .await
+                .unwrap_or_else(|err| Err(io::Error::other(err)));
+
+            finish_rewrite(&db, &tmp, file);
+        });
+
+        true
+    }
+}
+
+/// Swap the rewritten file in, once the snapshot has been written to `tmp`.
+fn finish_rewrite(db: &Db, tmp: &Path, file: io::Result<File>) {
+    let mut aof = db.aof();
+
+    let aof = match aof.as_mut() {
+        Some(aof) => aof,
+        None => return,
+    };
+
+    let pending = aof.rewrite_buffer.take().unwrap_or_default();
+
+    let file = file.and_then(|mut file| {
+        file.write_all(&pending)?;
+        file.sync_all()?;
+        fs::rename(tmp, &aof.path)?;
+        Ok(file)
+    });
+
+    match file.and_then(|file| Ok((file.metadata()?.len(), file))) {
+        Ok((size, file)) => *aof = Aof::new(aof.path.clone(), file, aof.fsync, size),
+        Err(err) => {
+            eprintln!("append only file rewrite failed: {}", err);
+            let _ = fs::remove_file(tmp);
+        }
+    }
+}
+
+/// Write the commands recreating `databases`, the entries of each database,
+/// to a new file at `path`.
+///
+/// Times to live are relative to `now`, the Unix time of the snapshot.
+fn write_snapshot(
+    path: &Path,
+    databases: Vec<Vec<(String, Value, Option<Duration>)>>,
+    now: Duration,
+) -> io::Result<File> {
+    let mut file = BufWriter::new(File::create(path)?);
+    let mut buf = vec![];
+
+    let databases = databases
+        .into_iter()
+        .enumerate()
+        .filter(|(_, entries)| !entries.is_empty());
+
+    for (index, entries) in databases {
+        file.write_all(&select(index))?;
+        write_entries(&mut file, &mut buf, entries, now)?;
+    }
+
+    let file = file.into_inner().map_err(|err| err.into_error())?;
+    file.sync_all()?;
+
+    Ok(file)
+}
+
+/// Write the commands recreating `entries`, using `buf` as scratch space.
+fn write_entries(
+    file: &mut impl Write,
+    buf: &mut Vec<u8>,
+    entries: Vec<(String, Value, Option<Duration>)>,
+    now: Duration,
+) -> io::Result<()> {
+    for (key, value, ttl) in entries {
+        let key = Bytes::from(key);
+
+        buf.clear();
+        for command in recreate(&key, value) {
+            encode(&command, buf);
+        }
+        if let Some(ttl) = ttl {
+            encode(&pexpireat(key, now + ttl), buf);
+        }
+
+        file.write_all(buf)?;
+    }
+
+    Ok(())
+}
+
+/// The commands recreating `value` at `key`. Collections are split into
+/// commands of at most `REWRITE_BATCH` items.
+fn recreate(key: &Bytes, value: Value) -> Vec<Vec<Bytes>> {
+    let (name, args): (&'static [u8], Vec<Vec<Bytes>>) = match value {
+        Value::String(value) => return vec![vec![Bytes::from_static(b"SET"), key.clone(), value]],
+        Value::List(list) => (
+            b"RPUSH",
+            list.into_iter().map(|value| vec![value]).collect(),
+        ),
+        Value::Hash(hash) => (
+            b"HSET",
+            hash.into_iter()
+                .map(|(field, value)| vec![field, value])
+                .collect(),
+        ),
+        Value::Set(set) => (
+            b"SADD",
+            set.into_iter().map(|member| vec![member]).collect(),
+        ),
+        Value::ZSet(zset) => (
+            b"ZADD",
+            zset.iter()
+                .map(|(member, score)| {
+                    vec![Bytes::from(frame::format_double(score)), member.clone()]
+                })
+                .collect(),
+        ),
+        Value::Stream(stream) => return recreate_stream(key, stream),
+    };
+
+    args.chunks(REWRITE_BATCH)
+        .map(|chunk| {
+            let mut command = vec![Bytes::from_static(name), key.clone()];
+            command.extend(chunk.iter().flatten().cloned());
+            command
+        })
+        .collect()
+}
+
+/// The commands recreating a stream: its entries, its last ID, then its
+/// consumer groups along with their pending entries.
+fn recreate_stream(key: &Bytes, stream: Stream) -> Vec<Vec<Bytes>> {
+    let command = |args: &[&[u8]]| -> Vec<Bytes> {
+        args.iter().map(|arg| Bytes::copy_from_slice(arg)).collect()
+    };
+    let id = |id: StreamId| id.to_string().into_bytes();
+
+    let mut commands = vec![];
+
+    for (entry_id, fields) in &stream.entries {
+        let mut xadd = command(&[b"XADD", key, &id(*entry_id)]);
+        for (field, value) in fields {
+            xadd.extend([field.clone(), value.clone()]);
+        }
+        commands.push(xadd);
+    }
+
+    match stream.entries.last_key_value() {
+        // An empty stream is created by adding an entry trimmed right away,
+        // its last ID may then be anything, even `0-0`.
+        None => {
+            commands.push(command(&[b"XADD", key, b"MAXLEN", b"0", b"0-1", b"", b""]));
+            commands.push(command(&[b"XSETID", key, &id(stream.last_id)]));
+        }
+        Some((last, _)) if *last < stream.last_id => {
+            commands.push(command(&[b"XSETID", key, &id(stream.last_id)]));
+        }
+        Some(_) => {}
+    }
+
+    for (name, group) in &stream.groups {
+        let name = name.as_bytes();
+        commands.push(command(&[
+            b"XGROUP",
+            b"CREATE",
+            key,
+            name,
+            &id(group.last_delivered),
+        ]));
+
+        for (entry_id, pending) in &group.pending {
+            commands.push(claim(key, name, *entry_id, pending));
+        }
+    }
+
+    commands
+}
+
+/// The `XCLAIM` handing the entry `id` of `group` over to its consumer, as
+/// `pending` describes it.
+fn claim(key: &[u8], group: &[u8], id: StreamId, pending: &Pending) -> Vec<Bytes> {
+    [
+        b"XCLAIM",
+        key,
+        group,
+        pending.consumer.as_bytes(),
+        b"0",
+        id.to_string().as_bytes(),
+        b"TIME",
+        pending.delivered_at.to_string().as_bytes(),
+        b"RETRYCOUNT",
+        pending.deliveries.to_string().as_bytes(),
+        b"FORCE",
+        b"JUSTID",
+    ]
+    .iter()
+    .map(|arg| Bytes::copy_from_slice(arg))
+    .collect()
+}
+
+/// `command`, which was just applied to `db`, encoded the way it is logged.
+pub(crate) fn encoded(db: &Db, command: &Frame) -> Vec<u8> {
+    let mut buf = vec![];
+    for args in propagate(db, command) {
+        encode(&args, &mut buf);
+    }
+
+    buf
+}
+
+/// The commands to log for `command`, which was just applied to `db`.
+fn propagate(db: &Db, command: &Frame) -> Vec<Vec<Bytes>> {
+    let args: Vec<_> = match command {
+        Frame::Array(parts) => parts.iter().map(arg).collect(),
+        _ => return vec![],
+    };
+
+    let name = args[0].to_ascii_lowercase();
+
+    // Relative expirations are logged as the absolute time they ended up at.
+    // The arguments were validated when the command ran.
+    match &name[..] {
+        b"expire" | b"pexpire" => expiration(db, &args[1]),
+        b"set" if args.len() > 3 => {
+            let mut commands = vec![args[..3].to_vec()];
+            commands.extend(expiration(db, &args[1]));
+            commands
+        }
+        // Generated stream IDs are logged as the ID they ended up as.
+        b"xadd" => {
+            let mut args = args;
+            let mut id = 2;
+            if args[id].eq_ignore_ascii_case(b"maxlen") {
+                id += match &args[id + 1][..] {
+                    b"=" | b"~" => 3,
+                    _ => 2,
+                };
+            }
+
+            if let Ok(Some(last)) = db.xlast_id(&String::from_utf8_lossy(&args[1])) {
+                args[id] = Bytes::from(last.to_string());
+            }
+            vec![args]
+        }
+        // Claims are logged as the delivery they ended up with, see
+        // `cmd::stream::claimed`.
+        b"xclaim" => {
+            let key = String::from_utf8_lossy(&args[1]);
+            let group = String::from_utf8_lossy(&args[2]);
+
+            args[5..]
+                .iter()
+                .filter_map(|id| StreamId::parse(std::str::from_utf8(id).ok()?, 0))
+                .filter_map(|id| {
+                    let pending = db.xpending_entry(&key, &group, id)?;
+                    Some(claim(&args[1], &args[2], id, &pending))
+                })
+                .collect()
+        }
+        _ => vec![args],
+    }
+}
+
+/// The command recreating the current expiration of `key`.
+fn expiration(db: &Db, key: &Bytes) -> Vec<Vec<Bytes>> {
+    match db.ttl(&String::from_utf8_lossy(key)) {
+        // The key expired right away
+        None => vec![vec![Bytes::from_static(b"DEL"), key.clone()]],
+        Some(None) => vec![],
+        Some(Some(ttl)) => vec![pexpireat(key.clone(), unix_time() + ttl)],
+    }
+}
+
+/// `SELECT index`, encoded the way it is logged.
+pub(crate) fn select(index: usize) -> Vec<u8> {
+    let mut buf = vec![];
+    let args = [
+        Bytes::from_static(b"SELECT"),
+        Bytes::from(index.to_string()),
+    ];
+    encode(&args, &mut buf);
+
+    buf
+}
+
+fn pexpireat(key: Bytes, when: Duration) -> Vec<Bytes> {
+    let when = Bytes::from(when.as_millis().to_string());
+    vec![Bytes::from_static(b"PEXPIREAT"), key, when]
+}
+
+/// A command argument as raw bytes.
+fn arg(frame: &Frame) -> Bytes {
+    match frame {
+        Frame::Bulk(data) => data.clone(),
+        Frame::Simple(s) => Bytes::from(s.clone()),
+        Frame::Integer(n) => Bytes::from(n.to_string()),
+        _ => Bytes::new(),
+    }
+}
+
+/// Encode a command as a RESP array of bulk strings.
+fn encode(args: &[Bytes], dst: &mut Vec<u8>) {
+    dst.extend_from_slice(format!("*{}\r\n", args.len()).as_bytes());
+
+    for arg in args {
+        dst.extend_from_slice(format!("${}\r\n", arg.len()).as_bytes());
+        dst.extend_from_slice(arg);
+        dst.extend_from_slice(b"\r\n");
+    }
+}
+
+/// Path of the temporary file a rewrite of `path` is written to.
+fn temp_path(path: &Path) -> PathBuf {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".rewrite");
+    PathBuf::from(tmp)
+}
+
+/// Flush `file` to disk every second, until it is closed or replaced.
+async fn sync_every_second(file: Weak<File>) {
+    let mut interval = time::interval(Duration::from_secs(1));
+
+    loop {
+        interval.tick().await;
+
+        let file = match file.upgrade() {
+            Some(file) => file,
+            None => return,
+        };
+
+        if let Ok(Err(err)) = task::spawn_blocking(move || file.sync_data()).await {
+            eprintln!("append only file fsync failed: {}", err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A fresh file path in the temporary directory.
+    fn temp_file(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("my-redis-2-{}-{}", std::process::id(), name));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    fn run(db: &Db, args: &[&str]) -> Frame {
+        let args = args
+            .iter()
+            .map(|arg| Frame::Bulk(Bytes::copy_from_slice(arg.as_bytes())))
+            .collect();
+        cmd::apply(Frame::Array(args), db)
+    }
+
+    #[tokio::test]
+    async fn replays_logged_writes() {
+        let path = temp_file("replay.aof");
+
+        let db = Db::new();
+        open(&db, &path, Fsync::Always).unwrap();
+        run(&db, &["SET", "a", "1"]);
+        run(&db, &["INCR", "a"]);
+        run(&db, &["SET", "b", "x", "EX", "100"]);
+        run(&db, &["SET", "c", "y"]);
+        run(&db, &["DEL", "c"]);
+        run(&db, &["INCR", "b"]);
+        run(&db.select(2).unwrap(), &["SET", "a", "other"]);
+        run(&db, &["INCR", "a"]);
+        drop(db);
+
+        let db = Db::new();
+        open(&db, &path, Fsync::No).unwrap();
+        assert_eq!(db.get("a"), Ok(Some(Bytes::from("3"))));
+        assert_eq!(db.get("b"), Ok(Some(Bytes::from("x"))));
+        let other = db.select(2).unwrap();
+        assert_eq!(other.get("a"), Ok(Some(Bytes::from("other"))));
+        assert_eq!(db.get("c"), Ok(None));
+
+        // The expiration was logged as an absolute time, it did not restart.
+        let ttl = db.ttl("b").unwrap().unwrap();
+        assert!(ttl <= Duration::from_secs(100) && ttl > Duration::from_secs(90));
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn claims_replay_as_they_happened() {
+        let path = temp_file("claims.aof");
+        let pending = |db: &Db| {
+            let all = (std::ops::Bound::Unbounded, std::ops::Bound::Unbounded);
+            let pending = db.xpending("s", "g", all, 10, None).unwrap();
+            pending
+                .into_iter()
+                .map(|(id, consumer, _, deliveries)| (id.to_string(), consumer, deliveries))
+                .collect::<Vec<_>>()
+        };
+
+        let db = Db::new();
+        open(&db, &path, Fsync::Always).unwrap();
+        run(&db, &["XADD", "s", "1-1", "f", "v"]);
+        run(&db, &["XGROUP", "CREATE", "s", "g", "0"]);
+        run(
+            &db,
+            &["XREADGROUP", "GROUP", "g", "c1", "STREAMS", "s", ">"],
+        );
+        std::thread::sleep(Duration::from_millis(50));
+
+        // Idle for long enough only when the claim first ran.
+        run(&db, &["XCLAIM", "s", "g", "c2", "20", "1-1"]);
+        let claimed = pending(&db);
+        assert_eq!(claimed, [("1-1".to_string(), "c2".to_string(), 2)]);
+
+        // Claims which claim nothing are not logged.
+        let len = fs::metadata(&path).unwrap().len();
+        run(&db, &["XCLAIM", "s", "g", "c3", "1000", "1-1", "JUSTID"]);
+        assert_eq!(fs::metadata(&path).unwrap().len(), len);
+        drop(db);
+
+        let db = Db::new();
+        open(&db, &path, Fsync::No).unwrap();
+        assert_eq!(pending(&db), claimed);
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn truncated_tail_is_discarded() {
+        let path = temp_file("truncated.aof");
+        let complete = b"*3\r\n$3\r\nSET\r\n$1\r\na\r\n$1\r\n1\r\n";
+        fs::write(&path, [&complete[..], b"*3\r\n$3\r\nSET\r\n$1"].concat()).unwrap();
+
+        let db = Db::new();
+        open(&db, &path, Fsync::No).unwrap();
+        assert_eq!(db.get("a"), Ok(Some(Bytes::from("1"))));
+        assert_eq!(fs::read(&path).unwrap(), complete);
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn rewrite_compacts_the_log() {
+        let path = temp_file("rewrite.aof");
+
+        let db = Db::new();
+        open(&db, &path, Fsync::No).unwrap();
+        for _ in 0..100 {
+            run(&db, &["INCR", "counter"]);
+        }
+        run(&db, &["SET", "temp", "x", "PX", "100000"]);
+        for i in 0..100 {
+            run(&db, &["RPUSH", "list", &i.to_string()]);
+        }
+        run(&db, &["HSET", "hash", "f", "v"]);
+        run(&db, &["SADD", "set", "a", "b"]);
+        run(&db, &["ZADD", "zset", "1.5", "a", "-inf", "b"]);
+        run(&db, &["XADD", "stream", "*", "f", "v"]);
+        run(&db, &["XGROUP", "CREATE", "stream", "g", "0"]);
+        run(
+            &db,
+            &["XREADGROUP", "GROUP", "g", "c", "STREAMS", "stream", ">"],
+        );
+        run(&db, &["XGROUP", "CREATE", "empty", "g", "$", "MKSTREAM"]);
+        run(&db.select(5).unwrap(), &["SET", "counter", "other"]);
+        let before = fs::metadata(&path).unwrap().len();
+
+        assert!(db.aof().as_mut().unwrap().rewrite(&db));
+        // Writes made during the rewrite end up in the new file as well.
+        run(&db, &["SET", "late", "1"]);
+
+        while db.aof().as_ref().unwrap().rewrite_buffer.is_some() {
+            time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(fs::metadata(&path).unwrap().len() < before);
+
+        let replayed = Db::new();
+        open(&replayed, &path, Fsync::No).unwrap();
+        assert_eq!(replayed.get("counter"), Ok(Some(Bytes::from("100"))));
+        assert_eq!(replayed.get("late"), Ok(Some(Bytes::from("1"))));
+        let other = replayed.select(5).unwrap();
+        assert_eq!(other.get("counter"), Ok(Some(Bytes::from("other"))));
+        assert!(replayed.ttl("temp").unwrap().is_some());
+        assert_eq!(replayed.lrange("list", 0, -1), db.lrange("list", 0, -1));
+        assert_eq!(replayed.hget_all("hash"), db.hget_all("hash"));
+        assert_eq!(replayed.sunion(&["set".to_string()]).unwrap().len(), 2);
+        assert_eq!(replayed.zrange("zset", 0, -1), db.zrange("zset", 0, -1));
+        assert_eq!(replayed.xlast_id("stream"), db.xlast_id("stream"));
+        assert_eq!(replayed.xlast_id("empty"), Ok(Some(StreamId::MIN)));
+        assert_eq!(
+            run(&replayed, &["XPENDING", "stream", "g"]),
+            run(&db, &["XPENDING", "stream", "g"])
+        );
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn incomplete_transaction_is_discarded() {
+        let path = temp_file("transaction.aof");
+
+        let mut data = vec![];
+        encode(
+            &[Bytes::from("SET"), Bytes::from("a"), Bytes::from("1")],
+            &mut data,
+        );
+        let complete = data.len();
+        encode(&[Bytes::from("MULTI")], &mut data);
+        encode(
+            &[Bytes::from("SET"), Bytes::from("b"), Bytes::from("2")],
+            &mut data,
+        );
+        fs::write(&path, &data).unwrap();
+
+        let db = Db::new();
+        open(&db, &path, Fsync::No).unwrap();
+        assert_eq!(db.get("a"), Ok(Some(Bytes::from("1"))));
+        assert_eq!(db.get("b"), Ok(None));
+        assert_eq!(fs::read(&path).unwrap(), &data[..complete]);
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn writes_only_lock_the_file_while_logged() {
+        let path = temp_file("logging.aof");
+
+        // Unlogged writes run while the file slot is locked.
+        let db = Db::new();
+        let aof = db.aof();
+        run(&db, &["SET", "a", "1"]);
+        drop(aof);
+
+        enable(&db, &path, Fsync::No).unwrap();
+        run(&db, &["SET", "b", "2"]);
+        while db.aof().as_ref().unwrap().rewrite_buffer.is_some() {
+            time::sleep(Duration::from_millis(10)).await;
+        }
+        disable(&db).unwrap();
+        run(&db, &["SET", "c", "3"]);
+
+        let db = Db::new();
+        open(&db, &path, Fsync::No).unwrap();
+        assert_eq!(db.get("a"), Ok(Some(Bytes::from("1"))));
+        assert_eq!(db.get("b"), Ok(Some(Bytes::from("2"))));
+        assert_eq!(db.get("c"), Ok(None));
+
+        fs::remove_file(&path).unwrap();
+    }
+}
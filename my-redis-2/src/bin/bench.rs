@@ -0,0 +1,455 @@
+//! A `redis-benchmark` style load generator.
+//!
+//! `bench [-h host] [-p port] [-a password] [-c clients] [-n requests]
+//! [-P pipeline] [-d size] [-r keyspace] [-t tests] [--csv]`
+//!
+//! * `-a`: password the connections authenticate with
+//! * `-c 50`: connections opened, each running requests one batch at a time
+//! * `-n 100000`: requests run per test, across every connection
+//! * `-P 1`: requests written per batch before reading their replies
+//! * `-d 3`: size in bytes of the `SET` and `LPUSH` values
+//! * `-r 1`: number of distinct keys, picked at random for each request
+//! * `-t get,set,incr,lpush`: tests to run, one after the other
+//! * `--csv`: print one CSV line per test instead of a report
+//!
+//! The latency of a request is the time from writing its batch to reading its
+//! reply.
+
+use my_redis_2::{Connection, Frame};
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use std::{env, process};
+use tokio::net::TcpStream;
+
+/// Percentiles reported, see `Latencies::percentile`.
+const PERCENTILES: [f64; 7] = [50.0, 75.0, 90.0, 95.0, 99.0, 99.9, 100.0];
+
+#[derive(Debug, Clone)]
+struct Options {
+    addr: String,
+    password: Option<String>,
+    clients: usize,
+    requests: usize,
+    pipeline: usize,
+    value_size: usize,
+    keyspace: u64,
+    tests: Vec<Test>,
+    csv: bool,
+}
+
+/// A command to benchmark.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Test {
+    Get,
+    Set,
+    Incr,
+    Lpush,
+}
+
+/// Outcome of running a test.
+#[derive(Debug, Default)]
+struct Report {
+    elapsed: Duration,
+    latencies: Latencies,
+    errors: usize,
+}
+
+/// Latency of every request, in microseconds, sorted once every request
+/// completed.
+#[derive(Debug, Default)]
+struct Latencies {
+    samples: Vec<u64>,
+}
+
+/// Cheap pseudo random numbers to pick keys, xorshift64*.
+#[derive(Debug)]
+struct Rng(u64);
+
+#[tokio::main]
+async fn main() {
+    let options = match options(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+    };
+
+    if options.csv {
+        println!("\"test\",\"rps\",\"avg_latency_ms\",\"min_latency_ms\",\"p50_latency_ms\",\"p99_latency_ms\",\"p999_latency_ms\",\"max_latency_ms\"");
+    }
+
+    for &test in &options.tests {
+        let report = match run(test, &options).await {
+            Ok(report) => report,
+            Err(err) => {
+                eprintln!("{} failed: {}", test.name(), err);
+                process::exit(1);
+            }
+        };
+
+        match options.csv {
+            true => println!("{}", report.csv_line(test)),
+            false => report.print(test, &options),
+        }
+    }
+}
+
+fn options(args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut host = "127.0.0.1".to_string();
+    let mut port = "6379".to_string();
+    let mut options = Options {
+        addr: String::new(),
+        password: None,
+        clients: 50,
+        requests: 100_000,
+        pipeline: 1,
+        value_size: 3,
+        keyspace: 1,
+        tests: vec![Test::Get, Test::Set, Test::Incr, Test::Lpush],
+        csv: false,
+    };
+
+    let mut args = args;
+    while let Some(arg) = args.next() {
+        if arg == "--csv" {
+            options.csv = true;
+            continue;
+        }
+
+        let value = args
+            .next()
+            .ok_or_else(|| format!("{} expects a value", arg))?;
+        let number = || {
+            value
+                .parse()
+                .ok()
+                .filter(|&n| n > 0)
+                .ok_or_else(|| format!("{} expects a positive integer", arg))
+        };
+
+        match &arg[..] {
+            "-h" => host = value.clone(),
+            "-p" => port = value.clone(),
+            "-a" => options.password = Some(value.clone()),
+            "-c" => options.clients = number()?,
+            "-n" => options.requests = number()?,
+            "-P" => options.pipeline = number()?,
+            "-d" => options.value_size = number()?,
+            "-r" => options.keyspace = number()? as u64,
+            "-t" => {
+                options.tests = value
+                    .split(',')
+                    .map(|name| Test::parse(name).ok_or_else(|| format!("unknown test {}", name)))
+                    .collect::<Result<_, _>>()?
+            }
+            _ => return Err(format!("unknown option {}", arg)),
+        }
+    }
+
+    options.addr = format!("{}:{}", host, port);
+    Ok(options)
+}
+
+/// Connect to the server, authenticating if `-a` was given.
+async fn connect(options: &Options) -> my_redis_2::Result<Connection> {
+    let mut connection = Connection::new(TcpStream::connect(&options.addr).await?);
+
+    if let Some(password) = &options.password {
+        let auth = Frame::Array(vec![
+            Frame::Bulk("AUTH".into()),
+            Frame::Bulk(password.clone().into()),
+        ]);
+        connection.write_frame(&auth).await?;
+
+        match connection.read_frame().await? {
+            Some(Frame::Error(err)) => return Err(err.into()),
+            Some(_) => {}
+            None => return Err("connection closed by the server".into()),
+        }
+    }
+
+    Ok(connection)
+}
+
+/// Run `options.requests` requests of `test` over `options.clients`
+/// connections.
+async fn run(test: Test, options: &Options) -> my_redis_2::Result<Report> {
+    // Connect first, so connecting does not count towards the elapsed time.
+    let mut connections = vec![];
+    for _ in 0..options.clients {
+        connections.push(connect(options).await?);
+    }
+
+    let remaining = Arc::new(AtomicUsize::new(options.requests));
+    let start = Instant::now();
+
+    let tasks: Vec<_> = connections
+        .into_iter()
+        .enumerate()
+        .map(|(i, connection)| {
+            let remaining = remaining.clone();
+            let options = options.clone();
+            tokio::spawn(async move { run_client(test, connection, &remaining, &options, i).await })
+        })
+        .collect();
+
+    let mut report = Report::default();
+    for task in tasks {
+        let client = task.await??;
+        report.latencies.samples.extend(client.latencies.samples);
+        report.errors += client.errors;
+    }
+    report.elapsed = start.elapsed();
+    report.latencies.samples.sort_unstable();
+
+    Ok(report)
+}
+
+/// Run batches of requests on `connection` until `remaining` runs out.
+async fn run_client(
+    test: Test,
+    mut connection: Connection,
+    remaining: &AtomicUsize,
+    options: &Options,
+    id: usize,
+) -> my_redis_2::Result<Report> {
+    let mut report = Report::default();
+    let mut rng = Rng::new(id as u64);
+    let value = vec![b'x'; options.value_size];
+    let mut batch = vec![];
+
+    loop {
+        // Claim the requests of the next batch.
+        let claimed = remaining
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| {
+                (n > 0).then(|| n - n.min(options.pipeline))
+            })
+            .map_or(0, |n| n.min(options.pipeline));
+        if claimed == 0 {
+            return Ok(report);
+        }
+
+        batch.clear();
+        for _ in 0..claimed {
+            let key = rng.next() % options.keyspace;
+            encode(&test.command(key, &value), &mut batch);
+        }
+
+        let start = Instant::now();
+        connection.write_raw(&batch).await?;
+
+        for _ in 0..claimed {
+            match connection.read_frame().await? {
+                Some(Frame::Error(_)) => report.errors += 1,
+                Some(_) => {}
+                None => return Err("connection closed by the server".into()),
+            }
+            report.latencies.record(start.elapsed());
+        }
+    }
+}
+
+/// Encode a command as a RESP array of bulk strings.
+fn encode(args: &[Vec<u8>], dst: &mut Vec<u8>) {
+    dst.extend_from_slice(format!("*{}\r\n", args.len()).as_bytes());
+
+    for arg in args {
+        dst.extend_from_slice(format!("${}\r\n", arg.len()).as_bytes());
+        dst.extend_from_slice(arg);
+        dst.extend_from_slice(b"\r\n");
+    }
+}
+
+impl Test {
+    fn parse(name: &str) -> Option<Test> {
+        Some(match &name.to_ascii_lowercase()[..] {
+            "get" => Test::Get,
+            "set" => Test::Set,
+            "incr" => Test::Incr,
+            "lpush" => Test::Lpush,
+            _ => return None,
+        })
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Test::Get => "GET",
+            Test::Set => "SET",
+            Test::Incr => "INCR",
+            Test::Lpush => "LPUSH",
+        }
+    }
+
+    /// The command run against the `key`th key.
+    fn command(self, key: u64, value: &[u8]) -> Vec<Vec<u8>> {
+        let name = self.name().as_bytes().to_vec();
+
+        match self {
+            Test::Get => vec![name, format!("key:{}", key).into_bytes()],
+            Test::Set => vec![name, format!("key:{}", key).into_bytes(), value.to_vec()],
+            Test::Incr => vec![name, format!("counter:{}", key).into_bytes()],
+            Test::Lpush => vec![name, format!("list:{}", key).into_bytes(), value.to_vec()],
+        }
+    }
+}
+
+impl Report {
+    fn requests_per_second(&self) -> f64 {
+        self.latencies.samples.len() as f64 / self.elapsed.as_secs_f64()
+    }
+
+    fn print(&self, test: Test, options: &Options) {
+        let latencies = &self.latencies;
+
+        println!("====== {} ======", test.name());
+        println!(
+            "  {} requests completed in {:.2} seconds",
+            latencies.samples.len(),
+            self.elapsed.as_secs_f64()
+        );
+        println!("  {} parallel clients", options.clients);
+        println!("  {} bytes payload", options.value_size);
+        println!("  pipeline depth {}", options.pipeline);
+        if self.errors > 0 {
+            println!("  {} error replies", self.errors);
+        }
+        println!();
+
+        println!("Latency by percentile distribution:");
+        for percentile in PERCENTILES {
+            println!(
+                "{:>8.3}% <= {:.3} milliseconds",
+                percentile,
+                millis(latencies.percentile(percentile))
+            );
+        }
+        println!();
+
+        println!("Summary:");
+        println!(
+            "  throughput summary: {:.2} requests per second",
+            self.requests_per_second()
+        );
+        println!("  latency summary (msec):");
+        println!(
+            "  {:>9} {:>9} {:>9} {:>9} {:>9} {:>9}",
+            "avg", "min", "p50", "p99", "p999", "max"
+        );
+        println!(
+            "  {:>9.3} {:>9.3} {:>9.3} {:>9.3} {:>9.3} {:>9.3}",
+            millis(latencies.mean()),
+            millis(latencies.percentile(0.0)),
+            millis(latencies.percentile(50.0)),
+            millis(latencies.percentile(99.0)),
+            millis(latencies.percentile(99.9)),
+            millis(latencies.percentile(100.0)),
+        );
+        println!();
+    }
+
+    fn csv_line(&self, test: Test) -> String {
+        let latencies = &self.latencies;
+
+        format!(
+            "\"{}\",\"{:.2}\",\"{:.3}\",\"{:.3}\",\"{:.3}\",\"{:.3}\",\"{:.3}\",\"{:.3}\"",
+            test.name(),
+            self.requests_per_second(),
+            millis(latencies.mean()),
+            millis(latencies.percentile(0.0)),
+            millis(latencies.percentile(50.0)),
+            millis(latencies.percentile(99.0)),
+            millis(latencies.percentile(99.9)),
+            millis(latencies.percentile(100.0)),
+        )
+    }
+}
+
+impl Latencies {
+    fn record(&mut self, latency: Duration) {
+        self.samples.push(latency.as_micros() as u64);
+    }
+
+    /// The latency under which `percentile` percent of the requests completed,
+    /// in microseconds. `0` is the fastest request, `100` the slowest.
+    fn percentile(&self, percentile: f64) -> u64 {
+        if self.samples.is_empty() {
+            return 0;
+        }
+
+        // In thousandths of a percent, so 99.9% of 1000 samples is exactly
+        // the 999th.
+        let scaled = (percentile * 1000.0).round() as usize;
+        let rank = (scaled * self.samples.len()).div_ceil(100_000);
+        self.samples[rank.clamp(1, self.samples.len()) - 1]
+    }
+
+    fn mean(&self) -> u64 {
+        match self.samples.len() {
+            0 => 0,
+            n => self.samples.iter().sum::<u64>() / n as u64,
+        }
+    }
+}
+
+fn millis(micros: u64) -> f64 {
+    micros as f64 / 1000.0
+}
+
+impl Rng {
+    fn new(seed: u64) -> Rng {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos() as u64;
+
+        // The state must not be zero.
+        Rng((nanos ^ seed.wrapping_mul(0x9e37_79b9_7f4a_7c15)) | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percentiles() {
+        let mut latencies = Latencies::default();
+        for micros in (1..=1000).rev() {
+            latencies.record(Duration::from_micros(micros));
+        }
+        latencies.samples.sort_unstable();
+
+        assert_eq!(latencies.percentile(0.0), 1);
+        assert_eq!(latencies.percentile(50.0), 500);
+        assert_eq!(latencies.percentile(99.0), 990);
+        assert_eq!(latencies.percentile(99.9), 999);
+        assert_eq!(latencies.percentile(100.0), 1000);
+        assert_eq!(latencies.mean(), 500);
+    }
+
+    #[test]
+    fn parses_options() {
+        let args = "-c 4 -n 10 -P 16 -t set,lpush --csv";
+        let options = options(args.split(' ').map(String::from)).unwrap();
+
+        assert_eq!(
+            (options.clients, options.requests, options.pipeline),
+            (4, 10, 16)
+        );
+        assert_eq!(options.tests, [Test::Set, Test::Lpush]);
+        assert!(options.csv);
+
+        assert!(super::options(["-t", "del"].iter().map(|s| s.to_string())).is_err());
+        assert!(super::options(["-c", "0"].iter().map(|s| s.to_string())).is_err());
+    }
+}
@@ -0,0 +1,579 @@
+//! A `redis-cli` style command line client.
+//!
+//! `cli [-h host] [-p port] [--user name] [-a password] [--pipe] [command [arg ...]]`
+//!
+//! * With `-a`, connections authenticate first, as `--user` if given.
+//! * With a command, runs it and prints the reply.
+//! * With `--pipe`, sends stdin, which must be encoded as RESP, as is and
+//!   reports how many replies and errors came back.
+//! * Otherwise, reads commands line by line. Arguments are separated by
+//!   spaces and may be quoted, e.g. `SET greeting "hello\nworld"`. Lines are
+//!   kept in `~/.my-redis-cli_history`, listed by `history`, except those
+//!   holding a password.
+
+use my_redis_2::client::command;
+use my_redis_2::frame::format_double;
+use my_redis_2::{Connection, Frame};
+
+use std::io::{IsTerminal, Write};
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::{env, fs, process};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::{select, signal};
+
+/// Lines kept in the history file.
+const HISTORY_LEN: usize = 1000;
+
+/// Command line options.
+#[derive(Debug)]
+struct Options {
+    addr: String,
+    pipe: bool,
+
+    /// The `AUTH` command sent after connecting, if a password was given.
+    auth: Option<Vec<Vec<u8>>>,
+
+    /// The command to run, if given on the command line.
+    command: Vec<Vec<u8>>,
+}
+
+/// The lines entered, oldest first, loaded from and saved to `path`.
+#[derive(Debug)]
+struct History {
+    path: Option<PathBuf>,
+    lines: Vec<String>,
+}
+
+#[tokio::main]
+async fn main() {
+    let options = options(env::args().skip(1));
+
+    let result = if options.pipe {
+        pipe(&options).await
+    } else if !options.command.is_empty() {
+        run_once(&options, &options.command).await
+    } else {
+        repl(&options).await
+    };
+
+    if let Err(err) = result {
+        eprintln!("{}", err);
+        process::exit(1);
+    }
+}
+
+fn options(args: impl Iterator<Item = String>) -> Options {
+    let mut host = "127.0.0.1".to_string();
+    let mut port = "6379".to_string();
+    let mut pipe = false;
+    let mut user = None;
+    let mut password = None;
+    let mut args = args.peekable();
+
+    while let Some(arg) = args.next_if(|arg| arg.starts_with('-')) {
+        match &arg[..] {
+            "-h" => host = args.next().expect("-h expects a host"),
+            "-p" => port = args.next().expect("-p expects a port"),
+            "--user" => user = Some(args.next().expect("--user expects a name")),
+            "-a" => password = Some(args.next().expect("-a expects a password")),
+            "--pipe" => pipe = true,
+            _ => {
+                eprintln!("unknown option {}", arg);
+                process::exit(1);
+            }
+        }
+    }
+
+    let auth = password.map(|password| {
+        let mut auth = vec![b"AUTH".to_vec()];
+        auth.extend(user.map(String::into_bytes));
+        auth.push(password.into_bytes());
+        auth
+    });
+
+    Options {
+        addr: format!("{}:{}", host, port),
+        pipe,
+        auth,
+        command: args.map(String::into_bytes).collect(),
+    }
+}
+
+/// Run `args`, printing the reply.
+async fn run_once(options: &Options, args: &[Vec<u8>]) -> my_redis_2::Result<()> {
+    let mut connection = connect(options).await?;
+
+    if is_subscribe(args) {
+        return subscribed(&mut connection, args).await;
+    }
+
+    println!("{}", format_reply(&call(&mut connection, args).await?));
+    Ok(())
+}
+
+/// Read commands from stdin until EOF or `quit`, printing their replies.
+async fn repl(options: &Options) -> my_redis_2::Result<()> {
+    let interactive = std::io::stdin().is_terminal();
+    let mut history = History::load();
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+
+    // Connected on the first command, and again after a failure.
+    let mut connection = None;
+
+    loop {
+        if interactive {
+            print!("{}> ", options.addr);
+            std::io::stdout().flush()?;
+        }
+
+        let line = match lines.next_line().await? {
+            Some(line) => line,
+            None => break,
+        };
+
+        let args = match split_args(&line) {
+            Some(args) if args.is_empty() => continue,
+            Some(args) => args,
+            None => {
+                println!("Invalid argument(s)");
+                continue;
+            }
+        };
+        if !sensitive(&args) {
+            history.add(&line);
+        }
+
+        match &args[0].to_ascii_lowercase()[..] {
+            b"quit" | b"exit" => break,
+            b"history" => {
+                history.print();
+                continue;
+            }
+            _ => {}
+        }
+
+        let conn = match &mut connection {
+            Some(conn) => conn,
+            None => match connect(options).await {
+                Ok(conn) => connection.insert(conn),
+                Err(err) => {
+                    println!("Could not connect to {}: {}", options.addr, err);
+                    continue;
+                }
+            },
+        };
+
+        if is_subscribe(&args) {
+            // Leaving subscriber mode takes Ctrl-C, which ends the session.
+            let result = subscribed(conn, &args).await;
+            history.save();
+            return result;
+        }
+
+        match call(conn, &args).await {
+            Ok(reply) => println!("{}", format_reply(&reply)),
+            Err(err) => {
+                println!("Error: {}", err);
+                connection = None;
+            }
+        }
+    }
+
+    history.save();
+    Ok(())
+}
+
+/// Returns `true` for the commands holding a password, kept out of the
+/// history.
+fn sensitive(args: &[Vec<u8>]) -> bool {
+    let is = |arg: &[u8], names: &[&[u8]]| names.iter().any(|name| arg.eq_ignore_ascii_case(name));
+
+    match args {
+        [name, ..] if is(name, &[b"auth"]) => true,
+        [name, rest @ ..] if is(name, &[b"hello"]) => rest.iter().any(|arg| is(arg, &[b"auth"])),
+        [name, sub, ..] if is(name, &[b"acl"]) => is(sub, &[b"setuser"]),
+        [name, sub, rest @ ..] if is(name, &[b"config"]) && is(sub, &[b"set"]) => rest
+            .iter()
+            .any(|arg| is(arg, &[b"requirepass", b"masterauth"])),
+        _ => false,
+    }
+}
+
+/// Send stdin to the server as is, counting the replies.
+///
+/// Once stdin is exhausted, an `ECHO` of a unique marker is sent. Its reply is
+/// the last one, so every command was answered once it comes back.
+async fn pipe(options: &Options) -> my_redis_2::Result<()> {
+    let mut connection = connect(options).await?;
+    let mut stdin = tokio::io::stdin();
+    let mut buf = vec![0; 16 * 1024];
+
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH)?.as_nanos();
+    let marker = format!("pipe-end-{:x}", nanos);
+
+    let mut done_sending = false;
+    let (mut replies, mut errors) = (0, 0);
+
+    loop {
+        // Replies are read while sending, so the server never stalls on a
+        // full socket buffer.
+        select! {
+            n = stdin.read(&mut buf), if !done_sending => {
+                match n? {
+                    0 => {
+                        done_sending = true;
+                        connection.write_frame(&command(&["ECHO", &marker])).await?;
+                        println!("All data transferred. Waiting for the last reply...");
+                    }
+                    n => connection.write_raw(&buf[..n]).await?,
+                }
+            }
+            frame = connection.read_frame() => match frame? {
+                Some(Frame::Bulk(data)) if done_sending && data == marker.as_bytes() => break,
+                Some(Frame::Error(err)) => {
+                    eprintln!("{}", err);
+                    replies += 1;
+                    errors += 1;
+                }
+                Some(_) => replies += 1,
+                None => return Err("connection closed by the server".into()),
+            },
+        }
+    }
+
+    println!("Last reply received from server.");
+    println!("errors: {}, replies: {}", errors, replies);
+
+    if errors > 0 {
+        process::exit(1);
+    }
+
+    Ok(())
+}
+
+/// Print the messages received after subscribing with `args`, until Ctrl-C.
+async fn subscribed(connection: &mut Connection, args: &[Vec<u8>]) -> my_redis_2::Result<()> {
+    connection.write_frame(&command(args)).await?;
+    println!("Reading messages... (press Ctrl-C to quit)");
+
+    loop {
+        let frame = select! {
+            frame = connection.read_frame() => frame?,
+            _ = signal::ctrl_c() => return Ok(()),
+        };
+
+        match frame {
+            Some(frame) => println!("{}", format_reply(&frame)),
+            None => return Err("connection closed by the server".into()),
+        }
+    }
+}
+
+/// Connect to the server, authenticating if `-a` was given.
+async fn connect(options: &Options) -> my_redis_2::Result<Connection> {
+    let mut connection = Connection::new(TcpStream::connect(&options.addr).await?);
+
+    if let Some(auth) = &options.auth {
+        if let Frame::Error(err) = call(&mut connection, auth).await? {
+            return Err(err.into());
+        }
+    }
+
+    Ok(connection)
+}
+
+/// Send `args` and wait for the reply.
+async fn call(connection: &mut Connection, args: &[Vec<u8>]) -> my_redis_2::Result<Frame> {
+    connection.write_frame(&command(args)).await?;
+
+    match connection.read_frame().await? {
+        Some(frame) => Ok(frame),
+        None => Err("connection closed by the server".into()),
+    }
+}
+
+fn is_subscribe(args: &[Vec<u8>]) -> bool {
+    matches!(
+        &args[0].to_ascii_lowercase()[..],
+        b"subscribe" | b"psubscribe"
+    )
+}
+
+/// Split a line into arguments the way `redis-cli` does.
+///
+/// Arguments are separated by spaces. Within double quotes, `\n`, `\r`, `\t`,
+/// `\b`, `\a`, `\\`, `\"` and `\xHH` escapes are decoded. Within single
+/// quotes, only `\'` is. A closing quote must end the argument. Returns `None`
+/// if quotes are unbalanced.
+fn split_args(line: &str) -> Option<Vec<Vec<u8>>> {
+    let mut args = vec![];
+    let mut chars = line.as_bytes().iter().copied().peekable();
+
+    loop {
+        while chars.next_if(u8::is_ascii_whitespace).is_some() {}
+
+        let quote = match chars.peek() {
+            None => return Some(args),
+            Some(&c) if c == b'"' || c == b'\'' => chars.next(),
+            Some(_) => None,
+        };
+
+        let mut arg = vec![];
+        loop {
+            let c = match (chars.next(), quote) {
+                // Unbalanced quotes.
+                (None, Some(_)) => return None,
+                (None, None) => break,
+                (Some(c), None) if c.is_ascii_whitespace() => break,
+                (Some(c), Some(q)) if c == q => {
+                    // The closing quote must be followed by a space.
+                    if chars.peek().is_some_and(|c| !c.is_ascii_whitespace()) {
+                        return None;
+                    }
+                    break;
+                }
+                (Some(b'\\'), Some(b'"')) => match chars.next()? {
+                    b'n' => b'\n',
+                    b'r' => b'\r',
+                    b't' => b'\t',
+                    b'b' => 8,
+                    b'a' => 7,
+                    b'x' => {
+                        let hex = [chars.next()?, chars.next()?];
+                        u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?
+                    }
+                    c => c,
+                },
+                (Some(b'\\'), Some(b'\'')) if chars.peek() == Some(&b'\'') => chars.next().unwrap(),
+                (Some(c), _) => c,
+            };
+            arg.push(c);
+        }
+
+        args.push(arg);
+    }
+}
+
+/// Format a reply the way `redis-cli` does, annotated with its type. Nested
+/// collections are numbered and indented.
+fn format_reply(frame: &Frame) -> String {
+    match frame {
+        Frame::Simple(s) => s.clone(),
+        Frame::Error(err) => format!("(error) {}", err),
+        Frame::Integer(n) => format!("(integer) {}", n),
+        Frame::Bulk(data) => quote(data),
+        Frame::Null | Frame::NullArray => "(nil)".to_string(),
+        Frame::Double(val) => format!("(double) {}", format_double(*val)),
+        Frame::Boolean(val) => format!("({})", val),
+        Frame::BigNumber(n) => format!("(big number) {}", n),
+        Frame::Verbatim(_, text) => String::from_utf8_lossy(text).into_owned(),
+        Frame::Array(parts) | Frame::Push(parts) => {
+            format_list(parts.iter().map(format_reply), ")", "(empty array)")
+        }
+        Frame::Set(parts) => format_list(parts.iter().map(format_reply), "~", "(empty set)"),
+        Frame::Map(pairs) => {
+            let pairs = pairs.iter().map(|(key, value)| {
+                hang(&format!("{} => ", format_reply(key)), &format_reply(value))
+            });
+            format_list(pairs, "#", "(empty hash)")
+        }
+    }
+}
+
+/// Number `items`, e.g. `1) ...`, `2) ...`, or `1# ...` with `#` as `marker`.
+fn format_list(items: impl ExactSizeIterator<Item = String>, marker: &str, empty: &str) -> String {
+    let width = items.len().to_string().len();
+    let lines: Vec<_> = items
+        .enumerate()
+        .map(|(i, item)| hang(&format!("{:>width$}{} ", i + 1, marker), &item))
+        .collect();
+
+    match lines.is_empty() {
+        true => empty.to_string(),
+        false => lines.join("\n"),
+    }
+}
+
+/// `body` preceded by `prefix`, its following lines aligned past the prefix.
+fn hang(prefix: &str, body: &str) -> String {
+    let pad = " ".repeat(prefix.len());
+    let mut lines = body.split('\n');
+
+    let mut out = format!("{}{}", prefix, lines.next().unwrap_or(""));
+    for line in lines {
+        out.push('\n');
+        out.push_str(&pad);
+        out.push_str(line);
+    }
+
+    out
+}
+
+/// Quote `data`, escaping quotes, backslashes and non printable bytes.
+fn quote(data: &[u8]) -> String {
+    let mut out = String::from("\"");
+
+    for &c in data {
+        match c {
+            b'\\' => out.push_str("\\\\"),
+            b'"' => out.push_str("\\\""),
+            b'\n' => out.push_str("\\n"),
+            b'\r' => out.push_str("\\r"),
+            b'\t' => out.push_str("\\t"),
+            7 => out.push_str("\\a"),
+            8 => out.push_str("\\b"),
+            c if c.is_ascii_graphic() || c == b' ' => out.push(c as char),
+            c => out.push_str(&format!("\\x{:02x}", c)),
+        }
+    }
+
+    out.push('"');
+    out
+}
+
+impl History {
+    fn load() -> History {
+        let path =
+            env::var_os("HOME").map(|home| PathBuf::from(home).join(".my-redis-cli_history"));
+        let lines = path
+            .as_ref()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .map(|data| data.lines().map(String::from).collect())
+            .unwrap_or_default();
+
+        History { path, lines }
+    }
+
+    fn add(&mut self, line: &str) {
+        // Repeating a command is recorded once.
+        if self.lines.last().map(String::as_str) != Some(line) {
+            self.lines.push(line.to_string());
+        }
+    }
+
+    fn print(&self) {
+        for (i, line) in self.lines.iter().enumerate() {
+            println!("{:>5}  {}", i + 1, line);
+        }
+    }
+
+    fn save(&self) {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return,
+        };
+
+        let start = self.lines.len().saturating_sub(HISTORY_LEN);
+        let mut data = self.lines[start..].join("\n");
+        data.push('\n');
+
+        // Readable by its owner only, as commands may hold secrets anyway.
+        let written = fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(path)
+            .and_then(|mut file| {
+                file.set_permissions(fs::Permissions::from_mode(0o600))?;
+                file.write_all(data.as_bytes())
+            });
+
+        if let Err(err) = written {
+            eprintln!("failed to save the history to {}: {}", path.display(), err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+
+    fn args(line: &str) -> Option<Vec<String>> {
+        let args = split_args(line)?;
+        Some(
+            args.into_iter()
+                .map(|arg| String::from_utf8(arg).unwrap())
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn arguments_are_split_and_unquoted() {
+        assert_eq!(
+            args("  set  a 1 "),
+            Some(vec!["set".into(), "a".into(), "1".into()])
+        );
+        assert_eq!(
+            args(r#"SET "a b" "x\ty\x41\"""#),
+            Some(vec!["SET".into(), "a b".into(), "x\tyA\"".into()])
+        );
+        assert_eq!(
+            args(r"ECHO 'it\'s \n'"),
+            Some(vec!["ECHO".into(), r"it's \n".into()])
+        );
+        assert_eq!(args(r#"GET """#), Some(vec!["GET".into(), "".into()]));
+        assert_eq!(args(""), Some(vec![]));
+
+        assert_eq!(args(r#"GET "a"#), None);
+        assert_eq!(args(r#"GET "a"b"#), None);
+        assert_eq!(args(r#"GET "\x4""#), None);
+    }
+
+    #[test]
+    fn passwords_stay_out_of_the_history() {
+        for line in [
+            "AUTH secret",
+            "auth alice secret",
+            "HELLO 3 AUTH alice secret",
+            "ACL SETUSER alice on >secret",
+            "CONFIG SET maxclients 10 masterauth secret",
+        ] {
+            assert!(sensitive(&split_args(line).unwrap()), "{}", line);
+        }
+
+        for line in [
+            "HELLO 3",
+            "ACL LIST",
+            "CONFIG SET maxclients 10",
+            "GET auth",
+        ] {
+            assert!(!sensitive(&split_args(line).unwrap()), "{}", line);
+        }
+    }
+
+    #[test]
+    fn replies_are_annotated_and_nested() {
+        assert_eq!(format_reply(&Frame::Integer(3)), "(integer) 3");
+        assert_eq!(
+            format_reply(&Frame::Bulk(Bytes::from("a\"\n\x01"))),
+            r#""a\"\n\x01""#
+        );
+        assert_eq!(format_reply(&Frame::Error("ERR x".into())), "(error) ERR x");
+        assert_eq!(format_reply(&Frame::Array(vec![])), "(empty array)");
+
+        let bulk = |s: &'static str| Frame::Bulk(Bytes::from(s));
+        let nested = Frame::Array(vec![
+            bulk("a"),
+            Frame::Array(vec![bulk("b"), Frame::Null]),
+            Frame::Map(vec![(bulk("k"), Frame::Set(vec![Frame::Double(1.5)]))]),
+        ]);
+        assert_eq!(
+            format_reply(&nested),
+            [
+                r#"1) "a""#,
+                r#"2) 1) "b""#,
+                r#"   2) (nil)"#,
+                r#"3) 1# "k" => 1~ (double) 1.5"#,
+            ]
+            .join("\n")
+        );
+
+        let long = Frame::Array((0..10).map(Frame::Integer).collect());
+        assert!(format_reply(&long).starts_with(" 1) (integer) 0\n"));
+    }
+}
@@ -1,64 +1,92 @@
-use bytes::Bytes;
-use mini_redis::{Connection, Frame};
-use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
-use tokio::net::{TcpListener, TcpStream};
-
-type Db = Arc<Mutex<HashMap<String, Bytes>>>;
-type SharedDb = Arc<Vec<Mutex<HashMap<String, Vec<u8>>>>>;
+use my_redis_2::config::{self, Config};
+use my_redis_2::server::{self, Listeners};
+use my_redis_2::Db;
+use std::fs::{self, Permissions};
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::process;
+use tokio::net::{TcpListener, UnixListener};
+use tokio::signal::{self, unix::SignalKind};
 
 #[tokio::main]
 async fn main() {
-    // Bind the listener to the address.
-    let listener = TcpListener::bind("127.0.0.1:6379").await.unwrap();
+    // Settings come from the configuration file given as first argument, if
+    // any, then from `--name value` flags, e.g. `--port 7000`. See
+    // `my_redis_2::config` for the settings.
+    let config = Config::from_args(std::env::args().skip(1)).unwrap_or_else(|err| {
+        eprintln!("invalid configuration: {}", err);
+        process::exit(1);
+    });
+
+    let listeners = bind(&config).await.unwrap_or_else(|err| {
+        eprintln!("failed to listen: {}", err);
+        process::exit(1);
+    });
     println!("Listenting");
 
-    let db = Arc::new(Mutex::new(HashMap::new()));
+    // The keyspace is split into `shards` independently locked shards. Keys
+    // with a time to live are purged by a background task owned by `db`.
+    let db = Db::with_shards(config.shards);
+
+    // Connections beyond `maxclients` wait to be accepted. On Ctrl-C or
+    // SIGTERM, connections get `shutdown-timeout` seconds to finish their
+    // command before the server flushes persistence and exits.
+    let server_config = server::Config {
+        max_connections: config.maxclients,
+        shutdown_timeout: config.shutdown_timeout,
+    };
+
+    // The keyspace is restored from the append-only file or the snapshot,
+    // then the memory limit, users, slow log, keyspace events and leader
+    // are set up.
+    config::init(&db, config).unwrap_or_else(|err| {
+        eprintln!("failed to start: {}", err);
+        process::exit(1);
+    });
 
-    loop {
-        // The second item contains the IP and port of the new connection.
-        let (socket, _) = listener.accept().await.unwrap();
-        let db = db.clone();
+    server::run(listeners, db, server_config, shutdown_signal()).await;
+}
+
+/// Listen on `port` of every `bind` address, unless the port is 0, and on
+/// the `unixsocket` if set.
+async fn bind(config: &Config) -> my_redis_2::Result<Listeners> {
+    let mut listeners = Listeners::default();
 
-        // A new task is spawned for each inbound socket. The socket is
-        // moved to the new task and processed there.
-        println!("Accepted");
-        tokio::spawn(async move {
-            process(socket, db).await;
-        });
+    if config.port != 0 {
+        for addr in &config.bind {
+            listeners
+                .tcp
+                .push(TcpListener::bind((addr.as_str(), config.port)).await?);
+        }
     }
+
+    if let Some(path) = &config.unixsocket {
+        // A socket file left behind by a server which did not shut down
+        // cleanly would fail the bind. Anything else at the path is kept.
+        let metadata = fs::symlink_metadata(path);
+        if metadata.is_ok_and(|metadata| metadata.file_type().is_socket()) {
+            fs::remove_file(path)?;
+        }
+        listeners.unix = Some(UnixListener::bind(path)?);
+
+        if let Some(mode) = config.unixsocketperm {
+            fs::set_permissions(path, Permissions::from_mode(mode))?;
+        }
+    }
+
+    if listeners.tcp.is_empty() && listeners.unix.is_none() {
+        return Err("nothing to listen on, set a port or a unixsocket".into());
+    }
+
+    Ok(listeners)
 }
 
-async fn process(socket: TcpStream, db: Db) {
-    use mini_redis::Command::{self, Get, Set};
-
-    // The `Connection` lets us read/write redis **frames** instead of
-    // byte streams. The `Connection` type is defined by mini-redis.
-    // Handled parsing frames from the socket.
-    let mut conn = Connection::new(socket);
-
-    // Use `read_frame` to receive a command from the connection.
-    while let Some(frame) = conn.read_frame().await.unwrap() {
-        let resp = match Command::from_frame(frame).unwrap() {
-            Set(cmd) => {
-                let mut db = db.lock().unwrap();
-                // let shard = db[hash(key)]
-                db.insert(cmd.key().to_string(), cmd.value().clone());
-                Frame::Simple("OK".to_string())
-            }
-            Get(cmd) => {
-                let db = db.lock().unwrap();
-                if let Some(value) = db.get(cmd.key()) {
-                    Frame::Bulk(value.clone().into())
-                } else {
-                    Frame::Null
-                }
-            }
-            cmd => panic!("unimplemented {:?}", cmd),
-        };
-
-        // Write the response to the client
-        conn.write_frame(&resp).await.unwrap();
+/// Completes on Ctrl-C or SIGTERM.
+async fn shutdown_signal() {
+    let mut terminate = signal::unix::signal(SignalKind::terminate()).unwrap();
+
+    tokio::select! {
+        _ = signal::ctrl_c() => {}
+        _ = terminate.recv() => {}
     }
 }
 
@@ -0,0 +1,342 @@
+//! A pool of client connections to a server.
+//!
+//! Each connection is owned by a task, which commands are sent to over a
+//! channel along with a oneshot responder. The task writes commands as they
+//! come, without waiting for the replies of the previous ones, up to
+//! `Config::pipeline_depth` commands in flight. Replies come back in the order
+//! the commands were written, so each one goes to the oldest responder.
+//!
+//! When a connection fails, the commands in flight fail with it, as there is
+//! no telling whether the server ran them. The task then reconnects. Commands
+//! sent while the server cannot be reached fail as well.
+
+use crate::{Connection, Frame};
+
+use bytes::Bytes;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::sync::{mpsc, oneshot};
+use tokio::{select, time};
+
+/// Pool settings, see `Pool::new`.
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Number of connections.
+    pub size: usize,
+
+    /// Most commands written to a connection before their replies are read.
+    pub pipeline_depth: usize,
+
+    /// Time to wait before connecting again after a failure.
+    pub reconnect_delay: Duration,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            size: 4,
+            pipeline_depth: 16,
+            reconnect_delay: Duration::from_millis(100),
+        }
+    }
+}
+
+/// Handle to a pool of connections. Cloning it is cheap, clones share the
+/// connections.
+///
+/// The connections close once every handle is dropped.
+#[derive(Debug, Clone)]
+pub struct Pool {
+    shared: Arc<Shared>,
+}
+
+#[derive(Debug)]
+struct Shared {
+    /// Send commands to the task of each connection.
+    connections: Vec<mpsc::Sender<Request>>,
+
+    /// Commands go to each connection in turn.
+    next: AtomicUsize,
+}
+
+/// A command sent to a connection task.
+#[derive(Debug)]
+struct Request {
+    command: Frame,
+
+    /// Provided by the requester and used by the connection task to send the
+    /// reply back.
+    resp: Responder,
+}
+
+type Responder = oneshot::Sender<crate::Result<Frame>>;
+
+impl Pool {
+    /// Create a pool of connections to the server at `addr`, e.g.
+    /// `127.0.0.1:6379`.
+    ///
+    /// Connections are established in the background, failing to connect
+    /// fails the commands sent in the meantime.
+    pub fn new(addr: impl Into<String>, config: Config) -> Pool {
+        let addr = addr.into();
+
+        let connections = (0..config.size.max(1))
+            .map(|_| {
+                let (tx, rx) = mpsc::channel(config.pipeline_depth.max(1));
+                tokio::spawn(run(addr.clone(), rx, config.clone()));
+                tx
+            })
+            .collect();
+
+        Pool {
+            shared: Arc::new(Shared {
+                connections,
+                next: AtomicUsize::new(0),
+            }),
+        }
+    }
+
+    /// Send `command` and wait for its reply.
+    ///
+    /// Error replies are returned as `Frame::Error`, the error is for failing
+    /// to get a reply.
+    pub async fn call(&self, command: Frame) -> crate::Result<Frame> {
+        let shared = &self.shared;
+        let index = shared.next.fetch_add(1, Ordering::Relaxed) % shared.connections.len();
+
+        let (resp, reply) = oneshot::channel();
+        shared.connections[index]
+            .send(Request { command, resp })
+            .await
+            .map_err(|_| "connection task stopped")?;
+
+        reply.await.map_err(|_| "connection task stopped")?
+    }
+
+    /// `GET key`
+    pub async fn get(&self, key: &str) -> crate::Result<Option<Bytes>> {
+        match self.call(command(&["GET", key])).await? {
+            Frame::Bulk(value) => Ok(Some(value)),
+            Frame::Null => Ok(None),
+            frame => Err(unexpected(frame)),
+        }
+    }
+
+    /// `SET key value`
+    pub async fn set(&self, key: &str, value: Bytes) -> crate::Result<()> {
+        let command = Frame::Array(vec![
+            Frame::Bulk(Bytes::from_static(b"SET")),
+            Frame::Bulk(Bytes::copy_from_slice(key.as_bytes())),
+            Frame::Bulk(value),
+        ]);
+
+        match self.call(command).await? {
+            Frame::Simple(ok) if ok == "OK" => Ok(()),
+            frame => Err(unexpected(frame)),
+        }
+    }
+}
+
+/// A command frame, an array of bulk strings.
+pub fn command<T: AsRef<[u8]>>(args: &[T]) -> Frame {
+    Frame::Array(
+        args.iter()
+            .map(|arg| Frame::Bulk(Bytes::copy_from_slice(arg.as_ref())))
+            .collect(),
+    )
+}
+
+fn unexpected(frame: Frame) -> crate::Error {
+    match frame {
+        Frame::Error(err) => err.into(),
+        frame => format!("unexpected reply {}", frame).into(),
+    }
+}
+
+/// Routine of the task owning a connection. Connects, serves requests until
+/// the connection fails, then starts over.
+///
+/// Returns once every `Pool` handle was dropped.
+async fn run(addr: String, mut requests: mpsc::Receiver<Request>, config: Config) {
+    loop {
+        let err = match TcpStream::connect(&addr).await {
+            Ok(socket) => {
+                // Commands are flushed one by one, don't hold them back.
+                let _ = socket.set_nodelay(true);
+
+                match serve(Connection::new(socket), &mut requests, &config).await {
+                    Ok(()) => return,
+                    Err(err) => err,
+                }
+            }
+            Err(err) => {
+                // Nothing can be sent until the server is back.
+                fail_waiting(&mut requests, &format!("failed to connect: {}", err));
+                err.into()
+            }
+        };
+
+        eprintln!("connection to {} failed: {}", addr, err);
+        time::sleep(config.reconnect_delay).await;
+    }
+}
+
+/// Write `requests` to `connection` and hand out the replies, until the
+/// connection fails, or until the requests end and every reply was read.
+async fn serve(
+    mut connection: Connection,
+    requests: &mut mpsc::Receiver<Request>,
+    config: &Config,
+) -> crate::Result<()> {
+    // Responders of the commands written, oldest first.
+    let mut in_flight: VecDeque<Responder> = VecDeque::new();
+    let mut closed = false;
+
+    let result = loop {
+        if closed && in_flight.is_empty() {
+            break Ok(());
+        }
+
+        select! {
+            request = requests.recv(), if !closed && in_flight.len() < config.pipeline_depth.max(1) => {
+                let request = match request {
+                    Some(request) => request,
+                    None => {
+                        closed = true;
+                        continue;
+                    }
+                };
+
+                if let Err(err) = connection.write_frame(&request.command).await {
+                    let _ = request.resp.send(Err(err.to_string().into()));
+                    break Err(err.into());
+                }
+                in_flight.push_back(request.resp);
+            }
+            // Reading is cancel safe, a partly read frame stays buffered. This
+            // also notices an idle connection closing, to reconnect before
+            // the next command.
+            frame = connection.read_frame() => {
+                let frame = match frame {
+                    Ok(Some(frame)) => frame,
+                    Ok(None) => break Err("connection closed by the server".into()),
+                    Err(err) => break Err(err),
+                };
+
+                match in_flight.pop_front() {
+                    // The requester may have given up waiting.
+                    Some(resp) => {
+                        let _ = resp.send(Ok(frame));
+                    }
+                    None => break Err(format!("unexpected reply {}", frame).into()),
+                }
+            }
+        }
+    };
+
+    if let Err(err) = &result {
+        for resp in in_flight {
+            let _ = resp.send(Err(err.to_string().into()));
+        }
+    }
+
+    result
+}
+
+/// Fail the requests which are already waiting.
+fn fail_waiting(requests: &mut mpsc::Receiver<Request>, err: &str) {
+    while let Ok(request) = requests.try_recv() {
+        let _ = request.resp.send(Err(err.into()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::{self, Config as ServerConfig};
+    use crate::Db;
+    use futures::future;
+    use tokio::net::TcpListener;
+    use tokio::sync::oneshot;
+
+    async fn start(listener: TcpListener) -> oneshot::Sender<()> {
+        let (tx, rx) = oneshot::channel();
+        tokio::spawn(server::run(
+            listener,
+            Db::new(),
+            ServerConfig::default(),
+            rx,
+        ));
+        tx
+    }
+
+    #[tokio::test]
+    async fn replies_match_pipelined_commands() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let _shutdown = start(listener).await;
+
+        let config = Config {
+            size: 2,
+            pipeline_depth: 4,
+            ..Config::default()
+        };
+        let pool = Pool::new(addr.to_string(), config);
+
+        let replies = future::join_all((0..100).map(|i| {
+            let pool = pool.clone();
+            async move {
+                let key = format!("key{}", i);
+                pool.set(&key, Bytes::from(i.to_string())).await.unwrap();
+                pool.call(command(&["INCR", &key])).await.unwrap()
+            }
+        }))
+        .await;
+
+        for (i, reply) in replies.into_iter().enumerate() {
+            assert_eq!(reply, Frame::Integer(i as i64 + 1));
+        }
+        assert_eq!(pool.get("key7").await.unwrap(), Some(Bytes::from("8")));
+        assert_eq!(pool.get("missing").await.unwrap(), None);
+
+        let reply = pool.call(command(&["LPUSH", "key1", "x"])).await.unwrap();
+        assert!(matches!(reply, Frame::Error(_)));
+    }
+
+    #[tokio::test]
+    async fn reconnects_after_the_server_restarts() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let shutdown = start(listener).await;
+
+        let config = Config {
+            size: 1,
+            reconnect_delay: Duration::from_millis(10),
+            ..Config::default()
+        };
+        let pool = Pool::new(addr.to_string(), config);
+        pool.set("a", Bytes::from("1")).await.unwrap();
+
+        shutdown.send(()).unwrap();
+        // The command fails with the connection, or while reconnecting.
+        while pool.get("a").await.is_ok() {
+            time::sleep(Duration::from_millis(10)).await;
+        }
+
+        let _shutdown = start(TcpListener::bind(addr).await.unwrap()).await;
+        let mut result = pool.get("a").await;
+        for _ in 0..100 {
+            if result.is_ok() {
+                break;
+            }
+            time::sleep(Duration::from_millis(10)).await;
+            result = pool.get("a").await;
+        }
+
+        // The new server starts out empty.
+        assert_eq!(result.unwrap(), None);
+    }
+}
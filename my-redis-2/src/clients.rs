@@ -0,0 +1,400 @@
+//! The connected clients, as listed by `CLIENT LIST`, and the clients
+//! watching every command with `MONITOR`.
+//!
+//! A monitor which falls `MONITOR_BUFFER` commands behind is disconnected.
+
+use crate::Frame;
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::{mpsc, Notify};
+use tokio::time::Instant;
+
+/// Commands buffered for a monitor before it is disconnected as too slow.
+const MONITOR_BUFFER: usize = 64 * 1024;
+
+/// The registry of connected clients, see `Db::clients`.
+#[derive(Debug, Default)]
+pub struct Clients {
+    /// Connected clients by ID, in the order they connected.
+    clients: Mutex<BTreeMap<u64, Arc<Client>>>,
+
+    /// Last ID given to a client.
+    last_id: AtomicU64,
+
+    /// Number of connections accepted since startup.
+    connections_received: AtomicU64,
+
+    /// Number of commands received since startup.
+    commands_processed: AtomicU64,
+
+    /// Every command received is sent to each monitor, formatted as a line.
+    monitors: Mutex<Vec<mpsc::Sender<String>>>,
+
+    /// Number of monitors, checked before formatting a command for them.
+    monitor_count: AtomicUsize,
+}
+
+/// A connected client.
+#[derive(Debug)]
+pub struct Client {
+    id: u64,
+
+    /// Address of the peer.
+    addr: String,
+
+    connected_at: Instant,
+
+    /// Notified by `CLIENT KILL`.
+    kill: Notify,
+
+    state: Mutex<State>,
+}
+
+/// What a client is up to, updated by its connection as it runs commands.
+#[derive(Debug)]
+struct State {
+    /// Set by `CLIENT SETNAME`.
+    name: String,
+
+    /// Index of the database selected by `SELECT`.
+    db: usize,
+
+    /// Name of the last command received.
+    last_command: String,
+
+    last_active: Instant,
+
+    /// Letters of `CLIENT LIST`, e.g. `N` for a client running regular
+    /// commands.
+    flags: &'static str,
+
+    /// Bytes received but not parsed yet, and capacity of the input buffer.
+    input_buffer: (usize, usize),
+
+    /// Bytes written but not flushed yet.
+    output_buffer: usize,
+}
+
+impl Clients {
+    /// Register a client connecting from `addr`.
+    pub(crate) fn connect(&self, addr: String) -> Arc<Client> {
+        let id = self.last_id.fetch_add(1, Ordering::Relaxed) + 1;
+        self.connections_received.fetch_add(1, Ordering::Relaxed);
+
+        let now = Instant::now();
+        let client = Arc::new(Client {
+            id,
+            addr,
+            connected_at: now,
+            kill: Notify::new(),
+            state: Mutex::new(State {
+                name: String::new(),
+                db: 0,
+                last_command: "NULL".to_string(),
+                last_active: now,
+                flags: "N",
+                input_buffer: (0, 0),
+                output_buffer: 0,
+            }),
+        });
+
+        self.clients.lock().unwrap().insert(id, client.clone());
+        client
+    }
+
+    /// Remove a client once its connection closes.
+    pub(crate) fn disconnect(&self, client: &Client) {
+        self.clients.lock().unwrap().remove(&client.id);
+    }
+
+    /// Number of clients connected.
+    pub fn len(&self) -> usize {
+        self.clients.lock().unwrap().len()
+    }
+
+    /// Returns `true` if no client is connected.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn connections_received(&self) -> u64 {
+        self.connections_received.load(Ordering::Relaxed)
+    }
+
+    pub fn commands_processed(&self) -> u64 {
+        self.commands_processed.load(Ordering::Relaxed)
+    }
+
+    /// Count `command`, received from `client`, and show it to the monitors.
+    pub(crate) fn received(&self, client: Option<&Client>, command: &Frame) {
+        self.commands_processed.fetch_add(1, Ordering::Relaxed);
+
+        if self.monitor_count.load(Ordering::Relaxed) == 0 {
+            return;
+        }
+
+        let addr = client.map_or("", |client| &client.addr);
+        let db = client.map_or(0, |client| client.state.lock().unwrap().db);
+        let line = monitor_line(db, addr, command);
+
+        // Monitors which went away dropped their receiver. Those too far
+        // behind are dropped, which closes their connection.
+        let mut monitors = self.monitors.lock().unwrap();
+        monitors.retain(|monitor| monitor.try_send(line.clone()).is_ok());
+        self.monitor_count.store(monitors.len(), Ordering::Relaxed);
+    }
+
+    /// Receive every command from now on, see `received`.
+    pub(crate) fn monitor(&self) -> mpsc::Receiver<String> {
+        let (tx, rx) = mpsc::channel(MONITOR_BUFFER);
+
+        let mut monitors = self.monitors.lock().unwrap();
+        monitors.push(tx);
+        self.monitor_count.store(monitors.len(), Ordering::Relaxed);
+
+        rx
+    }
+
+    /// One line per client, as replied to `CLIENT LIST`.
+    pub(crate) fn list(&self) -> String {
+        let now = Instant::now();
+        let clients = self.clients.lock().unwrap();
+
+        let mut list = String::new();
+        for client in clients.values() {
+            let state = client.state.lock().unwrap();
+            let (qbuf, capacity) = state.input_buffer;
+
+            let _ = writeln!(
+                list,
+                "id={} addr={} name={} age={} idle={} flags={} db={} qbuf={} qbuf-free={} obl={} cmd={}",
+                client.id,
+                client.addr,
+                state.name,
+                (now - client.connected_at).as_secs(),
+                (now - state.last_active).as_secs(),
+                state.flags,
+                state.db,
+                qbuf,
+                capacity.saturating_sub(qbuf),
+                state.output_buffer,
+                state.last_command,
+            );
+        }
+
+        list
+    }
+
+    /// Close the connections of the clients matching `filter`, returning how
+    /// many there were.
+    pub(crate) fn kill(&self, filter: impl Fn(&Client) -> bool) -> usize {
+        let clients = self.clients.lock().unwrap();
+
+        let killed: Vec<_> = clients.values().filter(|client| filter(client)).collect();
+        for client in &killed {
+            client.kill.notify_one();
+        }
+
+        killed.len()
+    }
+}
+
+impl Client {
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn addr(&self) -> &str {
+        &self.addr
+    }
+
+    pub fn name(&self) -> String {
+        self.state.lock().unwrap().name.clone()
+    }
+
+    pub(crate) fn set_name(&self, name: String) {
+        self.state.lock().unwrap().name = name;
+    }
+
+    /// Record that the client selected the database `db`.
+    pub(crate) fn select(&self, db: usize) {
+        self.state.lock().unwrap().db = db;
+    }
+
+    /// Record that the client started running `command`, with its connection
+    /// buffers as given.
+    pub(crate) fn started(
+        &self,
+        command: &str,
+        flags: &'static str,
+        input_buffer: (usize, usize),
+        output_buffer: usize,
+    ) {
+        let mut state = self.state.lock().unwrap();
+        state.last_command = command.to_string();
+        state.last_active = Instant::now();
+        state.flags = flags;
+        state.input_buffer = input_buffer;
+        state.output_buffer = output_buffer;
+    }
+
+    /// Completes once `CLIENT KILL` closed the connection of this client.
+    pub(crate) async fn killed(&self) {
+        self.kill.notified().await
+    }
+}
+
+/// A command run on the database `db`, as shown to monitors, e.g.
+/// `1339518083.107412 [0 127.0.0.1:60866] "keys" "*"`.
+fn monitor_line(db: usize, addr: &str, command: &Frame) -> String {
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let mut line = format!(
+        "{}.{:06} [{} {}]",
+        time.as_secs(),
+        time.subsec_micros(),
+        db,
+        addr
+    );
+
+    if let Frame::Array(args) = command {
+        for arg in args {
+            let arg = match arg {
+                Frame::Bulk(data) => String::from_utf8_lossy(data).into_owned(),
+                frame => frame.to_string(),
+            };
+            let _ = write!(line, " {:?}", arg);
+        }
+    }
+
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::command;
+    use crate::server;
+    use crate::{Connection, Db};
+    use tokio::net::TcpStream;
+
+    async fn call(connection: &mut Connection, args: &[&str]) -> Frame {
+        connection.write_frame(&command(args)).await.unwrap();
+        connection.read_frame().await.unwrap().unwrap()
+    }
+
+    fn text(frame: Frame) -> String {
+        match frame {
+            Frame::Bulk(data) => String::from_utf8(data.to_vec()).unwrap(),
+            frame => panic!("unexpected reply {:?}", frame),
+        }
+    }
+
+    #[tokio::test]
+    async fn clients_are_listed_named_and_killed() {
+        let db = Db::new();
+        let addr = server::spawn(&db).await;
+
+        let mut first = Connection::new(TcpStream::connect(addr).await.unwrap());
+        let mut second = Connection::new(TcpStream::connect(addr).await.unwrap());
+
+        assert_eq!(
+            call(&mut first, &["CLIENT", "SETNAME", "first"]).await,
+            Frame::Simple("OK".into())
+        );
+        call(&mut second, &["PING"]).await;
+        assert_eq!(db.clients().len(), 2);
+
+        let list = text(call(&mut second, &["CLIENT", "LIST"]).await);
+        let lines: Vec<_> = list.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("id=1 addr=127.0.0.1:"), "{}", list);
+        assert!(lines[0].contains(" name=first "), "{}", list);
+        assert!(lines[0].ends_with(" cmd=client"), "{}", list);
+        assert!(lines[1].contains(" name= "), "{}", list);
+
+        assert_eq!(
+            call(&mut second, &["CLIENT", "KILL", "ID", "1"]).await,
+            Frame::Integer(1)
+        );
+        assert_eq!(first.read_frame().await.unwrap(), None);
+        assert_eq!(
+            call(&mut second, &["CLIENT", "KILL", "ID", "1"]).await,
+            Frame::Integer(0)
+        );
+        assert!(matches!(
+            call(&mut second, &["CLIENT", "KILL", "127.0.0.1:1"]).await,
+            Frame::Error(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn monitors_see_every_command() {
+        let db = Db::new();
+        let addr = server::spawn(&db).await;
+
+        let mut monitor = Connection::new(TcpStream::connect(addr).await.unwrap());
+        assert_eq!(
+            call(&mut monitor, &["MONITOR"]).await,
+            Frame::Simple("OK".into())
+        );
+
+        let mut client = Connection::new(TcpStream::connect(addr).await.unwrap());
+        call(&mut client, &["SET", "a", "hello world"]).await;
+
+        let line = match monitor.read_frame().await.unwrap().unwrap() {
+            Frame::Simple(line) => line,
+            frame => panic!("unexpected frame {:?}", frame),
+        };
+        assert!(line.ends_with(r#"] "SET" "a" "hello world""#), "{}", line);
+    }
+
+    #[test]
+    fn monitors_too_far_behind_are_dropped() {
+        let clients = Clients::default();
+        let mut monitor = clients.monitor();
+
+        for _ in 0..=MONITOR_BUFFER {
+            clients.received(None, &command(&["GET", "a"]));
+        }
+        assert_eq!(clients.monitor_count.load(Ordering::Relaxed), 0);
+
+        // The commands buffered are still delivered, then the channel closes.
+        for _ in 0..MONITOR_BUFFER {
+            assert!(monitor.try_recv().is_ok());
+        }
+        assert!(monitor.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn info_reports_counters() {
+        let db = Db::new();
+        let addr = server::spawn(&db).await;
+        let mut client = Connection::new(TcpStream::connect(addr).await.unwrap());
+
+        call(&mut client, &["SET", "a", "1"]).await;
+        call(&mut client, &["GET", "a"]).await;
+        call(&mut client, &["GET", "b"]).await;
+
+        let info = text(call(&mut client, &["INFO"]).await);
+        for line in [
+            "# Server",
+            "connected_clients:1",
+            "total_commands_processed:4",
+            "keyspace_hits:1",
+            "keyspace_misses:1",
+            "role:master",
+            "db0:keys=1,expires=0",
+        ] {
+            assert!(info.lines().any(|l| l == line), "{} not in {}", line, info);
+        }
+
+        let info = text(call(&mut client, &["INFO", "keyspace"]).await);
+        assert_eq!(info, "# Keyspace\r\ndb0:keys=1,expires=0\r\n");
+    }
+}
@@ -0,0 +1,820 @@
+//! Command dispatch for the server.
+//!
+//! Every command is an array frame whose first entry is the command name. The
+//! name selects a handler, which reads its arguments through a `Parse` and
+//! returns the reply frame.
+
+mod connection;
+mod hash;
+mod keys;
+mod list;
+mod pubsub;
+mod server;
+mod set;
+mod stream;
+mod string;
+mod transaction;
+mod zset;
+
+use crate::aof::{self, Aof};
+use crate::clients::Client;
+use crate::{db, Connection, Db, Frame, Parse, ParseError, Socket};
+
+use bytes::Bytes;
+use std::io;
+use std::sync::{Arc, MutexGuard, RwLockReadGuard};
+use std::time::{Duration, Instant};
+use transaction::Transaction;
+
+/// A command handler. Reads its arguments from `parse` and returns the reply.
+type Handler = fn(&Db, &mut Parse) -> Result<Frame, ParseError>;
+
+/// State of a client connection, carried across its commands.
+#[derive(Debug, Default)]
+pub struct Session {
+    /// The commands queued since `MULTI`, if any.
+    transaction: Option<Transaction>,
+
+    /// Index of the database selected by `SELECT`, 0 until then.
+    db: usize,
+
+    /// The keys passed to `WATCH`, along with the index of their database and
+    /// their version at the time.
+    watched: Vec<(usize, String, Option<u64>)>,
+
+    /// The client running the session, unset if it does not come from a
+    /// connection.
+    client: Option<Arc<Client>>,
+
+    /// The user the session authenticated as, unset until it does, see
+    /// `acl`.
+    user: Option<String>,
+}
+
+impl Session {
+    /// A session for the commands of `client`.
+    pub fn new(client: Arc<Client>) -> Session {
+        Session {
+            client: Some(client),
+            ..Session::default()
+        }
+    }
+
+    /// The `CLIENT LIST` flags of a client about to run `command`.
+    pub(crate) fn flags(&self, command: &[u8]) -> &'static str {
+        match command {
+            _ if self.transaction.is_some() => "x",
+            b"subscribe" | b"psubscribe" => "P",
+            b"monitor" => "O",
+            b"psync" => "S",
+            _ => "N",
+        }
+    }
+}
+
+/// Reply to write commands sent to a follower.
+pub(crate) const READ_ONLY: &str = "READONLY You can't write against a read only replica.";
+
+/// Run the command carried by `frame` and write the reply to `dst`.
+///
+/// Commands acting on the connection itself are handled here rather than by
+/// `apply`. The pub/sub subscription commands switch the connection into
+/// subscriber mode; this only returns once the connection leaves it.
+pub async fn run(
+    frame: Frame,
+    db: &Db,
+    dst: &mut Connection<impl Socket>,
+    session: &mut Session,
+) -> crate::Result<()> {
+    let db = &db.select(session.db).expect("selected database exists");
+    let name = name(&frame);
+
+    let command = match admit(db, &frame, session) {
+        Ok(command) => command,
+        Err(err) => {
+            dst.write_frame(&err).await?;
+            return Ok(());
+        }
+    };
+
+    let start = Instant::now();
+
+    // Commands which may wait write their own replies, setting `written`
+    // instead of returning a response.
+    let mut written = Ok(());
+    let response = match &name[..] {
+        b"multi" | b"exec" | b"discard" | b"watch" | b"unwatch" => {
+            Some(local(frame, |parse| match &name[..] {
+                b"multi" => transaction::multi(parse, session),
+                b"exec" => transaction::exec(db, parse, session),
+                b"discard" => transaction::discard(parse, session),
+                b"watch" => transaction::watch(db, parse, session),
+                _ => transaction::unwatch(parse, session),
+            }))
+        }
+        // Within `MULTI`, other commands are queued until `EXEC`
+        _ if session.transaction.is_some() => Some(transaction::queue(db, frame, session)),
+        _ if is_write(&name) && db.replication().is_follower() => {
+            Some(Frame::Error(READ_ONLY.to_string()))
+        }
+        b"subscribe" | b"psubscribe" | b"unsubscribe" | b"punsubscribe" => {
+            written = pubsub::subscribe(db, frame, dst, session).await;
+            None
+        }
+        b"blpop" | b"brpop" | b"blmove" => {
+            written = list::block(db, frame, dst).await;
+            None
+        }
+        b"xread" | b"xreadgroup" => {
+            written = stream::read(db, frame, dst).await;
+            None
+        }
+        b"hello" => Some(local(frame, |parse| {
+            connection::hello(db, parse, dst, session)
+        })),
+        b"auth" => Some(local(frame, |parse| connection::auth(db, parse, session))),
+        b"select" => Some(local(frame, |parse| connection::select(db, parse, session))),
+        b"acl" => Some(local(frame, |parse| server::acl(db, parse, session))),
+        b"psync" => {
+            written = server::psync(db, frame, dst).await;
+            None
+        }
+        b"client" => Some(local(frame, |parse| connection::client(db, parse, session))),
+        b"monitor" => {
+            written = connection::monitor(db, frame, dst).await;
+            None
+        }
+        _ => Some(apply(frame, db)),
+    };
+
+    let duration = response.is_some().then(|| start.elapsed());
+    record(db, &name, duration, command, session);
+
+    match response {
+        Some(response) => dst.write_frame(&response).await?,
+        None => written?,
+    }
+
+    Ok(())
+}
+
+/// Count `frame`, show it to the monitors and check it against the ACL of the
+/// session user, before it runs.
+///
+/// Returns the command to record once it ran, see `record`, or the error to
+/// reply with.
+fn admit(db: &Db, frame: &Frame, session: &Session) -> Result<Frame, Frame> {
+    let name = name(frame);
+
+    // Kept for the slow log, the handlers take the frame. Passwords stay out
+    // of it and of `MONITOR`.
+    let command = redacted(frame);
+    db.clients().received(session.client.as_deref(), &command);
+
+    let user = session.user.as_deref();
+    db.acl()
+        .check(user, &String::from_utf8_lossy(&name), frame)
+        .map_err(Frame::Error)?;
+
+    Ok(command)
+}
+
+/// Count a call to `command`, named `name`, in the command statistics, and
+/// log it if it was slow. `duration` is unset if the command waited.
+fn record(db: &Db, name: &[u8], duration: Option<Duration>, command: Frame, session: &Session) {
+    // Unknown commands are not counted, so that clients can't grow the
+    // statistics without bounds.
+    if let Some(name) = known(name) {
+        let client = session.client.as_deref();
+        db.slowlog().record(name, duration, command, client);
+    }
+}
+
+/// Apply the command carried by `frame` to `db` and return the reply frame.
+///
+/// Malformed commands are reported to the client as error frames. Successful
+/// write commands are logged, see `log`.
+pub fn apply(frame: Frame, db: &Db) -> Frame {
+    apply_with(frame, db, true)
+}
+
+/// Apply a command streamed by the leader to `db`, see `apply`.
+///
+/// The leader made room for its writes already: followers neither evict keys
+/// nor refuse writes past the memory limit, or they would drift apart from
+/// it.
+pub(crate) fn apply_replicated(frame: Frame, db: &Db) -> Frame {
+    apply_with(frame, db, false)
+}
+
+/// Same as `apply`, evicting keys to make room for writes only if `evict` is
+/// set.
+fn apply_with(frame: Frame, db: &Db, evict: bool) -> Frame {
+    let _lock = db.lock_command();
+
+    let write = is_write(&name(&frame));
+    let response = execute(frame, db, evict);
+
+    // Values pushed by the command go to the clients blocked on them first.
+    if write {
+        list::serve_blocked(db);
+    }
+
+    response
+}
+
+/// Same as `apply_with`, without locking out transactions. Used by `EXEC`,
+/// which holds the lock for the whole transaction.
+fn execute(frame: Frame, db: &Db, evict: bool) -> Frame {
+    let write = is_write(&name(&frame));
+    let mut log_lock = write.then(|| lock_log(db));
+    let command = write.then(|| frame.clone());
+
+    let mut parse = match Parse::new(frame) {
+        Ok(parse) => parse,
+        Err(err) => return Frame::Error(format!("ERR {}", err)),
+    };
+
+    // All redis commands begin with the command name as a string. The name is
+    // read and converted to lower cases in order to do case insensitive
+    // matching.
+    let name = match parse.next_string() {
+        Ok(name) => name.to_lowercase(),
+        Err(err) => return Frame::Error(format!("ERR {}", err)),
+    };
+
+    let handler = match handler(&name) {
+        Some(handler) => handler,
+        None => return Frame::Error(format!("ERR unknown command '{}'", name)),
+    };
+
+    // Make room for the write first. Once no key can be evicted, only the
+    // commands which free memory still run.
+    if write && evict {
+        if let Err(err) = self::evict(db, log_lock.as_mut().unwrap()) {
+            if grows(name.as_bytes()) {
+                return err.into();
+            }
+        }
+    }
+
+    let result = handler(db, &mut parse).and_then(|frame| parse.finish().map(|_| frame));
+    let response = reply(&name, result);
+
+    if write && !matches!(response, Frame::Error(_)) {
+        // Writes which turned out to change nothing are neither counted nor
+        // logged.
+        let command = match command.and_then(|command| logged(&name, command, &response)) {
+            Some(command) => command,
+            None => return response,
+        };
+
+        db.record_change();
+
+        if let Some(log_lock) = log_lock.as_mut() {
+            if let Err(err) = log(db, log_lock, &command) {
+                return Frame::Error(format!(
+                    "ERR failed to log to the append only file: {}",
+                    err
+                ));
+            }
+        }
+    }
+
+    response
+}
+
+/// What to log for `command`, the `name` write command which replied
+/// `response`, none if it changed nothing.
+fn logged(name: &str, command: Frame, response: &Frame) -> Option<Frame> {
+    match name {
+        "xclaim" => stream::claimed(command, response),
+        // Nothing was delivered, as when a blocking read keeps waiting.
+        "xreadgroup" if *response == Frame::NullArray => None,
+        _ => Some(command),
+    }
+}
+
+/// Held by a write command while it runs, see `lock_log`.
+pub(crate) struct LogLock<'a> {
+    /// The append-only file slot, locked only if write commands are logged.
+    aof: Option<MutexGuard<'a, Option<Aof>>>,
+
+    /// Keeps logging from starting halfway through the command.
+    _logging: RwLockReadGuard<'a, bool>,
+}
+
+/// Lock the log for a write command about to run.
+///
+/// While write commands are logged, the append-only file stays locked until
+/// the command is logged, so commands are logged in the order they are
+/// applied. Otherwise, write commands only share a read lock and run in
+/// parallel on their shards.
+pub(crate) fn lock_log(db: &Db) -> LogLock<'_> {
+    let logging = db.logging();
+
+    LogLock {
+        aof: (*logging).then(|| db.aof()),
+        _logging: logging,
+    }
+}
+
+/// Log `command`, a write command which was just applied to `db`, to the
+/// append-only file, if enabled, and to the replication stream.
+///
+/// Callers hold `lock`, taken before applying the command. Nothing is logged
+/// if write commands were not logged then.
+pub(crate) fn log(db: &Db, lock: &mut LogLock, command: &Frame) -> io::Result<()> {
+    let aof = match &mut lock.aof {
+        Some(aof) => aof,
+        None => return Ok(()),
+    };
+    let data = aof::encoded(db, command);
+
+    db.replication().feed(db.index(), &data);
+
+    match &mut **aof {
+        Some(aof) => aof.append(db, &data),
+        None => Ok(()),
+    }
+}
+
+/// Evict keys until the keyspace is back under the memory limit, logging
+/// their removal.
+fn evict(db: &Db, lock: &mut LogLock) -> Result<(), db::Error> {
+    let mut evicted = match db.evict() {
+        Ok(evicted) if evicted.is_empty() => return Ok(()),
+        Ok(evicted) => evicted,
+        Err(err) => return Err(err),
+    };
+
+    db.record_change();
+
+    // One `DEL` per database the keys were evicted from.
+    evicted.sort();
+    for keys in evicted.chunk_by(|a, b| a.0 == b.0) {
+        let db = db
+            .select(keys[0].0)
+            .expect("evicted from an existing database");
+        let del = std::iter::once(Bytes::from_static(b"DEL"))
+            .chain(keys.iter().map(|(_, key)| Bytes::from(key.clone())))
+            .map(Frame::Bulk)
+            .collect();
+
+        if let Err(err) = log(&db, lock, &Frame::Array(del)) {
+            eprintln!("failed to log to the append only file: {}", err);
+        }
+    }
+
+    Ok(())
+}
+
+/// The handler of the `name` command, which must be lowercase.
+fn handler(name: &str) -> Option<Handler> {
+    Some(match name {
+        "get" => string::get,
+        "set" => string::set,
+        "setnx" => string::setnx,
+        "getset" => string::getset,
+        "mget" => string::mget,
+        "mset" => string::mset,
+        "incr" => string::incr,
+        "decr" => string::decr,
+        "incrby" => string::incrby,
+        "decrby" => string::decrby,
+        "append" => string::append,
+        "strlen" => string::strlen,
+        "del" => keys::del,
+        "exists" => keys::exists,
+        "keys" => keys::keys,
+        "move" => keys::move_,
+        "scan" => keys::scan,
+        "expire" => keys::expire,
+        "pexpire" => keys::pexpire,
+        "expireat" => keys::expireat,
+        "pexpireat" => keys::pexpireat,
+        "ttl" => keys::ttl,
+        "pttl" => keys::pttl,
+        "persist" => keys::persist,
+        "type" => keys::type_,
+        "dbsize" => keys::dbsize,
+        "flushdb" => keys::flushdb,
+        "flushall" => keys::flushall,
+        "swapdb" => keys::swapdb,
+        "lpush" => list::lpush,
+        "rpush" => list::rpush,
+        "lpop" => list::lpop,
+        "rpop" => list::rpop,
+        "lrange" => list::lrange,
+        "lmove" => list::lmove,
+        "blpop" => list::blpop,
+        "brpop" => list::brpop,
+        "blmove" => list::blmove,
+        "hset" => hash::hset,
+        "hget" => hash::hget,
+        "hgetall" => hash::hgetall,
+        "hdel" => hash::hdel,
+        "hscan" => hash::hscan,
+        "sadd" => set::sadd,
+        "smembers" => set::smembers,
+        "sinter" => set::sinter,
+        "sunion" => set::sunion,
+        "sscan" => set::sscan,
+        "zadd" => zset::zadd,
+        "zrange" => zset::zrange,
+        "zrank" => zset::zrank,
+        "zincrby" => zset::zincrby,
+        "zscan" => zset::zscan,
+        "xadd" => stream::xadd,
+        "xlen" => stream::xlen,
+        "xrange" => stream::xrange,
+        "xrevrange" => stream::xrevrange,
+        "xread" => stream::xread,
+        "xreadgroup" => stream::xreadgroup,
+        "xgroup" => stream::xgroup,
+        "xack" => stream::xack,
+        "xpending" => stream::xpending,
+        "xclaim" => stream::xclaim,
+        "xsetid" => stream::xsetid,
+        "publish" => pubsub::publish,
+        "ping" => connection::ping,
+        "echo" => connection::echo,
+        "bgrewriteaof" => server::bgrewriteaof,
+        "save" => server::save,
+        "bgsave" => server::bgsave,
+        "lastsave" => server::lastsave,
+        "info" => server::info,
+        "slowlog" => server::slowlog,
+        "config" => server::config,
+        "replicaof" => server::replicaof,
+        _ => return None,
+    })
+}
+
+/// Returns `true` for the commands modifying the keyspace.
+fn is_write(name: &[u8]) -> bool {
+    matches!(
+        name,
+        b"set"
+            | b"setnx"
+            | b"getset"
+            | b"mset"
+            | b"incr"
+            | b"decr"
+            | b"incrby"
+            | b"decrby"
+            | b"append"
+            | b"del"
+            | b"expire"
+            | b"pexpire"
+            | b"expireat"
+            | b"pexpireat"
+            | b"persist"
+            | b"move"
+            | b"flushdb"
+            | b"flushall"
+            | b"swapdb"
+            | b"lpush"
+            | b"rpush"
+            | b"lpop"
+            | b"rpop"
+            | b"lmove"
+            | b"blpop"
+            | b"brpop"
+            | b"blmove"
+            | b"hset"
+            | b"hdel"
+            | b"sadd"
+            | b"zadd"
+            | b"zincrby"
+            | b"xadd"
+            | b"xgroup"
+            | b"xreadgroup"
+            | b"xack"
+            | b"xclaim"
+            | b"xsetid"
+    )
+}
+
+/// Returns `true` for the write commands which may use more memory, refused
+/// once the memory limit is reached and no key can be evicted.
+fn grows(name: &[u8]) -> bool {
+    is_write(name)
+        && !matches!(
+            name,
+            b"del"
+                | b"lpop"
+                | b"rpop"
+                | b"blpop"
+                | b"brpop"
+                | b"hdel"
+                | b"persist"
+                | b"move"
+                | b"flushdb"
+                | b"flushall"
+                | b"swapdb"
+                | b"xack"
+        )
+}
+
+/// Run a command which needs more than the `Db`, reporting argument errors
+/// the way `apply` does.
+fn local(frame: Frame, handler: impl FnOnce(&mut Parse) -> Result<Frame, ParseError>) -> Frame {
+    let mut parse = match Parse::new(frame) {
+        Ok(parse) => parse,
+        Err(err) => return Frame::Error(format!("ERR {}", err)),
+    };
+
+    let name = match parse.next_string() {
+        Ok(name) => name.to_lowercase(),
+        Err(err) => return Frame::Error(format!("ERR {}", err)),
+    };
+
+    let result = handler(&mut parse).and_then(|frame| parse.finish().map(|_| frame));
+    reply(&name, result)
+}
+
+/// Turn the result of the `name` command into the reply frame, reporting
+/// argument errors to the client.
+fn reply(name: &str, result: Result<Frame, ParseError>) -> Frame {
+    match result {
+        Ok(frame) => frame,
+        Err(ParseError::EndOfStream) => Frame::Error(format!(
+            "ERR wrong number of arguments for '{}' command",
+            name
+        )),
+        Err(ParseError::Other(err)) => Frame::Error(format!("ERR {}", err)),
+    }
+}
+
+/// Reply with `f` applied to the outcome of a keyspace operation, or with the
+/// error it failed with.
+fn reply_with<T>(
+    result: Result<T, db::Error>,
+    f: impl FnOnce(T) -> Frame,
+) -> Result<Frame, ParseError> {
+    Ok(result.map_or_else(Frame::from, f))
+}
+
+impl From<db::Error> for Frame {
+    fn from(err: db::Error) -> Frame {
+        Frame::Error(err.to_string())
+    }
+}
+
+/// The options of `SCAN` and of the collection scans.
+#[derive(Debug)]
+struct ScanArgs {
+    cursor: u64,
+    pattern: Option<String>,
+    count: usize,
+
+    /// Only for `SCAN`.
+    type_: Option<String>,
+}
+
+/// Read `cursor [MATCH pattern] [COUNT count]`, followed by `[TYPE type]` for
+/// `SCAN` if `keyspace` is set.
+fn scan_args(parse: &mut Parse, keyspace: bool) -> Result<ScanArgs, ParseError> {
+    let cursor = parse.next_string()?;
+    let mut args = ScanArgs {
+        cursor: cursor.parse().map_err(|_| "invalid cursor")?,
+        pattern: None,
+        count: 10,
+        type_: None,
+    };
+
+    while parse.remaining() > 0 {
+        let option = parse.next_string()?.to_lowercase();
+        match &option[..] {
+            "match" => args.pattern = Some(parse.next_string()?),
+            "count" => {
+                args.count = usize::try_from(parse.next_int()?)
+                    .ok()
+                    .filter(|&count| count > 0)
+                    .ok_or("syntax error")?
+            }
+            "type" if keyspace => args.type_ = Some(parse.next_string()?),
+            _ => return Err("syntax error".into()),
+        }
+    }
+
+    Ok(args)
+}
+
+/// The reply to a scan: the cursor to continue from, then the items.
+fn scan_reply(cursor: u64, items: Vec<Frame>) -> Frame {
+    Frame::Array(vec![
+        Frame::Bulk(Bytes::from(cursor.to_string())),
+        Frame::Array(items),
+    ])
+}
+
+/// Read one or more keys, up to the end of the command.
+fn keys_arg(parse: &mut Parse) -> Result<Vec<String>, ParseError> {
+    let mut keys = vec![parse.next_string()?];
+    while parse.remaining() > 0 {
+        keys.push(parse.next_string()?);
+    }
+
+    Ok(keys)
+}
+
+/// Read a database index. A value which is not an integer is reported as
+/// `invalid`.
+fn db_index_arg(db: &Db, parse: &mut Parse, invalid: &str) -> Result<usize, ParseError> {
+    let index = match parse.next_int() {
+        Ok(index) => index,
+        Err(ParseError::Other(_)) => return Err(invalid.into()),
+        Err(err) => return Err(err),
+    };
+
+    usize::try_from(index)
+        .ok()
+        .filter(|&index| index < db.databases())
+        .ok_or_else(|| "DB index is out of range".into())
+}
+
+/// The Unix time in milliseconds `amount` units of `unit` milliseconds after
+/// `base`, failing as Redis does when it does not fit in an `i64`.
+fn expire_time(command: &str, amount: i64, unit: i64, base: i64) -> Result<i64, ParseError> {
+    amount
+        .checked_mul(unit)
+        .and_then(|ms| ms.checked_add(base))
+        .ok_or_else(|| format!("invalid expire time in '{}' command", command).into())
+}
+
+/// The name of a command this server knows, as counted by `INFO
+/// commandstats`.
+fn known(name: &[u8]) -> Option<&str> {
+    const LOCAL: [&str; 21] = [
+        "multi",
+        "exec",
+        "discard",
+        "watch",
+        "unwatch",
+        "subscribe",
+        "psubscribe",
+        "unsubscribe",
+        "punsubscribe",
+        "blpop",
+        "brpop",
+        "blmove",
+        "xread",
+        "xreadgroup",
+        "hello",
+        "auth",
+        "select",
+        "acl",
+        "psync",
+        "client",
+        "monitor",
+    ];
+
+    let name = std::str::from_utf8(name).ok()?;
+    (LOCAL.contains(&name) || handler(name).is_some()).then_some(name)
+}
+
+/// `command` with every argument which may hold a password replaced by
+/// `(redacted)`.
+fn redacted(command: &Frame) -> Frame {
+    match command {
+        Frame::Array(args) => Frame::Array(
+            args.iter()
+                .enumerate()
+                .map(|(i, arg)| match secret(args, i) {
+                    true => Frame::Bulk(Bytes::from_static(b"(redacted)")),
+                    false => arg.clone(),
+                })
+                .collect(),
+        ),
+        frame => frame.clone(),
+    }
+}
+
+/// Returns `true` if the argument at `i` of the command `args` may hold a
+/// password.
+fn secret(args: &[Frame], i: usize) -> bool {
+    let arg = |i: usize| match args.get(i) {
+        Some(Frame::Bulk(arg)) => arg.to_ascii_lowercase(),
+        Some(Frame::Simple(arg)) => arg.to_ascii_lowercase().into_bytes(),
+        _ => vec![],
+    };
+
+    match &arg(0)[..] {
+        b"auth" | b"hello" => i > 0,
+        // The rules of `ACL SETUSER` may set passwords anywhere.
+        b"acl" => i > 1 && arg(1) == b"setuser",
+        // `CONFIG SET` takes parameters and values in pairs.
+        b"config" => {
+            i > 2
+                && i % 2 == 1
+                && arg(1) == b"set"
+                && matches!(&arg(i - 1)[..], b"requirepass" | b"masterauth")
+        }
+        _ => false,
+    }
+}
+
+/// The database index selected by `command`, if it is a valid `SELECT`, as
+/// found in the append-only file and the replication stream.
+pub(crate) fn select_index(command: &Frame) -> Option<usize> {
+    match command {
+        Frame::Array(parts) if parts.len() == 2 && name(command) == b"select" => match &parts[1] {
+            Frame::Bulk(index) => std::str::from_utf8(index).ok()?.parse().ok(),
+            Frame::Integer(index) => usize::try_from(*index).ok(),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// The lowercased name of the command carried by `frame`, empty if the frame is
+/// not a command.
+pub(crate) fn name(frame: &Frame) -> Vec<u8> {
+    match frame {
+        Frame::Array(parts) => match parts.first() {
+            Some(Frame::Bulk(name)) => name.to_ascii_lowercase(),
+            Some(Frame::Simple(name)) => name.to_ascii_lowercase().into_bytes(),
+            _ => vec![],
+        },
+        _ => vec![],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::client::command;
+    use crate::server;
+    use crate::{Connection, Db, Frame};
+    use tokio::net::TcpStream;
+
+    async fn call(connection: &mut Connection, args: &[&str]) -> Frame {
+        connection.write_frame(&command(args)).await.unwrap();
+        connection.read_frame().await.unwrap().unwrap()
+    }
+
+    #[tokio::test]
+    async fn errors_quoting_line_breaks_keep_replies_in_sync() {
+        let db = Db::new();
+        let addr = server::spawn(&db).await;
+        let mut client = Connection::new(TcpStream::connect(addr).await.unwrap());
+
+        assert_eq!(
+            call(&mut client, &["NOPE\r\n+OK"]).await,
+            Frame::Error("ERR unknown command 'nope  +ok'".to_string())
+        );
+        assert_eq!(
+            call(&mut client, &["PING"]).await,
+            Frame::Simple("PONG".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn passwords_are_redacted() {
+        let db = Db::new();
+        let addr = server::spawn(&db).await;
+
+        let mut monitor = Connection::new(TcpStream::connect(addr).await.unwrap());
+        call(&mut monitor, &["MONITOR"]).await;
+
+        let mut client = Connection::new(TcpStream::connect(addr).await.unwrap());
+        call(
+            &mut client,
+            &["CONFIG", "SET", "slowlog-log-slower-than", "0"],
+        )
+        .await;
+        call(&mut client, &["ACL", "SETUSER", "alice", "on", ">secret"]).await;
+        call(&mut client, &["ACL", "WHOAMI"]).await;
+        let set = ["CONFIG", "SET", "maxclients", "100", "masterauth", "secret"];
+        call(&mut client, &set).await;
+
+        let mut lines = vec![];
+        for _ in 0..4 {
+            match monitor.read_frame().await.unwrap().unwrap() {
+                Frame::Simple(line) => lines.push(line),
+                frame => panic!("unexpected frame {:?}", frame),
+            }
+        }
+        assert!(
+            lines[1].ends_with(r#"] "ACL" "SETUSER" "(redacted)" "(redacted)" "(redacted)""#),
+            "{}",
+            lines[1]
+        );
+        assert!(lines[2].ends_with(r#"] "ACL" "WHOAMI""#), "{}", lines[2]);
+        assert!(
+            lines[3].ends_with(r#""maxclients" "100" "masterauth" "(redacted)""#),
+            "{}",
+            lines[3]
+        );
+
+        let entries = match call(&mut client, &["SLOWLOG", "GET"]).await {
+            Frame::Array(entries) => entries,
+            frame => panic!("unexpected reply {:?}", frame),
+        };
+        let logged = format!("{:?}", entries);
+        assert!(logged.contains("SETUSER"), "{}", logged);
+        assert!(logged.contains("masterauth"), "{}", logged);
+        assert!(!logged.contains("secret"), "{}", logged);
+    }
+}
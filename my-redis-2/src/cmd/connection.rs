@@ -0,0 +1,294 @@
+use super::{db_index_arg, local, Session};
+use crate::acl::DEFAULT_USER;
+use crate::clients::Client;
+use crate::replication;
+use crate::{Connection, Db, Frame, Parse, ParseError, Socket};
+
+use bytes::Bytes;
+use tokio::select;
+
+/// `PING [message]`
+pub(crate) fn ping(_db: &Db, parse: &mut Parse) -> Result<Frame, ParseError> {
+    if parse.remaining() == 0 {
+        return Ok(Frame::Simple("PONG".to_string()));
+    }
+
+    Ok(Frame::Bulk(parse.next_bytes()?))
+}
+
+/// `ECHO message`
+pub(crate) fn echo(_db: &Db, parse: &mut Parse) -> Result<Frame, ParseError> {
+    Ok(Frame::Bulk(parse.next_bytes()?))
+}
+
+/// `HELLO [protover [AUTH username password]]`
+///
+/// Switches the connection to RESP `protover`, if given, after authenticating
+/// as `username`, and replies with a map describing the server.
+pub(crate) fn hello(
+    db: &Db,
+    parse: &mut Parse,
+    dst: &mut Connection<impl Socket>,
+    session: &mut Session,
+) -> Result<Frame, ParseError> {
+    if parse.remaining() > 0 {
+        let protocol = parse
+            .next_int()
+            .map_err(|_| "Protocol version is not an integer or out of range")?;
+
+        if !(2..=3).contains(&protocol) {
+            return Ok(Frame::Error(
+                "NOPROTO unsupported protocol version".to_string(),
+            ));
+        }
+
+        if parse.remaining() > 0 {
+            let option = parse.next_string()?;
+            if !option.eq_ignore_ascii_case("auth") {
+                return Err(format!("Syntax error in HELLO option '{}'", option).into());
+            }
+
+            let user = parse.next_string()?;
+            let password = parse.next_string()?;
+            if let Err(err) = authenticate(db, session, user, &password) {
+                return Ok(err);
+            }
+        }
+
+        dst.set_protocol(protocol as u8);
+    }
+
+    let field = |name: &'static str| Frame::Bulk(Bytes::from_static(name.as_bytes()));
+    let role = match replication::leader(db) {
+        Some(_) => "replica",
+        None => "master",
+    };
+
+    Ok(Frame::Map(vec![
+        (field("server"), field("redis")),
+        (field("version"), field(env!("CARGO_PKG_VERSION"))),
+        (field("proto"), Frame::Integer(dst.protocol() as i64)),
+        (field("mode"), field("standalone")),
+        (field("role"), field(role)),
+        (field("modules"), Frame::array()),
+    ]))
+}
+
+/// `AUTH [username] password`
+///
+/// Authenticates the connection as `username`, the default user if not
+/// given, see `acl`.
+pub(super) fn auth(db: &Db, parse: &mut Parse, session: &mut Session) -> Result<Frame, ParseError> {
+    let first = parse.next_string()?;
+
+    let (user, password) = match parse.remaining() {
+        0 if db.acl().default_nopass() => {
+            return Ok(Frame::Error(
+                "ERR AUTH <password> called without any password configured for the default \
+                 user. Are you sure your configuration is correct?"
+                    .to_string(),
+            ))
+        }
+        0 => (DEFAULT_USER.to_string(), first),
+        _ => (first, parse.next_string()?),
+    };
+
+    Ok(match authenticate(db, session, user, &password) {
+        Ok(()) => Frame::Simple("OK".to_string()),
+        Err(err) => err,
+    })
+}
+
+/// Authenticate `session` as `user`, or return the error reply.
+fn authenticate(db: &Db, session: &mut Session, user: String, password: &str) -> Result<(), Frame> {
+    if !db.acl().authenticate(&user, password) {
+        return Err(Frame::Error(
+            "WRONGPASS invalid username-password pair or user is disabled.".to_string(),
+        ));
+    }
+
+    session.user = Some(user);
+    Ok(())
+}
+
+/// `SELECT index`
+///
+/// Switches the connection to the database `index`.
+pub(super) fn select(
+    db: &Db,
+    parse: &mut Parse,
+    session: &mut Session,
+) -> Result<Frame, ParseError> {
+    let index = db_index_arg(db, parse, "value is not an integer or out of range")?;
+
+    session.db = index;
+    if let Some(client) = &session.client {
+        client.select(index);
+    }
+
+    Ok(Frame::Simple("OK".to_string()))
+}
+
+/// `CLIENT LIST`, `CLIENT KILL`, `CLIENT SETNAME name`, `CLIENT GETNAME` or
+/// `CLIENT ID`
+pub(super) fn client(db: &Db, parse: &mut Parse, session: &Session) -> Result<Frame, ParseError> {
+    let subcommand = parse.next_string()?.to_lowercase();
+
+    if subcommand == "list" {
+        return Ok(Frame::Bulk(Bytes::from(db.clients().list())));
+    }
+    if subcommand == "kill" {
+        return kill(db, parse, session.client.as_deref());
+    }
+
+    let client = match &session.client {
+        Some(client) => client,
+        None => return Ok(Frame::Error("ERR no client for this session".to_string())),
+    };
+
+    Ok(match &subcommand[..] {
+        "setname" => {
+            let name = parse.next_string()?;
+            if name.chars().any(|c| !c.is_ascii_graphic()) {
+                return Ok(Frame::Error(
+                    "ERR Client names cannot contain spaces, newlines or special characters."
+                        .to_string(),
+                ));
+            }
+
+            client.set_name(name);
+            Frame::Simple("OK".to_string())
+        }
+        "getname" => match client.name() {
+            name if name.is_empty() => Frame::Null,
+            name => Frame::Bulk(Bytes::from(name)),
+        },
+        "id" => Frame::Integer(client.id() as i64),
+        _ => {
+            return Err(format!(
+                "unknown subcommand '{}'. Try CLIENT LIST, KILL, SETNAME, GETNAME or ID.",
+                subcommand
+            )
+            .into())
+        }
+    })
+}
+
+/// `CLIENT KILL addr` or `CLIENT KILL [ID id] [ADDR addr] [SKIPME yes/no]`
+///
+/// The first form replies `OK` once the client at `addr` is killed. The second
+/// one kills every client matching the filters, except the caller unless
+/// `SKIPME no` is given, and replies with their number.
+fn kill(db: &Db, parse: &mut Parse, this: Option<&Client>) -> Result<Frame, ParseError> {
+    let mut option = parse.next_string()?;
+
+    if parse.remaining() == 0 {
+        return Ok(match db.clients().kill(|client| client.addr() == option) {
+            0 => Frame::Error("ERR No such client".to_string()),
+            _ => Frame::Simple("OK".to_string()),
+        });
+    }
+
+    let (mut id, mut addr, mut skip_me) = (None, None, true);
+    loop {
+        let value = parse.next_string()?;
+
+        match &option.to_lowercase()[..] {
+            "id" => {
+                id = Some(
+                    value
+                        .parse::<u64>()
+                        .map_err(|_| "client-id should be greater than 0")?,
+                )
+            }
+            "addr" => addr = Some(value),
+            "skipme" => {
+                skip_me = match &value.to_lowercase()[..] {
+                    "yes" => true,
+                    "no" => false,
+                    _ => return Err("syntax error".into()),
+                }
+            }
+            _ => return Err("syntax error".into()),
+        }
+
+        if parse.remaining() == 0 {
+            break;
+        }
+        option = parse.next_string()?;
+    }
+
+    let this = this.map(Client::id);
+    let killed = db.clients().kill(|client| {
+        id.is_none_or(|id| client.id() == id)
+            && addr.as_ref().is_none_or(|addr| client.addr() == addr)
+            && !(skip_me && this == Some(client.id()))
+    });
+
+    Ok(Frame::Integer(killed as i64))
+}
+
+/// `MONITOR`
+///
+/// Streams every command the server receives to the connection, until it
+/// closes. The connection is closed if it falls too far behind, see
+/// `Clients::received`.
+pub(super) async fn monitor(
+    db: &Db,
+    frame: Frame,
+    dst: &mut Connection<impl Socket>,
+) -> crate::Result<()> {
+    let reply = local(frame, |_| Ok(Frame::Simple("OK".to_string())));
+    if let Frame::Error(_) = reply {
+        return Ok(dst.write_frame(&reply).await?);
+    }
+
+    let mut commands = db.clients().monitor();
+    dst.write_frame(&reply).await?;
+
+    loop {
+        select! {
+            line = commands.recv() => match line {
+                Some(line) => dst.write_frame(&Frame::Simple(line)).await?,
+                None => return Err("monitor fell too far behind".into()),
+            },
+            _ = dst.closed() => return Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::command;
+    use crate::server;
+    use tokio::net::{TcpListener, TcpStream};
+
+    async fn role(db: &Db) -> Frame {
+        let addr = server::spawn(db).await;
+        let mut client = Connection::new(TcpStream::connect(addr).await.unwrap());
+        client.write_frame(&command(&["HELLO"])).await.unwrap();
+
+        // RESP2 connections get the map as an array of fields and values.
+        match client.read_frame().await.unwrap() {
+            Some(Frame::Array(fields)) => {
+                let at = fields.iter().position(|field| *field == "role").unwrap();
+                fields[at + 1].clone()
+            }
+            frame => panic!("unexpected reply {:?}", frame),
+        }
+    }
+
+    #[tokio::test]
+    async fn hello_reports_the_role() {
+        let db = Db::new();
+        assert_eq!(role(&db).await, Frame::Bulk("master".into()));
+
+        // Nothing listens on the leader's port, the follower keeps retrying.
+        let leader = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = leader.local_addr().unwrap().port();
+        drop(leader);
+        replication::follow(&db, "127.0.0.1".into(), port);
+        assert_eq!(role(&db).await, Frame::Bulk("replica".into()));
+    }
+}
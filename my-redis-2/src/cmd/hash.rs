@@ -0,0 +1,69 @@
+use super::{reply_with, scan_args, scan_reply};
+use crate::{Db, Frame, Parse, ParseError};
+
+/// `HSET key field value [field value ...]`
+pub(crate) fn hset(db: &Db, parse: &mut Parse) -> Result<Frame, ParseError> {
+    let key = parse.next_string()?;
+
+    let mut pairs = vec![(parse.next_bytes()?, parse.next_bytes()?)];
+    while parse.remaining() > 0 {
+        pairs.push((parse.next_bytes()?, parse.next_bytes()?));
+    }
+
+    reply_with(db.hset(&key, pairs), |added| Frame::Integer(added as i64))
+}
+
+/// `HGET key field`
+pub(crate) fn hget(db: &Db, parse: &mut Parse) -> Result<Frame, ParseError> {
+    let key = parse.next_string()?;
+    let field = parse.next_bytes()?;
+
+    reply_with(db.hget(&key, &field), |value| {
+        value.map_or(Frame::Null, Frame::Bulk)
+    })
+}
+
+/// `HGETALL key`
+///
+/// Replies with a map, sent as a flat array of fields and values in RESP2.
+pub(crate) fn hgetall(db: &Db, parse: &mut Parse) -> Result<Frame, ParseError> {
+    let key = parse.next_string()?;
+
+    reply_with(db.hget_all(&key), |pairs| {
+        Frame::Map(
+            pairs
+                .into_iter()
+                .map(|(field, value)| (Frame::Bulk(field), Frame::Bulk(value)))
+                .collect(),
+        )
+    })
+}
+
+/// `HDEL key field [field ...]`
+pub(crate) fn hdel(db: &Db, parse: &mut Parse) -> Result<Frame, ParseError> {
+    let key = parse.next_string()?;
+
+    let mut fields = vec![parse.next_bytes()?];
+    while parse.remaining() > 0 {
+        fields.push(parse.next_bytes()?);
+    }
+
+    reply_with(db.hdel(&key, &fields), |removed| {
+        Frame::Integer(removed as i64)
+    })
+}
+
+/// `HSCAN key cursor [MATCH pattern] [COUNT count]`
+pub(crate) fn hscan(db: &Db, parse: &mut Parse) -> Result<Frame, ParseError> {
+    let key = parse.next_string()?;
+    let args = scan_args(parse, false)?;
+
+    let page = db.hscan(&key, args.cursor, args.count, args.pattern.as_deref());
+    reply_with(page, |(cursor, pairs)| {
+        let items = pairs
+            .into_iter()
+            .flat_map(|(field, value)| [Frame::Bulk(field), Frame::Bulk(value)])
+            .collect();
+        scan_reply(cursor, items)
+    })
+}
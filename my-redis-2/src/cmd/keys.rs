@@ -0,0 +1,268 @@
+use super::{db_index_arg, expire_time, keys_arg, scan_args, scan_reply};
+use crate::db::unix_time;
+use crate::{Db, Frame, Parse, ParseError};
+
+use std::time::Duration;
+
+/// `EXPIRE key seconds`
+///
+/// A non-positive timeout expires the key immediately.
+pub(crate) fn expire(db: &Db, parse: &mut Parse) -> Result<Frame, ParseError> {
+    let key = parse.next_string()?;
+    let secs = parse.next_int()?;
+
+    let unix_ms = expire_time("expire", secs, 1000, now_ms())?;
+    Ok(expire_at(db, &key, unix_ms))
+}
+
+/// `PEXPIRE key milliseconds`
+pub(crate) fn pexpire(db: &Db, parse: &mut Parse) -> Result<Frame, ParseError> {
+    let key = parse.next_string()?;
+    let ms = parse.next_int()?;
+
+    let unix_ms = expire_time("pexpire", ms, 1, now_ms())?;
+    Ok(expire_at(db, &key, unix_ms))
+}
+
+/// `EXPIREAT key unix-time-seconds`
+///
+/// A timestamp in the past expires the key immediately.
+pub(crate) fn expireat(db: &Db, parse: &mut Parse) -> Result<Frame, ParseError> {
+    let key = parse.next_string()?;
+    let secs = parse.next_int()?;
+
+    let unix_ms = expire_time("expireat", secs, 1000, 0)?;
+    Ok(expire_at(db, &key, unix_ms))
+}
+
+/// `PEXPIREAT key unix-time-milliseconds`
+pub(crate) fn pexpireat(db: &Db, parse: &mut Parse) -> Result<Frame, ParseError> {
+    let key = parse.next_string()?;
+    let ms = parse.next_int()?;
+
+    Ok(expire_at(db, &key, ms))
+}
+
+fn expire_at(db: &Db, key: &str, unix_ms: i64) -> Frame {
+    let duration = Duration::from_millis(unix_ms.saturating_sub(now_ms()).max(0) as u64);
+    Frame::Integer(db.expire(key, duration) as i64)
+}
+
+/// The current Unix time in milliseconds.
+fn now_ms() -> i64 {
+    unix_time().as_millis() as i64
+}
+
+/// `TTL key`
+///
+/// Replies `-2` if the key does not exist and `-1` if it has no expiration.
+pub(crate) fn ttl(db: &Db, parse: &mut Parse) -> Result<Frame, ParseError> {
+    let key = parse.next_string()?;
+
+    Ok(ttl_reply(db, &key, |ttl| {
+        ((ttl.as_millis() + 500) / 1000) as i64
+    }))
+}
+
+/// `PTTL key`
+pub(crate) fn pttl(db: &Db, parse: &mut Parse) -> Result<Frame, ParseError> {
+    let key = parse.next_string()?;
+
+    Ok(ttl_reply(db, &key, |ttl| ttl.as_millis() as i64))
+}
+
+/// `PERSIST key`
+pub(crate) fn persist(db: &Db, parse: &mut Parse) -> Result<Frame, ParseError> {
+    let key = parse.next_string()?;
+
+    Ok(Frame::Integer(db.persist(&key) as i64))
+}
+
+fn ttl_reply(db: &Db, key: &str, unit: impl Fn(Duration) -> i64) -> Frame {
+    Frame::Integer(match db.ttl(key) {
+        None => -2,
+        Some(None) => -1,
+        Some(Some(ttl)) => unit(ttl),
+    })
+}
+
+/// `DEL key [key ...]`
+pub(crate) fn del(db: &Db, parse: &mut Parse) -> Result<Frame, ParseError> {
+    let keys = keys_arg(parse)?;
+
+    Ok(Frame::Integer(db.remove(&keys) as i64))
+}
+
+/// `EXISTS key [key ...]`
+pub(crate) fn exists(db: &Db, parse: &mut Parse) -> Result<Frame, ParseError> {
+    let keys = keys_arg(parse)?;
+
+    Ok(Frame::Integer(db.exists(&keys) as i64))
+}
+
+/// `MOVE key db`
+///
+/// Replies 0 if the key does not exist, or if `db` already holds it.
+pub(crate) fn move_(db: &Db, parse: &mut Parse) -> Result<Frame, ParseError> {
+    let key = parse.next_string()?;
+    let index = db_index_arg(db, parse, "value is not an integer or out of range")?;
+
+    if index == db.index() {
+        return Err("source and destination objects are the same".into());
+    }
+
+    Ok(Frame::Integer(db.move_to(&key, index) as i64))
+}
+
+/// `KEYS pattern`
+pub(crate) fn keys(db: &Db, parse: &mut Parse) -> Result<Frame, ParseError> {
+    let pattern = parse.next_string()?;
+
+    let mut frame = Frame::array();
+    for key in db.keys(&pattern) {
+        frame.push_bulk(key.into());
+    }
+
+    Ok(frame)
+}
+
+/// `SCAN cursor [MATCH pattern] [COUNT count] [TYPE type]`
+///
+/// Walks about `count` keys per call, see `Db::scan`.
+pub(crate) fn scan(db: &Db, parse: &mut Parse) -> Result<Frame, ParseError> {
+    let args = scan_args(parse, true)?;
+
+    let pattern = args.pattern.as_deref();
+    let (cursor, keys) = db.scan(args.cursor, args.count, pattern, args.type_.as_deref());
+
+    let keys = keys
+        .into_iter()
+        .map(|key| Frame::Bulk(key.into()))
+        .collect();
+    Ok(scan_reply(cursor, keys))
+}
+
+/// `TYPE key`
+pub(crate) fn type_(db: &Db, parse: &mut Parse) -> Result<Frame, ParseError> {
+    let key = parse.next_string()?;
+
+    Ok(Frame::Simple(db.key_type(&key).to_string()))
+}
+
+/// `DBSIZE`
+pub(crate) fn dbsize(db: &Db, _parse: &mut Parse) -> Result<Frame, ParseError> {
+    let (keys, _) = db.key_counts();
+
+    Ok(Frame::Integer(keys as i64))
+}
+
+/// `FLUSHDB [ASYNC | SYNC]`
+///
+/// Keys are always removed right away, whichever the option.
+pub(crate) fn flushdb(db: &Db, parse: &mut Parse) -> Result<Frame, ParseError> {
+    flush_mode(parse)?;
+    db.clear();
+
+    Ok(Frame::Simple("OK".to_string()))
+}
+
+/// `FLUSHALL [ASYNC | SYNC]`
+pub(crate) fn flushall(db: &Db, parse: &mut Parse) -> Result<Frame, ParseError> {
+    flush_mode(parse)?;
+    db.clear_all();
+
+    Ok(Frame::Simple("OK".to_string()))
+}
+
+fn flush_mode(parse: &mut Parse) -> Result<(), ParseError> {
+    if parse.remaining() > 0 {
+        let mode = parse.next_string()?.to_lowercase();
+        if mode != "async" && mode != "sync" {
+            return Err("syntax error".into());
+        }
+    }
+
+    Ok(())
+}
+
+/// `SWAPDB index1 index2`
+///
+/// Connections having selected either database see the keys of the other one
+/// from then on.
+pub(crate) fn swapdb(db: &Db, parse: &mut Parse) -> Result<Frame, ParseError> {
+    let a = db_index_arg(db, parse, "invalid first DB index")?;
+    let b = db_index_arg(db, parse, "invalid second DB index")?;
+    db.swap(a, b);
+
+    Ok(Frame::Simple("OK".to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::apply;
+    use super::*;
+    use crate::client::command;
+
+    #[tokio::test]
+    async fn expire_times_out_of_range_are_rejected() {
+        let db = Db::new();
+        apply(command(&["SET", "a", "1"]), &db);
+
+        for name in ["EXPIRE", "PEXPIRE", "EXPIREAT"] {
+            let reply = apply(command(&[name, "a", "9223372036854775807"]), &db);
+            let err = format!(
+                "ERR invalid expire time in '{}' command",
+                name.to_lowercase()
+            );
+            assert_eq!(reply, Frame::Error(err));
+        }
+        assert_eq!(db.ttl("a"), Some(None));
+
+        // The shard and the append-only file lock are still usable.
+        assert_eq!(
+            apply(command(&["EXPIRE", "a", "100"]), &db),
+            Frame::Integer(1)
+        );
+        assert_eq!(
+            apply(command(&["SET", "b", "2"]), &db),
+            Frame::Simple("OK".into())
+        );
+    }
+
+    #[tokio::test]
+    async fn absolute_expirations_far_ahead_are_kept() {
+        let db = Db::new();
+        let call = |args: &[&str]| apply(command(args), &db);
+        call(&["SET", "a", "1"]);
+
+        assert_eq!(
+            call(&["PEXPIREAT", "a", "9223372036854775807"]),
+            Frame::Integer(1)
+        );
+        assert_eq!(call(&["GET", "a"]), Frame::Bulk("1".into()));
+        assert!(matches!(call(&["TTL", "a"]), Frame::Integer(ttl) if ttl > 0));
+
+        // A time in the past removes the key.
+        assert_eq!(
+            call(&["PEXPIREAT", "a", "-9223372036854775808"]),
+            Frame::Integer(1)
+        );
+        assert_eq!(call(&["EXISTS", "a"]), Frame::Integer(0));
+    }
+
+    #[tokio::test]
+    async fn expirations_are_integers() {
+        let db = Db::new();
+        let call = |args: &[&str]| apply(command(args), &db);
+        call(&["SET", "a", "1"]);
+
+        let not_an_integer = Frame::Error("ERR value is not an integer or out of range".into());
+        assert_eq!(call(&["EXPIRE", "a", "ten"]), not_an_integer);
+        assert_eq!(
+            call(&["PEXPIRE", "a", "9223372036854775808"]),
+            not_an_integer
+        );
+        assert_eq!(call(&["TTL", "a"]), Frame::Integer(-1));
+        assert_eq!(call(&["TTL", "missing"]), Frame::Integer(-2));
+    }
+}
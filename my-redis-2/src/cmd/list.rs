@@ -0,0 +1,350 @@
+use super::{lock_log, reply, reply_with, LogLock};
+use crate::db::{Block, End, Pop, Popped};
+use crate::{Connection, Db, Frame, Parse, ParseError, Socket};
+
+use bytes::Bytes;
+use std::time::Duration;
+use tokio::select;
+use tokio::time::{self, Instant};
+
+/// `LPUSH key value [value ...]`
+pub(crate) fn lpush(db: &Db, parse: &mut Parse) -> Result<Frame, ParseError> {
+    push(db, parse, End::Left)
+}
+
+/// `RPUSH key value [value ...]`
+pub(crate) fn rpush(db: &Db, parse: &mut Parse) -> Result<Frame, ParseError> {
+    push(db, parse, End::Right)
+}
+
+fn push(db: &Db, parse: &mut Parse, end: End) -> Result<Frame, ParseError> {
+    let key = parse.next_string()?;
+
+    let mut values = vec![parse.next_bytes()?];
+    while parse.remaining() > 0 {
+        values.push(parse.next_bytes()?);
+    }
+
+    reply_with(db.push(&key, end, values), |len| Frame::Integer(len as i64))
+}
+
+/// `LPOP key [count]`
+pub(crate) fn lpop(db: &Db, parse: &mut Parse) -> Result<Frame, ParseError> {
+    pop(db, parse, End::Left)
+}
+
+/// `RPOP key [count]`
+pub(crate) fn rpop(db: &Db, parse: &mut Parse) -> Result<Frame, ParseError> {
+    pop(db, parse, End::Right)
+}
+
+/// Without a count, replies with the popped value alone. With one, replies
+/// with an array of up to `count` values.
+fn pop(db: &Db, parse: &mut Parse, end: End) -> Result<Frame, ParseError> {
+    let key = parse.next_string()?;
+
+    let count = match parse.remaining() {
+        0 => None,
+        _ => match parse.next_int()? {
+            count if count < 0 => return Err("value is out of range, must be positive".into()),
+            count => Some(count as usize),
+        },
+    };
+
+    reply_with(db.pop(&key, end, count.unwrap_or(1)), |values| {
+        match (values, count) {
+            (None, None) => Frame::Null,
+            (None, Some(_)) => Frame::NullArray,
+            (Some(mut values), None) => Frame::Bulk(values.remove(0)),
+            (Some(values), Some(_)) => Frame::Array(values.into_iter().map(Frame::Bulk).collect()),
+        }
+    })
+}
+
+/// `LRANGE key start stop`
+pub(crate) fn lrange(db: &Db, parse: &mut Parse) -> Result<Frame, ParseError> {
+    let key = parse.next_string()?;
+    let start = parse.next_int()?;
+    let stop = parse.next_int()?;
+
+    reply_with(db.lrange(&key, start, stop), |values| {
+        Frame::Array(values.into_iter().map(Frame::Bulk).collect())
+    })
+}
+
+/// `LMOVE source destination LEFT|RIGHT LEFT|RIGHT`
+pub(crate) fn lmove(db: &Db, parse: &mut Parse) -> Result<Frame, ParseError> {
+    let source = parse.next_string()?;
+    let destination = parse.next_string()?;
+    let from = end_arg(parse)?;
+    let to = end_arg(parse)?;
+
+    reply_with(db.lmove(&source, &destination, from, to), |value| {
+        value.map_or(Frame::Null, Frame::Bulk)
+    })
+}
+
+/// `BLPOP key [key ...] timeout`, `BRPOP key [key ...] timeout` and
+/// `BLMOVE source destination LEFT|RIGHT LEFT|RIGHT timeout`, when they
+/// cannot block: inside a transaction or replayed from the append-only file.
+/// The lists are then tried once, as if the timeout elapsed right away.
+pub(crate) fn blpop(db: &Db, parse: &mut Parse) -> Result<Frame, ParseError> {
+    try_pop(db, blocking_args(parse, "blpop")?)
+}
+
+pub(crate) fn brpop(db: &Db, parse: &mut Parse) -> Result<Frame, ParseError> {
+    try_pop(db, blocking_args(parse, "brpop")?)
+}
+
+pub(crate) fn blmove(db: &Db, parse: &mut Parse) -> Result<Frame, ParseError> {
+    try_pop(db, blocking_args(parse, "blmove")?)
+}
+
+fn try_pop(
+    db: &Db,
+    (keys, pop, _): (Vec<String>, Pop, Option<Duration>),
+) -> Result<Frame, ParseError> {
+    for key in &keys {
+        let value = match &pop.to {
+            Some((destination, to)) => db.lmove(key, destination, pop.from, *to),
+            None => db
+                .pop(key, pop.from, 1)
+                .map(|values| values.and_then(|mut values| values.pop())),
+        };
+
+        match value {
+            Ok(Some(value)) => return Ok(popped_reply(key, value, &pop)),
+            Ok(None) => {}
+            Err(err) => return Ok(err.into()),
+        }
+    }
+
+    Ok(timeout_reply(&pop))
+}
+
+/// Run a blocking pop, waiting until a value is pushed, the timeout elapses or
+/// the client disconnects.
+pub(super) async fn block(
+    db: &Db,
+    frame: Frame,
+    dst: &mut Connection<impl Socket>,
+) -> crate::Result<()> {
+    let name = String::from_utf8_lossy(&super::name(&frame)).into_owned();
+
+    let args = Parse::new(frame).and_then(|mut parse| {
+        parse.next_string()?;
+        let args = blocking_args(&mut parse, &name)?;
+        parse.finish()?;
+        Ok(args)
+    });
+    let (keys, pop, timeout) = match args {
+        Ok(args) => args,
+        Err(err) => {
+            return dst
+                .write_frame(&reply(&name, Err(err)))
+                .await
+                .map_err(Into::into)
+        }
+    };
+
+    let block = {
+        let _lock = db.lock_command();
+        let mut log_lock = lock_log(db);
+
+        let block = db.block_pop(keys, pop.clone());
+        if let Ok(Block::Ready(popped)) = &block {
+            log(db, &mut log_lock, popped);
+        }
+        drop(log_lock);
+
+        // A `BLMOVE` may have pushed to a list others are blocked on.
+        serve_blocked(db);
+        block
+    };
+
+    let mut waiter = match block {
+        Ok(Block::Ready(popped)) => {
+            let frame = popped_reply(&popped.key, popped.value, &popped.pop);
+            return dst.write_frame(&frame).await.map_err(Into::into);
+        }
+        Ok(Block::Waiting(waiter)) => waiter,
+        Err(err) => return dst.write_frame(&err.into()).await.map_err(Into::into),
+    };
+
+    // A timeout too long to be represented never elapses.
+    let deadline = timeout.and_then(|timeout| Instant::now().checked_add(timeout));
+
+    let result = select! {
+        result = waiter.recv() => Some(result),
+        _ = time::sleep_until(deadline.unwrap_or_else(Instant::now)), if deadline.is_some() => {
+            // A value may have been handed over just as the timeout elapsed.
+            waiter.cancel()
+        }
+        // The client left, which takes it out of line.
+        _ = dst.closed() => return Ok(()),
+    };
+
+    let frame = match result {
+        Some(Ok((key, value))) => popped_reply(&key, value, &pop),
+        Some(Err(err)) => err.into(),
+        None => timeout_reply(&pop),
+    };
+
+    dst.write_frame(&frame).await?;
+
+    Ok(())
+}
+
+/// Hand values pushed by the last command to the clients blocked on them,
+/// logging the pops to the append-only file.
+pub(super) fn serve_blocked(db: &Db) {
+    if !db.has_ready() {
+        return;
+    }
+
+    let mut log_lock = lock_log(db);
+
+    for popped in db.serve_blocked() {
+        log(db, &mut log_lock, &popped);
+    }
+}
+
+/// Record the pop on behalf of a blocked client, logged as the equivalent
+/// non-blocking command so replaying it never blocks.
+fn log(db: &Db, log_lock: &mut LogLock, popped: &Popped) {
+    db.record_change();
+
+    // The client may have blocked on another database than the caller's.
+    let db = &db
+        .select(popped.db)
+        .expect("popped from an existing database");
+
+    let end = |end: End| match end {
+        End::Left => Bytes::from_static(b"LEFT"),
+        End::Right => Bytes::from_static(b"RIGHT"),
+    };
+    let key = Bytes::from(popped.key.clone());
+
+    let args = match &popped.pop.to {
+        Some((destination, to)) => vec![
+            Bytes::from_static(b"LMOVE"),
+            key,
+            Bytes::from(destination.clone()),
+            end(popped.pop.from),
+            end(*to),
+        ],
+        None if popped.pop.from == End::Left => vec![Bytes::from_static(b"LPOP"), key],
+        None => vec![Bytes::from_static(b"RPOP"), key],
+    };
+    let command = Frame::Array(args.into_iter().map(Frame::Bulk).collect());
+
+    if let Err(err) = super::log(db, log_lock, &command) {
+        eprintln!("failed to log to the append only file: {}", err);
+    }
+}
+
+/// Read the arguments of the blocking command `name`: the keys, what to do
+/// with the value and the timeout, `None` to wait forever.
+fn blocking_args(
+    parse: &mut Parse,
+    name: &str,
+) -> Result<(Vec<String>, Pop, Option<Duration>), ParseError> {
+    let (keys, pop) = match name {
+        "blmove" => {
+            let source = parse.next_string()?;
+            let destination = parse.next_string()?;
+            let from = end_arg(parse)?;
+            let to = end_arg(parse)?;
+
+            let pop = Pop {
+                from,
+                to: Some((destination, to)),
+            };
+            (vec![source], pop)
+        }
+        _ => {
+            let mut keys = vec![parse.next_string()?];
+            while parse.remaining() > 1 {
+                keys.push(parse.next_string()?);
+            }
+
+            let from = match name {
+                "blpop" => End::Left,
+                _ => End::Right,
+            };
+            (keys, Pop { from, to: None })
+        }
+    };
+
+    let timeout = parse.next_float()?;
+    if timeout < 0.0 {
+        return Err("timeout is negative".into());
+    }
+
+    let timeout = match timeout == 0.0 {
+        true => None,
+        false => Some(Duration::try_from_secs_f64(timeout).map_err(|_| "timeout is out of range")?),
+    };
+
+    Ok((keys, pop, timeout))
+}
+
+/// Read `LEFT` or `RIGHT`.
+fn end_arg(parse: &mut Parse) -> Result<End, ParseError> {
+    match &parse.next_string()?.to_uppercase()[..] {
+        "LEFT" => Ok(End::Left),
+        "RIGHT" => Ok(End::Right),
+        _ => Err("syntax error".into()),
+    }
+}
+
+/// `BLMOVE` replies with the value alone, `BLPOP` and `BRPOP` along with the
+/// key it was popped from.
+fn popped_reply(key: &str, value: Bytes, pop: &Pop) -> Frame {
+    match pop.to {
+        Some(_) => Frame::Bulk(value),
+        None => Frame::Array(vec![
+            Frame::Bulk(Bytes::from(key.to_string())),
+            Frame::Bulk(value),
+        ]),
+    }
+}
+
+fn timeout_reply(pop: &Pop) -> Frame {
+    match pop.to {
+        Some(_) => Frame::Null,
+        None => Frame::NullArray,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::apply;
+    use super::*;
+    use crate::client::command;
+    use crate::server;
+    use tokio::net::TcpStream;
+
+    async fn connect(db: &Db) -> Connection {
+        let addr = server::spawn(db).await;
+        Connection::new(TcpStream::connect(addr).await.unwrap())
+    }
+
+    #[tokio::test]
+    async fn timeouts_out_of_range_block_forever() {
+        let db = Db::new();
+        let mut client = connect(&db).await;
+
+        client
+            .write_frame(&command(&["BLPOP", "list", "10000000000000000000"]))
+            .await
+            .unwrap();
+        time::sleep(Duration::from_millis(50)).await;
+        apply(command(&["RPUSH", "list", "a"]), &db);
+
+        assert_eq!(
+            client.read_frame().await.unwrap(),
+            Some(command(&["list", "a"]))
+        );
+    }
+}
@@ -0,0 +1,456 @@
+use super::Session;
+use crate::{Connection, Db, Frame, Parse, ParseError, Socket};
+
+use bytes::Bytes;
+use std::pin::Pin;
+use std::time::Instant;
+use tokio::select;
+use tokio::sync::broadcast;
+use tokio_stream::{Stream, StreamExt, StreamMap};
+
+/// Stream of messages. The stream receives messages from the
+/// `broadcast::Receiver`. We use `stream!` to create a `Stream` that consumes
+/// messages. Because `stream!` values cannot be named, we box the stream using
+/// a trait object.
+type Messages<T> = Pin<Box<dyn Stream<Item = T> + Send>>;
+
+/// `PUBLISH channel message`
+pub(crate) fn publish(db: &Db, parse: &mut Parse) -> Result<Frame, ParseError> {
+    let channel = parse.next_string()?;
+    let message = parse.next_bytes()?;
+
+    Ok(Frame::Integer(db.publish(&channel, message) as i64))
+}
+
+/// The channels and patterns a connection in subscriber mode listens on.
+///
+/// Broadcast channels left without subscribers are dropped from `db` as
+/// subscriptions end, including when the connection goes away.
+struct Subscriptions {
+    db: Db,
+    channels: StreamMap<String, Messages<Bytes>>,
+    patterns: StreamMap<String, Messages<(String, Bytes)>>,
+}
+
+impl Subscriptions {
+    fn new(db: &Db) -> Subscriptions {
+        Subscriptions {
+            db: db.clone(),
+            channels: StreamMap::new(),
+            patterns: StreamMap::new(),
+        }
+    }
+
+    /// Total number of subscriptions, as reported to the client.
+    fn len(&self) -> usize {
+        self.channels.len() + self.patterns.len()
+    }
+
+    fn subscribe(&mut self, channel: String) {
+        let rx = into_stream(self.db.subscribe(channel.clone()));
+        self.channels.insert(channel, rx);
+    }
+
+    fn psubscribe(&mut self, pattern: String) {
+        let rx = into_stream(self.db.psubscribe(pattern.clone()));
+        self.patterns.insert(pattern, rx);
+    }
+
+    fn unsubscribe(&mut self, channel: &str) {
+        // The receiver has to be dropped first.
+        self.channels.remove(channel);
+        self.db.unsubscribe(channel);
+    }
+
+    fn punsubscribe(&mut self, pattern: &str) {
+        self.patterns.remove(pattern);
+        self.db.punsubscribe(pattern);
+    }
+}
+
+impl Drop for Subscriptions {
+    fn drop(&mut self) {
+        let channels: Vec<_> = self.channels.keys().cloned().collect();
+        for channel in channels {
+            self.unsubscribe(&channel);
+        }
+
+        let patterns: Vec<_> = self.patterns.keys().cloned().collect();
+        for pattern in patterns {
+            self.punsubscribe(&pattern);
+        }
+    }
+}
+
+/// Run a connection in subscriber mode.
+///
+/// `frame` is the `SUBSCRIBE`, `PSUBSCRIBE`, `UNSUBSCRIBE` or `PUNSUBSCRIBE`
+/// command which switched the connection into subscriber mode. While
+/// subscribed, published messages are forwarded to the client and only the
+/// pub/sub commands, `PING` and `QUIT` are accepted.
+///
+/// Returns once the connection has no subscriptions left, after which the
+/// connection is back to normal mode, or once the client disconnects.
+pub(crate) async fn subscribe(
+    db: &Db,
+    frame: Frame,
+    dst: &mut Connection<impl Socket>,
+    session: &mut Session,
+) -> crate::Result<()> {
+    let mut subscriptions = Subscriptions::new(db);
+
+    // The command was already checked and is recorded by `run`.
+    if !handle_pub_sub(frame, &mut subscriptions, dst).await? {
+        return Ok(());
+    }
+
+    while subscriptions.len() > 0 {
+        select! {
+            Some((channel, msg)) = subscriptions.channels.next() => {
+                dst.write_frame(&make_frame(&[b"message", channel.as_bytes(), &msg]))
+                    .await?;
+            }
+            Some((pattern, (channel, msg))) = subscriptions.patterns.next() => {
+                let frame = make_frame(&[b"pmessage", pattern.as_bytes(), channel.as_bytes(), &msg]);
+                dst.write_frame(&frame).await?;
+            }
+            res = dst.read_frame() => {
+                let frame = match res? {
+                    Some(frame) => frame,
+                    // This happens if the remote client has disconnected.
+                    None => return Ok(()),
+                };
+
+                if !handle_command(db, frame, &mut subscriptions, dst, session).await? {
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Handle a command received while in subscriber mode.
+///
+/// Returns `false` if the client asked to close the connection.
+async fn handle_command(
+    db: &Db,
+    frame: Frame,
+    subscriptions: &mut Subscriptions,
+    dst: &mut Connection<impl Socket>,
+    session: &mut Session,
+) -> crate::Result<bool> {
+    // With RESP3, messages are pushed out-of-band, so the connection can keep
+    // running regular commands while subscribed.
+    if dst.protocol() >= 3 && !is_pub_sub(&super::name(&frame)) {
+        Box::pin(super::run(frame, db, dst, session)).await?;
+        return Ok(true);
+    }
+
+    // The same checks as in `run` apply.
+    let command = match super::admit(db, &frame, session) {
+        Ok(command) => command,
+        Err(err) => {
+            dst.write_frame(&err).await?;
+            return Ok(true);
+        }
+    };
+    let name = super::name(&frame);
+    let start = Instant::now();
+
+    let open = handle_pub_sub(frame, subscriptions, dst).await?;
+    super::record(db, &name, Some(start.elapsed()), command, session);
+
+    Ok(open)
+}
+
+/// Handle a command accepted in RESP2 subscriber mode, or reject it.
+///
+/// Returns `false` if the client asked to close the connection.
+async fn handle_pub_sub(
+    frame: Frame,
+    subscriptions: &mut Subscriptions,
+    dst: &mut Connection<impl Socket>,
+) -> crate::Result<bool> {
+    let (name, args) = match parse_args(frame) {
+        Ok(command) => command,
+        Err(err) => {
+            dst.write_frame(&Frame::Error(format!("ERR {}", err)))
+                .await?;
+            return Ok(true);
+        }
+    };
+
+    match &name[..] {
+        "subscribe" | "psubscribe" if args.is_empty() => {
+            let response = Frame::Error(format!(
+                "ERR wrong number of arguments for '{}' command",
+                name
+            ));
+            dst.write_frame(&response).await?;
+        }
+        "subscribe" => {
+            for channel in args {
+                subscriptions.subscribe(channel.clone());
+
+                let response = make_count_frame("subscribe", Some(&channel), subscriptions.len());
+                dst.write_frame(&response).await?;
+            }
+        }
+        "psubscribe" => {
+            for pattern in args {
+                subscriptions.psubscribe(pattern.clone());
+
+                let response = make_count_frame("psubscribe", Some(&pattern), subscriptions.len());
+                dst.write_frame(&response).await?;
+            }
+        }
+        "unsubscribe" => {
+            // If no channels are specified, this requests unsubscribing from
+            // **all** channels.
+            let channels = match args.is_empty() {
+                true => subscriptions.channels.keys().cloned().collect(),
+                false => args,
+            };
+
+            if channels.is_empty() {
+                let response = make_count_frame("unsubscribe", None, subscriptions.len());
+                dst.write_frame(&response).await?;
+            }
+
+            for channel in channels {
+                subscriptions.unsubscribe(&channel);
+
+                let response = make_count_frame("unsubscribe", Some(&channel), subscriptions.len());
+                dst.write_frame(&response).await?;
+            }
+        }
+        "punsubscribe" => {
+            let patterns = match args.is_empty() {
+                true => subscriptions.patterns.keys().cloned().collect(),
+                false => args,
+            };
+
+            if patterns.is_empty() {
+                let response = make_count_frame("punsubscribe", None, subscriptions.len());
+                dst.write_frame(&response).await?;
+            }
+
+            for pattern in patterns {
+                subscriptions.punsubscribe(&pattern);
+
+                let response =
+                    make_count_frame("punsubscribe", Some(&pattern), subscriptions.len());
+                dst.write_frame(&response).await?;
+            }
+        }
+        "ping" => {
+            let message = args.into_iter().next().unwrap_or_default();
+            dst.write_frame(&make_frame(&[b"pong", message.as_bytes()]))
+                .await?;
+        }
+        "quit" => {
+            dst.write_frame(&Frame::Simple("OK".to_string())).await?;
+            return Ok(false);
+        }
+        _ => {
+            let response = Frame::Error(format!(
+                "ERR Can't execute '{}': only (P)SUBSCRIBE / (P)UNSUBSCRIBE / PING / QUIT are allowed in this context",
+                name
+            ));
+            dst.write_frame(&response).await?;
+        }
+    }
+
+    Ok(true)
+}
+
+/// The lowercase name and the arguments of a command.
+fn parse_args(frame: Frame) -> Result<(String, Vec<String>), ParseError> {
+    let mut parse = Parse::new(frame)?;
+    let name = parse.next_string()?.to_lowercase();
+
+    let mut args = vec![];
+    while parse.remaining() > 0 {
+        args.push(parse.next_string()?);
+    }
+
+    Ok((name, args))
+}
+
+/// Returns `true` for the commands accepted in RESP2 subscriber mode.
+fn is_pub_sub(name: &[u8]) -> bool {
+    matches!(
+        name,
+        b"subscribe" | b"psubscribe" | b"unsubscribe" | b"punsubscribe" | b"ping" | b"quit"
+    )
+}
+
+/// Turn a broadcast receiver into a stream, skipping over messages lost
+/// because the subscriber lagged behind.
+fn into_stream<T: Clone + Send + 'static>(mut rx: broadcast::Receiver<T>) -> Messages<T> {
+    Box::pin(async_stream::stream! {
+        loop {
+            match rx.recv().await {
+                Ok(msg) => yield msg,
+                // If we lagged in consuming messages, just resume.
+                Err(broadcast::error::RecvError::Lagged(_)) => {}
+                Err(_) => break,
+            }
+        }
+    })
+}
+
+/// Creates the response to a (un)subscribe request: the kind of request, the
+/// channel or pattern concerned and the number of remaining subscriptions.
+///
+/// Pub/sub replies are push frames, which RESP2 connections receive as arrays.
+fn make_count_frame(kind: &'static str, name: Option<&str>, count: usize) -> Frame {
+    Frame::Push(vec![
+        Frame::Bulk(Bytes::from_static(kind.as_bytes())),
+        name.map_or(Frame::Null, |name| {
+            Frame::Bulk(Bytes::copy_from_slice(name.as_bytes()))
+        }),
+        Frame::Integer(count as i64),
+    ])
+}
+
+/// Creates a push frame of bulk strings.
+fn make_frame(parts: &[&[u8]]) -> Frame {
+    let parts = parts
+        .iter()
+        .map(|part| Frame::Bulk(Bytes::copy_from_slice(part)))
+        .collect();
+
+    Frame::Push(parts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::command;
+    use crate::server;
+    use tokio::net::TcpStream;
+
+    async fn connect(db: &Db) -> Connection {
+        let addr = server::spawn(db).await;
+        Connection::new(TcpStream::connect(addr).await.unwrap())
+    }
+
+    async fn call(connection: &mut Connection, args: &[&str]) -> Frame {
+        connection.write_frame(&command(args)).await.unwrap();
+        read(connection).await
+    }
+
+    async fn read(connection: &mut Connection) -> Frame {
+        connection.read_frame().await.unwrap().unwrap()
+    }
+
+    /// A RESP2 pub/sub reply, e.g. `["subscribe", "news", 1]`.
+    fn reply(kind: &str, name: &str, count: i64) -> Frame {
+        Frame::Array(vec![
+            Frame::Bulk(Bytes::copy_from_slice(kind.as_bytes())),
+            Frame::Bulk(Bytes::copy_from_slice(name.as_bytes())),
+            Frame::Integer(count),
+        ])
+    }
+
+    #[tokio::test]
+    async fn subscriptions_are_counted() {
+        let db = Db::new();
+        let mut client = connect(&db).await;
+
+        client
+            .write_frame(&command(&["SUBSCRIBE", "a", "b"]))
+            .await
+            .unwrap();
+        assert_eq!(read(&mut client).await, reply("subscribe", "a", 1));
+        assert_eq!(read(&mut client).await, reply("subscribe", "b", 2));
+        assert_eq!(
+            call(&mut client, &["PSUBSCRIBE", "n*"]).await,
+            reply("psubscribe", "n*", 3)
+        );
+        assert_eq!(
+            call(&mut client, &["UNSUBSCRIBE", "a"]).await,
+            reply("unsubscribe", "a", 2)
+        );
+        assert_eq!(
+            call(&mut client, &["PUNSUBSCRIBE"]).await,
+            reply("punsubscribe", "n*", 1)
+        );
+        assert_eq!(
+            call(&mut client, &["UNSUBSCRIBE"]).await,
+            reply("unsubscribe", "b", 0)
+        );
+
+        // Without subscriptions left, the connection is back to normal.
+        assert_eq!(
+            call(&mut client, &["PING"]).await,
+            Frame::Simple("PONG".into())
+        );
+        assert_eq!(db.publish("b", "x".into()), 0);
+    }
+
+    #[tokio::test]
+    async fn messages_are_delivered() {
+        let db = Db::new();
+        let mut client = connect(&db).await;
+
+        assert_eq!(
+            call(&mut client, &["SUBSCRIBE", "news"]).await,
+            reply("subscribe", "news", 1)
+        );
+        assert_eq!(
+            call(&mut client, &["PSUBSCRIBE", "n*"]).await,
+            reply("psubscribe", "n*", 2)
+        );
+
+        assert_eq!(db.publish("news", "hi".into()), 2);
+        let mut received = vec![read(&mut client).await, read(&mut client).await];
+        received.sort_by_key(|frame| frame.to_string());
+
+        assert_eq!(
+            received,
+            [
+                command(&["message", "news", "hi"]),
+                command(&["pmessage", "n*", "news", "hi"]),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn other_commands_are_rejected() {
+        let db = Db::new();
+        let mut client = connect(&db).await;
+
+        call(&mut client, &["SUBSCRIBE", "news"]).await;
+        assert_eq!(
+            call(&mut client, &["GET", "a"]).await,
+            Frame::Error(
+                "ERR Can't execute 'get': only (P)SUBSCRIBE / (P)UNSUBSCRIBE / PING / QUIT are allowed in this context"
+                    .into()
+            )
+        );
+
+        // Malformed commands are answered as well, keeping the connection.
+        client
+            .write_frame(&command(&[&b"SUBSCRIBE"[..], b"\xff"]))
+            .await
+            .unwrap();
+        assert!(matches!(read(&mut client).await, Frame::Error(_)));
+        client.write_frame(&Frame::Integer(1)).await.unwrap();
+        assert!(matches!(read(&mut client).await, Frame::Error(_)));
+
+        assert_eq!(call(&mut client, &["PING"]).await, command(&["pong", ""]));
+
+        // Commands run in subscriber mode count in the statistics, once the
+        // next one was read.
+        call(&mut client, &["PUNSUBSCRIBE"]).await;
+        let stats = db
+            .slowlog()
+            .commands()
+            .any(|(name, stats)| name == "ping" && stats.calls == 1);
+        assert!(stats);
+    }
+}
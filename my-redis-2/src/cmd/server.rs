@@ -0,0 +1,440 @@
+use super::{reply, Session};
+use crate::acl::{self, DEFAULT_USER};
+use crate::{
+    config, replication, slowlog, snapshot, Connection, Db, Frame, Parse, ParseError, Socket,
+};
+
+use bytes::Bytes;
+use std::fmt::Write;
+use std::process;
+
+/// Sections of `INFO`, in the order they are listed.
+const SECTIONS: [&str; 8] = [
+    "server",
+    "clients",
+    "memory",
+    "persistence",
+    "stats",
+    "replication",
+    "commandstats",
+    "keyspace",
+];
+
+/// Sections listed by default, leaving out the longer ones.
+const DEFAULT_SECTIONS: [&str; 7] = [
+    "server",
+    "clients",
+    "memory",
+    "persistence",
+    "stats",
+    "replication",
+    "keyspace",
+];
+
+/// `BGREWRITEAOF`
+///
+/// Compacts the append-only file in the background.
+pub(crate) fn bgrewriteaof(db: &Db, _parse: &mut Parse) -> Result<Frame, ParseError> {
+    let mut aof = db.aof();
+
+    let aof = match aof.as_mut() {
+        Some(aof) => aof,
+        None => return Ok(Frame::Error("ERR append only file is disabled".to_string())),
+    };
+
+    Ok(match aof.rewrite(db) {
+        true => Frame::Simple("Background append only file rewriting started".to_string()),
+        false => Frame::Error(
+            "ERR Background append only file rewriting already in progress".to_string(),
+        ),
+    })
+}
+
+/// `SAVE`
+///
+/// Writes a snapshot, blocking until it is on disk.
+pub(crate) fn save(db: &Db, _parse: &mut Parse) -> Result<Frame, ParseError> {
+    Ok(match snapshot::save(db) {
+        Ok(()) => Frame::Simple("OK".to_string()),
+        Err(err) => Frame::Error(format!("ERR {}", err)),
+    })
+}
+
+/// `BGSAVE`
+pub(crate) fn bgsave(db: &Db, _parse: &mut Parse) -> Result<Frame, ParseError> {
+    Ok(match snapshot::bgsave(db) {
+        true => Frame::Simple("Background saving started".to_string()),
+        false => Frame::Error("ERR Background save already in progress".to_string()),
+    })
+}
+
+/// `LASTSAVE`
+///
+/// Unix time, in seconds, of the last successful snapshot.
+pub(crate) fn lastsave(db: &Db, _parse: &mut Parse) -> Result<Frame, ParseError> {
+    Ok(Frame::Integer(snapshot::last_save(db).as_secs() as i64))
+}
+
+/// `INFO [section ...]`
+///
+/// Lists the state of the server as `field:value` lines, under a `# Section`
+/// header per section. `INFO all` lists `commandstats` on top of the default
+/// sections.
+pub(crate) fn info(db: &Db, parse: &mut Parse) -> Result<Frame, ParseError> {
+    // Sections which do not exist are left out, without defaulting.
+    let mut sections = vec![];
+    if parse.remaining() == 0 {
+        sections.extend(DEFAULT_SECTIONS);
+    }
+    while parse.remaining() > 0 {
+        let section = parse.next_string()?.to_lowercase();
+        match &section[..] {
+            "all" | "everything" => sections.extend(SECTIONS),
+            "default" => sections.extend(DEFAULT_SECTIONS),
+            section => sections.extend(SECTIONS.iter().find(|s| **s == section)),
+        }
+    }
+
+    let mut info = String::new();
+    for section in SECTIONS.iter().filter(|s| sections.contains(s)) {
+        if !info.is_empty() {
+            info.push_str("\r\n");
+        }
+
+        let title = section[..1].to_uppercase() + &section[1..];
+        let _ = write!(info, "# {}\r\n", title);
+        if *section == "commandstats" {
+            for (name, stats) in db.slowlog().commands() {
+                let _ = write!(
+                    info,
+                    "cmdstat_{}:calls={},usec={},usec_per_call={:.2}\r\n",
+                    name,
+                    stats.calls,
+                    stats.usec,
+                    stats.usec as f64 / stats.calls as f64
+                );
+            }
+        }
+        if *section == "keyspace" {
+            // One line per database holding keys.
+            for index in 0..db.databases() {
+                let selected = db.select(index).expect("database index in range");
+                match selected.key_counts() {
+                    (0, _) => {}
+                    (keys, expires) => {
+                        let _ = write!(info, "db{}:keys={},expires={}\r\n", index, keys, expires);
+                    }
+                }
+            }
+        }
+        for (field, value) in fields(db, section) {
+            let _ = write!(info, "{}:{}\r\n", field, value);
+        }
+    }
+
+    Ok(Frame::Bulk(Bytes::from(info)))
+}
+
+/// The fields of an `INFO` section.
+fn fields(db: &Db, section: &str) -> Vec<(&'static str, String)> {
+    match section {
+        "server" => {
+            let uptime = db.stats().uptime.as_secs();
+            vec![
+                ("redis_version", env!("CARGO_PKG_VERSION").to_string()),
+                ("process_id", process::id().to_string()),
+                ("uptime_in_seconds", uptime.to_string()),
+                ("uptime_in_days", (uptime / 86400).to_string()),
+            ]
+        }
+        "clients" => vec![("connected_clients", db.clients().len().to_string())],
+        "memory" => {
+            let (max_memory, policy) = db.max_memory();
+            vec![
+                ("used_memory", db.used_memory().to_string()),
+                ("used_memory_human", human(db.used_memory())),
+                ("maxmemory", max_memory.to_string()),
+                ("maxmemory_human", human(max_memory)),
+                ("maxmemory_policy", policy.to_string()),
+            ]
+        }
+        "persistence" => vec![
+            ("aof_enabled", (db.aof().is_some() as u8).to_string()),
+            ("rdb_changes_since_last_save", db.changes().to_string()),
+            (
+                "rdb_last_save_time",
+                snapshot::last_save(db).as_secs().to_string(),
+            ),
+        ],
+        "stats" => {
+            let stats = db.stats();
+            let clients = db.clients();
+            vec![
+                (
+                    "total_connections_received",
+                    clients.connections_received().to_string(),
+                ),
+                (
+                    "total_commands_processed",
+                    clients.commands_processed().to_string(),
+                ),
+                ("keyspace_hits", stats.keyspace_hits.to_string()),
+                ("keyspace_misses", stats.keyspace_misses.to_string()),
+                ("evicted_keys", stats.evicted_keys.to_string()),
+            ]
+        }
+        "replication" => replication::info(db),
+        _ => vec![],
+    }
+}
+
+/// `SLOWLOG GET [count]`, `SLOWLOG LEN` or `SLOWLOG RESET`
+///
+/// Entries are listed most recent first, 10 by default or all of them if
+/// `count` is negative.
+pub(crate) fn slowlog(db: &Db, parse: &mut Parse) -> Result<Frame, ParseError> {
+    let subcommand = parse.next_string()?.to_lowercase();
+
+    Ok(match &subcommand[..] {
+        "get" => {
+            let count = match parse.next_int() {
+                Ok(count) => usize::try_from(count).unwrap_or(usize::MAX),
+                Err(ParseError::EndOfStream) => 10,
+                Err(err) => return Err(err),
+            };
+
+            let entries = db.slowlog().get(count);
+            Frame::Array(entries.into_iter().map(entry).collect())
+        }
+        "len" => Frame::Integer(db.slowlog().len() as i64),
+        "reset" => {
+            db.slowlog().reset();
+            Frame::Simple("OK".to_string())
+        }
+        _ => {
+            return Err(format!(
+                "unknown subcommand '{}'. Try SLOWLOG GET, LEN or RESET.",
+                subcommand
+            )
+            .into())
+        }
+    })
+}
+
+/// `CONFIG GET pattern [pattern ...]` or `CONFIG SET name value [name value
+/// ...]`
+///
+/// Lists the settings whose name matches any of the glob-style patterns, or
+/// changes settings, see `config`.
+pub(crate) fn config(db: &Db, parse: &mut Parse) -> Result<Frame, ParseError> {
+    let subcommand = parse.next_string()?.to_lowercase();
+    let bulk = |s: &str| Frame::Bulk(Bytes::copy_from_slice(s.as_bytes()));
+
+    Ok(match &subcommand[..] {
+        "get" => {
+            let mut patterns = vec![parse.next_string()?];
+            while parse.remaining() > 0 {
+                patterns.push(parse.next_string()?);
+            }
+
+            let settings = config::get(db, &patterns).into_iter();
+            Frame::Map(
+                settings
+                    .map(|(name, value)| (bulk(name), bulk(&value)))
+                    .collect(),
+            )
+        }
+        "set" => {
+            let mut settings = vec![(parse.next_string()?, parse.next_string()?)];
+            while parse.remaining() > 0 {
+                settings.push((parse.next_string()?, parse.next_string()?));
+            }
+
+            config::set(db, &settings)?;
+            Frame::Simple("OK".to_string())
+        }
+        _ => {
+            return Err(format!(
+                "unknown subcommand '{}'. Try CONFIG GET or SET.",
+                subcommand
+            )
+            .into())
+        }
+    })
+}
+
+/// A slow log entry, as listed by `SLOWLOG GET`.
+fn entry(entry: slowlog::Entry) -> Frame {
+    Frame::Array(vec![
+        Frame::Integer(entry.id as i64),
+        Frame::Integer(entry.time.as_secs() as i64),
+        Frame::Integer(entry.duration.as_micros() as i64),
+        Frame::Array(entry.args.into_iter().map(Frame::Bulk).collect()),
+        Frame::Bulk(Bytes::from(entry.addr)),
+        Frame::Bulk(Bytes::from(entry.name)),
+    ])
+}
+
+/// `bytes` with a unit, e.g. `1.50M`.
+fn human(bytes: usize) -> String {
+    let units = ["B", "K", "M", "G", "T"];
+
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < units.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+
+    match unit {
+        0 => format!("{}B", bytes),
+        _ => format!("{:.2}{}", value, units[unit]),
+    }
+}
+
+/// `ACL SETUSER name [rule ...]`, `ACL DELUSER name [name ...]`, `ACL LIST`,
+/// `ACL USERS`, `ACL WHOAMI`, `ACL CAT [category]`, `ACL LOAD` or `ACL SAVE`
+///
+/// Manages the users, see `acl` for the rules.
+pub(super) fn acl(db: &Db, parse: &mut Parse, session: &Session) -> Result<Frame, ParseError> {
+    let subcommand = parse.next_string()?.to_lowercase();
+    let bulk = |s: &str| Frame::Bulk(Bytes::copy_from_slice(s.as_bytes()));
+
+    Ok(match &subcommand[..] {
+        "setuser" => {
+            let name = parse.next_string()?;
+            let mut rules = vec![];
+            while parse.remaining() > 0 {
+                rules.push(parse.next_string()?);
+            }
+
+            db.acl()
+                .set_user(&name, &rules)
+                .map_err(|err| format!("Error in ACL SETUSER modifier: {}", err))?;
+            Frame::Simple("OK".to_string())
+        }
+        "deluser" => {
+            let mut names = vec![parse.next_string()?];
+            while parse.remaining() > 0 {
+                names.push(parse.next_string()?);
+            }
+
+            if names.iter().any(|name| name == DEFAULT_USER) {
+                return Err("The 'default' user cannot be removed".into());
+            }
+
+            let mut acl = db.acl();
+            let removed = names.iter().filter(|name| acl.remove_user(name)).count();
+            Frame::Integer(removed as i64)
+        }
+        "list" => Frame::Array(db.acl().list().iter().map(|line| bulk(line)).collect()),
+        "users" => Frame::Array(db.acl().users().map(bulk).collect()),
+        "whoami" => bulk(session.user.as_deref().unwrap_or(DEFAULT_USER)),
+        "cat" => match parse.remaining() {
+            0 => Frame::Array(acl::categories().map(bulk).collect()),
+            _ => {
+                let category = parse.next_string()?.to_lowercase();
+                if !acl::categories().any(|c| c == category) {
+                    return Err(format!("Unknown category '{}'", category).into());
+                }
+                Frame::Array(acl::commands_in(&category).map(bulk).collect())
+            }
+        },
+        "load" => {
+            db.acl().reload()?;
+            Frame::Simple("OK".to_string())
+        }
+        "save" => {
+            db.acl().save()?;
+            Frame::Simple("OK".to_string())
+        }
+        _ => {
+            return Err(format!(
+                "unknown subcommand '{}'. Try ACL SETUSER, DELUSER, LIST, USERS, WHOAMI, CAT, \
+                 LOAD or SAVE.",
+                subcommand
+            )
+            .into())
+        }
+    })
+}
+
+/// `REPLICAOF host port` or `REPLICAOF NO ONE`
+///
+/// Follows the leader at `host:port`, or stops following any.
+pub(crate) fn replicaof(db: &Db, parse: &mut Parse) -> Result<Frame, ParseError> {
+    let host = parse.next_string()?;
+    let port = parse.next_string()?;
+
+    if host.eq_ignore_ascii_case("no") && port.eq_ignore_ascii_case("one") {
+        replication::promote(db);
+        config::set_leader(db, None);
+        return Ok(Frame::Simple("OK".to_string()));
+    }
+
+    let port = match port.parse() {
+        Ok(port) => port,
+        Err(_) => return Ok(Frame::Error("ERR Invalid master port".to_string())),
+    };
+
+    if replication::leader(db) == Some((host.clone(), port)) {
+        return Ok(Frame::Simple(
+            "OK Already connected to specified master".to_string(),
+        ));
+    }
+
+    config::set_leader(db, Some((host.clone(), port)));
+    replication::follow(db, host, port);
+
+    Ok(Frame::Simple("OK".to_string()))
+}
+
+/// `PSYNC replid offset`
+///
+/// Sent by followers, the connection then carries the replication stream, see
+/// `replication::serve`. `PSYNC ? -1` asks for a full sync.
+pub(super) async fn psync(
+    db: &Db,
+    frame: Frame,
+    dst: &mut Connection<impl Socket>,
+) -> crate::Result<()> {
+    let args = Parse::new(frame).and_then(|mut parse| {
+        parse.next_string()?;
+        let replid = parse.next_string()?;
+        let offset = parse.next_string()?;
+        parse.finish()?;
+        Ok((replid, offset))
+    });
+    let (replid, offset) = match args {
+        Ok(args) => args,
+        Err(err) => return Ok(dst.write_frame(&reply("psync", Err(err))).await?),
+    };
+
+    // A negative offset also asks for a full sync.
+    replication::serve(db, &replid, offset.parse().ok(), dst).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::apply;
+    use super::*;
+    use crate::client::command;
+
+    fn info(db: &Db, args: &[&str]) -> String {
+        let args: Vec<_> = ["INFO"].iter().chain(args).copied().collect();
+        match apply(command(&args), db) {
+            Frame::Bulk(data) => String::from_utf8(data.to_vec()).unwrap(),
+            frame => panic!("unexpected reply {:?}", frame),
+        }
+    }
+
+    #[tokio::test]
+    async fn info_lists_the_sections_asked_for() {
+        let db = Db::new();
+
+        assert!(info(&db, &[]).starts_with("# Server\r\n"));
+        assert!(info(&db, &["replication"]).starts_with("# Replication\r\n"));
+        assert!(!info(&db, &["bogus", "replication"]).contains("# Server"));
+        assert_eq!(info(&db, &["bogus"]), "");
+    }
+}
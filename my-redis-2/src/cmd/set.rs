@@ -0,0 +1,52 @@
+use super::{keys_arg, reply_with, scan_args, scan_reply};
+use crate::{Db, Frame, Parse, ParseError};
+
+use bytes::Bytes;
+
+/// `SADD key member [member ...]`
+pub(crate) fn sadd(db: &Db, parse: &mut Parse) -> Result<Frame, ParseError> {
+    let key = parse.next_string()?;
+
+    let mut members = vec![parse.next_bytes()?];
+    while parse.remaining() > 0 {
+        members.push(parse.next_bytes()?);
+    }
+
+    reply_with(db.sadd(&key, members), |added| Frame::Integer(added as i64))
+}
+
+/// `SMEMBERS key`
+pub(crate) fn smembers(db: &Db, parse: &mut Parse) -> Result<Frame, ParseError> {
+    let key = parse.next_string()?;
+
+    reply_with(db.smembers(&key), set)
+}
+
+/// `SINTER key [key ...]`
+pub(crate) fn sinter(db: &Db, parse: &mut Parse) -> Result<Frame, ParseError> {
+    let keys = keys_arg(parse)?;
+
+    reply_with(db.sinter(&keys), set)
+}
+
+/// `SUNION key [key ...]`
+pub(crate) fn sunion(db: &Db, parse: &mut Parse) -> Result<Frame, ParseError> {
+    let keys = keys_arg(parse)?;
+
+    reply_with(db.sunion(&keys), set)
+}
+
+fn set(members: Vec<Bytes>) -> Frame {
+    Frame::Set(members.into_iter().map(Frame::Bulk).collect())
+}
+
+/// `SSCAN key cursor [MATCH pattern] [COUNT count]`
+pub(crate) fn sscan(db: &Db, parse: &mut Parse) -> Result<Frame, ParseError> {
+    let key = parse.next_string()?;
+    let args = scan_args(parse, false)?;
+
+    let page = db.sscan(&key, args.cursor, args.count, args.pattern.as_deref());
+    reply_with(page, |(cursor, members)| {
+        scan_reply(cursor, members.into_iter().map(Frame::Bulk).collect())
+    })
+}
@@ -0,0 +1,682 @@
+use super::{apply, reply, reply_with};
+use crate::db::{unix_time, Claim, Fields, StreamId, XAddId};
+use crate::{Connection, Db, Frame, Parse, ParseError, Socket};
+
+use bytes::Bytes;
+use std::collections::BTreeMap;
+use std::ops::Bound;
+use std::time::Duration;
+use tokio::select;
+use tokio::time::{self, Instant};
+
+const INVALID_ID: &str = "Invalid stream ID specified as stream command argument";
+
+/// The arguments of `XREAD` and `XREADGROUP`.
+#[derive(Debug)]
+struct Read {
+    /// The group and consumer of `XREADGROUP`.
+    group: Option<(String, String)>,
+    count: usize,
+
+    /// How long to block for, zero meaning forever.
+    block: Option<Duration>,
+    noack: bool,
+    streams: Vec<(String, ReadId)>,
+}
+
+/// Where to read a stream from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ReadId {
+    /// The entries after the given ID.
+    After(StreamId),
+
+    /// `$`, the entries added from now on.
+    Last,
+
+    /// `>`, the entries never delivered to the group.
+    New,
+}
+
+/// `XADD key [MAXLEN [=|~] threshold] *|id field value [field value ...]`
+///
+/// Trimming is always exact, `~` is accepted for compatibility.
+pub(crate) fn xadd(db: &Db, parse: &mut Parse) -> Result<Frame, ParseError> {
+    let key = parse.next_string()?;
+
+    let mut arg = parse.next_string()?;
+    let mut max_len = None;
+    if arg.eq_ignore_ascii_case("maxlen") {
+        let mut threshold = parse.next_string()?;
+        if threshold == "=" || threshold == "~" {
+            threshold = parse.next_string()?;
+        }
+
+        max_len = Some(
+            threshold
+                .parse()
+                .map_err(|_| "value is not an integer or out of range")?,
+        );
+        arg = parse.next_string()?;
+    }
+
+    let id = match arg.split_once('-') {
+        _ if arg == "*" => XAddId::Auto,
+        Some((ms, "*")) => XAddId::Seq(ms.parse().map_err(|_| INVALID_ID)?),
+        _ => XAddId::Exact(StreamId::parse(&arg, 0).ok_or(INVALID_ID)?),
+    };
+
+    let mut fields = vec![(parse.next_bytes()?, parse.next_bytes()?)];
+    while parse.remaining() > 0 {
+        fields.push((parse.next_bytes()?, parse.next_bytes()?));
+    }
+
+    reply_with(db.xadd(&key, id, fields, max_len), |id| {
+        Frame::Bulk(Bytes::from(id.to_string()))
+    })
+}
+
+/// `XLEN key`
+pub(crate) fn xlen(db: &Db, parse: &mut Parse) -> Result<Frame, ParseError> {
+    let key = parse.next_string()?;
+
+    reply_with(db.xlen(&key), |len| Frame::Integer(len as i64))
+}
+
+/// `XRANGE key start end [COUNT count]`
+///
+/// `-` and `+` stand for the smallest and greatest IDs, and an ID prefixed by
+/// `(` is excluded from the range.
+pub(crate) fn xrange(db: &Db, parse: &mut Parse) -> Result<Frame, ParseError> {
+    let key = parse.next_string()?;
+    let start = start_arg(parse)?;
+    let end = end_arg(parse)?;
+
+    range(db, parse, &key, start, end, false)
+}
+
+/// `XREVRANGE key end start [COUNT count]`
+pub(crate) fn xrevrange(db: &Db, parse: &mut Parse) -> Result<Frame, ParseError> {
+    let key = parse.next_string()?;
+    let end = end_arg(parse)?;
+    let start = start_arg(parse)?;
+
+    range(db, parse, &key, start, end, true)
+}
+
+fn range(
+    db: &Db,
+    parse: &mut Parse,
+    key: &str,
+    start: Bound<StreamId>,
+    end: Bound<StreamId>,
+    rev: bool,
+) -> Result<Frame, ParseError> {
+    let count = match parse.remaining() {
+        0 => usize::MAX,
+        _ => count_arg(parse)?,
+    };
+
+    reply_with(db.xrange(key, start, end, count, rev), |entries| {
+        Frame::Array(
+            entries
+                .into_iter()
+                .map(|(id, fields)| entry(id, Some(fields)))
+                .collect(),
+        )
+    })
+}
+
+/// `XREAD [COUNT count] [BLOCK milliseconds] STREAMS key [key ...] id [id ...]`
+///
+/// This handler never blocks, see `read` for the blocking variant.
+pub(crate) fn xread(db: &Db, parse: &mut Parse) -> Result<Frame, ParseError> {
+    let read = read_args(parse, false)?;
+    Ok(read_once(db, &read))
+}
+
+/// `XREADGROUP GROUP group consumer [COUNT count] [BLOCK milliseconds] [NOACK]
+/// STREAMS key [key ...] id [id ...]`
+///
+/// `>` reads the entries never delivered to the group, any other ID the
+/// entries pending for the consumer.
+pub(crate) fn xreadgroup(db: &Db, parse: &mut Parse) -> Result<Frame, ParseError> {
+    let read = read_args(parse, true)?;
+    Ok(read_once(db, &read))
+}
+
+/// Run `XREAD` or `XREADGROUP`, blocking until an entry is added to one of the
+/// streams if there is nothing to read yet and `BLOCK` was passed.
+pub(super) async fn read(
+    db: &Db,
+    frame: Frame,
+    dst: &mut Connection<impl Socket>,
+) -> crate::Result<()> {
+    let name = String::from_utf8_lossy(&super::name(&frame)).into_owned();
+
+    let args = Parse::new(frame.clone()).and_then(|mut parse| {
+        parse.next_string()?;
+        let read = read_args(&mut parse, name == "xreadgroup")?;
+        parse.finish()?;
+        Ok(read)
+    });
+    let read = match args {
+        Ok(read) => read,
+        Err(err) => {
+            return dst
+                .write_frame(&reply(&name, Err(err)))
+                .await
+                .map_err(Into::into)
+        }
+    };
+
+    let timeout = match read.block {
+        Some(timeout) => timeout,
+        None => return dst.write_frame(&apply(frame, db)).await.map_err(Into::into),
+    };
+
+    // Armed before reading, so an entry added in between is not missed.
+    let keys = read.streams.iter().map(|(key, _)| key.clone()).collect();
+    let watch = db.watch_streams(keys);
+
+    // `$` stands for the last ID at the time of the call, not of each retry.
+    let frame = match resolve_last(db, frame, &read) {
+        Ok(frame) => frame,
+        Err(err) => return dst.write_frame(&err).await.map_err(Into::into),
+    };
+
+    // A timeout too long to be represented never elapses, as zero.
+    let deadline = match timeout.is_zero() {
+        true => None,
+        false => Instant::now().checked_add(timeout),
+    };
+
+    loop {
+        let response = apply(frame.clone(), db);
+        if !matches!(response, Frame::NullArray) {
+            dst.write_frame(&response).await?;
+            return Ok(());
+        }
+
+        select! {
+            _ = watch.changed() => {}
+            _ = time::sleep_until(deadline.unwrap_or_else(Instant::now)), if deadline.is_some() => {
+                dst.write_frame(&Frame::NullArray).await?;
+                return Ok(());
+            }
+            _ = dst.closed() => return Ok(()),
+        }
+    }
+}
+
+/// `XGROUP CREATE key group id|$ [MKSTREAM]`
+pub(crate) fn xgroup(db: &Db, parse: &mut Parse) -> Result<Frame, ParseError> {
+    let subcommand = parse.next_string()?;
+    if !subcommand.eq_ignore_ascii_case("create") {
+        return Ok(Frame::Error(format!(
+            "ERR unknown subcommand '{}'. Try XGROUP HELP.",
+            subcommand
+        )));
+    }
+
+    let key = parse.next_string()?;
+    let group = parse.next_string()?;
+    let id = match &parse.next_string()?[..] {
+        "$" => None,
+        id => Some(StreamId::parse(id, 0).ok_or(INVALID_ID)?),
+    };
+
+    let mkstream = match parse.remaining() {
+        0 => false,
+        _ if parse.next_string()?.eq_ignore_ascii_case("mkstream") => true,
+        _ => return Err("syntax error".into()),
+    };
+
+    reply_with(db.xgroup_create(&key, &group, id, mkstream), |()| {
+        Frame::Simple("OK".to_string())
+    })
+}
+
+/// `XACK key group id [id ...]`
+pub(crate) fn xack(db: &Db, parse: &mut Parse) -> Result<Frame, ParseError> {
+    let key = parse.next_string()?;
+    let group = parse.next_string()?;
+
+    let mut ids = vec![id_arg(parse)?];
+    while parse.remaining() > 0 {
+        ids.push(id_arg(parse)?);
+    }
+
+    reply_with(db.xack(&key, &group, &ids), |acked| {
+        Frame::Integer(acked as i64)
+    })
+}
+
+/// `XPENDING key group [start end count [consumer]]`
+///
+/// Without a range, replies with a summary: the number of pending entries,
+/// the smallest and greatest of their IDs and the number pending for each
+/// consumer.
+pub(crate) fn xpending(db: &Db, parse: &mut Parse) -> Result<Frame, ParseError> {
+    let key = parse.next_string()?;
+    let group = parse.next_string()?;
+
+    if parse.remaining() == 0 {
+        let all = (Bound::Unbounded, Bound::Unbounded);
+
+        return reply_with(
+            db.xpending(&key, &group, all, usize::MAX, None),
+            |pending| {
+                let (first, last) = match (pending.first(), pending.last()) {
+                    (Some(first), Some(last)) => (id_frame(first.0), id_frame(last.0)),
+                    _ => {
+                        return Frame::Array(vec![
+                            Frame::Integer(0),
+                            Frame::Null,
+                            Frame::Null,
+                            Frame::NullArray,
+                        ])
+                    }
+                };
+
+                let mut consumers = BTreeMap::new();
+                for (_, consumer, _, _) in &pending {
+                    *consumers.entry(consumer.clone()).or_insert(0) += 1;
+                }
+
+                let consumers = consumers
+                    .into_iter()
+                    .map(|(consumer, count): (String, u64)| {
+                        Frame::Array(vec![
+                            Frame::Bulk(Bytes::from(consumer)),
+                            Frame::Bulk(Bytes::from(count.to_string())),
+                        ])
+                    })
+                    .collect();
+
+                Frame::Array(vec![
+                    Frame::Integer(pending.len() as i64),
+                    first,
+                    last,
+                    Frame::Array(consumers),
+                ])
+            },
+        );
+    }
+
+    let start = start_arg(parse)?;
+    let end = end_arg(parse)?;
+    let count = count_value(parse.next_int()?)?;
+    let consumer = match parse.remaining() {
+        0 => None,
+        _ => Some(parse.next_string()?),
+    };
+
+    reply_with(
+        db.xpending(&key, &group, (start, end), count, consumer.as_deref()),
+        |pending| {
+            Frame::Array(
+                pending
+                    .into_iter()
+                    .map(|(id, consumer, idle, deliveries)| {
+                        Frame::Array(vec![
+                            id_frame(id),
+                            Frame::Bulk(Bytes::from(consumer)),
+                            Frame::Integer(idle.as_millis() as i64),
+                            Frame::Integer(deliveries as i64),
+                        ])
+                    })
+                    .collect(),
+            )
+        },
+    )
+}
+
+/// `XCLAIM key group consumer min-idle-time id [id ...] [IDLE ms]
+/// [TIME unix-time-milliseconds] [RETRYCOUNT count] [FORCE] [JUSTID]`
+pub(crate) fn xclaim(db: &Db, parse: &mut Parse) -> Result<Frame, ParseError> {
+    let key = parse.next_string()?;
+    let group = parse.next_string()?;
+    let consumer = parse.next_string()?;
+
+    let mut claim = Claim {
+        min_idle: Duration::from_millis(parse.next_int()?.max(0) as u64),
+        increment: true,
+        ..Claim::default()
+    };
+
+    let mut ids = vec![id_arg(parse)?];
+    let mut justid = false;
+
+    while parse.remaining() > 0 {
+        let arg = parse.next_string()?;
+
+        match &arg.to_uppercase()[..] {
+            "IDLE" => {
+                let idle = parse.next_int()?.max(0) as u64;
+                let now = unix_time().as_millis() as u64;
+                claim.delivered_at = Some(now.saturating_sub(idle));
+            }
+            "TIME" => claim.delivered_at = Some(parse.next_int()?.max(0) as u64),
+            "RETRYCOUNT" => claim.deliveries = Some(parse.next_int()?.max(0) as u64),
+            "FORCE" => claim.force = true,
+            "JUSTID" => {
+                justid = true;
+                claim.increment = false;
+            }
+            _ => ids.push(StreamId::parse(&arg, 0).ok_or(INVALID_ID)?),
+        }
+    }
+
+    reply_with(db.xclaim(&key, &group, &consumer, &ids, claim), |claimed| {
+        Frame::Array(
+            claimed
+                .into_iter()
+                .map(|(id, fields)| match justid {
+                    true => id_frame(id),
+                    false => entry(id, Some(fields)),
+                })
+                .collect(),
+        )
+    })
+}
+
+/// `XCLAIM key group consumer 0 id [id ...]` for the entries claimed by
+/// `command`, an `XCLAIM` which replied `reply`, none if it claimed none.
+///
+/// Whether an entry is claimed depends on the time, so only the claims made
+/// are logged, see `aof::propagate`.
+pub(super) fn claimed(command: Frame, reply: &Frame) -> Option<Frame> {
+    let ids: Vec<_> = match reply {
+        Frame::Array(claimed) if !claimed.is_empty() => claimed
+            .iter()
+            .filter_map(|claimed| match claimed {
+                Frame::Array(entry) => entry.first().cloned(),
+                id => Some(id.clone()),
+            })
+            .collect(),
+        _ => return None,
+    };
+
+    match command {
+        Frame::Array(mut args) => {
+            args.truncate(4);
+            args.push(Frame::Bulk(Bytes::from_static(b"0")));
+            args.extend(ids);
+            Some(Frame::Array(args))
+        }
+        _ => None,
+    }
+}
+
+/// `XSETID key last-id`
+pub(crate) fn xsetid(db: &Db, parse: &mut Parse) -> Result<Frame, ParseError> {
+    let key = parse.next_string()?;
+    let id = id_arg(parse)?;
+
+    reply_with(db.xsetid(&key, id), |set| match set {
+        true => Frame::Simple("OK".to_string()),
+        false => Frame::Error("ERR no such key".to_string()),
+    })
+}
+
+/// Read the arguments of `XREAD`, or of `XREADGROUP` if `group` is set.
+fn read_args(parse: &mut Parse, group: bool) -> Result<Read, ParseError> {
+    let group = match group {
+        true => {
+            if !parse.next_string()?.eq_ignore_ascii_case("group") {
+                return Err("syntax error".into());
+            }
+            Some((parse.next_string()?, parse.next_string()?))
+        }
+        false => None,
+    };
+
+    let mut read = Read {
+        group,
+        count: usize::MAX,
+        block: None,
+        noack: false,
+        streams: vec![],
+    };
+
+    loop {
+        match &parse.next_string()?.to_uppercase()[..] {
+            "COUNT" => read.count = count_value(parse.next_int()?)?,
+            "BLOCK" => match parse.next_int()? {
+                ms if ms < 0 => return Err("timeout is negative".into()),
+                ms => read.block = Some(Duration::from_millis(ms as u64)),
+            },
+            "NOACK" if read.group.is_some() => read.noack = true,
+            "STREAMS" => break,
+            _ => return Err("syntax error".into()),
+        }
+    }
+
+    let mut args = vec![];
+    while parse.remaining() > 0 {
+        args.push(parse.next_string()?);
+    }
+
+    if args.is_empty() || args.len() % 2 != 0 {
+        return Err("Unbalanced 'xread' list of streams: for each stream key an ID or '$' must be specified.".into());
+    }
+
+    let ids = args.split_off(args.len() / 2);
+    for (key, id) in args.into_iter().zip(ids) {
+        let id = match &id[..] {
+            "$" if read.group.is_none() => ReadId::Last,
+            ">" if read.group.is_some() => ReadId::New,
+            id => ReadId::After(StreamId::parse(id, 0).ok_or(INVALID_ID)?),
+        };
+
+        read.streams.push((key, id));
+    }
+
+    Ok(read)
+}
+
+/// Read the streams once, replying with a null array if there is nothing to
+/// read.
+fn read_once(db: &Db, read: &Read) -> Frame {
+    let mut streams = vec![];
+
+    for (key, id) in &read.streams {
+        let entries = match (&read.group, *id) {
+            (None, ReadId::After(id)) => db
+                .xrange(
+                    key,
+                    Bound::Excluded(id),
+                    Bound::Unbounded,
+                    read.count,
+                    false,
+                )
+                .map(|entries| {
+                    entries
+                        .into_iter()
+                        .map(|(id, fields)| (id, Some(fields)))
+                        .collect()
+                }),
+            (None, _) => Ok(vec![]),
+            (Some((group, consumer)), id) => {
+                let after = match id {
+                    ReadId::After(id) => Some(id),
+                    _ => None,
+                };
+                db.xreadgroup(key, group, consumer, after, read.count, read.noack)
+            }
+        };
+
+        let entries = match entries {
+            Ok(entries) => entries,
+            Err(err) => return err.into(),
+        };
+
+        // Reading the pending entries of a consumer replies for every
+        // stream, even without entries.
+        let history = read.group.is_some() && *id != ReadId::New;
+
+        if !entries.is_empty() || history {
+            streams.push(Frame::Array(vec![
+                Frame::Bulk(Bytes::from(key.clone())),
+                Frame::Array(
+                    entries
+                        .into_iter()
+                        .map(|(id, fields)| entry(id, fields))
+                        .collect(),
+                ),
+            ]));
+        }
+    }
+
+    match streams.is_empty() {
+        true => Frame::NullArray,
+        false => Frame::Array(streams),
+    }
+}
+
+/// Replace the `$` IDs of the `XREAD` command carried by `frame` with the
+/// current last ID of their stream.
+fn resolve_last(db: &Db, frame: Frame, read: &Read) -> Result<Frame, Frame> {
+    let mut parts = match frame {
+        Frame::Array(parts) => parts,
+        frame => return Ok(frame),
+    };
+
+    // The IDs are the last arguments, in the order of the keys.
+    let first = parts.len() - read.streams.len();
+
+    for ((key, id), part) in read.streams.iter().zip(&mut parts[first..]) {
+        if *id == ReadId::Last {
+            let last = db.xlast_id(key).map_err(Frame::from)?;
+            let last = last.unwrap_or(StreamId::MIN);
+            *part = Frame::Bulk(Bytes::from(last.to_string()));
+        }
+    }
+
+    Ok(Frame::Array(parts))
+}
+
+/// Read the start of a range: `-`, an ID, or an ID excluded with `(`.
+fn start_arg(parse: &mut Parse) -> Result<Bound<StreamId>, ParseError> {
+    bound_arg(parse, "-", 0)
+}
+
+/// Read the end of a range: `+`, an ID, or an ID excluded with `(`.
+fn end_arg(parse: &mut Parse) -> Result<Bound<StreamId>, ParseError> {
+    bound_arg(parse, "+", u64::MAX)
+}
+
+/// An ID without sequence number gets `seq`, so a millisecond alone covers all
+/// of its entries.
+fn bound_arg(parse: &mut Parse, unbounded: &str, seq: u64) -> Result<Bound<StreamId>, ParseError> {
+    let arg = parse.next_string()?;
+
+    if arg == unbounded {
+        return Ok(Bound::Unbounded);
+    }
+
+    Ok(match arg.strip_prefix('(') {
+        Some(id) => Bound::Excluded(StreamId::parse(id, seq).ok_or(INVALID_ID)?),
+        None => Bound::Included(StreamId::parse(&arg, seq).ok_or(INVALID_ID)?),
+    })
+}
+
+fn id_arg(parse: &mut Parse) -> Result<StreamId, ParseError> {
+    Ok(StreamId::parse(&parse.next_string()?, 0).ok_or(INVALID_ID)?)
+}
+
+/// Read `COUNT count`, the keyword being already consumed.
+fn count_arg(parse: &mut Parse) -> Result<usize, ParseError> {
+    if !parse.next_string()?.eq_ignore_ascii_case("count") {
+        return Err("syntax error".into());
+    }
+
+    count_value(parse.next_int()?)
+}
+
+fn count_value(count: i64) -> Result<usize, ParseError> {
+    match count {
+        count if count < 0 => Err("value is out of range, must be positive".into()),
+        count => Ok(count as usize),
+    }
+}
+
+fn id_frame(id: StreamId) -> Frame {
+    Frame::Bulk(Bytes::from(id.to_string()))
+}
+
+/// An entry as replied by the stream commands: its ID, then its fields and
+/// values, null if the entry was deleted.
+fn entry(id: StreamId, fields: Option<Fields>) -> Frame {
+    let fields = match fields {
+        Some(fields) => Frame::Array(
+            fields
+                .into_iter()
+                .flat_map(|(field, value)| [Frame::Bulk(field), Frame::Bulk(value)])
+                .collect(),
+        ),
+        None => Frame::NullArray,
+    };
+
+    Frame::Array(vec![id_frame(id), fields])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::apply;
+    use super::*;
+    use crate::client::command;
+    use crate::server;
+    use tokio::net::TcpStream;
+
+    async fn connect(db: &Db) -> Connection {
+        let addr = server::spawn(db).await;
+        Connection::new(TcpStream::connect(addr).await.unwrap())
+    }
+
+    #[tokio::test]
+    async fn timeouts_out_of_range_block_forever() {
+        let db = Db::new();
+        let mut client = connect(&db).await;
+
+        let read = ["XREAD", "BLOCK", "9223372036854775807", "STREAMS", "s", "$"];
+        client.write_frame(&command(&read)).await.unwrap();
+        time::sleep(Duration::from_millis(50)).await;
+        apply(command(&["XADD", "s", "1-1", "f", "v"]), &db);
+
+        let reply = client.read_frame().await.unwrap().unwrap();
+        assert!(matches!(reply, Frame::Array(streams) if streams.len() == 1));
+    }
+
+    #[tokio::test]
+    async fn group_reads_count_as_changes_once_they_deliver() {
+        let db = Db::new();
+        let mut client = connect(&db).await;
+        apply(
+            command(&["XGROUP", "CREATE", "s", "g", "$", "MKSTREAM"]),
+            &db,
+        );
+        let changes = db.changes();
+
+        let read = [
+            "XREADGROUP",
+            "GROUP",
+            "g",
+            "c",
+            "BLOCK",
+            "20",
+            "STREAMS",
+            "s",
+            ">",
+        ];
+        client.write_frame(&command(&read)).await.unwrap();
+        assert_eq!(client.read_frame().await.unwrap(), Some(Frame::NullArray));
+        assert_eq!(db.changes(), changes);
+
+        apply(command(&["XADD", "s", "1-1", "f", "v"]), &db);
+        client.write_frame(&command(&read)).await.unwrap();
+        let reply = client.read_frame().await.unwrap().unwrap();
+        assert!(matches!(reply, Frame::Array(streams) if streams.len() == 1));
+        assert_eq!(db.changes(), changes + 2);
+    }
+}
@@ -0,0 +1,223 @@
+use super::{expire_time, keys_arg, reply_with};
+use crate::db::unix_time;
+use crate::{Db, Frame, Parse, ParseError};
+
+use std::time::Duration;
+
+/// `GET key`
+pub(crate) fn get(db: &Db, parse: &mut Parse) -> Result<Frame, ParseError> {
+    let key = parse.next_string()?;
+
+    reply_with(db.get(&key), |value| value.map_or(Frame::Null, Frame::Bulk))
+}
+
+/// `SET key value [EX seconds|PX milliseconds]`
+///
+/// Any previous time to live associated with the key is discarded.
+pub(crate) fn set(db: &Db, parse: &mut Parse) -> Result<Frame, ParseError> {
+    let key = parse.next_string()?;
+    let value = parse.next_bytes()?;
+
+    let mut expire = None;
+
+    while parse.remaining() > 0 {
+        let option = parse.next_string()?.to_uppercase();
+        let unit = match &option[..] {
+            // Only one of `EX` and `PX` may be given.
+            "EX" if expire.is_none() => 1000,
+            "PX" if expire.is_none() => 1,
+            _ => return Err("syntax error".into()),
+        };
+        let amount = parse.next_int()?;
+
+        if amount <= 0 {
+            return Ok(Frame::Error(
+                "ERR invalid expire time in 'set' command".to_string(),
+            ));
+        }
+
+        // Checked as a Unix time, like Redis, so the expiration fits in an
+        // `Instant` too.
+        let now = unix_time().as_millis() as i64;
+        let unix_ms = expire_time("set", amount, unit, now)?;
+        expire = Some(Duration::from_millis((unix_ms - now) as u64));
+    }
+
+    db.set(key, value, expire);
+
+    Ok(Frame::Simple("OK".to_string()))
+}
+
+/// `SETNX key value`
+pub(crate) fn setnx(db: &Db, parse: &mut Parse) -> Result<Frame, ParseError> {
+    let key = parse.next_string()?;
+    let value = parse.next_bytes()?;
+
+    Ok(Frame::Integer(db.set_nx(key, value) as i64))
+}
+
+/// `GETSET key value`
+pub(crate) fn getset(db: &Db, parse: &mut Parse) -> Result<Frame, ParseError> {
+    let key = parse.next_string()?;
+    let value = parse.next_bytes()?;
+
+    reply_with(db.get_set(key, value), |value| {
+        value.map_or(Frame::Null, Frame::Bulk)
+    })
+}
+
+/// `MGET key [key ...]`
+pub(crate) fn mget(db: &Db, parse: &mut Parse) -> Result<Frame, ParseError> {
+    let keys = keys_arg(parse)?;
+
+    let values = db
+        .get_many(&keys)
+        .into_iter()
+        .map(|value| value.map_or(Frame::Null, Frame::Bulk))
+        .collect();
+
+    Ok(Frame::Array(values))
+}
+
+/// `MSET key value [key value ...]`
+pub(crate) fn mset(db: &Db, parse: &mut Parse) -> Result<Frame, ParseError> {
+    let mut pairs = vec![(parse.next_string()?, parse.next_bytes()?)];
+    while parse.remaining() > 0 {
+        pairs.push((parse.next_string()?, parse.next_bytes()?));
+    }
+
+    db.set_many(pairs);
+
+    Ok(Frame::Simple("OK".to_string()))
+}
+
+/// `INCR key`
+pub(crate) fn incr(db: &Db, parse: &mut Parse) -> Result<Frame, ParseError> {
+    let key = parse.next_string()?;
+    incr_by(db, &key, 1)
+}
+
+/// `DECR key`
+pub(crate) fn decr(db: &Db, parse: &mut Parse) -> Result<Frame, ParseError> {
+    let key = parse.next_string()?;
+    incr_by(db, &key, -1)
+}
+
+/// `INCRBY key increment`
+pub(crate) fn incrby(db: &Db, parse: &mut Parse) -> Result<Frame, ParseError> {
+    let key = parse.next_string()?;
+    let delta = parse.next_int()?;
+    incr_by(db, &key, delta)
+}
+
+/// `DECRBY key decrement`
+pub(crate) fn decrby(db: &Db, parse: &mut Parse) -> Result<Frame, ParseError> {
+    let key = parse.next_string()?;
+    let delta = parse.next_int()?;
+
+    match delta.checked_neg() {
+        Some(delta) => incr_by(db, &key, delta),
+        None => Err("decrement would overflow".into()),
+    }
+}
+
+fn incr_by(db: &Db, key: &str, delta: i64) -> Result<Frame, ParseError> {
+    reply_with(db.incr_by(key, delta), Frame::Integer)
+}
+
+/// `APPEND key value`
+pub(crate) fn append(db: &Db, parse: &mut Parse) -> Result<Frame, ParseError> {
+    let key = parse.next_string()?;
+    let value = parse.next_bytes()?;
+
+    reply_with(db.append(&key, &value), |len| Frame::Integer(len as i64))
+}
+
+/// `STRLEN key`
+pub(crate) fn strlen(db: &Db, parse: &mut Parse) -> Result<Frame, ParseError> {
+    let key = parse.next_string()?;
+
+    reply_with(db.strlen(&key), |len| Frame::Integer(len as i64))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::apply;
+    use super::*;
+    use crate::client::command;
+
+    #[tokio::test]
+    async fn set_checks_its_expiration() {
+        let db = Db::new();
+        let call = |args: &[&str]| apply(command(args), &db);
+
+        assert_eq!(
+            call(&["SET", "a", "1", "EX", "9223372036854775807"]),
+            Frame::Error("ERR invalid expire time in 'set' command".into())
+        );
+        assert_eq!(
+            call(&["SET", "a", "1", "EX", "10", "PX", "100"]),
+            Frame::Error("ERR syntax error".into())
+        );
+        assert_eq!(db.get("a"), Ok(None));
+
+        assert_eq!(
+            call(&["SET", "a", "1", "PX", "100000"]),
+            Frame::Simple("OK".into())
+        );
+        assert!(db.ttl("a").unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn counters_do_not_overflow() {
+        let db = Db::new();
+        let call = |args: &[&str]| apply(command(args), &db);
+        let overflow = Frame::Error("ERR increment or decrement would overflow".into());
+
+        call(&["SET", "max", "9223372036854775807"]);
+        assert_eq!(call(&["INCR", "max"]), overflow);
+        assert_eq!(call(&["INCRBY", "max", "1"]), overflow);
+        assert_eq!(db.get("max"), Ok(Some("9223372036854775807".into())));
+
+        call(&["SET", "min", "-9223372036854775808"]);
+        assert_eq!(call(&["DECR", "min"]), overflow);
+        assert_eq!(
+            call(&["DECRBY", "min", "-9223372036854775808"]),
+            Frame::Error("ERR decrement would overflow".into())
+        );
+
+        call(&["SET", "text", "abc"]);
+        assert_eq!(
+            call(&["INCR", "text"]),
+            Frame::Error("ERR value is not an integer or out of range".into())
+        );
+    }
+
+    #[tokio::test]
+    async fn string_commands_check_the_type() {
+        let db = Db::new();
+        let call = |args: &[&str]| apply(command(args), &db);
+        let wrong_type = Frame::Error(
+            "WRONGTYPE Operation against a key holding the wrong kind of value".into(),
+        );
+
+        call(&["RPUSH", "list", "a"]);
+        assert_eq!(call(&["GET", "list"]), wrong_type);
+        assert_eq!(call(&["INCR", "list"]), wrong_type);
+        assert_eq!(call(&["APPEND", "list", "b"]), wrong_type);
+        assert_eq!(call(&["GETSET", "list", "b"]), wrong_type);
+
+        // `SET` replaces a value of any type.
+        assert_eq!(call(&["SET", "list", "b"]), Frame::Simple("OK".into()));
+    }
+
+    #[tokio::test]
+    async fn unknown_commands_are_reported() {
+        let db = Db::new();
+
+        assert_eq!(
+            apply(command(&["GETT", "a"]), &db),
+            Frame::Error("ERR unknown command 'gett'".into())
+        );
+    }
+}
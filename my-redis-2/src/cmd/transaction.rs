@@ -0,0 +1,301 @@
+use super::{connection, execute, handler, is_write, local, name, Session, READ_ONLY};
+use crate::{Db, Frame, Parse, ParseError};
+
+use bytes::Bytes;
+
+/// The commands queued by `MULTI`.
+#[derive(Debug, Default)]
+pub(super) struct Transaction {
+    commands: Vec<Frame>,
+
+    /// Set when a command could not be queued, `EXEC` then discards the
+    /// transaction.
+    failed: bool,
+}
+
+/// `MULTI`
+pub(super) fn multi(_parse: &mut Parse, session: &mut Session) -> Result<Frame, ParseError> {
+    if session.transaction.is_some() {
+        return Ok(Frame::Error(
+            "ERR MULTI calls can not be nested".to_string(),
+        ));
+    }
+
+    session.transaction = Some(Transaction::default());
+
+    Ok(Frame::Simple("OK".to_string()))
+}
+
+/// Queue a command received after `MULTI`.
+///
+/// Unknown commands, and write commands sent to a follower, are rejected right
+/// away and fail the whole transaction.
+pub(super) fn queue(db: &Db, frame: Frame, session: &mut Session) -> Frame {
+    let transaction = session.transaction.as_mut().expect("not in a transaction");
+
+    let name = String::from_utf8_lossy(&name(&frame)).into_owned();
+
+    if handler(&name).is_none() && name != "select" {
+        transaction.failed = true;
+
+        return Frame::Error(match &name[..] {
+            "subscribe" | "psubscribe" | "unsubscribe" | "punsubscribe" | "hello" | "auth"
+            | "acl" | "client" | "monitor" | "psync" => {
+                format!("ERR Command '{}' not allowed inside a transaction", name)
+            }
+            _ => format!("ERR unknown command '{}'", name),
+        });
+    }
+
+    if is_write(name.as_bytes()) && db.replication().is_follower() {
+        transaction.failed = true;
+
+        return Frame::Error(READ_ONLY.to_string());
+    }
+
+    transaction.commands.push(frame);
+
+    Frame::Simple("QUEUED".to_string())
+}
+
+/// `EXEC`
+///
+/// Runs the queued commands with every other command locked out, so no
+/// client observes the transaction half applied. Replies with the reply of
+/// each command, or a null array if a watched key was modified.
+pub(super) fn exec(db: &Db, parse: &mut Parse, session: &mut Session) -> Result<Frame, ParseError> {
+    parse.finish()?;
+
+    let transaction = match session.transaction.take() {
+        Some(transaction) => transaction,
+        None => return Ok(Frame::Error("ERR EXEC without MULTI".to_string())),
+    };
+    let watched = std::mem::take(&mut session.watched);
+
+    if transaction.failed {
+        return Ok(Frame::Error(
+            "EXECABORT Transaction discarded because of previous errors.".to_string(),
+        ));
+    }
+
+    let _lock = db.lock_transaction();
+
+    if watched.iter().any(|(index, key, version)| {
+        let db = db.select(*index).expect("watched database exists");
+        db.version(key) != *version
+    }) {
+        return Ok(Frame::NullArray);
+    }
+
+    // Logged transactions are wrapped in `MULTI`/`EXEC`, so the append-only
+    // file is never replayed, nor a follower left, in the middle of one.
+    let logged = transaction
+        .commands
+        .iter()
+        .any(|command| is_write(&name(command)));
+
+    if logged {
+        log_marker(db, b"MULTI")?;
+    }
+
+    // `SELECT` switches the database of the commands following it, and of
+    // the connection once the transaction is done.
+    let replies = transaction
+        .commands
+        .into_iter()
+        .map(|command| match &name(&command)[..] {
+            b"select" => local(command, |parse| {
+                let db = db.select(session.db).expect("selected database exists");
+                connection::select(&db, parse, session)
+            }),
+            _ => execute(
+                command,
+                &db.select(session.db).expect("selected database exists"),
+                true,
+            ),
+        })
+        .collect();
+
+    if logged {
+        log_marker(db, b"EXEC")?;
+    }
+
+    super::list::serve_blocked(db);
+
+    Ok(Frame::Array(replies))
+}
+
+/// `DISCARD`
+pub(super) fn discard(_parse: &mut Parse, session: &mut Session) -> Result<Frame, ParseError> {
+    if session.transaction.take().is_none() {
+        return Ok(Frame::Error("ERR DISCARD without MULTI".to_string()));
+    }
+
+    session.watched.clear();
+
+    Ok(Frame::Simple("OK".to_string()))
+}
+
+/// `WATCH key [key ...]`
+///
+/// `EXEC` aborts if any of the keys is modified in the meantime.
+pub(super) fn watch(
+    db: &Db,
+    parse: &mut Parse,
+    session: &mut Session,
+) -> Result<Frame, ParseError> {
+    let keys = super::keys_arg(parse)?;
+
+    if session.transaction.is_some() {
+        return Ok(Frame::Error(
+            "ERR WATCH inside MULTI is not allowed".to_string(),
+        ));
+    }
+
+    for key in keys {
+        let version = db.version(&key);
+        session.watched.push((db.index(), key, version));
+    }
+
+    Ok(Frame::Simple("OK".to_string()))
+}
+
+/// `UNWATCH`
+pub(super) fn unwatch(_parse: &mut Parse, session: &mut Session) -> Result<Frame, ParseError> {
+    session.watched.clear();
+
+    Ok(Frame::Simple("OK".to_string()))
+}
+
+fn log_marker(db: &Db, marker: &'static [u8]) -> Result<(), ParseError> {
+    let command = Frame::Array(vec![Frame::Bulk(Bytes::from_static(marker))]);
+
+    super::log(db, &mut super::lock_log(db), &command)
+        .map_err(|err| format!("failed to log to the append only file: {}", err).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::run;
+    use super::*;
+    use crate::Connection;
+
+    use tokio::net::{TcpListener, TcpStream};
+
+    /// A connected client and server connection, along with the server side
+    /// session.
+    struct Client {
+        db: Db,
+        client: Connection,
+        server: Connection,
+        session: Session,
+    }
+
+    impl Client {
+        async fn new(db: &Db) -> Client {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let client = TcpStream::connect(listener.local_addr().unwrap());
+            let (client, server) = tokio::join!(client, listener.accept());
+
+            Client {
+                db: db.clone(),
+                client: Connection::new(client.unwrap()),
+                server: Connection::new(server.unwrap().0),
+                session: Session::default(),
+            }
+        }
+
+        async fn call(&mut self, args: &[&str]) -> Frame {
+            let frame = Frame::Array(
+                args.iter()
+                    .map(|arg| Frame::Bulk(Bytes::copy_from_slice(arg.as_bytes())))
+                    .collect(),
+            );
+
+            run(frame, &self.db, &mut self.server, &mut self.session)
+                .await
+                .unwrap();
+            self.client.read_frame().await.unwrap().unwrap()
+        }
+    }
+
+    fn ok() -> Frame {
+        Frame::Simple("OK".to_string())
+    }
+
+    fn queued() -> Frame {
+        Frame::Simple("QUEUED".to_string())
+    }
+
+    #[tokio::test]
+    async fn exec_applies_queued_commands() {
+        let db = Db::new();
+        let mut client = Client::new(&db).await;
+
+        assert_eq!(client.call(&["MULTI"]).await, ok());
+        assert_eq!(client.call(&["SET", "a", "1"]).await, queued());
+        assert_eq!(client.call(&["INCR", "a"]).await, queued());
+        assert_eq!(client.call(&["INCR", "b", "c"]).await, queued());
+        assert_eq!(db.get("a"), Ok(None));
+
+        let replies = match client.call(&["EXEC"]).await {
+            Frame::Array(replies) => replies,
+            frame => panic!("unexpected reply {:?}", frame),
+        };
+        assert_eq!(replies[..2], [ok(), Frame::Integer(2)]);
+        // Errors of individual commands do not stop the transaction
+        assert!(matches!(replies[2], Frame::Error(_)));
+        assert_eq!(db.get("a"), Ok(Some(Bytes::from("2"))));
+
+        assert!(matches!(client.call(&["EXEC"]).await, Frame::Error(_)));
+    }
+
+    #[tokio::test]
+    async fn discard_and_failed_queueing() {
+        let db = Db::new();
+        let mut client = Client::new(&db).await;
+
+        client.call(&["MULTI"]).await;
+        client.call(&["SET", "a", "1"]).await;
+        assert_eq!(client.call(&["DISCARD"]).await, ok());
+        assert_eq!(db.get("a"), Ok(None));
+
+        client.call(&["MULTI"]).await;
+        client.call(&["SET", "a", "1"]).await;
+        assert!(matches!(client.call(&["NOPE"]).await, Frame::Error(_)));
+        assert_eq!(
+            client.call(&["EXEC"]).await,
+            Frame::Error("EXECABORT Transaction discarded because of previous errors.".to_string())
+        );
+        assert_eq!(db.get("a"), Ok(None));
+    }
+
+    #[tokio::test]
+    async fn watch_aborts_on_modification() {
+        let db = Db::new();
+        let mut client = Client::new(&db).await;
+        db.set("a".to_string(), Bytes::from("1"), None);
+
+        assert_eq!(client.call(&["WATCH", "a", "missing"]).await, ok());
+        db.set("a".to_string(), Bytes::from("1"), None);
+        client.call(&["MULTI"]).await;
+        client.call(&["INCR", "a"]).await;
+        assert_eq!(client.call(&["EXEC"]).await, Frame::NullArray);
+        assert_eq!(db.get("a"), Ok(Some(Bytes::from("1"))));
+
+        // Keys are unwatched by `EXEC`, so the next transaction goes through.
+        client.call(&["WATCH", "missing"]).await;
+        client.call(&["MULTI"]).await;
+        client.call(&["INCR", "a"]).await;
+        assert_eq!(
+            client.call(&["EXEC"]).await,
+            Frame::Array(vec![Frame::Integer(2)])
+        );
+
+        // Creating a watched key counts as a modification.
+        client.call(&["WATCH", "missing"]).await;
+        db.set("missing".to_string(), Bytes::from("1"), None);
+        client.call(&["MULTI"]).await;
+        assert_eq!(client.call(&["EXEC"]).await, Frame::NullArray);
+    }
+}
@@ -0,0 +1,172 @@
+use super::{reply_with, scan_args, scan_reply};
+use crate::db::ZAdd;
+use crate::frame::format_double;
+use crate::{Db, Frame, Parse, ParseError};
+
+use bytes::Bytes;
+
+/// `ZADD key [NX|XX] [GT|LT] [CH] [INCR] score member [score member ...]`
+///
+/// With `INCR`, the score of the single member is incremented as by
+/// `ZINCRBY`, and the reply is nil if the conditions are not met.
+pub(crate) fn zadd(db: &Db, parse: &mut Parse) -> Result<Frame, ParseError> {
+    let key = parse.next_string()?;
+
+    let mut flags = ZAdd::default();
+    let mut incr = false;
+    let score = loop {
+        let arg = parse.next_string()?;
+        match &arg.to_uppercase()[..] {
+            "NX" => flags.nx = true,
+            "XX" => flags.xx = true,
+            "GT" => flags.gt = true,
+            "LT" => flags.lt = true,
+            "CH" => flags.ch = true,
+            "INCR" => incr = true,
+            _ => break score_arg(&arg)?,
+        }
+    };
+
+    let mut members = vec![(score, parse.next_bytes()?)];
+    while parse.remaining() > 0 {
+        members.push((parse.next_float()?, parse.next_bytes()?));
+    }
+
+    if flags.nx && flags.xx {
+        return Err("XX and NX options at the same time are not compatible".into());
+    }
+    if [flags.nx, flags.gt, flags.lt]
+        .iter()
+        .filter(|set| **set)
+        .count()
+        > 1
+    {
+        return Err("GT, LT, and/or NX options at the same time are not compatible".into());
+    }
+
+    if !incr {
+        return reply_with(db.zadd(&key, members, flags), |count| {
+            Frame::Integer(count as i64)
+        });
+    }
+
+    if members.len() > 1 {
+        return Err("INCR option supports a single increment-element pair".into());
+    }
+    let (delta, member) = members.pop().unwrap();
+
+    reply_with(db.zadd_incr(&key, member, delta, flags), |score| {
+        score.map_or(Frame::Null, Frame::Double)
+    })
+}
+
+/// Read a score given as a string, as `Parse::next_float` does.
+fn score_arg(arg: &str) -> Result<f64, ParseError> {
+    match arg.parse::<f64>() {
+        Ok(score) if !score.is_nan() => Ok(score),
+        _ => Err("value is not a valid float".into()),
+    }
+}
+
+/// `ZRANGE key start stop [WITHSCORES]`
+pub(crate) fn zrange(db: &Db, parse: &mut Parse) -> Result<Frame, ParseError> {
+    let key = parse.next_string()?;
+    let start = parse.next_int()?;
+    let stop = parse.next_int()?;
+
+    let with_scores = match parse.remaining() {
+        0 => false,
+        _ if parse.next_string()?.eq_ignore_ascii_case("withscores") => true,
+        _ => return Err("syntax error".into()),
+    };
+
+    reply_with(db.zrange(&key, start, stop), |members| {
+        let mut frames = vec![];
+        for (member, score) in members {
+            frames.push(Frame::Bulk(member));
+            if with_scores {
+                frames.push(Frame::Double(score));
+            }
+        }
+        Frame::Array(frames)
+    })
+}
+
+/// `ZRANK key member`
+pub(crate) fn zrank(db: &Db, parse: &mut Parse) -> Result<Frame, ParseError> {
+    let key = parse.next_string()?;
+    let member = parse.next_bytes()?;
+
+    reply_with(db.zrank(&key, &member), |rank| {
+        rank.map_or(Frame::Null, |rank| Frame::Integer(rank as i64))
+    })
+}
+
+/// `ZINCRBY key increment member`
+pub(crate) fn zincrby(db: &Db, parse: &mut Parse) -> Result<Frame, ParseError> {
+    let key = parse.next_string()?;
+    let delta = parse.next_float()?;
+    let member = parse.next_bytes()?;
+
+    reply_with(db.zincr_by(&key, member, delta), Frame::Double)
+}
+
+/// `ZSCAN key cursor [MATCH pattern] [COUNT count]`
+///
+/// Scores are replied as bulk strings, following each member.
+pub(crate) fn zscan(db: &Db, parse: &mut Parse) -> Result<Frame, ParseError> {
+    let key = parse.next_string()?;
+    let args = scan_args(parse, false)?;
+
+    let page = db.zscan(&key, args.cursor, args.count, args.pattern.as_deref());
+    reply_with(page, |(cursor, members)| {
+        let items = members
+            .into_iter()
+            .flat_map(|(member, score)| {
+                let score = Bytes::from(format_double(score));
+                [Frame::Bulk(member), Frame::Bulk(score)]
+            })
+            .collect();
+        scan_reply(cursor, items)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::apply;
+    use super::*;
+    use crate::client::command;
+
+    #[tokio::test]
+    async fn zadd_parses_its_flags() {
+        let db = Db::new();
+        let call = |args: &[&str]| apply(command(args), &db);
+
+        assert_eq!(call(&["ZADD", "z", "NX", "5", "a"]), Frame::Integer(1));
+        assert_eq!(
+            call(&["zadd", "z", "xx", "ch", "6", "a"]),
+            Frame::Integer(1)
+        );
+        assert_eq!(call(&["ZADD", "z", "INCR", "2", "a"]), Frame::Double(8.0));
+        assert_eq!(call(&["ZADD", "z", "GT", "INCR", "-1", "a"]), Frame::Null);
+
+        assert_eq!(
+            call(&["ZADD", "z", "NX", "XX", "1", "a"]),
+            Frame::Error("ERR XX and NX options at the same time are not compatible".into())
+        );
+        assert_eq!(
+            call(&["ZADD", "z", "GT", "LT", "1", "a"]),
+            Frame::Error(
+                "ERR GT, LT, and/or NX options at the same time are not compatible".into()
+            )
+        );
+        assert_eq!(
+            call(&["ZADD", "z", "INCR", "1", "a", "2", "b"]),
+            Frame::Error("ERR INCR option supports a single increment-element pair".into())
+        );
+        assert_eq!(
+            call(&["ZADD", "z", "FOO", "a"]),
+            Frame::Error("ERR value is not a valid float".into())
+        );
+    }
+}
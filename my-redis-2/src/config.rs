@@ -0,0 +1,761 @@
+//! Server settings, read from a configuration file at startup and changed
+//! by `CONFIG SET` while running.
+//!
+//! The file holds a setting per line, its name followed by its value:
+//!
+//! ```text
+//! # Serve local TCP clients and sidecars talking over a socket file.
+//! bind 127.0.0.1 ::1
+//! port 6379
+//! unixsocket /run/my-redis.sock
+//! unixsocketperm 770
+//!
+//! save 3600 1
+//! save 300 100
+//! appendonly yes
+//! maxmemory 100mb
+//! maxmemory-policy allkeys-lru
+//! requirepass "correct horse"
+//! ```
+//!
+//! Lines starting with `#` are comments. Arguments may be double quoted, `""`
+//! being an empty value. `save` lines add up, `save ""` removing the rules
+//! before it; any other setting given twice keeps its last value. Settings
+//! are also given on the command line as `--name value`, overriding the file.
+//!
+//! Settings only read at startup, such as the addresses listened on, can't
+//! be changed by `CONFIG SET`.
+
+use crate::aof::{self, Fsync};
+use crate::db::{parse_memory, Events, Policy, DEFAULT_SHARDS};
+use crate::snapshot::{self, SaveRule};
+use crate::{acl, glob, replication, slowlog, Db};
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+/// The settings of a server, see `Db::config`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    /// Addresses the TCP listeners bind, one listener each.
+    pub bind: Vec<String>,
+
+    /// Port of the TCP listeners, 0 not to listen on TCP.
+    pub port: u16,
+
+    /// Path of the Unix socket listened on, if any.
+    pub unixsocket: Option<PathBuf>,
+
+    /// Permissions of the socket file, by default those of new files.
+    pub unixsocketperm: Option<u32>,
+
+    /// Number of independently locked shards of each database.
+    pub shards: usize,
+
+    /// Most connections served at once.
+    pub maxclients: usize,
+
+    /// How long connections get to finish their command on shutdown.
+    pub shutdown_timeout: Duration,
+
+    /// When snapshots are written, to `dbfilename`.
+    pub save: Vec<SaveRule>,
+    pub dbfilename: PathBuf,
+
+    /// Whether write commands are logged to `appendfilename`, which is then
+    /// loaded at startup rather than the snapshot.
+    pub appendonly: bool,
+    pub appendfilename: PathBuf,
+    pub appendfsync: Fsync,
+
+    /// Bytes the keyspace may use, 0 for no limit, and what happens past it.
+    pub maxmemory: usize,
+    pub maxmemory_policy: Policy,
+
+    /// Password of the default user, none if empty.
+    pub requirepass: String,
+
+    /// File the users are loaded from, if any.
+    pub aclfile: Option<PathBuf>,
+
+    pub notify_keyspace_events: Events,
+
+    /// Commands taking at least this long are kept in the slow log, none if
+    /// unset.
+    pub slowlog_log_slower_than: Option<Duration>,
+    pub slowlog_max_len: usize,
+
+    /// Host and port of the leader followed, if any.
+    pub replicaof: Option<(String, u16)>,
+
+    /// User and password a follower authenticates with to its leader, none
+    /// if the password is empty. An empty user stands for the default one.
+    pub masteruser: String,
+    pub masterauth: String,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            bind: vec!["127.0.0.1".to_string()],
+            port: 6379,
+            unixsocket: None,
+            unixsocketperm: None,
+            shards: DEFAULT_SHARDS,
+            maxclients: 10_000,
+            shutdown_timeout: Duration::from_secs(10),
+            // After an hour, 5 minutes or a minute depending on the number of
+            // changes.
+            save: snapshot::parse_rules("3600 1 300 100 60 10000").unwrap(),
+            dbfilename: PathBuf::from("dump.rdb"),
+            appendonly: false,
+            appendfilename: PathBuf::from("appendonly.aof"),
+            appendfsync: Fsync::EverySec,
+            maxmemory: 0,
+            maxmemory_policy: Policy::default(),
+            requirepass: String::new(),
+            aclfile: None,
+            notify_keyspace_events: Events::default(),
+            slowlog_log_slower_than: Some(Duration::from_millis(10)),
+            slowlog_max_len: 128,
+            replicaof: None,
+            masteruser: String::new(),
+            masterauth: String::new(),
+        }
+    }
+}
+
+/// A setting, as named in the file and by `CONFIG`.
+struct Parameter {
+    name: &'static str,
+
+    get: fn(&Config) -> String,
+
+    set: fn(&mut Config, &str) -> Result<(), String>,
+
+    /// Applies the setting to a running server, unset for the settings only
+    /// read at startup.
+    apply: Option<Apply>,
+}
+
+/// Applies settings of `Config` to a running server.
+type Apply = fn(&Db, &Config) -> crate::Result<()>;
+
+/// Every setting, in the order `CONFIG GET` lists them.
+const PARAMETERS: &[Parameter] = &[
+    Parameter {
+        name: "bind",
+        get: |config| config.bind.join(" "),
+        set: |config, value| {
+            config.bind = value.split_whitespace().map(str::to_string).collect();
+            Ok(())
+        },
+        apply: None,
+    },
+    Parameter {
+        name: "port",
+        get: |config| config.port.to_string(),
+        set: |config, value| assign(&mut config.port, number(value)),
+        apply: None,
+    },
+    Parameter {
+        name: "unixsocket",
+        get: |config| path(&config.unixsocket),
+        set: |config, value| {
+            config.unixsocket = (!value.is_empty()).then(|| PathBuf::from(value));
+            Ok(())
+        },
+        apply: None,
+    },
+    Parameter {
+        name: "unixsocketperm",
+        get: |config| format!("{:o}", config.unixsocketperm.unwrap_or(0)),
+        set: |config, value| {
+            let mode = u32::from_str_radix(value, 8)
+                .ok()
+                .filter(|&mode| mode <= 0o777)
+                .ok_or("argument must be an octal file mode")?;
+            config.unixsocketperm = (mode != 0).then_some(mode);
+            Ok(())
+        },
+        apply: None,
+    },
+    Parameter {
+        name: "shards",
+        get: |config| config.shards.to_string(),
+        set: |config, value| assign(&mut config.shards, positive(value)),
+        apply: None,
+    },
+    Parameter {
+        name: "maxclients",
+        get: |config| config.maxclients.to_string(),
+        set: |config, value| assign(&mut config.maxclients, positive(value)),
+        apply: None,
+    },
+    Parameter {
+        name: "shutdown-timeout",
+        get: |config| config.shutdown_timeout.as_secs().to_string(),
+        set: |config, value| {
+            assign(
+                &mut config.shutdown_timeout,
+                number(value).map(Duration::from_secs),
+            )
+        },
+        apply: None,
+    },
+    Parameter {
+        name: "save",
+        get: |config| {
+            let rules = config.save.iter();
+            let rules = rules.map(|rule| format!("{} {}", rule.after.as_secs(), rule.changes));
+            rules.collect::<Vec<_>>().join(" ")
+        },
+        set: |config, value| assign(&mut config.save, snapshot::parse_rules(value)),
+        apply: Some(save),
+    },
+    Parameter {
+        name: "dbfilename",
+        get: |config| config.dbfilename.display().to_string(),
+        set: |config, value| {
+            if value.is_empty() {
+                return Err("argument can't be empty".to_string());
+            }
+            config.dbfilename = PathBuf::from(value);
+            Ok(())
+        },
+        apply: Some(save),
+    },
+    Parameter {
+        name: "appendonly",
+        get: |config| yes_no(config.appendonly),
+        set: |config, value| assign(&mut config.appendonly, boolean(value)),
+        apply: Some(|db, config| match config.appendonly {
+            true => aof::enable(db, &config.appendfilename, config.appendfsync),
+            false => Ok(aof::disable(db)?),
+        }),
+    },
+    Parameter {
+        name: "appendfilename",
+        get: |config| config.appendfilename.display().to_string(),
+        set: |config, value| {
+            if value.is_empty() {
+                return Err("argument can't be empty".to_string());
+            }
+            config.appendfilename = PathBuf::from(value);
+            Ok(())
+        },
+        apply: None,
+    },
+    Parameter {
+        name: "appendfsync",
+        get: |config| config.appendfsync.to_string(),
+        set: |config, value| assign(&mut config.appendfsync, value.parse()),
+        apply: Some(|db, config| Ok(aof::set_fsync(db, config.appendfsync)?)),
+    },
+    Parameter {
+        name: "maxmemory",
+        get: |config| config.maxmemory.to_string(),
+        set: |config, value| {
+            config.maxmemory = parse_memory(value).ok_or("argument must be a memory value")?;
+            Ok(())
+        },
+        apply: Some(max_memory),
+    },
+    Parameter {
+        name: "maxmemory-policy",
+        get: |config| config.maxmemory_policy.to_string(),
+        set: |config, value| assign(&mut config.maxmemory_policy, value.parse()),
+        apply: Some(max_memory),
+    },
+    Parameter {
+        name: "requirepass",
+        get: |config| config.requirepass.clone(),
+        set: |config, value| {
+            config.requirepass = value.to_string();
+            Ok(())
+        },
+        apply: Some(|db, config| {
+            acl::require_pass(db, &config.requirepass);
+            Ok(())
+        }),
+    },
+    Parameter {
+        name: "aclfile",
+        get: |config| path(&config.aclfile),
+        set: |config, value| {
+            config.aclfile = (!value.is_empty()).then(|| PathBuf::from(value));
+            Ok(())
+        },
+        apply: None,
+    },
+    Parameter {
+        name: "notify-keyspace-events",
+        get: |config| config.notify_keyspace_events.to_string(),
+        set: |config, value| assign(&mut config.notify_keyspace_events, value.parse()),
+        apply: Some(|db, config| {
+            db.set_notify_keyspace_events(config.notify_keyspace_events);
+            Ok(())
+        }),
+    },
+    Parameter {
+        name: "slowlog-log-slower-than",
+        get: |config| match config.slowlog_log_slower_than {
+            Some(duration) => duration.as_micros().to_string(),
+            None => "-1".to_string(),
+        },
+        set: |config, value| {
+            // A negative value disables the slow log.
+            let usec: i64 = number(value)?;
+            config.slowlog_log_slower_than = u64::try_from(usec).ok().map(Duration::from_micros);
+            Ok(())
+        },
+        apply: Some(slowlog),
+    },
+    Parameter {
+        name: "slowlog-max-len",
+        get: |config| config.slowlog_max_len.to_string(),
+        set: |config, value| assign(&mut config.slowlog_max_len, number(value)),
+        apply: Some(slowlog),
+    },
+    Parameter {
+        name: "replicaof",
+        get: |config| match &config.replicaof {
+            Some((host, port)) => format!("{} {}", host, port),
+            None => String::new(),
+        },
+        set: |config, value| {
+            if value.trim().is_empty() || value.eq_ignore_ascii_case("no one") {
+                config.replicaof = None;
+                return Ok(());
+            }
+
+            // Either `host port`, or `host:port` as on the command line.
+            let invalid = "argument must be a host and a port";
+            let (host, port) = match value.split_whitespace().collect::<Vec<_>>()[..] {
+                [host, port] => (host, port),
+                [leader] => leader.rsplit_once(':').ok_or(invalid)?,
+                _ => return Err(invalid.to_string()),
+            };
+            config.replicaof = Some((host.to_string(), number(port)?));
+            Ok(())
+        },
+        apply: None,
+    },
+    Parameter {
+        name: "masteruser",
+        get: |config| config.masteruser.clone(),
+        set: |config, value| {
+            config.masteruser = value.to_string();
+            Ok(())
+        },
+        apply: None,
+    },
+    Parameter {
+        name: "masterauth",
+        get: |config| config.masterauth.clone(),
+        set: |config, value| {
+            config.masterauth = value.to_string();
+            Ok(())
+        },
+        apply: None,
+    },
+];
+
+impl Config {
+    /// The settings of the configuration file given as first argument, if
+    /// any, overridden by the `--name value` flags following it.
+    pub fn from_args(args: impl Iterator<Item = String>) -> crate::Result<Config> {
+        let mut config = Config::default();
+        let mut args = args.peekable();
+
+        if let Some(path) = args.next_if(|arg| !arg.starts_with("--")) {
+            config.read_file(path)?;
+        }
+
+        while let Some(flag) = args.next() {
+            let name = flag
+                .strip_prefix("--")
+                .ok_or_else(|| format!("unexpected argument `{}`", flag))?;
+            let value = args
+                .next()
+                .ok_or_else(|| format!("missing value for `{}`", flag))?;
+            config
+                .set(name, &value)
+                .map_err(|err| format!("`{}`: {}", flag, err))?;
+        }
+
+        Ok(config)
+    }
+
+    /// Apply the settings of the configuration file at `path`.
+    pub fn read_file(&mut self, path: impl AsRef<Path>) -> crate::Result<()> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)
+            .map_err(|err| format!("can't read {}: {}", path.display(), err))?;
+
+        self.parse(&text)
+            .map_err(|err| format!("{}:{}", path.display(), err).into())
+    }
+
+    /// Apply the settings of a configuration file, `text`. Errors start with
+    /// the number of the offending line.
+    fn parse(&mut self, text: &str) -> Result<(), String> {
+        // The rules of the `save` lines read so far.
+        let mut save: Option<String> = None;
+
+        for (number, line) in text.lines().enumerate() {
+            let error = |err: String| format!("{}: {}", number + 1, err);
+
+            let args = split(line).map_err(error)?;
+            let (name, args) = match args.split_first() {
+                Some((name, args)) => (name, args.join(" ")),
+                None => continue,
+            };
+
+            let value = match &save {
+                Some(rules) if name.eq_ignore_ascii_case("save") && !args.is_empty() => {
+                    format!("{} {}", rules, args)
+                }
+                _ => args,
+            };
+            if name.eq_ignore_ascii_case("save") {
+                save = Some(value.clone());
+            }
+
+            self.set(name, &value).map_err(error)?;
+        }
+
+        Ok(())
+    }
+
+    /// Set the setting `name` to `value`, checking the value.
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), String> {
+        let parameter = parameter(name).ok_or_else(|| format!("unknown setting `{}`", name))?;
+        (parameter.set)(self, value)
+    }
+
+    /// The value of the setting `name`, as `CONFIG GET` lists it.
+    pub fn get(&self, name: &str) -> Option<String> {
+        parameter(name).map(|parameter| (parameter.get)(self))
+    }
+}
+
+/// Set up `db` as `config` says, once at startup: restore the keyspace from
+/// persistence, then apply every setting. `config` is kept for `CONFIG GET`.
+pub fn init(db: &Db, config: Config) -> crate::Result<()> {
+    snapshot::configure(db, &config.dbfilename, config.save.clone());
+
+    // The append-only file is more recent than the snapshot.
+    if config.appendonly {
+        aof::open(db, &config.appendfilename, config.appendfsync)?;
+    } else {
+        snapshot::load(db)?;
+    }
+
+    // The limit applies once the keyspace is restored.
+    max_memory(db, &config)?;
+
+    if let Some(path) = &config.aclfile {
+        acl::load(db, path)?;
+    }
+    if !config.requirepass.is_empty() {
+        acl::require_pass(db, &config.requirepass);
+    }
+
+    slowlog(db, &config)?;
+    db.set_notify_keyspace_events(config.notify_keyspace_events);
+
+    if let Some((host, port)) = &config.replicaof {
+        replication::follow(db, host.clone(), *port);
+    }
+
+    *db.config() = config;
+
+    Ok(())
+}
+
+/// `CONFIG GET` replies: the settings whose name matches any of `patterns`,
+/// along with their value.
+pub(crate) fn get(db: &Db, patterns: &[String]) -> Vec<(&'static str, String)> {
+    let config = db.config();
+
+    PARAMETERS
+        .iter()
+        .filter(|parameter| {
+            let name = parameter.name;
+            patterns
+                .iter()
+                .any(|pattern| glob::matches(&pattern.to_lowercase(), name))
+        })
+        .map(|parameter| (parameter.name, (parameter.get)(&config)))
+        .collect()
+}
+
+/// Change the settings of a running server, as `CONFIG SET` does, returning
+/// the error reply if any of them can't be.
+///
+/// Either every setting is changed or none is: the values are all checked
+/// before any is applied, and a setting failing to apply, such as an
+/// append-only file which can't be created, reverts those applied before.
+pub(crate) fn set(db: &Db, settings: &[(String, String)]) -> Result<(), String> {
+    let mut config = db.config();
+    let mut changed = config.clone();
+    let mut applied: Vec<(&str, Apply)> = vec![];
+
+    let failed = |name: &str, err: &dyn std::fmt::Display| {
+        format!(
+            "CONFIG SET failed (possibly related to argument '{}') - {}",
+            name, err
+        )
+    };
+
+    for (name, value) in settings {
+        let parameter = parameter(name).ok_or_else(|| {
+            format!(
+                "Unknown option or number of arguments for CONFIG SET - '{}'",
+                name
+            )
+        })?;
+        let apply = parameter
+            .apply
+            .ok_or_else(|| failed(parameter.name, &"can't set immutable config"))?;
+
+        (parameter.set)(&mut changed, value).map_err(|err| failed(parameter.name, &err))?;
+        applied.push((parameter.name, apply));
+    }
+
+    for (i, (name, apply)) in applied.iter().enumerate() {
+        if let Err(err) = apply(db, &changed) {
+            for (_, apply) in &applied[..i] {
+                let _ = apply(db, &config);
+            }
+            return Err(failed(name, &err));
+        }
+    }
+
+    *config = changed;
+
+    Ok(())
+}
+
+/// Record the leader now followed, for `CONFIG GET replicaof`.
+pub(crate) fn set_leader(db: &Db, leader: Option<(String, u16)>) {
+    db.config().replicaof = leader;
+}
+
+fn parameter(name: &str) -> Option<&'static Parameter> {
+    PARAMETERS
+        .iter()
+        .find(|parameter| parameter.name.eq_ignore_ascii_case(name))
+}
+
+fn save(db: &Db, config: &Config) -> crate::Result<()> {
+    snapshot::configure(db, &config.dbfilename, config.save.clone());
+    Ok(())
+}
+
+fn max_memory(db: &Db, config: &Config) -> crate::Result<()> {
+    db.set_max_memory(config.maxmemory, config.maxmemory_policy);
+    Ok(())
+}
+
+fn slowlog(db: &Db, config: &Config) -> crate::Result<()> {
+    let log_slower_than = config.slowlog_log_slower_than;
+    slowlog::configure(db, log_slower_than, config.slowlog_max_len);
+    Ok(())
+}
+
+/// Set `field` to `value`, if valid.
+fn assign<T>(field: &mut T, value: Result<T, String>) -> Result<(), String> {
+    *field = value?;
+    Ok(())
+}
+
+fn number<T: FromStr>(value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| "argument couldn't be parsed into an integer".to_string())
+}
+
+fn positive(value: &str) -> Result<usize, String> {
+    match number(value)? {
+        0 => Err("argument must be positive".to_string()),
+        n => Ok(n),
+    }
+}
+
+fn boolean(value: &str) -> Result<bool, String> {
+    match &value.to_lowercase()[..] {
+        "yes" => Ok(true),
+        "no" => Ok(false),
+        _ => Err("argument must be 'yes' or 'no'".to_string()),
+    }
+}
+
+fn yes_no(value: bool) -> String {
+    match value {
+        true => "yes".to_string(),
+        false => "no".to_string(),
+    }
+}
+
+fn path(path: &Option<PathBuf>) -> String {
+    match path {
+        Some(path) => path.display().to_string(),
+        None => String::new(),
+    }
+}
+
+/// The arguments of a line of the configuration file, none for a blank line
+/// or a comment.
+///
+/// Arguments are separated by whitespace, or double quoted with `\"` and
+/// `\\` escapes.
+fn split(line: &str) -> Result<Vec<String>, String> {
+    let line = line.trim();
+    if line.starts_with('#') {
+        return Ok(vec![]);
+    }
+
+    let mut args = vec![];
+    let mut chars = line.chars().peekable();
+
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+
+        let mut arg = String::new();
+        match chars.peek() {
+            None => return Ok(args),
+            Some('"') => {
+                chars.next();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => arg.extend(chars.next()),
+                        Some(c) => arg.push(c),
+                        None => return Err("unbalanced quotes".to_string()),
+                    }
+                }
+                if chars.peek().is_some_and(|c| !c.is_whitespace()) {
+                    return Err("closing quote must be followed by a space".to_string());
+                }
+            }
+            Some(_) => {
+                while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                    arg.push(c);
+                }
+            }
+        }
+
+        args.push(arg);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> impl Iterator<Item = String> {
+        args.iter()
+            .map(|arg| arg.to_string())
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+
+    #[test]
+    fn file_settings_and_flags() {
+        let mut config = Config::default();
+        let text = "\
+            # Listen on two addresses and a socket file\n\
+            bind 127.0.0.1 ::1\n\
+            \n\
+            unixsocket \"/tmp/my redis.sock\"\n\
+            unixsocketperm 770\n\
+            save 3600 1\n\
+            port 7000\n\
+            save 300 100\n\
+            MAXMEMORY 100mb\n\
+            requirepass \"\"\n\
+            replicaof leader 6380\n";
+        config.parse(text).unwrap();
+
+        assert_eq!(config.bind, ["127.0.0.1", "::1"]);
+        assert_eq!(config.unixsocket, Some(PathBuf::from("/tmp/my redis.sock")));
+        assert_eq!(config.get("unixsocketperm").unwrap(), "770");
+        assert_eq!(config.get("save").unwrap(), "3600 1 300 100");
+        assert_eq!(config.maxmemory, 100 << 20);
+        assert_eq!(config.requirepass, "");
+        assert_eq!(config.get("replicaof").unwrap(), "leader 6380");
+
+        config.parse("save \"\"\n").unwrap();
+        assert!(config.save.is_empty());
+
+        assert_eq!(
+            config.parse("port 6379\nport x\n"),
+            Err("2: argument couldn't be parsed into an integer".to_string())
+        );
+        assert_eq!(
+            config.parse("dir /tmp\n"),
+            Err("1: unknown setting `dir`".to_string())
+        );
+        assert!(config.parse("requirepass \"open\n").is_err());
+
+        // Flags override the file, and take `host:port` leaders.
+        let config = Config::from_args(args(&["--port", "7000", "--replicaof", "a:1"])).unwrap();
+        assert_eq!(config.port, 7000);
+        assert_eq!(config.replicaof, Some(("a".to_string(), 1)));
+        assert!(Config::from_args(args(&["--port"])).is_err());
+        assert!(Config::from_args(args(&["/missing.conf"])).is_err());
+    }
+
+    #[tokio::test]
+    async fn set_applies_every_setting_or_none() {
+        let db = Db::new();
+        let setting = |name: &str, value: &str| (name.to_string(), value.to_string());
+
+        set(
+            &db,
+            &[
+                setting("maxmemory", "1kb"),
+                setting("maxmemory-policy", "allkeys-lru"),
+                setting("notify-keyspace-events", "KEA"),
+            ],
+        )
+        .unwrap();
+        assert_eq!(db.max_memory(), (1024, Policy::AllKeysLru));
+        assert_eq!(
+            db.notify_keyspace_events(),
+            Events::ALL | Events::KEYSPACE | Events::KEYEVENT
+        );
+
+        let patterns = ["maxmemory*".to_string()];
+        assert_eq!(
+            get(&db, &patterns),
+            [
+                ("maxmemory", "1024".to_string()),
+                ("maxmemory-policy", "allkeys-lru".to_string())
+            ]
+        );
+
+        // An invalid or immutable setting fails the whole command.
+        let err = set(&db, &[setting("maxmemory", "0"), setting("port", "7000")]);
+        assert_eq!(
+            err,
+            Err(
+                "CONFIG SET failed (possibly related to argument 'port') - can't set immutable \
+                 config"
+                    .to_string()
+            )
+        );
+        assert!(set(
+            &db,
+            &[setting("maxmemory", "0"), setting("appendfsync", "x")]
+        )
+        .is_err());
+        assert!(set(&db, &[setting("nope", "1")]).is_err());
+        assert_eq!(db.max_memory().0, 1024);
+        assert_eq!(db.config().get("port").unwrap(), "6379");
+    }
+}
@@ -0,0 +1,1492 @@
+use tokio::sync::{broadcast, Notify};
+use tokio::time::{self, Duration, Instant};
+
+mod blocking;
+mod databases;
+mod eviction;
+mod hash;
+mod list;
+mod notify;
+mod scan;
+mod set;
+mod stream;
+mod zset;
+
+use blocking::Blocking;
+pub use blocking::{Block, Pop, Popped, Waiter};
+use eviction::Limit;
+pub use eviction::{parse_memory, Policy};
+pub use list::End;
+pub use notify::Events;
+use notify::Notifier;
+use stream::StreamWaiters;
+pub use stream::{Claim, Fields, StreamId, StreamWatch, XAddId};
+pub(crate) use stream::{Group, Pending, Stream};
+pub use zset::ZAdd;
+pub(crate) use zset::ZSet;
+
+use crate::acl::Acl;
+use crate::aof::Aof;
+use crate::clients::Clients;
+use crate::config::Config;
+use crate::glob;
+use crate::replication::Replication;
+use crate::slowlog::SlowLog;
+use crate::snapshot::Snapshot;
+
+use bytes::{Bytes, BytesMut};
+use std::collections::hash_map::{DefaultHasher, RandomState};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::hash::{BuildHasher, Hash, Hasher};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{
+    Arc, Mutex, MutexGuard, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard, Weak,
+};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Number of shards used by `Db::new`.
+pub const DEFAULT_SHARDS: usize = 16;
+
+/// Number of logical databases, see `Db::select`.
+pub const DATABASES: usize = 16;
+
+/// Server state shared across all connections.
+///
+/// The server holds `DATABASES` logical databases, each a keyspace of its
+/// own. A `Db` handle operates on the database it selects, 0 by default;
+/// everything else is shared by the handles.
+///
+/// Each keyspace is split into shards, each guarded by its own mutex, so
+/// commands on different keys rarely contend for the same lock. A key always
+/// lives in the shard picked by its hash.
+///
+/// Every shard contains a `HashMap` storing the key/value data, a `BTreeSet`
+/// of pending expirations and another of the keys in `SCAN` order. A
+/// background task purges keys once their time to live has elapsed; keys are
+/// also purged lazily when they are accessed.
+///
+/// Cloning a `Db` is shallow and only increments a reference count.
+#[derive(Debug, Clone)]
+pub struct Db {
+    shared: Arc<Shared>,
+
+    /// Index of the selected database.
+    index: usize,
+}
+
+#[derive(Debug)]
+struct Shared {
+    /// The logical databases, by index.
+    databases: Vec<Database>,
+
+    /// Notifies the background task handling entry expiration. The background
+    /// task waits on this to be notified, then checks for expired values or the
+    /// shutdown signal.
+    background_task: Notify,
+
+    /// Held for reading by every command and for writing by transactions, so
+    /// a transaction never interleaves with other commands.
+    commands: RwLock<()>,
+
+    /// The pub/sub channels. This is independent of the keyspace, so
+    /// publishing never touches a shard lock. Shards publish keyspace events
+    /// while locked, never the other way around.
+    pub_sub: Arc<Mutex<PubSub>>,
+
+    /// Publishes keyspace events to `pub_sub`, shared with every shard.
+    notifier: Arc<Notifier>,
+
+    /// Clients blocked on lists, in any database. Locked before any shard,
+    /// never while a shard lock is held.
+    blocking: Mutex<Blocking>,
+
+    /// Tasks waiting for entries to be added to streams, in any database.
+    stream_waiters: Mutex<StreamWaiters>,
+
+    /// Whether write commands are logged, to the append-only file or the
+    /// replication stream. Write commands hold a read lock while they run, so
+    /// logging only starts once those running unlogged are done.
+    logging: RwLock<bool>,
+
+    /// The append-only file, if enabled. Logged write commands hold this lock
+    /// while they run, so they are logged in the order they are applied.
+    aof: Mutex<Option<Aof>>,
+
+    /// Snapshot settings and the state of the running save, if any.
+    snapshot: Mutex<Snapshot>,
+
+    /// The replication stream and the leader followed, if any. Locked after
+    /// the append-only file, so commands are streamed in the order they are
+    /// applied.
+    replication: Mutex<Replication>,
+
+    /// Number of write commands applied since the last snapshot.
+    changes: AtomicU64,
+
+    /// The connected clients, along with the commands they sent.
+    clients: Clients,
+
+    /// Reads of a key which found it, and which did not, see `Db::lookup`.
+    keyspace_hits: AtomicU64,
+    keyspace_misses: AtomicU64,
+
+    /// Keys removed to stay under the memory limit.
+    evicted_keys: AtomicU64,
+
+    /// When the server started, for `INFO`.
+    started_at: Instant,
+
+    /// The slow log and statistics of the commands run.
+    slowlog: Mutex<SlowLog>,
+
+    /// The users and their permissions.
+    acl: Mutex<Acl>,
+
+    /// The settings, as listed by `CONFIG GET`.
+    config: Mutex<Config>,
+
+    /// The memory limit and eviction policy.
+    limit: Mutex<Limit>,
+
+    /// Approximate bytes used by the entries of all the shards of every
+    /// database, which each hold a reference to it.
+    used_memory: Arc<AtomicUsize>,
+
+    /// True when the Db instance is shutting down. This happens when all `Db`
+    /// values drop. Setting this to `true` signals to the background task to
+    /// exit.
+    shutdown: AtomicBool,
+}
+
+/// Server-wide counters, see `Db::stats`.
+#[derive(Debug, Clone, Copy)]
+pub struct Stats {
+    pub uptime: Duration,
+    pub keyspace_hits: u64,
+    pub keyspace_misses: u64,
+    pub evicted_keys: u64,
+}
+
+/// A logical database.
+#[derive(Debug)]
+struct Database {
+    /// The keyspace shards. No async operations are performed while a shard
+    /// lock is held.
+    shards: Vec<Mutex<Shard>>,
+}
+
+/// The pub/sub key-space. There is one broadcast channel per subscribed
+/// channel name and per subscribed pattern.
+#[derive(Debug, Default)]
+struct PubSub {
+    /// Senders for `SUBSCRIBE`d channels, keyed by channel name.
+    channels: HashMap<String, broadcast::Sender<Bytes>>,
+
+    /// Senders for `PSUBSCRIBE`d patterns, keyed by pattern. Messages carry
+    /// the name of the channel they were published to.
+    patterns: HashMap<String, broadcast::Sender<(String, Bytes)>>,
+}
+
+#[derive(Debug, Default)]
+struct Shard {
+    /// Index of the database the shard belongs to.
+    db: usize,
+
+    /// The key-value data.
+    entries: HashMap<String, Entry>,
+
+    /// Tracks key TTLs, ordered by when they expire.
+    ///
+    /// The key is part of the tuple, so two keys expiring at the same instant
+    /// are kept apart.
+    expirations: BTreeSet<(Instant, String)>,
+
+    /// Every key along with its position, in the order `SCAN` walks them.
+    positions: BTreeSet<(u64, String)>,
+
+    /// Last version stamped on an entry of this shard.
+    version: u64,
+
+    /// Approximate bytes used by the entries of all the shards, see
+    /// `Shard::account`.
+    used_memory: Arc<AtomicUsize>,
+
+    /// Publishes the events of the keys this shard expires, see `Db::notify`.
+    notifier: Arc<Notifier>,
+}
+
+/// Entry in the key-value store
+#[derive(Debug)]
+struct Entry {
+    /// Stored data
+    value: Value,
+
+    /// Instant at which the entry expires and should be removed from the
+    /// database.
+    expires_at: Option<Instant>,
+
+    /// Changes whenever the entry is written, see `Db::version`.
+    version: u64,
+
+    /// Approximate bytes used by the key and its value.
+    size: usize,
+
+    /// When the entry was last accessed, for the LRU eviction policies.
+    accessed_at: Instant,
+
+    /// How often the entry is accessed, for the LFU eviction policy, see
+    /// `Entry::accessed`.
+    frequency: u8,
+}
+
+/// A value stored in the keyspace.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Value {
+    String(Bytes),
+    List(VecDeque<Bytes>),
+    Hash(HashMap<Bytes, Bytes>),
+    Set(HashSet<Bytes>),
+    ZSet(ZSet),
+    Stream(Stream),
+}
+
+/// Error of an operation on the keyspace.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// The key holds a value of another type than the operation expects.
+    WrongType,
+
+    /// The operation does not apply to the value, e.g. incrementing a string
+    /// which is not an integer.
+    Invalid(&'static str),
+
+    /// The stream consumer group to create already exists.
+    BusyGroup,
+
+    /// The stream or its consumer group does not exist.
+    NoGroup,
+
+    /// The memory limit is reached and no key can be evicted.
+    OutOfMemory,
+}
+
+/// The collection types of `Value`, see `Db::read` and `Db::update`.
+trait Collection: Default {
+    fn from_value(value: &Value) -> Option<&Self>;
+
+    fn from_value_mut(value: &mut Value) -> Option<&mut Self>;
+
+    fn into_value(self) -> Value;
+
+    fn is_empty(&self) -> bool;
+}
+
+impl Db {
+    /// Create a new, empty, `Db` instance with `DEFAULT_SHARDS` shards.
+    pub fn new() -> Db {
+        Db::with_shards(DEFAULT_SHARDS)
+    }
+
+    /// Create a new, empty, `Db` instance split into `shards` shards.
+    /// Allocates shared state and spawns a background task to manage key
+    /// expiration.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `shards` is zero.
+    pub fn with_shards(shards: usize) -> Db {
+        assert!(shards > 0, "a Db needs at least one shard");
+
+        let used_memory = Arc::new(AtomicUsize::new(0));
+        let pub_sub = Arc::new(Mutex::default());
+        let notifier = Arc::new(Notifier::new(Arc::clone(&pub_sub)));
+
+        let shared = Arc::new(Shared {
+            databases: (0..DATABASES)
+                .map(|db| Database {
+                    shards: (0..shards)
+                        .map(|_| {
+                            Mutex::new(Shard {
+                                db,
+                                used_memory: used_memory.clone(),
+                                notifier: notifier.clone(),
+                                ..Shard::default()
+                            })
+                        })
+                        .collect(),
+                })
+                .collect(),
+            background_task: Notify::new(),
+            commands: RwLock::default(),
+            pub_sub,
+            notifier,
+            blocking: Mutex::default(),
+            stream_waiters: Mutex::default(),
+            logging: RwLock::default(),
+            aof: Mutex::default(),
+            snapshot: Mutex::default(),
+            replication: Mutex::default(),
+            changes: AtomicU64::new(0),
+            clients: Clients::default(),
+            keyspace_hits: AtomicU64::new(0),
+            keyspace_misses: AtomicU64::new(0),
+            evicted_keys: AtomicU64::new(0),
+            started_at: Instant::now(),
+            slowlog: Mutex::default(),
+            acl: Mutex::default(),
+            config: Mutex::default(),
+            limit: Mutex::default(),
+            used_memory,
+            shutdown: AtomicBool::new(false),
+        });
+
+        // Start the background task.
+        tokio::spawn(purge_expired_tasks(shared.clone()));
+
+        Db { shared, index: 0 }
+    }
+
+    /// Number of shards each keyspace is split into.
+    pub fn shards(&self) -> usize {
+        self.database().shards.len()
+    }
+
+    /// Get the value associated with a key.
+    ///
+    /// Returns `None` if there is no value associated with the key, or if the
+    /// value has expired.
+    pub fn get(&self, key: &str) -> Result<Option<Bytes>, Error> {
+        let mut shard = self.database().shard(key);
+        let entry = self.lookup(shard.live_entry(key));
+        entry.map(|entry| entry.string().cloned()).transpose()
+    }
+
+    /// Set the value associated with a key along with an optional expiration
+    /// Duration.
+    ///
+    /// If a value is already associated with the key, it is replaced and its
+    /// previous time to live is discarded.
+    pub fn set(&self, key: String, value: Bytes, expire: Option<Duration>) {
+        self.insert(key.clone(), Value::String(value), expire);
+
+        self.notify(Events::STRING, "set", &key);
+        if expire.is_some() {
+            self.notify(Events::GENERIC, "expire", &key);
+        }
+    }
+
+    /// Store a value of any type, see `set`.
+    pub(crate) fn insert(&self, key: String, value: Value, expire: Option<Duration>) {
+        let expires_at = expire.map(expires_at);
+        let notify = self.database().shard(&key).insert(key, value, expires_at);
+
+        if notify {
+            self.shared.background_task.notify_one();
+        }
+    }
+
+    /// Set `key` to `value` only if it does not exist yet.
+    ///
+    /// Returns `true` if the value was set.
+    pub fn set_nx(&self, key: String, value: Bytes) -> bool {
+        let mut shard = self.database().shard(&key);
+
+        if shard.live_entry(&key).is_some() {
+            return false;
+        }
+
+        shard.insert(key.clone(), Value::String(value), None);
+        shard.notify(Events::STRING, "set", &key);
+        true
+    }
+
+    /// Set `key` to `value`, returning the previous value.
+    ///
+    /// Like `set`, this discards any previous time to live. Nothing is set if
+    /// the key holds another type than a string.
+    pub fn get_set(&self, key: String, value: Bytes) -> Result<Option<Bytes>, Error> {
+        let mut shard = self.database().shard(&key);
+
+        let prev = shard
+            .live_entry(&key)
+            .map(|entry| entry.string().cloned())
+            .transpose()?;
+        shard.insert(key.clone(), Value::String(value), None);
+        shard.notify(Events::STRING, "set", &key);
+        Ok(prev)
+    }
+
+    /// Get the values of several keys at once.
+    ///
+    /// All the shards involved are locked together, so the reply is a
+    /// consistent view of the keys. Keys holding another type than a string
+    /// are reported as missing.
+    pub fn get_many(&self, keys: &[String]) -> Vec<Option<Bytes>> {
+        let mut shards = self.database().lock(keys);
+
+        keys.iter()
+            .map(|key| {
+                self.lookup(shards.shard(key).live_entry(key))
+                    .and_then(|entry| entry.string().ok().cloned())
+            })
+            .collect()
+    }
+
+    /// Set several keys at once. Other connections observe either none or all
+    /// of the new values.
+    pub fn set_many(&self, pairs: Vec<(String, Bytes)>) {
+        let keys: Vec<_> = pairs.iter().map(|(key, _)| key.clone()).collect();
+        let mut shards = self.database().lock(&keys);
+
+        for (key, value) in pairs {
+            let shard = shards.shard(&key);
+            shard.insert(key.clone(), Value::String(value), None);
+            shard.notify(Events::STRING, "set", &key);
+        }
+    }
+
+    /// Remove keys, returning how many of them existed.
+    pub fn remove(&self, keys: &[String]) -> usize {
+        let mut shards = self.database().lock(keys);
+
+        keys.iter()
+            .filter(|key| {
+                let shard = shards.shard(key);
+                let removed = shard.remove(key);
+                if removed {
+                    shard.notify(Events::GENERIC, "del", key);
+                }
+                removed
+            })
+            .count()
+    }
+
+    /// Remove every key of the selected database.
+    pub fn clear(&self) {
+        self.database().clear();
+    }
+
+    /// Count how many of `keys` exist. A key mentioned twice is counted twice.
+    pub fn exists(&self, keys: &[String]) -> usize {
+        let mut shards = self.database().lock(keys);
+
+        keys.iter()
+            .filter(|key| shards.shard(key).live_entry(key).is_some())
+            .count()
+    }
+
+    /// Add `delta` to the integer stored at `key`, treating a missing key as
+    /// zero. The time to live of the key is kept.
+    ///
+    /// Returns the new value, or an error if the stored value is not an
+    /// integer or the result would overflow.
+    pub fn incr_by(&self, key: &str, delta: i64) -> Result<i64, Error> {
+        let mut shard = self.database().shard(key);
+
+        let current = match shard.live_entry(key) {
+            Some(entry) => std::str::from_utf8(entry.string()?)
+                .ok()
+                .and_then(|data| data.parse::<i64>().ok())
+                .ok_or(Error::Invalid("value is not an integer or out of range"))?,
+            None => 0,
+        };
+
+        let value = current
+            .checked_add(delta)
+            .ok_or(Error::Invalid("increment or decrement would overflow"))?;
+        let data = Value::String(Bytes::from(value.to_string()));
+
+        match shard.live_entry(key) {
+            Some(entry) => {
+                entry.value = data;
+                shard.touch(key);
+            }
+            None => {
+                shard.insert(key.to_string(), data, None);
+            }
+        }
+        shard.notify(Events::STRING, "incrby", key);
+
+        Ok(value)
+    }
+
+    /// Append `value` to the string stored at `key`, creating it if needed.
+    /// The time to live of the key is kept.
+    ///
+    /// Returns the length of the string after the append.
+    pub fn append(&self, key: &str, value: &[u8]) -> Result<usize, Error> {
+        let mut shard = self.database().shard(key);
+
+        match shard.live_entry(key) {
+            Some(entry) => {
+                let prev = entry.string()?;
+                let mut data = BytesMut::with_capacity(prev.len() + value.len());
+                data.extend_from_slice(prev);
+                data.extend_from_slice(value);
+
+                let len = data.len();
+                entry.value = Value::String(data.freeze());
+                shard.touch(key);
+                shard.notify(Events::STRING, "append", key);
+                Ok(len)
+            }
+            None => {
+                let data = Value::String(Bytes::copy_from_slice(value));
+                shard.insert(key.to_string(), data, None);
+                shard.notify(Events::STRING, "append", key);
+                Ok(value.len())
+            }
+        }
+    }
+
+    /// Length of the string stored at `key`, zero if the key does not exist.
+    pub fn strlen(&self, key: &str) -> Result<usize, Error> {
+        let mut shard = self.database().shard(key);
+
+        match shard.live_entry(key) {
+            Some(entry) => Ok(entry.string()?.len()),
+            None => Ok(0),
+        }
+    }
+
+    /// Name of the type of the value stored at `key`, `none` if the key does
+    /// not exist.
+    pub fn key_type(&self, key: &str) -> &'static str {
+        let mut shard = self.database().shard(key);
+        shard
+            .live_entry(key)
+            .map_or("none", |entry| entry.value.type_name())
+    }
+
+    /// Read the collection stored at `key`.
+    ///
+    /// Returns `None` if the key does not exist.
+    fn read<C: Collection, T>(
+        &self,
+        key: &str,
+        f: impl FnOnce(&C) -> T,
+    ) -> Result<Option<T>, Error> {
+        let mut shard = self.database().shard(key);
+
+        match self.lookup(shard.live_entry(key)) {
+            Some(entry) => C::from_value(&entry.value)
+                .map(|collection| Some(f(collection)))
+                .ok_or(Error::WrongType),
+            None => Ok(None),
+        }
+    }
+
+    /// Read the collections stored at each of `keys` at once, `None` standing
+    /// for keys which do not exist.
+    fn read_many<C: Collection, T>(
+        &self,
+        keys: &[String],
+        f: impl FnOnce(Vec<Option<&C>>) -> T,
+    ) -> Result<T, Error> {
+        let mut shards = self.database().lock(keys);
+
+        // Drop expired keys first, so they are not mistaken for live ones.
+        for key in keys {
+            self.lookup(shards.shard(key).live_entry(key));
+        }
+
+        let collections = keys
+            .iter()
+            .map(|key| match shards.get(key).entries.get(key) {
+                Some(entry) => C::from_value(&entry.value)
+                    .map(Some)
+                    .ok_or(Error::WrongType),
+                None => Ok(None),
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(f(collections))
+    }
+
+    /// Modify the collection stored at `key`, see `Shard::update`.
+    fn update<C: Collection, T>(
+        &self,
+        key: &str,
+        create: bool,
+        f: impl FnOnce(&mut C) -> T,
+    ) -> Result<Option<T>, Error> {
+        self.database().shard(key).update(key, create, f)
+    }
+
+    /// All live keys matching the glob-style `pattern`.
+    ///
+    /// Shards are walked one at a time, so this never locks the whole
+    /// keyspace at once.
+    pub fn keys(&self, pattern: &str) -> Vec<String> {
+        let now = Instant::now();
+
+        self.database()
+            .shards
+            .iter()
+            .flat_map(|shard| {
+                let shard = shard.lock().unwrap();
+                shard
+                    .entries
+                    .iter()
+                    .filter(|(key, entry)| !entry.is_expired(now) && glob::matches(pattern, key))
+                    .map(|(key, _)| key.clone())
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    /// Set a time to live on an existing key, replacing any previous one.
+    ///
+    /// Returns `false` if the key does not exist.
+    pub fn expire(&self, key: &str, duration: Duration) -> bool {
+        let mut shard = self.database().shard(key);
+
+        let prev = match shard.live_entry(key) {
+            Some(entry) => entry.expires_at.take(),
+            None => return false,
+        };
+
+        if let Some(when) = prev {
+            shard.expirations.remove(&(when, key.to_string()));
+        }
+
+        let notify = shard.set_expiration(key.to_string(), expires_at(duration));
+        shard.notify(Events::GENERIC, "expire", key);
+
+        drop(shard);
+
+        if notify {
+            self.shared.background_task.notify_one();
+        }
+
+        true
+    }
+
+    /// Remove the time to live of a key, making it persistent.
+    ///
+    /// Returns `false` if the key does not exist or has no time to live.
+    pub fn persist(&self, key: &str) -> bool {
+        let mut shard = self.database().shard(key);
+
+        let when = match shard
+            .live_entry(key)
+            .and_then(|entry| entry.expires_at.take())
+        {
+            Some(when) => when,
+            None => return false,
+        };
+
+        shard.expirations.remove(&(when, key.to_string()));
+        shard.touch(key);
+        shard.notify(Events::GENERIC, "persist", key);
+        true
+    }
+
+    /// Version of a key, which changes every time the key is written. `None`
+    /// if the key does not exist.
+    ///
+    /// Comparing versions tells whether a key was modified in between, as
+    /// needed by `WATCH`.
+    pub fn version(&self, key: &str) -> Option<u64> {
+        let mut shard = self.database().shard(key);
+        shard.live_entry(key).map(|entry| entry.version)
+    }
+
+    /// Remaining time to live of a key.
+    ///
+    /// Returns `None` if the key does not exist and `Some(None)` if it exists
+    /// but has no associated expiration.
+    pub fn ttl(&self, key: &str) -> Option<Option<Duration>> {
+        let mut shard = self.database().shard(key);
+        let now = Instant::now();
+
+        shard.live_entry(key).map(|entry| {
+            entry
+                .expires_at
+                .map(|when| when.saturating_duration_since(now))
+        })
+    }
+
+    /// Every live key of each database, by index, along with its value and
+    /// remaining time to live.
+    ///
+    /// All shards are locked together while the entries are copied, so the
+    /// result is a point-in-time view of the keyspace. Strings are reference
+    /// counted, so only collections are actually copied.
+    pub(crate) fn dump(&self) -> Vec<Vec<(String, Value, Option<Duration>)>> {
+        let databases: Vec<Vec<_>> = self
+            .shared
+            .databases
+            .iter()
+            .map(|database| {
+                database
+                    .shards
+                    .iter()
+                    .map(|shard| shard.lock().unwrap())
+                    .collect()
+            })
+            .collect();
+        let now = Instant::now();
+
+        databases
+            .iter()
+            .map(|shards| {
+                shards
+                    .iter()
+                    .flat_map(|shard| &shard.entries)
+                    .filter(|(_, entry)| !entry.is_expired(now))
+                    .map(|(key, entry)| {
+                        let ttl = entry
+                            .expires_at
+                            .map(|when| when.saturating_duration_since(now));
+                        (key.clone(), entry.value.clone(), ttl)
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Lock out transactions while a single command runs.
+    pub(crate) fn lock_command(&self) -> RwLockReadGuard<'_, ()> {
+        self.shared.commands.read().unwrap()
+    }
+
+    /// Lock out every other command while a transaction runs.
+    pub(crate) fn lock_transaction(&self) -> RwLockWriteGuard<'_, ()> {
+        self.shared.commands.write().unwrap()
+    }
+
+    /// Lock whether write commands are logged, for a write command to run.
+    pub(crate) fn logging(&self) -> RwLockReadGuard<'_, bool> {
+        self.shared.logging.read().unwrap()
+    }
+
+    /// Lock whether write commands are logged, to change it once no write
+    /// command runs.
+    pub(crate) fn logging_mut(&self) -> RwLockWriteGuard<'_, bool> {
+        self.shared.logging.write().unwrap()
+    }
+
+    /// Lock the append-only file slot.
+    ///
+    /// A command panicking while it holds the lock leaves the file as it
+    /// was, so the lock is taken over rather than failing every later write.
+    pub(crate) fn aof(&self) -> MutexGuard<'_, Option<Aof>> {
+        self.shared
+            .aof
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Lock the snapshot settings.
+    pub(crate) fn snapshot(&self) -> MutexGuard<'_, Snapshot> {
+        self.shared.snapshot.lock().unwrap()
+    }
+
+    /// Lock the users.
+    pub(crate) fn acl(&self) -> MutexGuard<'_, Acl> {
+        self.shared.acl.lock().unwrap()
+    }
+
+    /// Lock the settings.
+    pub(crate) fn config(&self) -> MutexGuard<'_, Config> {
+        self.shared.config.lock().unwrap()
+    }
+
+    /// Lock the slow log.
+    pub(crate) fn slowlog(&self) -> MutexGuard<'_, SlowLog> {
+        self.shared.slowlog.lock().unwrap()
+    }
+
+    /// The connected clients.
+    pub fn clients(&self) -> &Clients {
+        &self.shared.clients
+    }
+
+    /// Count a read of a key towards the keyspace hits or misses, depending
+    /// on whether `entry` was found.
+    fn lookup<T>(&self, entry: Option<T>) -> Option<T> {
+        let counter = match entry {
+            Some(_) => &self.shared.keyspace_hits,
+            None => &self.shared.keyspace_misses,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+
+        entry
+    }
+
+    /// Server-wide counters, as reported by `INFO`.
+    pub fn stats(&self) -> Stats {
+        let shared = &self.shared;
+
+        Stats {
+            uptime: shared.started_at.elapsed(),
+            keyspace_hits: shared.keyspace_hits.load(Ordering::Relaxed),
+            keyspace_misses: shared.keyspace_misses.load(Ordering::Relaxed),
+            evicted_keys: shared.evicted_keys.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Number of keys of the selected database, and of those with a time to
+    /// live. Keys which expired but were not purged yet are counted.
+    pub fn key_counts(&self) -> (usize, usize) {
+        self.database()
+            .shards
+            .iter()
+            .fold((0, 0), |(keys, expires), shard| {
+                let shard = shard.lock().unwrap();
+                (
+                    keys + shard.entries.len(),
+                    expires + shard.expirations.len(),
+                )
+            })
+    }
+
+    /// Lock the replication state.
+    pub(crate) fn replication(&self) -> MutexGuard<'_, Replication> {
+        self.shared.replication.lock().unwrap()
+    }
+
+    /// Count a write command towards the next snapshot.
+    pub(crate) fn record_change(&self) {
+        self.shared.changes.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Number of write commands applied since the last snapshot.
+    pub(crate) fn changes(&self) -> u64 {
+        self.shared.changes.load(Ordering::Relaxed)
+    }
+
+    /// Forget the `n` changes a snapshot which just completed contains.
+    pub(crate) fn saved_changes(&self, n: u64) {
+        self.shared.changes.fetch_sub(n, Ordering::Relaxed);
+    }
+
+    /// A handle which does not keep the database alive, for background tasks
+    /// which should stop along with it.
+    pub(crate) fn downgrade(&self) -> WeakDb {
+        WeakDb {
+            shared: Arc::downgrade(&self.shared),
+            index: self.index,
+        }
+    }
+
+    /// Returns a `Receiver` for the requested channel.
+    ///
+    /// The returned `Receiver` is used to receive values broadcast by `PUBLISH`
+    /// commands.
+    pub fn subscribe(&self, channel: String) -> broadcast::Receiver<Bytes> {
+        let mut pub_sub = self.shared.pub_sub.lock().unwrap();
+        subscribe(&mut pub_sub.channels, channel)
+    }
+
+    /// Returns a `Receiver` for every channel matching the glob-style
+    /// `pattern`. Received values are `(channel, message)` pairs.
+    pub fn psubscribe(&self, pattern: String) -> broadcast::Receiver<(String, Bytes)> {
+        let mut pub_sub = self.shared.pub_sub.lock().unwrap();
+        subscribe(&mut pub_sub.patterns, pattern)
+    }
+
+    /// Drop the broadcast channel for `channel` if its last `Receiver` was
+    /// dropped.
+    pub(crate) fn unsubscribe(&self, channel: &str) {
+        let mut pub_sub = self.shared.pub_sub.lock().unwrap();
+        unsubscribe(&mut pub_sub.channels, channel);
+    }
+
+    /// Drop the broadcast channel for `pattern` if its last `Receiver` was
+    /// dropped.
+    pub(crate) fn punsubscribe(&self, pattern: &str) {
+        let mut pub_sub = self.shared.pub_sub.lock().unwrap();
+        unsubscribe(&mut pub_sub.patterns, pattern);
+    }
+
+    /// Publish a message to the channel. Returns the number of subscribers
+    /// listening on the channel, counting pattern subscriptions that match it.
+    pub fn publish(&self, channel: &str, message: Bytes) -> usize {
+        let mut pub_sub = self.shared.pub_sub.lock().unwrap();
+        publish(&mut pub_sub, channel, message)
+    }
+}
+
+/// When a time to live of `duration` from now elapses. Past the range of
+/// `Instant`, which commands never reach, it is about 30 years from now.
+fn expires_at(duration: Duration) -> Instant {
+    let now = Instant::now();
+    now.checked_add(duration)
+        .unwrap_or_else(|| now + Duration::from_secs(30 * 365 * 86400))
+}
+
+/// Time elapsed since the Unix epoch, the reference of absolute expirations.
+pub(crate) fn unix_time() -> Duration {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+}
+
+/// Publish a message to `channel`, see `Db::publish`.
+fn publish(pub_sub: &mut PubSub, channel: &str, message: Bytes) -> usize {
+    // On a successful message send on the broadcast channel, the number
+    // of subscribers is returned. An error indicates there are no
+    // receivers, in which case the channel is dropped.
+    let mut receivers = match pub_sub.channels.get(channel) {
+        Some(tx) => tx.send(message.clone()).unwrap_or(0),
+        None => 0,
+    };
+
+    pub_sub.patterns.retain(|pattern, tx| {
+        if tx.receiver_count() == 0 {
+            return false;
+        }
+
+        if glob::matches(pattern, channel) {
+            receivers += tx.send((channel.to_string(), message.clone())).unwrap_or(0);
+        }
+
+        true
+    });
+
+    if receivers == 0 {
+        pub_sub.channels.remove(channel);
+    }
+
+    receivers
+}
+
+/// Get or create the broadcast channel for `name` and subscribe to it.
+fn subscribe<T: Clone>(
+    senders: &mut HashMap<String, broadcast::Sender<T>>,
+    name: String,
+) -> broadcast::Receiver<T> {
+    use std::collections::hash_map::Entry;
+
+    match senders.entry(name) {
+        Entry::Occupied(e) => e.get().subscribe(),
+        Entry::Vacant(e) => {
+            // No broadcast channel exists yet, so create one.
+            //
+            // The channel is created with a capacity of `1024` messages. A
+            // message is stored in the channel until **all** subscribers
+            // have seen it. This means that a slow subscriber could result
+            // in messages being held indefinitely.
+            //
+            // When the channel's capacity fills up, publishing will result
+            // in old messages being dropped. This prevents slow consumers
+            // from blocking the entire system.
+            let (tx, rx) = broadcast::channel(1024);
+            e.insert(tx);
+            rx
+        }
+    }
+}
+
+/// Remove the broadcast channel for `name` once nobody is subscribed to it.
+fn unsubscribe<T>(senders: &mut HashMap<String, broadcast::Sender<T>>, name: &str) {
+    if senders.get(name).is_some_and(|tx| tx.receiver_count() == 0) {
+        senders.remove(name);
+    }
+}
+
+/// A weak reference to a `Db`, see `Db::downgrade`.
+#[derive(Debug, Clone)]
+pub(crate) struct WeakDb {
+    shared: Weak<Shared>,
+    index: usize,
+}
+
+impl WeakDb {
+    /// The database, unless every `Db` handle has been dropped.
+    pub(crate) fn upgrade(&self) -> Option<Db> {
+        let index = self.index;
+        self.shared.upgrade().map(|shared| Db { shared, index })
+    }
+}
+
+impl Default for Db {
+    fn default() -> Db {
+        Db::new()
+    }
+}
+
+impl Drop for Db {
+    fn drop(&mut self) {
+        // If this is the last active `Db` instance besides the one held by the
+        // background task, the background task must be notified to shut down.
+        if Arc::strong_count(&self.shared) == 2 {
+            self.shared.shutdown.store(true, Ordering::SeqCst);
+            self.shared.background_task.notify_one();
+        }
+    }
+}
+
+impl Database {
+    /// Index of the shard owning `key`.
+    fn index(&self, key: &str) -> usize {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        (hasher.finish() % self.shards.len() as u64) as usize
+    }
+
+    /// Lock the shard owning `key`.
+    fn shard(&self, key: &str) -> MutexGuard<'_, Shard> {
+        self.shards[self.index(key)].lock().unwrap()
+    }
+
+    /// Lock every shard owning one of `keys`.
+    ///
+    /// Shards are always locked in index order, so two multi-key commands can
+    /// never deadlock each other.
+    fn lock(&self, keys: &[String]) -> Locked<'_> {
+        let indices: BTreeSet<_> = keys.iter().map(|key| self.index(key)).collect();
+
+        Locked {
+            database: self,
+            guards: indices
+                .into_iter()
+                .map(|i| (i, self.shards[i].lock().unwrap()))
+                .collect(),
+        }
+    }
+}
+
+impl Shared {
+    /// Purge all expired keys of every database and return the `Instant` at
+    /// which the **next** key will expire. The background task will sleep
+    /// until this instant.
+    ///
+    /// Shards are locked one at a time, so the purge never blocks the whole
+    /// keyspace.
+    fn purge_expired_keys(&self) -> Option<Instant> {
+        if self.is_shutdown() {
+            // The database is shutting down. All handles to the shared state
+            // have dropped. The background task should exit.
+            return None;
+        }
+
+        let now = Instant::now();
+
+        self.databases
+            .iter()
+            .flat_map(|database| &database.shards)
+            .filter_map(|shard| shard.lock().unwrap().purge_expired_keys(now))
+            .min()
+    }
+
+    /// Returns `true` if the database is shutting down
+    ///
+    /// The `shutdown` flag is set when all `Db` values have dropped, indicating
+    /// that the shared state can no longer be accessed.
+    fn is_shutdown(&self) -> bool {
+        self.shutdown.load(Ordering::SeqCst)
+    }
+}
+
+/// A set of shard locks held together, see `Database::lock`.
+struct Locked<'a> {
+    database: &'a Database,
+    guards: BTreeMap<usize, MutexGuard<'a, Shard>>,
+}
+
+impl Locked<'_> {
+    /// The locked shard owning `key`, for reading.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `key` was not passed to `Database::lock`.
+    fn get(&self, key: &str) -> &Shard {
+        let index = self.database.index(key);
+        self.guards.get(&index).expect("shard not locked")
+    }
+
+    /// The locked shard owning `key`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `key` was not passed to `Database::lock`.
+    fn shard(&mut self, key: &str) -> &mut Shard {
+        let index = self.database.index(key);
+        self.guards.get_mut(&index).expect("shard not locked")
+    }
+}
+
+impl Shard {
+    fn next_expiration(&self) -> Option<Instant> {
+        self.expirations
+            .iter()
+            .next()
+            .map(|expiration| expiration.0)
+    }
+
+    /// Look up an entry, removing it first if its time to live has elapsed.
+    ///
+    /// The lookup counts as an access to the entry.
+    fn live_entry(&mut self, key: &str) -> Option<&mut Entry> {
+        let now = Instant::now();
+
+        if self.entries.get(key)?.is_expired(now) {
+            self.remove(key);
+            self.notify(Events::EXPIRED, "expired", key);
+            return None;
+        }
+
+        let entry = self.entries.get_mut(key)?;
+        entry.accessed(now);
+        Some(entry)
+    }
+
+    /// Insert an entry, replacing any previous value and time to live.
+    ///
+    /// Returns `true` if the background task needs to be notified of the new
+    /// expiration.
+    fn insert(&mut self, key: String, value: Value, expires_at: Option<Instant>) -> bool {
+        self.version += 1;
+
+        let entry = Entry::new(&key, value, self.version);
+        let size = entry.size;
+        let prev = self.entries.insert(key.clone(), entry);
+        self.account(size, prev.as_ref().map_or(0, |prev| prev.size));
+
+        if prev.is_none() {
+            self.positions.insert((scan::position(&key), key.clone()));
+        }
+
+        if let Some(when) = prev.and_then(|prev| prev.expires_at) {
+            self.expirations.remove(&(when, key.clone()));
+        }
+
+        expires_at.is_some_and(|when| self.set_expiration(key, when))
+    }
+
+    /// Remove an entry along with its expiration.
+    ///
+    /// Returns `true` if a live entry was removed.
+    fn remove(&mut self, key: &str) -> bool {
+        self.take(key)
+            .is_some_and(|entry| !entry.is_expired(Instant::now()))
+    }
+
+    /// Remove an entry along with its expiration, returning it even if its
+    /// time to live has elapsed.
+    fn take(&mut self, key: &str) -> Option<Entry> {
+        let entry = self.entries.remove(key)?;
+        self.account(0, entry.size);
+        self.positions
+            .remove(&(scan::position(key), key.to_string()));
+
+        if let Some(when) = entry.expires_at {
+            self.expirations.remove(&(when, key.to_string()));
+        }
+        Some(entry)
+    }
+
+    /// Record the expiration of an existing entry.
+    ///
+    /// Returns `true` if the background task needs to be notified because the
+    /// new expiration is earlier than any other in this shard.
+    fn set_expiration(&mut self, key: String, when: Instant) -> bool {
+        let notify = self
+            .next_expiration()
+            .map(|expiration| expiration > when)
+            .unwrap_or(true);
+
+        if let Some(entry) = self.entries.get_mut(&key) {
+            entry.expires_at = Some(when);
+            self.expirations.insert((when, key.clone()));
+            self.touch(&key);
+        }
+
+        notify
+    }
+
+    /// Modify the collection stored at `key` with `f`.
+    ///
+    /// A missing key is first created as an empty collection if `create` is
+    /// set; otherwise `f` is not called and `None` is returned. Collections
+    /// left empty are removed, as Redis never stores empty collections.
+    fn update<C: Collection, T>(
+        &mut self,
+        key: &str,
+        create: bool,
+        f: impl FnOnce(&mut C) -> T,
+    ) -> Result<Option<T>, Error> {
+        if self.live_entry(key).is_none() {
+            if !create {
+                return Ok(None);
+            }
+
+            self.insert(key.to_string(), C::default().into_value(), None);
+        }
+
+        let entry = self.entries.get_mut(key).expect("entry just checked");
+        let collection = C::from_value_mut(&mut entry.value).ok_or(Error::WrongType)?;
+
+        let out = f(collection);
+
+        if collection.is_empty() {
+            self.remove(key);
+        } else {
+            self.touch(key);
+        }
+
+        Ok(Some(out))
+    }
+
+    /// Stamp a new version on an entry which was modified in place, and
+    /// estimate its size again.
+    fn touch(&mut self, key: &str) {
+        if let Some(entry) = self.entries.get_mut(key) {
+            self.version += 1;
+            entry.version = self.version;
+
+            let prev = entry.resize(key);
+            let size = entry.size;
+            self.account(size, prev);
+        }
+    }
+
+    /// Remove every key whose expiration is at or before `now`, returning the
+    /// next pending expiration of this shard.
+    fn purge_expired_keys(&mut self, now: Instant) -> Option<Instant> {
+        while let Some((when, key)) = self.expirations.iter().next().cloned() {
+            if when > now {
+                return Some(when);
+            }
+
+            // The key expired, remove it
+            if let Some(entry) = self.entries.remove(&key) {
+                self.account(0, entry.size);
+                self.positions.remove(&(scan::position(&key), key.clone()));
+                self.notify(Events::EXPIRED, "expired", &key);
+            }
+            self.expirations.remove(&(when, key));
+        }
+
+        None
+    }
+}
+
+impl Entry {
+    fn is_expired(&self, now: Instant) -> bool {
+        self.expires_at.is_some_and(|when| when <= now)
+    }
+
+    /// The value, which must be a string.
+    fn string(&self) -> Result<&Bytes, Error> {
+        match &self.value {
+            Value::String(data) => Ok(data),
+            _ => Err(Error::WrongType),
+        }
+    }
+}
+
+impl Value {
+    /// Name of the type, as reported by `TYPE`.
+    pub(crate) fn type_name(&self) -> &'static str {
+        match self {
+            Value::String(_) => "string",
+            Value::List(_) => "list",
+            Value::Hash(_) => "hash",
+            Value::Set(_) => "set",
+            Value::ZSet(_) => "zset",
+            Value::Stream(_) => "stream",
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::WrongType => {
+                "WRONGTYPE Operation against a key holding the wrong kind of value".fmt(fmt)
+            }
+            Error::Invalid(msg) => write!(fmt, "ERR {}", msg),
+            Error::BusyGroup => "BUSYGROUP Consumer Group name already exists".fmt(fmt),
+            Error::NoGroup => "NOGROUP No such key or consumer group".fmt(fmt),
+            Error::OutOfMemory => {
+                "OOM command not allowed when used memory > 'maxmemory'.".fmt(fmt)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Resolve the inclusive `start` and `stop` indices of a range over `len`
+/// items, negative indices counting from the end.
+///
+/// Returns `None` if the range is empty.
+fn index_range(len: usize, start: i64, stop: i64) -> Option<std::ops::RangeInclusive<usize>> {
+    let len = len as i64;
+    let start = if start < 0 {
+        (len + start).max(0)
+    } else {
+        start
+    };
+    let stop = if stop < 0 {
+        len + stop
+    } else {
+        stop.min(len - 1)
+    };
+
+    if start > stop || start >= len {
+        return None;
+    }
+
+    Some(start as usize..=stop as usize)
+}
+
+/// A random number, good enough to sample keys or generate IDs.
+pub(crate) fn random() -> u64 {
+    // Every `RandomState` is seeded differently.
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(0);
+    hasher.finish()
+}
+
+/// Routine executed by the background task.
+///
+/// Wait to be notified. On notification, purge any expired keys from the shared
+/// state handle. If `shutdown` is set, terminate the task.
+async fn purge_expired_tasks(shared: Arc<Shared>) {
+    // If the shutdown flag is set, then the task should exit.
+    while !shared.is_shutdown() {
+        // Purge all keys that are expired. The function returns the instant at
+        // which the **next** key will expire. The worker should wait until the
+        // instant has passed then purge again.
+        if let Some(when) = shared.purge_expired_keys() {
+            // Wait until the next key expires **or** until the background task
+            // is notified. If the task is notified, then it must reload its
+            // state as new keys have been set to expire early. This is done by
+            // looping.
+            tokio::select! {
+                _ = time::sleep_until(when) => {}
+                _ = shared.background_task.notified() => {}
+            }
+        } else {
+            // There are no keys expiring in the future. Wait until the task is
+            // notified.
+            shared.background_task.notified().await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn key_expires() {
+        let db = Db::new();
+        db.set("foo".into(), "bar".into(), Some(Duration::from_secs(1)));
+
+        assert_eq!(db.get("foo"), Ok(Some("bar".into())));
+        assert_eq!(db.ttl("foo"), Some(Some(Duration::from_secs(1))));
+
+        time::advance(Duration::from_secs(2)).await;
+        assert_eq!(db.get("foo"), Ok(None));
+        assert_eq!(db.ttl("foo"), None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn background_task_purges_keys() {
+        let db = Db::new();
+        db.set("foo".into(), "bar".into(), Some(Duration::from_millis(10)));
+
+        time::sleep(Duration::from_millis(20)).await;
+        for shard in &db.database().shards {
+            assert!(shard.lock().unwrap().entries.is_empty());
+        }
+    }
+
+    #[tokio::test]
+    async fn keys_spread_over_shards() {
+        let db = Db::with_shards(4);
+        for i in 0..64 {
+            db.set(format!("key:{}", i), "v".into(), None);
+        }
+
+        for i in 0..64 {
+            assert_eq!(db.get(&format!("key:{}", i)), Ok(Some("v".into())));
+        }
+        for shard in &db.database().shards {
+            assert!(!shard.lock().unwrap().entries.is_empty());
+        }
+    }
+
+    #[tokio::test]
+    async fn operations_check_the_type() {
+        let db = Db::new();
+        db.push("list", End::Left, vec!["a".into()]).unwrap();
+
+        assert_eq!(db.key_type("list"), "list");
+        assert_eq!(db.key_type("missing"), "none");
+        assert_eq!(db.get("list"), Err(Error::WrongType));
+        assert_eq!(db.incr_by("list", 1), Err(Error::WrongType));
+        assert_eq!(db.sadd("list", vec!["a".into()]), Err(Error::WrongType));
+        // `MGET` reports keys of other types as missing
+        assert_eq!(db.get_many(&["list".to_string()]), vec![None]);
+
+        // `SET` replaces a value of any type
+        db.set("list".into(), "x".into(), None);
+        assert_eq!(db.key_type("list"), "string");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn persist_and_overwrite_clear_ttl() {
+        let db = Db::new();
+        assert!(!db.expire("foo", Duration::from_secs(1)));
+
+        db.set("foo".into(), "bar".into(), None);
+        assert_eq!(db.ttl("foo"), Some(None));
+        assert!(!db.persist("foo"));
+
+        assert!(db.expire("foo", Duration::from_secs(1)));
+        assert!(db.persist("foo"));
+        assert_eq!(db.ttl("foo"), Some(None));
+
+        db.expire("foo", Duration::from_secs(1));
+        db.set("foo".into(), "baz".into(), None);
+        time::advance(Duration::from_secs(2)).await;
+        assert_eq!(db.get("foo"), Ok(Some("baz".into())));
+    }
+
+    #[tokio::test]
+    async fn publish_counts_channel_and_pattern_subscribers() {
+        let db = Db::new();
+        let mut rx = db.subscribe("news".into());
+        let mut prx = db.psubscribe("n*".into());
+
+        assert_eq!(db.publish("news", "hi".into()), 2);
+        assert_eq!(db.publish("nope", "x".into()), 1);
+        assert_eq!(db.publish("other", "x".into()), 0);
+
+        assert_eq!(rx.recv().await.unwrap(), "hi");
+        assert_eq!(prx.recv().await.unwrap(), ("news".into(), "hi".into()));
+        assert_eq!(prx.recv().await.unwrap(), ("nope".into(), "x".into()));
+
+        drop(rx);
+        assert_eq!(db.publish("news", "bye".into()), 1);
+    }
+
+    #[tokio::test]
+    async fn channels_are_dropped_with_their_last_subscriber() {
+        let db = Db::new();
+        let channels = |db: &Db| db.shared.pub_sub.lock().unwrap().channels.len();
+        let first = db.subscribe("news".into());
+        let second = db.subscribe("news".into());
+        let pattern = db.psubscribe("n*".into());
+
+        drop(first);
+        db.unsubscribe("news");
+        assert_eq!(channels(&db), 1);
+
+        drop(second);
+        db.unsubscribe("news");
+        assert_eq!(channels(&db), 0);
+
+        drop(pattern);
+        db.punsubscribe("n*");
+        assert!(db.shared.pub_sub.lock().unwrap().patterns.is_empty());
+    }
+}
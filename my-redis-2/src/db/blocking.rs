@@ -0,0 +1,326 @@
+use super::{Db, End, Error, Shared};
+
+use bytes::Bytes;
+use std::collections::{HashMap, VecDeque};
+use tokio::sync::oneshot;
+
+/// Clients blocked by `BLPOP`, `BRPOP` and `BLMOVE`, waiting for lists to be
+/// pushed to.
+#[derive(Debug, Default)]
+pub(super) struct Blocking {
+    next_id: u64,
+
+    /// The clients blocked on each key, by database index then key, first
+    /// come first served.
+    queues: HashMap<(usize, String), VecDeque<u64>>,
+
+    clients: HashMap<u64, Client>,
+
+    /// Keys pushed to since blocked clients were last served, along with the
+    /// index of their database.
+    ready: Vec<(usize, String)>,
+}
+
+#[derive(Debug)]
+struct Client {
+    /// Index of the database holding `keys`.
+    db: usize,
+    keys: Vec<String>,
+    pop: Pop,
+    tx: oneshot::Sender<Result<(String, Bytes), Error>>,
+}
+
+/// What a blocked client does with the value it pops.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pop {
+    /// The end of the list the value is popped from.
+    pub from: End,
+
+    /// The list the value is pushed to, and at which end, for `BLMOVE`.
+    pub to: Option<(String, End)>,
+}
+
+/// A value popped on behalf of a client, along with the key it came from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Popped {
+    /// Index of the database holding `key`.
+    pub db: usize,
+    pub key: String,
+    pub value: Bytes,
+    pub pop: Pop,
+}
+
+/// Outcome of `Db::block_pop`.
+#[derive(Debug)]
+pub enum Block {
+    /// A value was available right away.
+    Ready(Popped),
+
+    /// Every list was empty, the client now waits in line.
+    Waiting(Waiter),
+}
+
+/// A client waiting in line for a value. Dropping it leaves the line.
+#[derive(Debug)]
+pub struct Waiter {
+    db: Db,
+    id: u64,
+    rx: oneshot::Receiver<Result<(String, Bytes), Error>>,
+}
+
+impl Db {
+    /// Pop a value as described by `pop` from the first non-empty list stored
+    /// at `keys`, or queue the client behind any other client blocked on them.
+    pub fn block_pop(&self, keys: Vec<String>, pop: Pop) -> Result<Block, Error> {
+        // Holding the lock until the client is queued ensures a push in the
+        // meantime is not missed.
+        let mut blocking = self.shared.blocking.lock().unwrap();
+
+        for key in &keys {
+            if let Some(value) = self.shared.take_for(self.index, key, &pop)? {
+                drop(blocking);
+
+                if let Some((destination, _)) = &pop.to {
+                    self.shared.list_pushed(self.index, destination);
+                }
+
+                return Ok(Block::Ready(Popped {
+                    db: self.index,
+                    key: key.clone(),
+                    value,
+                    pop,
+                }));
+            }
+        }
+
+        let (tx, rx) = oneshot::channel();
+        let id = blocking.next_id;
+        blocking.next_id += 1;
+
+        for key in &keys {
+            blocking
+                .queues
+                .entry((self.index, key.clone()))
+                .or_default()
+                .push_back(id);
+        }
+        let db = self.index;
+        blocking.clients.insert(id, Client { db, keys, pop, tx });
+
+        Ok(Block::Waiting(Waiter {
+            db: self.clone(),
+            id,
+            rx,
+        }))
+    }
+
+    /// Hand the values pushed since the last call to the clients blocked on
+    /// them, in the order they blocked, whichever their database.
+    ///
+    /// Returns the values popped, so the caller can log them.
+    pub fn serve_blocked(&self) -> Vec<Popped> {
+        let mut blocking = self.shared.blocking.lock().unwrap();
+        let mut served = vec![];
+
+        while let Some((db, key)) = blocking.ready.pop() {
+            let queued = (db, key);
+            while let Some(&id) = blocking.queues.get(&queued).and_then(|queue| queue.front()) {
+                let key = &queued.1;
+                let client = blocking.clients.get(&id).expect("queued client");
+
+                let value = match self.shared.take_for(db, key, &client.pop) {
+                    Ok(Some(value)) => Ok((key.clone(), value)),
+                    // The list is empty again
+                    Ok(None) => break,
+                    Err(err) => Err(err),
+                };
+
+                let client = blocking.remove(id).expect("queued client");
+
+                if let Ok((_, value)) = &value {
+                    if let Some((destination, _)) = &client.pop.to {
+                        let destination = (db, destination.clone());
+                        if blocking.queues.contains_key(&destination) {
+                            blocking.ready.push(destination);
+                        }
+                    }
+
+                    served.push(Popped {
+                        db,
+                        key: key.clone(),
+                        value: value.clone(),
+                        pop: client.pop,
+                    });
+                }
+
+                // Clients leave the line before they drop their receiver, so
+                // a queued client is always listening.
+                let _ = client.tx.send(value);
+            }
+        }
+
+        served
+    }
+
+    /// Returns `true` if a list some client is blocked on was pushed to.
+    pub fn has_ready(&self) -> bool {
+        !self.shared.blocking.lock().unwrap().ready.is_empty()
+    }
+}
+
+impl Shared {
+    /// Note a push to the list at `key` of the database `db`, to be served by
+    /// `Db::serve_blocked`.
+    pub(super) fn list_pushed(&self, db: usize, key: &str) {
+        let mut blocking = self.blocking.lock().unwrap();
+        let key = (db, key.to_string());
+
+        if blocking.queues.contains_key(&key) && !blocking.ready.contains(&key) {
+            blocking.ready.push(key);
+        }
+    }
+
+    /// Note that the databases `a` and `b` exchanged their keys, so every key
+    /// either is blocked on may now hold a list.
+    pub(super) fn databases_swapped(&self, a: usize, b: usize) {
+        {
+            let mut blocking = self.blocking.lock().unwrap();
+            let Blocking { queues, ready, .. } = &mut *blocking;
+
+            for key in queues.keys() {
+                if (key.0 == a || key.0 == b) && !ready.contains(key) {
+                    ready.push(key.clone());
+                }
+            }
+        }
+
+        self.streams_swapped(a, b);
+    }
+
+    fn take_for(&self, db: usize, key: &str, pop: &Pop) -> Result<Option<Bytes>, Error> {
+        let to = pop
+            .to
+            .as_ref()
+            .map(|(destination, end)| (&destination[..], *end));
+        self.databases[db].take(key, pop.from, to)
+    }
+}
+
+impl Blocking {
+    /// Take the client `id` out of every line it waits in.
+    fn remove(&mut self, id: u64) -> Option<Client> {
+        let client = self.clients.remove(&id)?;
+
+        for key in &client.keys {
+            let key = (client.db, key.clone());
+            if let Some(queue) = self.queues.get_mut(&key) {
+                queue.retain(|&queued| queued != id);
+
+                if queue.is_empty() {
+                    self.queues.remove(&key);
+                }
+            }
+        }
+
+        Some(client)
+    }
+}
+
+impl Waiter {
+    /// Wait for a value to be handed to this client.
+    pub async fn recv(&mut self) -> Result<(String, Bytes), Error> {
+        (&mut self.rx).await.expect("waiter left the line")
+    }
+
+    /// Leave the line. Returns the value handed to this client while it was
+    /// leaving, if any.
+    pub fn cancel(mut self) -> Option<Result<(String, Bytes), Error>> {
+        self.db.shared.blocking.lock().unwrap().remove(self.id);
+        self.rx.try_recv().ok()
+    }
+}
+
+impl Drop for Waiter {
+    fn drop(&mut self) {
+        self.db.shared.blocking.lock().unwrap().remove(self.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pop() -> Pop {
+        Pop {
+            from: End::Left,
+            to: None,
+        }
+    }
+
+    fn waiting(block: Result<Block, Error>) -> Waiter {
+        match block {
+            Ok(Block::Waiting(waiter)) => waiter,
+            block => panic!("unexpected {:?}", block),
+        }
+    }
+
+    #[tokio::test]
+    async fn clients_are_served_in_order() {
+        let db = Db::new();
+
+        let mut first = waiting(db.block_pop(vec!["a".into(), "b".into()], pop()));
+        let mut second = waiting(db.block_pop(vec!["b".into()], pop()));
+        let third = waiting(db.block_pop(vec!["b".into()], pop()));
+
+        db.push("b", End::Right, vec!["1".into(), "2".into()])
+            .unwrap();
+        assert!(db.has_ready());
+        assert_eq!(db.serve_blocked().len(), 2);
+
+        assert_eq!(first.recv().await, Ok(("b".into(), "1".into())));
+        assert_eq!(second.recv().await, Ok(("b".into(), "2".into())));
+        assert_eq!(third.cancel(), None);
+
+        // Every client left the line
+        db.push("b", End::Right, vec!["3".into()]).unwrap();
+        assert!(!db.has_ready());
+        assert_eq!(db.lrange("b", 0, -1), Ok(vec!["3".into()]));
+    }
+
+    #[tokio::test]
+    async fn dropped_clients_leave_the_line() {
+        let db = Db::new();
+
+        let gone = waiting(db.block_pop(vec!["a".into()], pop()));
+        let mut waiting = waiting(db.block_pop(vec!["a".into()], pop()));
+        drop(gone);
+
+        db.push("a", End::Left, vec!["1".into()]).unwrap();
+        db.serve_blocked();
+        assert_eq!(waiting.recv().await, Ok(("a".into(), "1".into())));
+    }
+
+    #[tokio::test]
+    async fn values_available_right_away() {
+        let db = Db::new();
+        db.push("b", End::Right, vec!["1".into()]).unwrap();
+
+        let pop = Pop {
+            from: End::Left,
+            to: Some(("c".into(), End::Left)),
+        };
+        match db.block_pop(vec!["a".into(), "b".into()], pop.clone()) {
+            Ok(Block::Ready(popped)) => assert_eq!(
+                popped,
+                Popped {
+                    db: 0,
+                    key: "b".into(),
+                    value: "1".into(),
+                    pop
+                }
+            ),
+            block => panic!("unexpected {:?}", block),
+        }
+        assert_eq!(db.lrange("c", 0, -1), Ok(vec!["1".into()]));
+    }
+}
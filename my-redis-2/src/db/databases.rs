@@ -0,0 +1,204 @@
+//! The logical databases, as in `SELECT`, `MOVE`, `SWAPDB` and `FLUSHALL`.
+//!
+//! Every database has shards of its own, the same number of them, so a key
+//! lives in the shard of the same index whichever the database. Commands
+//! spanning two databases lock their shards in database order, so they never
+//! deadlock each other.
+
+use super::{Database, Db, Events, Shard, Value};
+
+use std::mem;
+use std::sync::MutexGuard;
+
+impl Db {
+    /// A handle on the database `index`, sharing everything else with this
+    /// one. Returns `None` if there is no such database.
+    pub fn select(&self, index: usize) -> Option<Db> {
+        (index < self.shared.databases.len()).then(|| Db {
+            shared: self.shared.clone(),
+            index,
+        })
+    }
+
+    /// Index of the selected database.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// Number of databases.
+    pub fn databases(&self) -> usize {
+        self.shared.databases.len()
+    }
+
+    /// The selected database.
+    pub(super) fn database(&self) -> &Database {
+        &self.shared.databases[self.index]
+    }
+
+    /// Remove every key of every database.
+    pub fn clear_all(&self) {
+        for database in &self.shared.databases {
+            database.clear();
+        }
+    }
+
+    /// Move `key`, along with its time to live, to the database `index`.
+    ///
+    /// Returns `false` if the key does not exist, or if the database `index`
+    /// already holds it.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is the selected database.
+    pub fn move_to(&self, key: &str, index: usize) -> bool {
+        assert_ne!(index, self.index, "a key can't move to its own database");
+
+        let shard = self.database().index(key);
+        let (mut source, mut target) = self.lock_pair(index, shard);
+
+        if source.live_entry(key).is_none() || target.live_entry(key).is_some() {
+            return false;
+        }
+
+        let entry = source.take(key).expect("entry just checked");
+        let kind = mem::discriminant(&entry.value);
+        let notify = target.insert(key.to_string(), entry.value, entry.expires_at);
+
+        source.notify(Events::GENERIC, "move_from", key);
+        target.notify(Events::GENERIC, "move_to", key);
+        drop((source, target));
+
+        // Clients blocked on the key in the target database may be served.
+        if kind == mem::discriminant(&Value::List(Default::default())) {
+            self.shared.list_pushed(index, key);
+        }
+        if kind == mem::discriminant(&Value::Stream(Default::default())) {
+            self.shared.stream_added(index, key);
+        }
+        if notify {
+            self.shared.background_task.notify_one();
+        }
+
+        true
+    }
+
+    /// Exchange the keys of the databases `a` and `b`. Handles keep the
+    /// database they selected, and thus see the keys of the other one.
+    ///
+    /// Every shard of both databases is locked while the keys are exchanged,
+    /// so no command sees them half swapped.
+    pub fn swap(&self, a: usize, b: usize) {
+        if a == b {
+            return;
+        }
+
+        let lock = |index: usize| -> Vec<_> {
+            let shards = &self.shared.databases[index].shards;
+            shards.iter().map(|shard| shard.lock().unwrap()).collect()
+        };
+        let mut low = lock(a.min(b));
+        let mut high = lock(a.max(b));
+
+        for (low, high) in low.iter_mut().zip(&mut high) {
+            low.swap_keys(high);
+        }
+        drop((low, high));
+
+        // Clients blocked in either database may find their keys.
+        self.shared.databases_swapped(a, b);
+    }
+
+    /// Lock the shard `shard` of the selected database, then the one of the
+    /// database `index`, in database order.
+    fn lock_pair(
+        &self,
+        index: usize,
+        shard: usize,
+    ) -> (MutexGuard<'_, Shard>, MutexGuard<'_, Shard>) {
+        let lock = |db: usize| self.shared.databases[db].shards[shard].lock().unwrap();
+
+        if self.index < index {
+            let source = lock(self.index);
+            (source, lock(index))
+        } else {
+            let target = lock(index);
+            (lock(self.index), target)
+        }
+    }
+}
+
+impl Database {
+    /// Remove every key.
+    pub(super) fn clear(&self) {
+        for shard in &self.shards {
+            let mut shard = shard.lock().unwrap();
+
+            let size = shard.entries.values().map(|entry| entry.size).sum();
+            shard.account(0, size);
+            shard.entries.clear();
+            shard.expirations.clear();
+            shard.positions.clear();
+        }
+    }
+}
+
+impl Shard {
+    /// Exchange the keys of this shard with those of `other`, the shard of
+    /// the same index in another database.
+    fn swap_keys(&mut self, other: &mut Shard) {
+        mem::swap(&mut self.entries, &mut other.entries);
+        mem::swap(&mut self.expirations, &mut other.expirations);
+        mem::swap(&mut self.positions, &mut other.positions);
+
+        // Every key changed as far as `WATCH` is concerned. The new versions
+        // are past those of both shards, so none is mistaken for an old one.
+        let version = self.version.max(other.version);
+        for shard in [self, other] {
+            shard.version = version;
+
+            for entry in shard.entries.values_mut() {
+                shard.version += 1;
+                entry.version = shard.version;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn databases_hold_separate_keyspaces() {
+        let db = Db::new();
+        let other = db.select(1).unwrap();
+        assert!(db.select(db.databases()).is_none());
+
+        db.set("a".into(), Bytes::from("0"), Some(Duration::from_secs(60)));
+        other.set("a".into(), Bytes::from("1"), None);
+        other.set("b".into(), Bytes::from("1"), None);
+        assert_eq!(db.get("a"), Ok(Some(Bytes::from("0"))));
+        assert_eq!(db.key_counts(), (1, 1));
+        assert_eq!(other.key_counts(), (2, 0));
+
+        // `a` exists in both, `b` only in the other.
+        assert!(!db.move_to("a", 1));
+        assert!(other.move_to("b", 0));
+        assert_eq!(db.get("b"), Ok(Some(Bytes::from("1"))));
+        assert_eq!(other.get("b"), Ok(None));
+
+        let version = db.version("a");
+        db.swap(0, 1);
+        assert_eq!(db.get("a"), Ok(Some(Bytes::from("1"))));
+        assert!(other.ttl("a").unwrap().is_some());
+        assert_ne!(db.version("a"), version);
+
+        other.clear();
+        assert_eq!(other.key_counts(), (0, 0));
+        assert_eq!(db.key_counts(), (1, 0));
+        db.clear_all();
+        assert_eq!(db.key_counts(), (0, 0));
+    }
+}
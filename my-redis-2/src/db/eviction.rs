@@ -0,0 +1,413 @@
+use super::{random, scan, Db, Entry, Error, Events, Shard, Value};
+
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::Ordering;
+use tokio::time::{Duration, Instant};
+
+/// Number of keys compared to pick each key to evict, as well as number of
+/// elements sampled to estimate the size of a collection.
+const SAMPLES: usize = 5;
+
+/// Bytes accounted for each key on top of its name and value.
+const ENTRY_OVERHEAD: usize = 64;
+
+/// Bytes accounted for each element of a collection on top of its data.
+const ELEMENT_OVERHEAD: usize = 16;
+
+/// Access frequency of a new key, so it is not evicted before it gets a
+/// chance to be used.
+const LFU_INIT: u8 = 5;
+
+/// How likely the access frequency is to grow as it gets higher, see
+/// `Entry::accessed`.
+const LFU_LOG_FACTOR: u64 = 10;
+
+/// How keys are picked for eviction once `maxmemory` is reached.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Policy {
+    /// Keys are never evicted, writes fail instead.
+    #[default]
+    NoEviction,
+
+    /// Evict the least recently used keys.
+    AllKeysLru,
+
+    /// Evict the least frequently used keys.
+    AllKeysLfu,
+
+    /// Evict the least recently used keys among those with a time to live.
+    VolatileLru,
+
+    /// Evict the keys closest to expiring.
+    VolatileTtl,
+}
+
+/// The memory limit, see `Db::set_max_memory`.
+#[derive(Debug, Default, Clone, Copy)]
+pub(super) struct Limit {
+    /// Bytes the keyspace may use, zero for no limit.
+    max_memory: usize,
+    policy: Policy,
+}
+
+impl Db {
+    /// Limit the keyspace to `max_memory` bytes, zero meaning no limit. Keys
+    /// are evicted following `policy` when writing past the limit, see
+    /// `evict`.
+    pub fn set_max_memory(&self, max_memory: usize, policy: Policy) {
+        *self.shared.limit.lock().unwrap() = Limit { max_memory, policy };
+    }
+
+    /// The memory limit and eviction policy.
+    pub fn max_memory(&self) -> (usize, Policy) {
+        let limit = *self.shared.limit.lock().unwrap();
+        (limit.max_memory, limit.policy)
+    }
+
+    /// Approximate bytes used by the keyspace.
+    pub fn used_memory(&self) -> usize {
+        self.shared.used_memory.load(Ordering::Relaxed)
+    }
+
+    /// Evict keys until the keyspace is back under the memory limit.
+    ///
+    /// Keys are evicted from any database. Returns the evicted keys along with
+    /// the index of their database, so the caller can log their removal, or
+    /// `Error::OutOfMemory` if the limit is still exceeded because the policy
+    /// forbids eviction or there is no key left to evict.
+    pub fn evict(&self) -> Result<Vec<(usize, String)>, Error> {
+        let limit = *self.shared.limit.lock().unwrap();
+        let mut evicted = vec![];
+
+        while limit.max_memory > 0 && self.used_memory() > limit.max_memory {
+            if limit.policy == Policy::NoEviction {
+                return Err(Error::OutOfMemory);
+            }
+
+            let (db, key) = self.sample(limit.policy).ok_or(Error::OutOfMemory)?;
+
+            let mut shard = self.shared.databases[db].shard(&key);
+            if shard.remove(&key) {
+                shard.notify(Events::EVICTED, "evicted", &key);
+                drop(shard);
+
+                self.shared.evicted_keys.fetch_add(1, Ordering::Relaxed);
+                evicted.push((db, key));
+            }
+        }
+
+        Ok(evicted)
+    }
+
+    /// Pick the best key to evict out of `SAMPLES` keys drawn at random,
+    /// along with the index of its database.
+    ///
+    /// Returns `None` if there is no key the policy may evict.
+    fn sample(&self, policy: Policy) -> Option<(usize, String)> {
+        let databases = &self.shared.databases;
+        let shards: Vec<_> = databases.iter().flat_map(|db| &db.shards).collect();
+        let now = Instant::now();
+        let mut best: Option<(u64, usize, String)> = None;
+
+        for _ in 0..SAMPLES {
+            // Draw from the first shard holding candidates, starting at a
+            // random one.
+            let start = random() as usize % shards.len();
+
+            let drawn = (0..shards.len()).find_map(|i| {
+                let index = (start + i) % shards.len();
+                let shard = shards[index].lock().unwrap();
+
+                shard
+                    .draw(policy)
+                    .map(|(key, entry)| (entry.eviction_score(policy, now), shard.db, key.clone()))
+            })?;
+
+            if best.as_ref().is_none_or(|best| drawn.0 > best.0) {
+                best = Some(drawn);
+            }
+        }
+
+        best.map(|(_, db, key)| (db, key))
+    }
+}
+
+impl Shard {
+    /// Draw a random key the policy may evict.
+    ///
+    /// The maps of a shard offer no random access, so this takes the first
+    /// key from a random point of its ordered sets: a random position among
+    /// the keys, or a random time among the expirations. Keys following a gap
+    /// are more likely to be drawn, which is good enough for sampling.
+    fn draw(&self, policy: Policy) -> Option<(&String, &Entry)> {
+        let key = match policy {
+            Policy::VolatileLru | Policy::VolatileTtl => {
+                let (first, _) = self.expirations.first()?;
+                let (last, _) = self.expirations.last()?;
+                let span = (*last - *first).as_nanos().min(u64::MAX as u128) as u64;
+                let at = *first + Duration::from_nanos(random() % span.saturating_add(1));
+
+                let (_, key) = self.expirations.range((at, String::new())..).next()?;
+                key
+            }
+            _ => {
+                let position = scan::random_position();
+                let (_, key) = self
+                    .positions
+                    .range((position, String::new())..)
+                    .next()
+                    .or_else(|| self.positions.first())?;
+                key
+            }
+        };
+
+        self.entries.get_key_value(key)
+    }
+
+    /// Account for a change of the memory used by this shard.
+    pub(super) fn account(&self, added: usize, removed: usize) {
+        self.used_memory.fetch_add(added, Ordering::Relaxed);
+        self.used_memory.fetch_sub(removed, Ordering::Relaxed);
+    }
+}
+
+impl Entry {
+    /// A new entry, not accessed yet.
+    pub(super) fn new(key: &str, value: Value, version: u64) -> Entry {
+        Entry {
+            size: size(key, &value),
+            value,
+            expires_at: None,
+            version,
+            accessed_at: Instant::now(),
+            frequency: LFU_INIT,
+        }
+    }
+
+    /// Record an access, for the LRU and LFU policies.
+    ///
+    /// The access frequency is a logarithmic counter: the higher it is, the
+    /// less likely an access increments it. It decays by one every minute the
+    /// key is not accessed.
+    pub(super) fn accessed(&mut self, now: Instant) {
+        let frequency = self.frequency(now);
+
+        let threshold = (frequency.saturating_sub(LFU_INIT) as u64) * LFU_LOG_FACTOR + 1;
+        self.frequency = match random() % threshold {
+            0 => frequency.saturating_add(1),
+            _ => frequency,
+        };
+        self.accessed_at = now;
+    }
+
+    /// Estimate the size of the entry again after its value changed.
+    ///
+    /// Returns the previous estimate.
+    pub(super) fn resize(&mut self, key: &str) -> usize {
+        std::mem::replace(&mut self.size, size(key, &self.value))
+    }
+
+    /// The access frequency, once decayed.
+    fn frequency(&self, now: Instant) -> u8 {
+        let minutes = now.saturating_duration_since(self.accessed_at).as_secs() / 60;
+        self.frequency
+            .saturating_sub(minutes.min(u8::MAX as u64) as u8)
+    }
+
+    /// How much the policy wants the entry evicted, the higher the sooner.
+    fn eviction_score(&self, policy: Policy, now: Instant) -> u64 {
+        match policy {
+            Policy::AllKeysLfu => (u8::MAX - self.frequency(now)) as u64,
+            Policy::VolatileTtl => {
+                let ttl = self.expires_at.map_or(u64::MAX, |when| {
+                    when.saturating_duration_since(now).as_millis() as u64
+                });
+                u64::MAX - ttl
+            }
+            _ => now.saturating_duration_since(self.accessed_at).as_millis() as u64,
+        }
+    }
+}
+
+impl FromStr for Policy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Policy, String> {
+        match &s.to_lowercase()[..] {
+            "noeviction" => Ok(Policy::NoEviction),
+            "allkeys-lru" => Ok(Policy::AllKeysLru),
+            "allkeys-lfu" => Ok(Policy::AllKeysLfu),
+            "volatile-lru" => Ok(Policy::VolatileLru),
+            "volatile-ttl" => Ok(Policy::VolatileTtl),
+            _ => Err(format!("invalid eviction policy '{}'", s)),
+        }
+    }
+}
+
+impl fmt::Display for Policy {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Policy::NoEviction => "noeviction",
+            Policy::AllKeysLru => "allkeys-lru",
+            Policy::AllKeysLfu => "allkeys-lfu",
+            Policy::VolatileLru => "volatile-lru",
+            Policy::VolatileTtl => "volatile-ttl",
+        }
+        .fmt(fmt)
+    }
+}
+
+/// Parse a memory amount such as `100mb`: a number of bytes, optionally
+/// followed by a `kb`, `mb` or `gb` unit of 1024, or `k`, `m` or `g` unit of
+/// 1000.
+pub fn parse_memory(s: &str) -> Option<usize> {
+    let s = s.to_lowercase();
+
+    let units = [
+        ("kb", 1 << 10),
+        ("mb", 1 << 20),
+        ("gb", 1 << 30),
+        ("k", 1000),
+        ("m", 1000 * 1000),
+        ("g", 1000 * 1000 * 1000),
+        ("b", 1),
+    ];
+
+    let (number, unit) = units
+        .iter()
+        .find_map(|&(suffix, unit)| Some((s.strip_suffix(suffix)?, unit)))
+        .unwrap_or((&s[..], 1));
+
+    number.parse::<usize>().ok()?.checked_mul(unit)
+}
+
+/// Approximate bytes used by `key` holding `value`.
+///
+/// The elements of a collection are not all walked over: the size of a few of
+/// them stands for all the others.
+fn size(key: &str, value: &Value) -> usize {
+    fn sampled<I: Iterator>(len: usize, items: I, size: impl Fn(I::Item) -> usize) -> usize {
+        let sampled: Vec<_> = items.take(SAMPLES).map(size).collect();
+
+        match sampled.len() {
+            0 => 0,
+            n => len * (sampled.iter().sum::<usize>() / n + ELEMENT_OVERHEAD),
+        }
+    }
+
+    let data = match value {
+        Value::String(data) => data.len(),
+        Value::List(list) => sampled(list.len(), list.iter(), |value| value.len()),
+        Value::Hash(hash) => sampled(hash.len(), hash.iter(), |(field, value)| {
+            field.len() + value.len()
+        }),
+        Value::Set(set) => sampled(set.len(), set.iter(), |member| member.len()),
+        Value::ZSet(zset) => sampled(zset.len(), zset.iter(), |(member, _)| member.len() + 8),
+        Value::Stream(stream) => {
+            let entries = &stream.entries;
+            sampled(entries.len(), entries.values(), |fields| {
+                16 + fields
+                    .iter()
+                    .map(|(field, value)| field.len() + value.len())
+                    .sum::<usize>()
+            })
+        }
+    };
+
+    ENTRY_OVERHEAD + key.len() + data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    fn fill(db: &Db, keys: usize, expire: Option<Duration>) {
+        for i in 0..keys {
+            db.set(format!("key:{}", i), "x".repeat(100).into(), expire);
+        }
+    }
+
+    #[tokio::test]
+    async fn draws_spread_over_the_keys() {
+        let db = Db::with_shards(1);
+        fill(&db, 100, Some(Duration::from_secs(100)));
+        let shard = db.shared.databases[0].shards[0].lock().unwrap();
+
+        for policy in [Policy::AllKeysLru, Policy::VolatileTtl] {
+            let drawn: HashSet<_> = (0..500)
+                .map(|_| shard.draw(policy).unwrap().0.clone())
+                .collect();
+            assert!(drawn.len() > 50, "{:?} drew {} keys", policy, drawn.len());
+        }
+    }
+
+    #[tokio::test]
+    async fn memory_is_accounted() {
+        let db = Db::new();
+        assert_eq!(db.used_memory(), 0);
+
+        fill(&db, 10, None);
+        let used = db.used_memory();
+        assert!(used >= 10 * 100);
+
+        db.append("key:0", &[b'y'; 100]).unwrap();
+        assert_eq!(db.used_memory(), used + 100);
+
+        db.remove(&["key:0".to_string(), "key:1".to_string()]);
+        assert!(db.used_memory() < used);
+    }
+
+    #[tokio::test]
+    async fn noeviction_refuses_writes() {
+        let db = Db::new();
+        fill(&db, 10, None);
+
+        db.set_max_memory(db.used_memory(), Policy::NoEviction);
+        assert_eq!(db.evict(), Ok(vec![]));
+
+        fill(&db, 11, None);
+        assert_eq!(db.evict(), Err(Error::OutOfMemory));
+        assert_eq!(db.keys("*").len(), 11);
+    }
+
+    #[tokio::test]
+    async fn allkeys_policies_evict_until_under_the_limit() {
+        for policy in [Policy::AllKeysLru, Policy::AllKeysLfu] {
+            let db = Db::new();
+            fill(&db, 100, None);
+            let limit = db.used_memory() / 2;
+
+            db.set_max_memory(limit, policy);
+            let evicted = db.evict().unwrap();
+            assert!(db.used_memory() <= limit);
+            assert_eq!(db.keys("*").len(), 100 - evicted.len());
+        }
+    }
+
+    #[tokio::test]
+    async fn volatile_policies_only_evict_keys_with_a_ttl() {
+        for policy in [Policy::VolatileLru, Policy::VolatileTtl] {
+            let db = Db::new();
+            fill(&db, 10, None);
+            db.set_max_memory(db.used_memory(), policy);
+
+            db.set("volatile".into(), "x".into(), Some(Duration::from_secs(60)));
+            assert_eq!(db.evict(), Ok(vec![(0, "volatile".to_string())]));
+
+            db.set("persistent".into(), "x".into(), None);
+            assert_eq!(db.evict(), Err(Error::OutOfMemory));
+        }
+    }
+
+    #[test]
+    fn memory_amounts() {
+        assert_eq!(parse_memory("100"), Some(100));
+        assert_eq!(parse_memory("1kb"), Some(1024));
+        assert_eq!(parse_memory("2MB"), Some(2 << 20));
+        assert_eq!(parse_memory("1g"), Some(1_000_000_000));
+        assert_eq!(parse_memory("mb"), None);
+        assert_eq!(parse_memory("-1"), None);
+    }
+}
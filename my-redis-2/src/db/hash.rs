@@ -0,0 +1,86 @@
+use super::{Collection, Db, Error, Events, Value};
+
+use bytes::Bytes;
+use std::collections::HashMap;
+
+impl Collection for HashMap<Bytes, Bytes> {
+    fn from_value(value: &Value) -> Option<&Self> {
+        match value {
+            Value::Hash(hash) => Some(hash),
+            _ => None,
+        }
+    }
+
+    fn from_value_mut(value: &mut Value) -> Option<&mut Self> {
+        match value {
+            Value::Hash(hash) => Some(hash),
+            _ => None,
+        }
+    }
+
+    fn into_value(self) -> Value {
+        Value::Hash(self)
+    }
+
+    fn is_empty(&self) -> bool {
+        HashMap::is_empty(self)
+    }
+}
+
+impl Db {
+    /// Set fields of the hash stored at `key`, creating the hash if needed.
+    ///
+    /// Returns the number of fields which were added rather than updated.
+    pub fn hset(&self, key: &str, pairs: Vec<(Bytes, Bytes)>) -> Result<usize, Error> {
+        let added = self.update(key, true, |hash: &mut HashMap<Bytes, Bytes>| {
+            pairs
+                .into_iter()
+                .filter(|(field, value)| hash.insert(field.clone(), value.clone()).is_none())
+                .count()
+        })?;
+
+        self.notify(Events::HASH, "hset", key);
+
+        Ok(added.unwrap_or(0))
+    }
+
+    /// Value of `field` in the hash stored at `key`.
+    pub fn hget(&self, key: &str, field: &[u8]) -> Result<Option<Bytes>, Error> {
+        let value = self.read(key, |hash: &HashMap<Bytes, Bytes>| hash.get(field).cloned())?;
+
+        Ok(value.flatten())
+    }
+
+    /// Every field of the hash stored at `key`, along with its value.
+    pub fn hget_all(&self, key: &str) -> Result<Vec<(Bytes, Bytes)>, Error> {
+        let pairs = self.read(key, |hash: &HashMap<Bytes, Bytes>| {
+            hash.iter()
+                .map(|(field, value)| (field.clone(), value.clone()))
+                .collect()
+        })?;
+
+        Ok(pairs.unwrap_or_default())
+    }
+
+    /// Remove fields from the hash stored at `key`.
+    ///
+    /// Returns the number of fields which existed.
+    pub fn hdel(&self, key: &str, fields: &[Bytes]) -> Result<usize, Error> {
+        let mut shard = self.database().shard(key);
+
+        let removed = shard
+            .update(key, false, |hash: &mut HashMap<Bytes, Bytes>| {
+                fields
+                    .iter()
+                    .filter(|field| hash.remove(*field).is_some())
+                    .count()
+            })?
+            .unwrap_or(0);
+
+        if removed > 0 {
+            shard.notify_modified(Events::HASH, "hdel", key);
+        }
+
+        Ok(removed)
+    }
+}
@@ -0,0 +1,216 @@
+use super::{index_range, Collection, Database, Db, Error, Events, Value};
+
+use bytes::Bytes;
+use std::collections::VecDeque;
+
+/// The end of a list an operation applies to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum End {
+    Left,
+    Right,
+}
+
+impl End {
+    /// The keyspace event of pushing at this end.
+    fn push_event(self) -> &'static str {
+        match self {
+            End::Left => "lpush",
+            End::Right => "rpush",
+        }
+    }
+
+    /// The keyspace event of popping from this end.
+    fn pop_event(self) -> &'static str {
+        match self {
+            End::Left => "lpop",
+            End::Right => "rpop",
+        }
+    }
+}
+
+impl Collection for VecDeque<Bytes> {
+    fn from_value(value: &Value) -> Option<&Self> {
+        match value {
+            Value::List(list) => Some(list),
+            _ => None,
+        }
+    }
+
+    fn from_value_mut(value: &mut Value) -> Option<&mut Self> {
+        match value {
+            Value::List(list) => Some(list),
+            _ => None,
+        }
+    }
+
+    fn into_value(self) -> Value {
+        Value::List(self)
+    }
+
+    fn is_empty(&self) -> bool {
+        VecDeque::is_empty(self)
+    }
+}
+
+impl Db {
+    /// Push `values` one after the other at the `end` of the list stored at
+    /// `key`, creating the list if needed.
+    ///
+    /// Returns the length of the list after the push.
+    pub fn push(&self, key: &str, end: End, values: Vec<Bytes>) -> Result<usize, Error> {
+        let len = self.update(key, true, |list: &mut VecDeque<Bytes>| {
+            for value in values {
+                match end {
+                    End::Left => list.push_front(value),
+                    End::Right => list.push_back(value),
+                }
+            }
+
+            list.len()
+        })?;
+
+        self.notify(Events::LIST, end.push_event(), key);
+        self.shared.list_pushed(self.index, key);
+
+        Ok(len.unwrap_or(0))
+    }
+
+    /// Pop a value from the `from` end of the list stored at `source` and push
+    /// it at the `to` end of the list stored at `destination`.
+    ///
+    /// Returns the value moved, or `None` if `source` does not exist.
+    pub fn lmove(
+        &self,
+        source: &str,
+        destination: &str,
+        from: End,
+        to: End,
+    ) -> Result<Option<Bytes>, Error> {
+        let value = self
+            .database()
+            .take(source, from, Some((destination, to)))?;
+
+        if value.is_some() {
+            self.shared.list_pushed(self.index, destination);
+        }
+
+        Ok(value)
+    }
+
+    /// Pop up to `count` values from the `end` of the list stored at `key`.
+    ///
+    /// Returns `None` if the key does not exist.
+    pub fn pop(&self, key: &str, end: End, count: usize) -> Result<Option<Vec<Bytes>>, Error> {
+        let mut shard = self.database().shard(key);
+
+        let values = shard.update(key, false, |list: &mut VecDeque<Bytes>| {
+            let count = count.min(list.len());
+
+            match end {
+                End::Left => list.drain(..count).collect::<Vec<_>>(),
+                End::Right => list.drain(list.len() - count..).rev().collect(),
+            }
+        })?;
+
+        if values.as_ref().is_some_and(|values| !values.is_empty()) {
+            shard.notify_modified(Events::LIST, end.pop_event(), key);
+        }
+
+        Ok(values)
+    }
+
+    /// The values of the list stored at `key` between the `start` and `stop`
+    /// indices, both inclusive. Negative indices count from the end.
+    pub fn lrange(&self, key: &str, start: i64, stop: i64) -> Result<Vec<Bytes>, Error> {
+        let values = self.read(key, |list: &VecDeque<Bytes>| {
+            match index_range(list.len(), start, stop) {
+                Some(range) => list.range(range).cloned().collect(),
+                None => vec![],
+            }
+        })?;
+
+        Ok(values.unwrap_or_default())
+    }
+}
+
+impl Database {
+    /// Pop a value from the `from` end of the list stored at `key`, then push
+    /// it to the list `to`, if any.
+    ///
+    /// The type of both keys is checked before anything is popped.
+    pub(super) fn take(
+        &self,
+        key: &str,
+        from: End,
+        to: Option<(&str, End)>,
+    ) -> Result<Option<Bytes>, Error> {
+        let mut keys = vec![key.to_string()];
+        keys.extend(to.map(|(destination, _)| destination.to_string()));
+        let mut shards = self.lock(&keys);
+
+        if let Some((destination, _)) = to {
+            let entry = shards.shard(destination).live_entry(destination);
+            if entry.is_some_and(|entry| !matches!(entry.value, Value::List(_))) {
+                return Err(Error::WrongType);
+            }
+        }
+
+        let value = shards
+            .shard(key)
+            .update(key, false, |list: &mut VecDeque<Bytes>| match from {
+                End::Left => list.pop_front(),
+                End::Right => list.pop_back(),
+            })?
+            .flatten();
+
+        if value.is_some() {
+            shards
+                .shard(key)
+                .notify_modified(Events::LIST, from.pop_event(), key);
+        }
+
+        if let (Some(value), Some((destination, to))) = (&value, to) {
+            let shard = shards.shard(destination);
+            shard.update(destination, true, |list: &mut VecDeque<Bytes>| match to {
+                End::Left => list.push_front(value.clone()),
+                End::Right => list.push_back(value.clone()),
+            })?;
+            shard.notify(Events::LIST, to.push_event(), destination);
+        }
+
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn push_pop_and_range() {
+        let db = Db::new();
+        let values = |values: &[&'static str]| -> Vec<Bytes> {
+            values.iter().map(|value| Bytes::from(*value)).collect()
+        };
+
+        assert_eq!(db.push("list", End::Right, values(&["b", "c"])), Ok(2));
+        assert_eq!(db.push("list", End::Left, values(&["a", "z"])), Ok(4));
+        assert_eq!(db.lrange("list", 0, -1), Ok(values(&["z", "a", "b", "c"])));
+        assert_eq!(db.lrange("list", -2, 10), Ok(values(&["b", "c"])));
+        assert_eq!(db.lrange("list", 3, 1), Ok(vec![]));
+
+        assert_eq!(db.pop("list", End::Left, 1), Ok(Some(values(&["z"]))));
+        assert_eq!(db.pop("list", End::Right, 2), Ok(Some(values(&["c", "b"]))));
+
+        // Popping the last value removes the key
+        assert_eq!(db.pop("list", End::Right, 5), Ok(Some(values(&["a"]))));
+        assert_eq!(db.key_type("list"), "none");
+        assert_eq!(db.pop("list", End::Right, 1), Ok(None));
+
+        db.set("string".into(), "x".into(), None);
+        assert_eq!(
+            db.push("string", End::Left, values(&["a"])),
+            Err(Error::WrongType)
+        );
+    }
+}
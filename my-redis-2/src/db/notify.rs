@@ -0,0 +1,261 @@
+//! Keyspace notifications.
+//!
+//! Commands which modify a key publish an event, such as `set`, `del` or
+//! `lpush`, as do the expiration and eviction of keys. Each event is
+//! published twice: on `__keyspace@<db>__:<key>` with the event as the
+//! message, and on `__keyevent@<db>__:<event>` with the key as the message,
+//! `<db>` being the index of the database holding the key. Which events
+//! are published is configured with `notify-keyspace-events`, none by
+//! default.
+
+use super::{publish, Db, PubSub, Shard};
+
+use bytes::Bytes;
+use std::fmt;
+use std::ops::BitOr;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU16, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// A set of event classes, written as in `notify-keyspace-events`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Events(u16);
+
+impl Events {
+    /// Publish on `__keyspace@<db>__:<key>`.
+    pub const KEYSPACE: Events = Events(1 << 0);
+
+    /// Publish on `__keyevent@<db>__:<event>`.
+    pub const KEYEVENT: Events = Events(1 << 1);
+
+    /// Commands on keys of any type, such as `del` or `expire`.
+    pub const GENERIC: Events = Events(1 << 2);
+    pub const STRING: Events = Events(1 << 3);
+    pub const LIST: Events = Events(1 << 4);
+    pub const SET: Events = Events(1 << 5);
+    pub const HASH: Events = Events(1 << 6);
+    pub const ZSET: Events = Events(1 << 7);
+    pub const STREAM: Events = Events(1 << 8);
+
+    /// Keys removed once their time to live elapsed.
+    pub const EXPIRED: Events = Events(1 << 9);
+
+    /// Keys removed to stay under the memory limit.
+    pub const EVICTED: Events = Events(1 << 10);
+
+    /// Every class of events, `A`.
+    pub const ALL: Events = Events(0b111_1111_1100);
+
+    /// Returns `true` if every class of `other` is in `self`.
+    pub fn contains(self, other: Events) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+/// The character of each class, in the order they are displayed.
+const CLASSES: [(char, Events); 11] = [
+    ('g', Events::GENERIC),
+    ('$', Events::STRING),
+    ('l', Events::LIST),
+    ('s', Events::SET),
+    ('h', Events::HASH),
+    ('z', Events::ZSET),
+    ('x', Events::EXPIRED),
+    ('e', Events::EVICTED),
+    ('t', Events::STREAM),
+    ('K', Events::KEYSPACE),
+    ('E', Events::KEYEVENT),
+];
+
+/// Publishes the events, see `Db::notify`. Shared by the shards, so they
+/// publish the keys they expire.
+#[derive(Debug, Default)]
+pub(super) struct Notifier {
+    /// The enabled `Events`, checked before anything is published.
+    events: AtomicU16,
+
+    /// The pub/sub channels, also used by `PUBLISH`.
+    pub_sub: Arc<Mutex<PubSub>>,
+}
+
+impl Db {
+    /// Publish the keyspace events of the classes in `events`, along with
+    /// `Events::KEYSPACE` or `Events::KEYEVENT` telling where.
+    pub fn set_notify_keyspace_events(&self, events: Events) {
+        let notifier = &self.shared.notifier;
+        notifier.events.store(events.0, Ordering::Relaxed);
+    }
+
+    /// The classes of keyspace events published.
+    pub fn notify_keyspace_events(&self) -> Events {
+        Events(self.shared.notifier.events.load(Ordering::Relaxed))
+    }
+
+    /// Publish `event` of the `class` for `key`, if enabled.
+    pub(super) fn notify(&self, class: Events, event: &str, key: &str) {
+        self.shared.notifier.notify(self.index, class, event, key);
+    }
+}
+
+impl Notifier {
+    /// A notifier publishing to `pub_sub`.
+    pub(super) fn new(pub_sub: Arc<Mutex<PubSub>>) -> Notifier {
+        Notifier {
+            events: AtomicU16::new(0),
+            pub_sub,
+        }
+    }
+
+    /// Publish `event` of the `class` for `key` of the database `db`, if
+    /// enabled.
+    pub(super) fn notify(&self, db: usize, class: Events, event: &str, key: &str) {
+        let events = Events(self.events.load(Ordering::Relaxed));
+        if !events.contains(class) {
+            return;
+        }
+
+        let mut pub_sub = self.pub_sub.lock().unwrap();
+
+        if events.contains(Events::KEYSPACE) {
+            let channel = format!("__keyspace@{}__:{}", db, key);
+            publish(
+                &mut pub_sub,
+                &channel,
+                Bytes::copy_from_slice(event.as_bytes()),
+            );
+        }
+        if events.contains(Events::KEYEVENT) {
+            let channel = format!("__keyevent@{}__:{}", db, event);
+            publish(
+                &mut pub_sub,
+                &channel,
+                Bytes::copy_from_slice(key.as_bytes()),
+            );
+        }
+    }
+}
+
+impl Shard {
+    /// Publish `event` of the `class` for `key`, if enabled.
+    pub(super) fn notify(&self, class: Events, event: &str, key: &str) {
+        self.notifier.notify(self.db, class, event, key);
+    }
+
+    /// Publish `event` for `key`, which a command just modified, followed by
+    /// `del` if that left the collection empty and removed it.
+    pub(super) fn notify_modified(&self, class: Events, event: &str, key: &str) {
+        self.notify(class, event, key);
+
+        if !self.entries.contains_key(key) {
+            self.notify(Events::GENERIC, "del", key);
+        }
+    }
+}
+
+impl FromStr for Events {
+    type Err = String;
+
+    /// Parse the classes as written in `notify-keyspace-events`, e.g. `KEA`
+    /// or `Elg`. An empty string disables the notifications.
+    fn from_str(s: &str) -> Result<Events, String> {
+        s.chars().try_fold(Events::default(), |events, c| {
+            let class = match c {
+                'A' => Events::ALL,
+                c => CLASSES
+                    .iter()
+                    .find(|(name, _)| *name == c)
+                    .map(|(_, class)| *class)
+                    .ok_or_else(|| format!("invalid event class '{}'", c))?,
+            };
+
+            Ok(events | class)
+        })
+    }
+}
+
+impl fmt::Display for Events {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let mut events = *self;
+
+        if events.contains(Events::ALL) {
+            fmt.write_str("A")?;
+            events = Events(events.0 & !Events::ALL.0);
+        }
+
+        for (name, class) in CLASSES {
+            if events.contains(class) {
+                write!(fmt, "{}", name)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl BitOr for Events {
+    type Output = Events;
+
+    fn bitor(self, other: Events) -> Events {
+        Events(self.0 | other.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::End;
+    use std::time::Duration;
+    use tokio::time;
+
+    #[test]
+    fn events_parse_and_display() {
+        let events: Events = "Elg$".parse().unwrap();
+        assert!(events.contains(Events::KEYEVENT | Events::LIST));
+        assert!(!events.contains(Events::KEYSPACE));
+        assert_eq!(events.to_string(), "g$lE");
+
+        assert_eq!("AKE".parse::<Events>().unwrap().to_string(), "AKE");
+        assert_eq!("".parse::<Events>(), Ok(Events::default()));
+        assert!("Kq".parse::<Events>().is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn writes_and_expirations_are_published() {
+        let db = Db::new();
+        let mut keyspace = db.psubscribe("__keyspace@0__:*".into());
+        let mut keyevent = db.subscribe("__keyevent@0__:del".into());
+
+        // Nothing is published until enabled.
+        db.set("a".into(), Bytes::from("1"), None);
+        db.set_notify_keyspace_events("KEA".parse().unwrap());
+
+        db.set("b".into(), Bytes::from("1"), Some(Duration::from_secs(1)));
+        db.push("list", End::Left, vec![Bytes::from("x")]).unwrap();
+        db.pop("list", End::Left, 1).unwrap();
+        db.remove(&["a".to_string(), "missing".to_string()]);
+
+        time::advance(Duration::from_secs(2)).await;
+        assert_eq!(db.get("b"), Ok(None));
+
+        let mut events = vec![];
+        while let Ok((channel, event)) = keyspace.try_recv() {
+            events.push(format!("{} {}", channel, String::from_utf8_lossy(&event)));
+        }
+        assert_eq!(
+            events,
+            [
+                "__keyspace@0__:b set",
+                "__keyspace@0__:b expire",
+                "__keyspace@0__:list lpush",
+                "__keyspace@0__:list lpop",
+                "__keyspace@0__:list del",
+                "__keyspace@0__:a del",
+                "__keyspace@0__:b expired",
+            ]
+        );
+
+        assert_eq!(keyevent.try_recv().unwrap(), "list");
+        assert_eq!(keyevent.try_recv().unwrap(), "a");
+        assert!(keyevent.try_recv().is_err());
+    }
+}
@@ -0,0 +1,269 @@
+//! Cursor based iteration, as in `SCAN`, `HSCAN`, `SSCAN` and `ZSCAN`.
+//!
+//! Items are walked in the order of their position, a hash of the item which
+//! only depends on the item itself. A cursor is the position to resume from,
+//! so it holds no state on the server, and neither inserting nor removing
+//! other items, nor the tables growing or shrinking, moves the items left to
+//! walk. Every item present for the whole iteration is thus returned, and
+//! only once unless it is removed and added back.
+//!
+//! Items sharing a position are returned in the same call, as a cursor can't
+//! point between them. A keyspace cursor also holds the shard, in its top
+//! bits, shards being walked one after the other. Each shard keeps its keys
+//! ordered by position, so a call only walks the keys it returns. Collections
+//! are walked whole on every call, which is no worse than `HGETALL`.
+
+use super::{Db, Error, ZSet};
+use crate::glob;
+
+use bytes::Bytes;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::hash::Hasher;
+use tokio::time::Instant;
+
+/// Bits of a keyspace cursor giving the position in the shard.
+const POSITION_BITS: u32 = 48;
+
+/// The position of an item, see the module documentation.
+fn hash(item: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    hasher.write(item);
+    hasher.finish()
+}
+
+/// The position of a key in its shard, fitting in `POSITION_BITS`.
+pub(super) fn position(key: &str) -> u64 {
+    hash(key.as_bytes()) >> (64 - POSITION_BITS)
+}
+
+/// A position drawn at random, as `position` gives them.
+pub(super) fn random_position() -> u64 {
+    super::random() >> (64 - POSITION_BITS)
+}
+
+impl Db {
+    /// Walk at least `count` keys from `cursor`, 0 to start over, returning
+    /// the cursor to continue from, 0 once every key was walked, and the keys
+    /// walked which match `pattern` and are of type `type_`, if given.
+    pub fn scan(
+        &self,
+        cursor: u64,
+        count: usize,
+        pattern: Option<&str>,
+        type_: Option<&str>,
+    ) -> (u64, Vec<String>) {
+        let shards = &self.database().shards;
+        let now = Instant::now();
+
+        let mut index = (cursor >> POSITION_BITS) as usize;
+        let mut from = cursor & ((1 << POSITION_BITS) - 1);
+        let mut keys = vec![];
+        let mut walked = 0;
+
+        while index < shards.len() && walked < count {
+            let shard = shards[index].lock().unwrap();
+            let mut positions = shard.positions.range((from, String::new())..).peekable();
+
+            while let Some((position, key)) = positions.next() {
+                walked += 1;
+
+                let entry = &shard.entries[key];
+                if !entry.is_expired(now)
+                    && pattern.is_none_or(|pattern| glob::matches(pattern, key))
+                    && type_.is_none_or(|type_| type_.eq_ignore_ascii_case(entry.value.type_name()))
+                {
+                    keys.push(key.clone());
+                }
+
+                match positions.peek() {
+                    Some((next, _)) if walked >= count && next != position => {
+                        let cursor = (index as u64) << POSITION_BITS | next;
+                        return (cursor, keys);
+                    }
+                    _ => {}
+                }
+            }
+
+            index += 1;
+            from = 0;
+        }
+
+        match index < shards.len() {
+            true => ((index as u64) << POSITION_BITS, keys),
+            false => (0, keys),
+        }
+    }
+
+    /// `scan` for the fields of the hash stored at `key`, along with their
+    /// values.
+    pub fn hscan(
+        &self,
+        key: &str,
+        cursor: u64,
+        count: usize,
+        pattern: Option<&str>,
+    ) -> Result<(u64, Vec<(Bytes, Bytes)>), Error> {
+        let page = self.read(key, |hash: &HashMap<Bytes, Bytes>| {
+            let (cursor, fields) = page(hash.iter(), |(field, _)| &field[..], cursor, count);
+            let fields = fields
+                .into_iter()
+                .filter(|(field, _)| matches(pattern, field))
+                .map(|(field, value)| (field.clone(), value.clone()))
+                .collect();
+            (cursor, fields)
+        })?;
+
+        Ok(page.unwrap_or_default())
+    }
+
+    /// `scan` for the members of the set stored at `key`.
+    pub fn sscan(
+        &self,
+        key: &str,
+        cursor: u64,
+        count: usize,
+        pattern: Option<&str>,
+    ) -> Result<(u64, Vec<Bytes>), Error> {
+        let page = self.read(key, |set: &HashSet<Bytes>| {
+            let (cursor, members) = page(set.iter(), |member| &member[..], cursor, count);
+            let members = members
+                .into_iter()
+                .filter(|member| matches(pattern, member))
+                .cloned()
+                .collect();
+            (cursor, members)
+        })?;
+
+        Ok(page.unwrap_or_default())
+    }
+
+    /// `scan` for the members of the sorted set stored at `key`, along with
+    /// their scores.
+    pub fn zscan(
+        &self,
+        key: &str,
+        cursor: u64,
+        count: usize,
+        pattern: Option<&str>,
+    ) -> Result<(u64, Vec<(Bytes, f64)>), Error> {
+        let page = self.read(key, |zset: &ZSet| {
+            let (cursor, members) = page(zset.iter(), |(member, _)| &member[..], cursor, count);
+            let members = members
+                .into_iter()
+                .filter(|(member, _)| matches(pattern, member))
+                .map(|(member, score)| (member.clone(), score))
+                .collect();
+            (cursor, members)
+        })?;
+
+        Ok(page.unwrap_or_default())
+    }
+}
+
+/// The items at `cursor` or after, at least `count` of them if there are as
+/// many, and the cursor of the next page, 0 if there is none. `item` gives
+/// the bytes an item is positioned by.
+fn page<T>(
+    items: impl Iterator<Item = T>,
+    item: impl Fn(&T) -> &[u8],
+    cursor: u64,
+    count: usize,
+) -> (u64, Vec<T>) {
+    let mut items: Vec<_> = items
+        .map(|it| (hash(item(&it)), it))
+        .filter(|(position, _)| *position >= cursor)
+        .collect();
+
+    if items.len() <= count {
+        return (0, items.into_iter().map(|(_, it)| it).collect());
+    }
+
+    // Everything up to the position of the `count`th item, including the
+    // items sharing it.
+    let last = items
+        .select_nth_unstable_by_key(count - 1, |(position, _)| *position)
+        .1
+         .0;
+    let (page, rest): (Vec<_>, Vec<_>) = items
+        .into_iter()
+        .partition(|(position, _)| *position <= last);
+
+    let next = rest
+        .iter()
+        .map(|(position, _)| *position)
+        .min()
+        .unwrap_or(0);
+    (next, page.into_iter().map(|(_, it)| it).collect())
+}
+
+fn matches(pattern: Option<&str>, item: &[u8]) -> bool {
+    pattern.is_none_or(|pattern| glob::matches_bytes(pattern.as_bytes(), item))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+
+    #[tokio::test]
+    async fn scan_returns_the_keys_present_throughout() {
+        let db = Db::new();
+        for i in 0..1000 {
+            db.set(format!("key:{}", i), Bytes::from("x"), None);
+        }
+
+        let mut seen = BTreeSet::new();
+        let mut cursor = 0;
+        let mut calls = 0;
+        loop {
+            let (next, keys) = db.scan(cursor, 10, Some("key:*"), None);
+            seen.extend(keys);
+            calls += 1;
+
+            // The tables grow and shrink between calls.
+            db.set(format!("other:{}", calls), Bytes::from("x"), None);
+            db.remove(&[format!("key:{}", 999 - calls)]);
+
+            if next == 0 {
+                break;
+            }
+            cursor = next;
+        }
+
+        for i in 0..(999 - calls) {
+            assert!(seen.contains(&format!("key:{}", i)), "key:{} missing", i);
+        }
+        assert!(calls > 50, "{} calls", calls);
+
+        let (_, keys) = db.scan(0, 10_000, None, Some("hash"));
+        assert!(keys.is_empty());
+    }
+
+    #[tokio::test]
+    async fn collection_scans_return_every_member_once() {
+        let db = Db::new();
+        let fields = (0..100).map(|i| (Bytes::from(format!("f{}", i)), Bytes::from("v")));
+        db.hset("hash", fields.collect()).unwrap();
+
+        let mut seen = vec![];
+        let mut cursor = 0;
+        loop {
+            let (next, fields) = db.hscan("hash", cursor, 7, None).unwrap();
+            seen.extend(fields.into_iter().map(|(field, _)| field));
+            if next == 0 {
+                break;
+            }
+            cursor = next;
+        }
+
+        assert_eq!(seen.len(), 100);
+        seen.sort();
+        seen.dedup();
+        assert_eq!(seen.len(), 100);
+
+        let (cursor, fields) = db.hscan("hash", 0, 1000, Some("f1?")).unwrap();
+        assert_eq!((cursor, fields.len()), (0, 10));
+        assert_eq!(db.sscan("missing", 0, 10, None).unwrap(), (0, vec![]));
+    }
+}
@@ -0,0 +1,121 @@
+use super::{Collection, Db, Error, Events, Value};
+
+use bytes::Bytes;
+use std::collections::HashSet;
+
+impl Collection for HashSet<Bytes> {
+    fn from_value(value: &Value) -> Option<&Self> {
+        match value {
+            Value::Set(set) => Some(set),
+            _ => None,
+        }
+    }
+
+    fn from_value_mut(value: &mut Value) -> Option<&mut Self> {
+        match value {
+            Value::Set(set) => Some(set),
+            _ => None,
+        }
+    }
+
+    fn into_value(self) -> Value {
+        Value::Set(self)
+    }
+
+    fn is_empty(&self) -> bool {
+        HashSet::is_empty(self)
+    }
+}
+
+impl Db {
+    /// Add members to the set stored at `key`, creating the set if needed.
+    ///
+    /// Returns the number of members which were not in the set yet.
+    pub fn sadd(&self, key: &str, members: Vec<Bytes>) -> Result<usize, Error> {
+        let added = self.update(key, true, |set: &mut HashSet<Bytes>| {
+            members
+                .into_iter()
+                .filter(|member| set.insert(member.clone()))
+                .count()
+        })?;
+
+        if added.is_some_and(|added| added > 0) {
+            self.notify(Events::SET, "sadd", key);
+        }
+
+        Ok(added.unwrap_or(0))
+    }
+
+    /// Every member of the set stored at `key`.
+    pub fn smembers(&self, key: &str) -> Result<Vec<Bytes>, Error> {
+        let members = self.read(key, |set: &HashSet<Bytes>| set.iter().cloned().collect())?;
+
+        Ok(members.unwrap_or_default())
+    }
+
+    /// The members found in every one of the sets stored at `keys`. Missing
+    /// keys are empty sets.
+    pub fn sinter(&self, keys: &[String]) -> Result<Vec<Bytes>, Error> {
+        self.read_many(keys, |sets: Vec<Option<&HashSet<Bytes>>>| {
+            let sets: Vec<_> = match sets.into_iter().collect::<Option<_>>() {
+                Some(sets) => sets,
+                None => return vec![],
+            };
+
+            // Only the members of the smallest set can be in every set
+            let smallest = sets.iter().min_by_key(|set| set.len()).unwrap();
+
+            smallest
+                .iter()
+                .filter(|member| sets.iter().all(|set| set.contains(*member)))
+                .cloned()
+                .collect()
+        })
+    }
+
+    /// The members found in any of the sets stored at `keys`.
+    pub fn sunion(&self, keys: &[String]) -> Result<Vec<Bytes>, Error> {
+        self.read_many(keys, |sets: Vec<Option<&HashSet<Bytes>>>| {
+            let union: HashSet<_> = sets.into_iter().flatten().flatten().collect();
+            union.into_iter().cloned().collect()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sorted(mut members: Vec<Bytes>) -> Vec<Bytes> {
+        members.sort();
+        members
+    }
+
+    #[tokio::test]
+    async fn set_operations() {
+        let db = Db::new();
+        let members = |members: &[&'static str]| -> Vec<Bytes> {
+            members.iter().map(|member| Bytes::from(*member)).collect()
+        };
+
+        assert_eq!(db.sadd("a", members(&["1", "2", "3", "2"])), Ok(3));
+        assert_eq!(db.sadd("b", members(&["2", "3", "4"])), Ok(3));
+        assert_eq!(db.sadd("a", members(&["1", "5"])), Ok(1));
+
+        let keys =
+            |keys: &[&str]| -> Vec<String> { keys.iter().map(|key| key.to_string()).collect() };
+
+        assert_eq!(
+            db.sinter(&keys(&["a", "b"])).map(sorted),
+            Ok(members(&["2", "3"]))
+        );
+        assert_eq!(db.sinter(&keys(&["a", "missing"])), Ok(vec![]));
+        assert_eq!(
+            db.sunion(&keys(&["a", "b", "missing"])).map(sorted),
+            Ok(members(&["1", "2", "3", "4", "5"]))
+        );
+
+        db.set("string".into(), "x".into(), None);
+        assert_eq!(db.sunion(&keys(&["a", "string"])), Err(Error::WrongType));
+    }
+}
@@ -0,0 +1,793 @@
+use super::{unix_time, Collection, Db, Error, Events, Shared, Value};
+
+use bytes::Bytes;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::ops::Bound;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::Notify;
+
+/// The field-value pairs of a stream entry.
+pub type Fields = Vec<(Bytes, Bytes)>;
+
+/// ID of a stream entry: the Unix time in milliseconds it was added at, and a
+/// sequence number telling apart entries added within the same millisecond.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct StreamId {
+    pub ms: u64,
+    pub seq: u64,
+}
+
+/// The ID requested by `XADD`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum XAddId {
+    /// `*`, generated from the current time.
+    Auto,
+
+    /// `<ms>-*`, with the sequence number generated.
+    Seq(u64),
+
+    /// `<ms>-<seq>`
+    Exact(StreamId),
+}
+
+/// An append-only log of entries, ordered by ID.
+///
+/// Unlike other collections, a stream remains when it has no entries left, so
+/// it keeps its last ID and consumer groups.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct Stream {
+    pub(crate) entries: BTreeMap<StreamId, Fields>,
+
+    /// The greatest ID ever added, new IDs must be greater.
+    pub(crate) last_id: StreamId,
+
+    pub(crate) groups: BTreeMap<String, Group>,
+}
+
+/// A consumer group, which hands each entry to one of its consumers.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct Group {
+    /// The last entry handed to a consumer.
+    pub(crate) last_delivered: StreamId,
+
+    /// Entries handed to a consumer and not acknowledged yet.
+    pub(crate) pending: BTreeMap<StreamId, Pending>,
+}
+
+/// An entry of a group's pending-entry list.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Pending {
+    pub(crate) consumer: String,
+
+    /// Unix time in milliseconds of the last delivery.
+    pub(crate) delivered_at: u64,
+
+    /// Number of times the entry was delivered.
+    pub(crate) deliveries: u64,
+}
+
+/// How `XCLAIM` hands entries over.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Claim {
+    /// Entries delivered more recently than this are not claimed.
+    pub min_idle: Duration,
+
+    /// Claim entries of the stream which are not pending as well.
+    pub force: bool,
+
+    /// Count the claim as a delivery.
+    pub increment: bool,
+
+    /// Overrides the delivery count of the claimed entries.
+    pub deliveries: Option<u64>,
+
+    /// Overrides the Unix time in milliseconds of the last delivery, which is
+    /// otherwise reset to now.
+    pub delivered_at: Option<u64>,
+}
+
+/// Tasks waiting for entries to be added to streams, see `Db::watch_streams`.
+#[derive(Debug, Default)]
+pub(super) struct StreamWaiters {
+    next_id: u64,
+
+    /// The waiting tasks for each key, by database index then key, and by
+    /// waiter ID.
+    keys: HashMap<(usize, String), HashMap<u64, Arc<Notify>>>,
+}
+
+/// Notified whenever an entry is added to one of the watched streams. Dropping
+/// it stops watching.
+#[derive(Debug)]
+pub struct StreamWatch {
+    db: Db,
+    id: u64,
+    keys: Vec<String>,
+    notify: Arc<Notify>,
+}
+
+impl StreamId {
+    pub const MIN: StreamId = StreamId { ms: 0, seq: 0 };
+    pub const MAX: StreamId = StreamId {
+        ms: u64::MAX,
+        seq: u64::MAX,
+    };
+
+    /// Parse `<ms>-<seq>`, or `<ms>` alone using `seq` as the sequence
+    /// number.
+    pub fn parse(s: &str, seq: u64) -> Option<StreamId> {
+        let (ms, seq) = match s.split_once('-') {
+            Some((ms, seq)) => (ms.parse().ok()?, seq.parse().ok()?),
+            None => (s.parse().ok()?, seq),
+        };
+
+        Some(StreamId { ms, seq })
+    }
+
+    /// The next ID, `None` for `MAX`.
+    pub fn next(self) -> Option<StreamId> {
+        match self.seq.checked_add(1) {
+            Some(seq) => Some(StreamId { ms: self.ms, seq }),
+            None => Some(StreamId {
+                ms: self.ms.checked_add(1)?,
+                seq: 0,
+            }),
+        }
+    }
+}
+
+impl fmt::Display for StreamId {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "{}-{}", self.ms, self.seq)
+    }
+}
+
+impl Stream {
+    /// The ID `XADD` adds an entry at, given the ID it was asked for.
+    fn next_id(&self, id: XAddId) -> Result<StreamId, Error> {
+        const TOO_SMALL: &str =
+            "The ID specified in XADD is equal or smaller than the target stream top item";
+
+        let last = self.last_id;
+
+        let id = match id {
+            XAddId::Auto => {
+                let now = unix_time().as_millis() as u64;
+                match now > last.ms {
+                    true => StreamId { ms: now, seq: 0 },
+                    false => last.next().ok_or(Error::Invalid(TOO_SMALL))?,
+                }
+            }
+            XAddId::Seq(ms) if ms == last.ms => last.next().ok_or(Error::Invalid(TOO_SMALL))?,
+            XAddId::Seq(ms) => StreamId { ms, seq: 0 },
+            XAddId::Exact(id) => id,
+        };
+
+        if id == StreamId::MIN {
+            return Err(Error::Invalid(
+                "The ID specified in XADD must be greater than 0-0",
+            ));
+        }
+        if id <= last {
+            return Err(Error::Invalid(TOO_SMALL));
+        }
+
+        Ok(id)
+    }
+
+    /// Entries between `start` and `end`, in reverse order if `rev` is set.
+    fn range(
+        &self,
+        start: Bound<StreamId>,
+        end: Bound<StreamId>,
+        count: usize,
+        rev: bool,
+    ) -> Vec<(StreamId, Fields)> {
+        // `BTreeMap::range` panics on a range ending before it starts
+        let empty = match (start, end) {
+            (Bound::Included(start), Bound::Included(end)) => start > end,
+            (Bound::Included(start) | Bound::Excluded(start), Bound::Excluded(end))
+            | (Bound::Excluded(start), Bound::Included(end)) => start >= end,
+            _ => false,
+        };
+        if empty {
+            return vec![];
+        }
+
+        let range = self.entries.range((start, end));
+        let clone = |(id, fields): (&StreamId, &Fields)| (*id, fields.clone());
+
+        match rev {
+            false => range.take(count).map(clone).collect(),
+            true => range.rev().take(count).map(clone).collect(),
+        }
+    }
+}
+
+impl Collection for Stream {
+    fn from_value(value: &Value) -> Option<&Self> {
+        match value {
+            Value::Stream(stream) => Some(stream),
+            _ => None,
+        }
+    }
+
+    fn from_value_mut(value: &mut Value) -> Option<&mut Self> {
+        match value {
+            Value::Stream(stream) => Some(stream),
+            _ => None,
+        }
+    }
+
+    fn into_value(self) -> Value {
+        Value::Stream(self)
+    }
+
+    fn is_empty(&self) -> bool {
+        false
+    }
+}
+
+impl Db {
+    /// Add an entry to the stream stored at `key`, creating the stream if
+    /// needed, then trim the stream to its `max_len` latest entries.
+    ///
+    /// Returns the ID of the new entry.
+    pub fn xadd(
+        &self,
+        key: &str,
+        id: XAddId,
+        fields: Fields,
+        max_len: Option<usize>,
+    ) -> Result<StreamId, Error> {
+        // Check the ID before creating the stream, so a failed `XADD` does not
+        // leave an empty stream behind.
+        if self.xlast_id(key)?.is_none() {
+            Stream::default().next_id(id)?;
+        }
+
+        let id = self
+            .update(key, true, |stream: &mut Stream| {
+                let id = stream.next_id(id)?;
+
+                stream.entries.insert(id, fields);
+                stream.last_id = id;
+
+                if let Some(max_len) = max_len {
+                    while stream.entries.len() > max_len {
+                        stream.entries.pop_first();
+                    }
+                }
+
+                Ok(id)
+            })?
+            .expect("stream created")?;
+
+        self.notify(Events::STREAM, "xadd", key);
+        self.shared.stream_added(self.index, key);
+
+        Ok(id)
+    }
+
+    /// Number of entries in the stream stored at `key`.
+    pub fn xlen(&self, key: &str) -> Result<usize, Error> {
+        let len = self.read(key, |stream: &Stream| stream.entries.len())?;
+
+        Ok(len.unwrap_or(0))
+    }
+
+    /// Up to `count` entries of the stream stored at `key` between `start`
+    /// and `end`, in reverse order if `rev` is set.
+    pub fn xrange(
+        &self,
+        key: &str,
+        start: Bound<StreamId>,
+        end: Bound<StreamId>,
+        count: usize,
+        rev: bool,
+    ) -> Result<Vec<(StreamId, Fields)>, Error> {
+        let entries = self.read(key, |stream: &Stream| stream.range(start, end, count, rev))?;
+
+        Ok(entries.unwrap_or_default())
+    }
+
+    /// The last ID added to the stream stored at `key`, which `$` stands for.
+    pub fn xlast_id(&self, key: &str) -> Result<Option<StreamId>, Error> {
+        self.read(key, |stream: &Stream| stream.last_id)
+    }
+
+    /// Set the last ID of the stream stored at `key`.
+    ///
+    /// Returns `false` if the key does not exist.
+    pub fn xsetid(&self, key: &str, id: StreamId) -> Result<bool, Error> {
+        let set = self.update(key, false, |stream: &mut Stream| {
+            match stream.entries.last_key_value() {
+                Some((last, _)) if id < *last => Err(Error::Invalid(
+                    "The ID specified in XSETID is smaller than the target stream top item",
+                )),
+                _ => {
+                    stream.last_id = id;
+                    Ok(())
+                }
+            }
+        })?;
+
+        let set = set.transpose()?.is_some();
+        if set {
+            self.notify(Events::STREAM, "xsetid", key);
+        }
+
+        Ok(set)
+    }
+
+    /// Create the consumer group `group` on the stream stored at `key`, which
+    /// delivers the entries after `id`, or only new entries if `id` is `None`.
+    pub fn xgroup_create(
+        &self,
+        key: &str,
+        group: &str,
+        id: Option<StreamId>,
+        mkstream: bool,
+    ) -> Result<(), Error> {
+        let created = self.update(key, mkstream, |stream: &mut Stream| {
+            if stream.groups.contains_key(group) {
+                return Err(Error::BusyGroup);
+            }
+
+            let group = group.to_string();
+            let last_delivered = id.unwrap_or(stream.last_id);
+            stream.groups.insert(
+                group,
+                Group {
+                    last_delivered,
+                    pending: BTreeMap::new(),
+                },
+            );
+
+            Ok(())
+        })?;
+
+        created.unwrap_or(Err(Error::Invalid(
+            "The XGROUP subcommand requires the key to exist. Note that for CREATE you may want to use the MKSTREAM option to create an empty stream automatically.",
+        )))?;
+        self.notify(Events::STREAM, "xgroup-create", key);
+
+        Ok(())
+    }
+
+    /// Read the stream stored at `key` as `consumer` of `group`.
+    ///
+    /// With `after` set to `None`, up to `count` entries never delivered to
+    /// the group are handed to `consumer` and added to the pending-entry
+    /// list, unless `noack` is set. Otherwise, the entries pending for
+    /// `consumer` with an ID greater than `after` are delivered again; those
+    /// deleted from the stream since come without fields.
+    pub fn xreadgroup(
+        &self,
+        key: &str,
+        group: &str,
+        consumer: &str,
+        after: Option<StreamId>,
+        count: usize,
+        noack: bool,
+    ) -> Result<Vec<(StreamId, Option<Fields>)>, Error> {
+        let now = unix_time().as_millis() as u64;
+
+        let entries = self.update(key, false, |stream: &mut Stream| {
+            let Stream {
+                entries, groups, ..
+            } = stream;
+            let group = groups.get_mut(group).ok_or(Error::NoGroup)?;
+
+            let read = match after {
+                None => {
+                    let start = Bound::Excluded(group.last_delivered);
+                    let read: Vec<_> = entries
+                        .range((start, Bound::Unbounded))
+                        .take(count)
+                        .map(|(id, fields)| (*id, Some(fields.clone())))
+                        .collect();
+
+                    if let Some((last, _)) = read.last() {
+                        group.last_delivered = *last;
+                    }
+
+                    if !noack {
+                        for (id, _) in &read {
+                            let pending = Pending {
+                                consumer: consumer.to_string(),
+                                delivered_at: now,
+                                deliveries: 1,
+                            };
+                            group.pending.insert(*id, pending);
+                        }
+                    }
+
+                    read
+                }
+                Some(after) => group
+                    .pending
+                    .range_mut((Bound::Excluded(after), Bound::Unbounded))
+                    .filter(|(_, pending)| pending.consumer == consumer)
+                    .take(count)
+                    .map(|(id, pending)| {
+                        pending.delivered_at = now;
+                        pending.deliveries += 1;
+                        (*id, entries.get(id).cloned())
+                    })
+                    .collect(),
+            };
+
+            Ok(read)
+        })?;
+
+        entries.unwrap_or(Err(Error::NoGroup))
+    }
+
+    /// Acknowledge entries of `group`, removing them from its pending-entry
+    /// list.
+    ///
+    /// Returns the number of entries which were pending.
+    pub fn xack(&self, key: &str, group: &str, ids: &[StreamId]) -> Result<usize, Error> {
+        let acked = self.update(key, false, |stream: &mut Stream| {
+            match stream.groups.get_mut(group) {
+                Some(group) => ids
+                    .iter()
+                    .filter(|id| group.pending.remove(id).is_some())
+                    .count(),
+                None => 0,
+            }
+        })?;
+
+        Ok(acked.unwrap_or(0))
+    }
+
+    /// The pending entries of `group` between `start` and `end`, up to
+    /// `count` of them, optionally only those of `consumer`.
+    ///
+    /// Returns the ID, consumer, time since the last delivery and number of
+    /// deliveries of each entry.
+    pub fn xpending(
+        &self,
+        key: &str,
+        group: &str,
+        (start, end): (Bound<StreamId>, Bound<StreamId>),
+        count: usize,
+        consumer: Option<&str>,
+    ) -> Result<Vec<(StreamId, String, Duration, u64)>, Error> {
+        let now = unix_time().as_millis() as u64;
+
+        let pending = self.read(key, |stream: &Stream| {
+            let group = stream.groups.get(group).ok_or(Error::NoGroup)?;
+
+            if matches!((start, end), (Bound::Included(start), Bound::Included(end)) if start > end)
+            {
+                return Ok(vec![]);
+            }
+
+            Ok(group
+                .pending
+                .range((start, end))
+                .filter(|(_, pending)| consumer.is_none_or(|consumer| pending.consumer == consumer))
+                .take(count)
+                .map(|(id, pending)| {
+                    let idle = Duration::from_millis(now.saturating_sub(pending.delivered_at));
+                    (*id, pending.consumer.clone(), idle, pending.deliveries)
+                })
+                .collect())
+        })?;
+
+        pending.unwrap_or(Err(Error::NoGroup))
+    }
+
+    /// Hand the pending entries `ids` of `group` over to `consumer`, see
+    /// `Claim`.
+    ///
+    /// Returns the entries claimed.
+    pub fn xclaim(
+        &self,
+        key: &str,
+        group: &str,
+        consumer: &str,
+        ids: &[StreamId],
+        claim: Claim,
+    ) -> Result<Vec<(StreamId, Fields)>, Error> {
+        let now = unix_time().as_millis() as u64;
+        let min_idle = claim.min_idle.as_millis() as u64;
+
+        let claimed = self.update(key, false, |stream: &mut Stream| {
+            let Stream {
+                entries, groups, ..
+            } = stream;
+            let group = groups.get_mut(group).ok_or(Error::NoGroup)?;
+
+            let mut claimed = vec![];
+            for id in ids {
+                let fields = match entries.get(id) {
+                    Some(fields) => fields,
+                    // Deleted entries cannot be claimed anymore
+                    None => {
+                        group.pending.remove(id);
+                        continue;
+                    }
+                };
+
+                let pending = match group.pending.get_mut(id) {
+                    Some(pending) if now.saturating_sub(pending.delivered_at) < min_idle => {
+                        continue
+                    }
+                    Some(pending) => pending,
+                    None if claim.force => group.pending.entry(*id).or_insert(Pending {
+                        consumer: String::new(),
+                        delivered_at: now,
+                        deliveries: 0,
+                    }),
+                    None => continue,
+                };
+
+                pending.consumer = consumer.to_string();
+                pending.delivered_at = claim.delivered_at.unwrap_or(now);
+                pending.deliveries = match claim.deliveries {
+                    Some(deliveries) => deliveries,
+                    None if claim.increment => pending.deliveries + 1,
+                    None => pending.deliveries,
+                };
+
+                claimed.push((*id, fields.clone()));
+            }
+
+            Ok(claimed)
+        })?;
+
+        claimed.unwrap_or(Err(Error::NoGroup))
+    }
+
+    /// The entry `id` pending in `group`, if any.
+    pub(crate) fn xpending_entry(&self, key: &str, group: &str, id: StreamId) -> Option<Pending> {
+        let pending = self.read(key, |stream: &Stream| {
+            stream.groups.get(group)?.pending.get(&id).cloned()
+        });
+
+        pending.ok().flatten().flatten()
+    }
+
+    /// Start watching `keys` for new stream entries.
+    ///
+    /// The watch is armed right away: an entry added before
+    /// `StreamWatch::changed` is awaited still wakes it up.
+    pub fn watch_streams(&self, keys: Vec<String>) -> StreamWatch {
+        let notify = Arc::new(Notify::new());
+        let mut waiters = self.shared.stream_waiters.lock().unwrap();
+
+        let id = waiters.next_id;
+        waiters.next_id += 1;
+
+        for key in &keys {
+            waiters
+                .keys
+                .entry((self.index, key.clone()))
+                .or_default()
+                .insert(id, notify.clone());
+        }
+
+        StreamWatch {
+            db: self.clone(),
+            id,
+            keys,
+            notify,
+        }
+    }
+}
+
+impl Shared {
+    /// Wake the tasks watching the stream at `key` of the database `db`.
+    pub(super) fn stream_added(&self, db: usize, key: &str) {
+        let waiters = self.stream_waiters.lock().unwrap();
+        let watches = waiters.keys.get(&(db, key.to_string()));
+
+        for notify in watches.into_iter().flat_map(HashMap::values) {
+            notify.notify_one();
+        }
+    }
+
+    /// Wake the tasks watching any stream of the databases `a` and `b`, which
+    /// exchanged their keys.
+    pub(super) fn streams_swapped(&self, a: usize, b: usize) {
+        let waiters = self.stream_waiters.lock().unwrap();
+        let watches = waiters
+            .keys
+            .iter()
+            .filter(|((db, _), _)| *db == a || *db == b);
+
+        for notify in watches.flat_map(|(_, watches)| watches.values()) {
+            notify.notify_one();
+        }
+    }
+}
+
+impl StreamWatch {
+    /// Wait for an entry to be added to one of the watched streams.
+    pub async fn changed(&self) {
+        self.notify.notified().await;
+    }
+}
+
+impl Drop for StreamWatch {
+    fn drop(&mut self) {
+        let waiters: &Mutex<StreamWaiters> = &self.db.shared.stream_waiters;
+        let mut waiters = waiters.lock().unwrap();
+
+        for key in &self.keys {
+            let key = (self.db.index, key.clone());
+            if let Some(watches) = waiters.keys.get_mut(&key) {
+                watches.remove(&self.id);
+
+                if watches.is_empty() {
+                    waiters.keys.remove(&key);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn id(ms: u64, seq: u64) -> StreamId {
+        StreamId { ms, seq }
+    }
+
+    fn fields(value: &'static str) -> Fields {
+        vec![(Bytes::from("field"), Bytes::from(value))]
+    }
+
+    #[tokio::test]
+    async fn ids_increase() {
+        let db = Db::new();
+
+        assert_eq!(
+            db.xadd("s", XAddId::Exact(id(5, 1)), fields("a"), None),
+            Ok(id(5, 1))
+        );
+        assert_eq!(
+            db.xadd("s", XAddId::Seq(5), fields("b"), None),
+            Ok(id(5, 2))
+        );
+        assert!(db
+            .xadd("s", XAddId::Exact(id(5, 2)), fields("c"), None)
+            .is_err());
+        assert!(db
+            .xadd("t", XAddId::Exact(id(0, 0)), fields("c"), None)
+            .is_err());
+
+        let auto = db.xadd("s", XAddId::Auto, fields("c"), None).unwrap();
+        assert!(auto > id(5, 2));
+
+        // Trimming keeps the last ID
+        db.xadd("s", XAddId::Auto, fields("d"), Some(1)).unwrap();
+        assert_eq!(db.xlen("s"), Ok(1));
+        assert!(db
+            .xadd("s", XAddId::Exact(id(6, 0)), fields("e"), None)
+            .is_err());
+        assert_eq!(db.key_type("t"), "none");
+    }
+
+    #[tokio::test]
+    async fn ranges() {
+        let db = Db::new();
+        for seq in 1..=4 {
+            db.xadd("s", XAddId::Exact(id(1, seq)), fields("v"), None)
+                .unwrap();
+        }
+
+        let ids = |entries: Vec<(StreamId, Fields)>| -> Vec<StreamId> {
+            entries.into_iter().map(|(id, _)| id).collect()
+        };
+
+        let all = db.xrange("s", Bound::Unbounded, Bound::Unbounded, 10, false);
+        assert_eq!(
+            all.map(ids),
+            Ok(vec![id(1, 1), id(1, 2), id(1, 3), id(1, 4)])
+        );
+
+        let range = db.xrange(
+            "s",
+            Bound::Excluded(id(1, 1)),
+            Bound::Included(id(1, 3)),
+            1,
+            true,
+        );
+        assert_eq!(range.map(ids), Ok(vec![id(1, 3)]));
+
+        let empty = db.xrange(
+            "s",
+            Bound::Included(id(2, 0)),
+            Bound::Included(id(1, 0)),
+            10,
+            false,
+        );
+        assert_eq!(empty, Ok(vec![]));
+    }
+
+    #[tokio::test]
+    async fn consumer_groups() {
+        let db = Db::new();
+        assert_eq!(
+            db.xgroup_create("s", "g", None, false),
+            Err(Error::Invalid("The XGROUP subcommand requires the key to exist. Note that for CREATE you may want to use the MKSTREAM option to create an empty stream automatically."))
+        );
+        db.xgroup_create("s", "g", Some(StreamId::MIN), true)
+            .unwrap();
+        assert_eq!(
+            db.xgroup_create("s", "g", None, false),
+            Err(Error::BusyGroup)
+        );
+
+        for seq in 1..=3 {
+            db.xadd("s", XAddId::Exact(id(1, seq)), fields("v"), None)
+                .unwrap();
+        }
+
+        let ids = |entries: Vec<(StreamId, Option<Fields>)>| -> Vec<StreamId> {
+            entries.into_iter().map(|(id, _)| id).collect()
+        };
+
+        // New entries are spread over the consumers
+        let read = db.xreadgroup("s", "g", "alice", None, 2, false);
+        assert_eq!(read.map(ids), Ok(vec![id(1, 1), id(1, 2)]));
+        let read = db.xreadgroup("s", "g", "bob", None, 10, false);
+        assert_eq!(read.map(ids), Ok(vec![id(1, 3)]));
+        assert_eq!(db.xreadgroup("s", "g", "bob", None, 10, false), Ok(vec![]));
+
+        // History only holds the entries pending for the consumer
+        let read = db.xreadgroup("s", "g", "alice", Some(StreamId::MIN), 10, false);
+        assert_eq!(read.map(ids), Ok(vec![id(1, 1), id(1, 2)]));
+
+        assert_eq!(db.xack("s", "g", &[id(1, 1), id(9, 9)]), Ok(1));
+
+        let pending = db
+            .xpending("s", "g", (Bound::Unbounded, Bound::Unbounded), 10, None)
+            .unwrap();
+        let pending: Vec<_> = pending
+            .into_iter()
+            .map(|(id, consumer, _, deliveries)| (id, consumer, deliveries))
+            .collect();
+        assert_eq!(
+            pending,
+            vec![
+                (id(1, 2), "alice".to_string(), 2),
+                (id(1, 3), "bob".to_string(), 1)
+            ]
+        );
+
+        let claim = Claim {
+            increment: true,
+            ..Claim::default()
+        };
+        let claimed = db.xclaim("s", "g", "bob", &[id(1, 2)], claim);
+        assert_eq!(claimed.map(|claimed| claimed.len()), Ok(1));
+        let read = db.xreadgroup("s", "g", "alice", Some(StreamId::MIN), 10, false);
+        assert_eq!(read, Ok(vec![]));
+
+        assert_eq!(
+            db.xreadgroup("s", "missing", "alice", None, 10, false),
+            Err(Error::NoGroup)
+        );
+    }
+
+    #[tokio::test]
+    async fn watches_wake_on_add() {
+        let db = Db::new();
+        let watch = db.watch_streams(vec!["s".to_string()]);
+
+        db.xadd("s", XAddId::Auto, fields("v"), None).unwrap();
+        // The entry was added before waiting, the watch still fires
+        watch.changed().await;
+
+        drop(watch);
+        assert!(db.shared.stream_waiters.lock().unwrap().keys.is_empty());
+    }
+}
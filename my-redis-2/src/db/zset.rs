@@ -0,0 +1,343 @@
+use super::{index_range, Collection, Db, Error, Events, Value};
+
+use bytes::Bytes;
+use std::cmp::Ordering;
+use std::collections::{BTreeSet, HashMap};
+
+/// A sorted set: members ordered by score, then lexicographically.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct ZSet {
+    /// Score of each member.
+    scores: HashMap<Bytes, f64>,
+
+    /// Members in order, the score first so ranges are walked in order.
+    order: BTreeSet<(Score, Bytes)>,
+}
+
+/// Conditions of `ZADD`, see `Db::zadd`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ZAdd {
+    /// `NX`: only add new members.
+    pub nx: bool,
+
+    /// `XX`: only update members already in the set.
+    pub xx: bool,
+
+    /// `GT` and `LT`: only update a score to a greater, or lower, one. New
+    /// members are still added.
+    pub gt: bool,
+    pub lt: bool,
+
+    /// `CH`: count the members whose score changed along with those added.
+    pub ch: bool,
+}
+
+/// What `ZSet::add` did to a member.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Change {
+    Added,
+    Updated,
+    Unchanged,
+
+    /// The conditions of `ZADD` were not met.
+    Skipped,
+}
+
+/// A score with a total order, scores are never NaN.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Score(f64);
+
+impl ZSet {
+    /// Set the score of `member`, returning `true` if it was not in the set.
+    pub(crate) fn insert(&mut self, member: Bytes, score: f64) -> bool {
+        match self.scores.insert(member.clone(), score) {
+            Some(previous) => {
+                self.order.remove(&(Score(previous), member.clone()));
+                self.order.insert((Score(score), member));
+                false
+            }
+            None => {
+                self.order.insert((Score(score), member));
+                true
+            }
+        }
+    }
+
+    /// Set the score of `member` unless the conditions of `flags` forbid it.
+    fn add(&mut self, member: Bytes, score: f64, flags: ZAdd) -> Change {
+        let change = match self.score(&member) {
+            None if flags.xx => Change::Skipped,
+            None => Change::Added,
+            Some(_) if flags.nx => Change::Skipped,
+            Some(old) if (flags.gt && score <= old) || (flags.lt && score >= old) => {
+                Change::Skipped
+            }
+            Some(old) if old == score => Change::Unchanged,
+            Some(_) => Change::Updated,
+        };
+
+        if matches!(change, Change::Added | Change::Updated) {
+            self.insert(member, score);
+        }
+        change
+    }
+
+    pub(crate) fn score(&self, member: &[u8]) -> Option<f64> {
+        self.scores.get(member).copied()
+    }
+
+    /// Position of `member` in the set, starting at 0 for the lowest score.
+    pub(crate) fn rank(&self, member: &[u8]) -> Option<usize> {
+        let score = self.score(member)?;
+        let member = Bytes::copy_from_slice(member);
+
+        Some(self.order.range(..(Score(score), member)).count())
+    }
+
+    /// Members along with their scores, in order.
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (&Bytes, f64)> {
+        self.order.iter().map(|(score, member)| (member, score.0))
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.scores.len()
+    }
+}
+
+impl Eq for Score {}
+
+impl PartialOrd for Score {
+    fn partial_cmp(&self, other: &Score) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Score {
+    fn cmp(&self, other: &Score) -> Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+impl Collection for ZSet {
+    fn from_value(value: &Value) -> Option<&Self> {
+        match value {
+            Value::ZSet(zset) => Some(zset),
+            _ => None,
+        }
+    }
+
+    fn from_value_mut(value: &mut Value) -> Option<&mut Self> {
+        match value {
+            Value::ZSet(zset) => Some(zset),
+            _ => None,
+        }
+    }
+
+    fn into_value(self) -> Value {
+        Value::ZSet(self)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.scores.is_empty()
+    }
+}
+
+impl Db {
+    /// Add members with their scores to the sorted set stored at `key`,
+    /// updating the score of members already in the set, as far as `flags`
+    /// allow.
+    ///
+    /// Returns the number of members which were added, counting those whose
+    /// score changed as well with `CH`.
+    pub fn zadd(&self, key: &str, members: Vec<(f64, Bytes)>, flags: ZAdd) -> Result<usize, Error> {
+        let changes = self.update(key, true, |zset: &mut ZSet| {
+            members
+                .into_iter()
+                .map(|(score, member)| zset.add(member, score, flags))
+                .collect::<Vec<_>>()
+        })?;
+        let changes = changes.unwrap_or_default();
+
+        let count = |change| changes.iter().filter(|c| **c == change).count();
+        let (added, updated) = (count(Change::Added), count(Change::Updated));
+
+        if added + updated > 0 {
+            self.notify(Events::ZSET, "zadd", key);
+        }
+
+        Ok(match flags.ch {
+            true => added + updated,
+            false => added,
+        })
+    }
+
+    /// The members of the sorted set stored at `key` between the `start` and
+    /// `stop` ranks, both inclusive, along with their scores. Negative ranks
+    /// count from the end.
+    pub fn zrange(&self, key: &str, start: i64, stop: i64) -> Result<Vec<(Bytes, f64)>, Error> {
+        let members = self.read(key, |zset: &ZSet| {
+            match index_range(zset.len(), start, stop) {
+                Some(range) => zset
+                    .iter()
+                    .skip(*range.start())
+                    .take(range.count())
+                    .map(|(member, score)| (member.clone(), score))
+                    .collect(),
+                None => vec![],
+            }
+        })?;
+
+        Ok(members.unwrap_or_default())
+    }
+
+    /// Rank of `member` in the sorted set stored at `key`.
+    pub fn zrank(&self, key: &str, member: &[u8]) -> Result<Option<usize>, Error> {
+        let rank = self.read(key, |zset: &ZSet| zset.rank(member))?;
+
+        Ok(rank.flatten())
+    }
+
+    /// Increment the score of `member` in the sorted set stored at `key`,
+    /// adding it with a score of `delta` if needed.
+    ///
+    /// Returns the new score.
+    pub fn zincr_by(&self, key: &str, member: Bytes, delta: f64) -> Result<f64, Error> {
+        let score = self.zadd_incr(key, member, delta, ZAdd::default())?;
+
+        Ok(score.expect("no condition to meet"))
+    }
+
+    /// Increment the score of `member` as `ZINCRBY` does, as far as `flags`
+    /// allow, for `ZADD INCR`.
+    ///
+    /// Returns the new score, or `None` if the conditions were not met.
+    pub fn zadd_incr(
+        &self,
+        key: &str,
+        member: Bytes,
+        delta: f64,
+        flags: ZAdd,
+    ) -> Result<Option<f64>, Error> {
+        let score = self.update(key, true, |zset: &mut ZSet| {
+            let score = zset.score(&member).unwrap_or(0.0) + delta;
+
+            // Adding infinities of opposite signs
+            if score.is_nan() {
+                return Err(Error::Invalid("resulting score is not a number (NaN)"));
+            }
+
+            Ok(match zset.add(member, score, flags) {
+                Change::Skipped => None,
+                _ => Some(score),
+            })
+        })?;
+
+        let score = score.expect("sorted set created")?;
+        if score.is_some() {
+            self.notify(Events::ZSET, "zincr", key);
+        }
+
+        Ok(score)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn members_are_ordered_by_score() {
+        let db = Db::new();
+        let members = vec![
+            (2.0, Bytes::from("b")),
+            (1.0, Bytes::from("c")),
+            (2.0, Bytes::from("a")),
+        ];
+
+        assert_eq!(db.zadd("zset", members, ZAdd::default()), Ok(3));
+        assert_eq!(
+            db.zadd("zset", vec![(0.5, Bytes::from("a"))], ZAdd::default()),
+            Ok(0)
+        );
+        assert_eq!(
+            db.zrange("zset", 0, -1),
+            Ok(vec![
+                (Bytes::from("a"), 0.5),
+                (Bytes::from("c"), 1.0),
+                (Bytes::from("b"), 2.0),
+            ])
+        );
+        assert_eq!(db.zrange("zset", -1, -1), Ok(vec![(Bytes::from("b"), 2.0)]));
+
+        assert_eq!(db.zrank("zset", b"c"), Ok(Some(1)));
+        assert_eq!(db.zrank("zset", b"missing"), Ok(None));
+        assert_eq!(db.zrank("missing", b"c"), Ok(None));
+
+        assert_eq!(db.zincr_by("zset", Bytes::from("a"), 2.0), Ok(2.5));
+        assert_eq!(db.zrank("zset", b"a"), Ok(Some(2)));
+
+        db.zadd(
+            "inf",
+            vec![(f64::INFINITY, Bytes::from("a"))],
+            ZAdd::default(),
+        )
+        .unwrap();
+        assert!(matches!(
+            db.zincr_by("inf", Bytes::from("a"), f64::NEG_INFINITY),
+            Err(Error::Invalid(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn zadd_follows_its_conditions() {
+        let db = Db::new();
+        let member = |name: &'static str, score| (score, Bytes::from(name));
+        let flags = |f: fn(&mut ZAdd)| {
+            let mut flags = ZAdd::default();
+            f(&mut flags);
+            flags
+        };
+        db.zadd("z", vec![member("a", 1.0)], ZAdd::default())
+            .unwrap();
+
+        // Only new members are added with NX, only existing ones updated with
+        // XX.
+        let nx = flags(|f| f.nx = true);
+        assert_eq!(
+            db.zadd("z", vec![member("a", 5.0), member("b", 2.0)], nx),
+            Ok(1)
+        );
+        let xx = flags(|f| f.xx = true);
+        assert_eq!(
+            db.zadd("z", vec![member("a", 3.0), member("c", 2.0)], xx),
+            Ok(0)
+        );
+        assert_eq!(db.zrank("z", b"c"), Ok(None));
+
+        // With CH, changed scores count too, unchanged ones do not.
+        let gt_ch = flags(|f| {
+            f.gt = true;
+            f.ch = true;
+        });
+        let members = vec![member("a", 4.0), member("b", 1.0), member("d", 0.0)];
+        assert_eq!(db.zadd("z", members, gt_ch), Ok(2));
+        let ch = flags(|f| f.ch = true);
+        assert_eq!(db.zadd("z", vec![member("a", 4.0)], ch), Ok(0));
+        assert_eq!(
+            db.zrange("z", 0, -1),
+            Ok(vec![
+                (Bytes::from("d"), 0.0),
+                (Bytes::from("b"), 2.0),
+                (Bytes::from("a"), 4.0),
+            ])
+        );
+
+        let lt = flags(|f| f.lt = true);
+        assert_eq!(db.zadd_incr("z", Bytes::from("a"), 1.0, lt), Ok(None));
+        assert_eq!(db.zadd_incr("z", Bytes::from("a"), -1.0, lt), Ok(Some(3.0)));
+
+        // XX on a missing key leaves no empty sorted set behind.
+        assert_eq!(db.zadd("missing", vec![member("a", 1.0)], xx), Ok(0));
+        assert_eq!(db.key_type("missing"), "none");
+    }
+}
@@ -0,0 +1,486 @@
+//! Provides a type representing a Redis protocol frame as well as utilities for
+//! parsing frames from a byte array.
+//!
+//! This mirrors `mini_redis::frame`, except that integers are signed so replies
+//! such as `TTL` (`-1`/`-2`) and `DECR` can be represented, and that the RESP3
+//! types negotiated with `HELLO 3` are supported as well.
+
+use bytes::{Buf, Bytes};
+use std::convert::TryInto;
+use std::fmt;
+use std::io::Cursor;
+use std::num::TryFromIntError;
+use std::string::FromUtf8Error;
+
+/// A frame in the Redis protocol.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Frame {
+    Simple(String),
+    Error(String),
+    Integer(i64),
+    Bulk(Bytes),
+    Null,
+    /// The null array, `*-1`, the RESP2 null reply of array-typed commands.
+    NullArray,
+    Array(Vec<Frame>),
+    // The types below are RESP3 only. On a RESP2 connection they are sent as
+    // their closest RESP2 equivalent, see `Connection::write_frame`.
+    Map(Vec<(Frame, Frame)>),
+    Set(Vec<Frame>),
+    Double(f64),
+    Boolean(bool),
+    BigNumber(String),
+    /// Text along with its three letter format, e.g. `txt` or `mkd`.
+    Verbatim(String, Bytes),
+    /// Out-of-band data such as pub/sub messages.
+    Push(Vec<Frame>),
+}
+
+/// The longest bulk string accepted, as Redis' default `proto-max-bulk-len`.
+const MAX_BULK_LEN: usize = 512 * 1024 * 1024;
+
+/// How deeply aggregate frames may be nested. Frames are parsed recursively,
+/// so deeper ones could overflow the stack.
+const MAX_DEPTH: usize = 128;
+
+#[derive(Debug)]
+pub enum Error {
+    /// Not enough data is available to parse a message
+    Incomplete,
+
+    /// Invalid message encoding
+    Other(mini_redis::Error),
+}
+
+impl Frame {
+    /// Returns an empty array
+    pub fn array() -> Frame {
+        Frame::Array(vec![])
+    }
+
+    /// Push a "bulk" frame into the array. `self` must be an Array frame.
+    ///
+    /// # Panics
+    ///
+    /// panics if `self` is not an array
+    pub fn push_bulk(&mut self, bytes: Bytes) {
+        match self {
+            Frame::Array(vec) => {
+                vec.push(Frame::Bulk(bytes));
+            }
+            _ => panic!("not an array frame"),
+        }
+    }
+
+    /// Push an "integer" frame into the array. `self` must be an Array frame.
+    ///
+    /// # Panics
+    ///
+    /// panics if `self` is not an array
+    pub fn push_int(&mut self, value: i64) {
+        match self {
+            Frame::Array(vec) => {
+                vec.push(Frame::Integer(value));
+            }
+            _ => panic!("not an array frame"),
+        }
+    }
+
+    /// Checks if an entire message can be decoded from `src`
+    pub fn check(src: &mut Cursor<&[u8]>) -> Result<(), Error> {
+        check_nested(src, 0)
+    }
+
+    /// The message has already been validated with `check`.
+    pub fn parse(src: &mut Cursor<&[u8]>) -> Result<Frame, Error> {
+        match get_u8(src)? {
+            b'+' => {
+                // Read the line and convert it to `Vec<u8>`
+                let line = get_line(src)?.to_vec();
+
+                // Convert the line to a String
+                let string = String::from_utf8(line)?;
+
+                Ok(Frame::Simple(string))
+            }
+            b'-' => {
+                // Read the line and convert it to `Vec<u8>`
+                let line = get_line(src)?.to_vec();
+
+                // Convert the line to a String
+                let string = String::from_utf8(line)?;
+
+                Ok(Frame::Error(string))
+            }
+            b':' => {
+                let val = get_decimal(src)?;
+                Ok(Frame::Integer(val))
+            }
+            b'$' => {
+                if b'-' == peek_u8(src)? {
+                    let line = get_line(src)?;
+
+                    if line != b"-1" {
+                        return Err("protocol error; invalid frame format".into());
+                    }
+
+                    Ok(Frame::Null)
+                } else {
+                    // Read the bulk string
+                    Ok(Frame::Bulk(get_blob(src)?))
+                }
+            }
+            b'*' => {
+                let len = get_decimal(src)?;
+
+                if len == -1 {
+                    return Ok(Frame::NullArray);
+                }
+
+                Ok(Frame::Array(parse_frames(src, len)?))
+            }
+            b'~' => {
+                let len = get_decimal(src)?;
+                Ok(Frame::Set(parse_frames(src, len)?))
+            }
+            b'>' => {
+                let len = get_decimal(src)?;
+                Ok(Frame::Push(parse_frames(src, len)?))
+            }
+            b'%' => {
+                let len = get_decimal(src)?.try_into()?;
+                let mut out = Vec::with_capacity(len);
+
+                for _ in 0..len {
+                    out.push((Frame::parse(src)?, Frame::parse(src)?));
+                }
+
+                Ok(Frame::Map(out))
+            }
+            b'_' => {
+                get_line(src)?;
+                Ok(Frame::Null)
+            }
+            b'#' => match get_line(src)? {
+                b"t" => Ok(Frame::Boolean(true)),
+                b"f" => Ok(Frame::Boolean(false)),
+                _ => Err("protocol error; invalid frame format".into()),
+            },
+            b',' => {
+                let line = get_line(src)?.to_vec();
+                let val = String::from_utf8(line)?
+                    .parse()
+                    .map_err(|_| "protocol error; invalid frame format")?;
+
+                Ok(Frame::Double(val))
+            }
+            b'(' => {
+                let line = get_line(src)?.to_vec();
+                Ok(Frame::BigNumber(String::from_utf8(line)?))
+            }
+            b'=' => {
+                let data = get_blob(src)?;
+
+                // The text is prefixed by its format and a colon, e.g. `txt:`
+                if data.len() < 4 || data[3] != b':' {
+                    return Err("protocol error; invalid frame format".into());
+                }
+
+                let format = String::from_utf8(data[..3].to_vec())?;
+                Ok(Frame::Verbatim(format, data.slice(4..)))
+            }
+            b'!' => {
+                let data = get_blob(src)?;
+                Ok(Frame::Error(String::from_utf8(data.to_vec())?))
+            }
+            actual => Err(format!("protocol error; invalid frame type byte `{}`", actual).into()),
+        }
+    }
+
+    /// Converts the frame to an "unexpected frame" error
+    pub fn to_error(&self) -> mini_redis::Error {
+        format!("unexpected frame: {}", self).into()
+    }
+}
+
+impl PartialEq<&str> for Frame {
+    fn eq(&self, other: &&str) -> bool {
+        match self {
+            Frame::Simple(s) => s.eq(other),
+            Frame::Bulk(s) => s.eq(other),
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Frame {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        use std::str;
+
+        match self {
+            Frame::Simple(response) => response.fmt(fmt),
+            Frame::Error(msg) => write!(fmt, "error: {}", msg),
+            Frame::Integer(num) => num.fmt(fmt),
+            Frame::Bulk(msg) => match str::from_utf8(msg) {
+                Ok(string) => string.fmt(fmt),
+                Err(_) => write!(fmt, "{:?}", msg),
+            },
+            Frame::Null | Frame::NullArray => "(nil)".fmt(fmt),
+            Frame::Double(val) => format_double(*val).fmt(fmt),
+            Frame::Boolean(val) => val.fmt(fmt),
+            Frame::BigNumber(val) => val.fmt(fmt),
+            Frame::Verbatim(_, text) => Frame::Bulk(text.clone()).fmt(fmt),
+            Frame::Map(pairs) => {
+                for (i, (key, value)) in pairs.iter().enumerate() {
+                    if i > 0 {
+                        write!(fmt, " ")?;
+                    }
+                    write!(fmt, "{} => {}", key, value)?;
+                }
+
+                Ok(())
+            }
+            Frame::Array(parts) | Frame::Set(parts) | Frame::Push(parts) => {
+                for (i, part) in parts.iter().enumerate() {
+                    if i > 0 {
+                        write!(fmt, " ")?;
+                    }
+                    part.fmt(fmt)?;
+                }
+
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Format a double the way RESP3 expects it, e.g. `1.5`, `inf` or `nan`.
+pub fn format_double(val: f64) -> String {
+    if val.is_nan() {
+        "nan".to_string()
+    } else if val.is_infinite() {
+        if val > 0.0 { "inf" } else { "-inf" }.to_string()
+    } else {
+        val.to_string()
+    }
+}
+
+/// Checks if an entire message, nested in `depth` aggregate frames, can be
+/// decoded from `src`.
+fn check_nested(src: &mut Cursor<&[u8]>, depth: usize) -> Result<(), Error> {
+    if depth > MAX_DEPTH {
+        return Err("protocol error; frame nested too deeply".into());
+    }
+
+    match get_u8(src)? {
+        b'+' => {
+            get_line(src)?;
+            Ok(())
+        }
+        b'-' | b'_' | b'#' | b',' | b'(' => {
+            get_line(src)?;
+            Ok(())
+        }
+        b':' => {
+            let _ = get_decimal(src)?;
+            Ok(())
+        }
+        b'$' | b'=' | b'!' => {
+            if b'-' == peek_u8(src)? {
+                // Skip '-1\r\n'
+                skip(src, 4)
+            } else {
+                // Read the bulk string
+                let len = bulk_len(src)?;
+
+                // skip that number of bytes + 2 (\r\n).
+                skip(src, len + 2)
+            }
+        }
+        b'*' | b'~' | b'>' => {
+            // A negative length is the null array, `*-1`
+            let len = get_decimal(src)?;
+
+            for _ in 0..len {
+                check_nested(src, depth + 1)?;
+            }
+
+            Ok(())
+        }
+        b'%' => {
+            let len = get_decimal(src)?
+                .checked_mul(2)
+                .ok_or("protocol error; invalid multibulk length")?;
+
+            // Each map entry is a key frame followed by a value frame
+            for _ in 0..len {
+                check_nested(src, depth + 1)?;
+            }
+
+            Ok(())
+        }
+        actual => Err(format!("protocol error; invalid frame type byte `{}`", actual).into()),
+    }
+}
+
+/// Read the length of a bulk string, which must be at most `MAX_BULK_LEN`.
+fn bulk_len(src: &mut Cursor<&[u8]>) -> Result<usize, Error> {
+    match get_decimal(src)?.try_into() {
+        Ok(len) if len <= MAX_BULK_LEN => Ok(len),
+        _ => Err("protocol error; invalid bulk length".into()),
+    }
+}
+
+/// Parse `len` consecutive frames, as found in aggregate types.
+fn parse_frames(src: &mut Cursor<&[u8]>, len: i64) -> Result<Vec<Frame>, Error> {
+    let len = len.try_into()?;
+    let mut out = Vec::with_capacity(len);
+
+    for _ in 0..len {
+        out.push(Frame::parse(src)?);
+    }
+
+    Ok(out)
+}
+
+/// Read a length-prefixed blob, as used by bulk strings.
+fn get_blob(src: &mut Cursor<&[u8]>) -> Result<Bytes, Error> {
+    let len = get_decimal(src)?.try_into()?;
+    let n = len + 2;
+
+    if src.remaining() < n {
+        return Err(Error::Incomplete);
+    }
+
+    let data = Bytes::copy_from_slice(&src.chunk()[..len]);
+
+    // skip that number of bytes + 2 (\r\n).
+    skip(src, n)?;
+
+    Ok(data)
+}
+
+fn peek_u8(src: &mut Cursor<&[u8]>) -> Result<u8, Error> {
+    if !src.has_remaining() {
+        return Err(Error::Incomplete);
+    }
+
+    Ok(src.chunk()[0])
+}
+
+fn get_u8(src: &mut Cursor<&[u8]>) -> Result<u8, Error> {
+    if !src.has_remaining() {
+        return Err(Error::Incomplete);
+    }
+
+    Ok(src.get_u8())
+}
+
+fn skip(src: &mut Cursor<&[u8]>, n: usize) -> Result<(), Error> {
+    if src.remaining() < n {
+        return Err(Error::Incomplete);
+    }
+
+    src.advance(n);
+    Ok(())
+}
+
+/// Read a new-line terminated decimal
+fn get_decimal(src: &mut Cursor<&[u8]>) -> Result<i64, Error> {
+    let line = get_line(src)?;
+
+    std::str::from_utf8(line)
+        .ok()
+        .and_then(|line| line.parse::<i64>().ok())
+        .ok_or_else(|| "protocol error; invalid frame format".into())
+}
+
+/// Find a line
+fn get_line<'a>(src: &mut Cursor<&'a [u8]>) -> Result<&'a [u8], Error> {
+    // Scan the bytes directly
+    let start = src.position() as usize;
+    // Scan to the second to last byte
+    let end = src.get_ref().len().saturating_sub(1);
+
+    for i in start..end {
+        if src.get_ref()[i] == b'\r' && src.get_ref()[i + 1] == b'\n' {
+            // We found a line, update the position to be *after* the \n
+            src.set_position((i + 2) as u64);
+
+            // Return the line
+            return Ok(&src.get_ref()[start..i]);
+        }
+    }
+
+    Err(Error::Incomplete)
+}
+
+impl From<String> for Error {
+    fn from(src: String) -> Error {
+        Error::Other(src.into())
+    }
+}
+
+impl From<&str> for Error {
+    fn from(src: &str) -> Error {
+        src.to_string().into()
+    }
+}
+
+impl From<FromUtf8Error> for Error {
+    fn from(_src: FromUtf8Error) -> Error {
+        "protocol error; invalid frame format".into()
+    }
+}
+
+impl From<TryFromIntError> for Error {
+    fn from(_src: TryFromIntError) -> Error {
+        "protocol error; invalid frame format".into()
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl fmt::Display for Error {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Incomplete => "stream ended early".fmt(fmt),
+            Error::Other(err) => err.fmt(fmt),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(src: &[u8]) -> Result<(), Error> {
+        Frame::check(&mut Cursor::new(src))
+    }
+
+    #[test]
+    fn out_of_range_lengths_are_rejected() {
+        assert!(matches!(
+            check(b"%9223372036854775807\r\n"),
+            Err(Error::Other(_))
+        ));
+        assert!(matches!(
+            check(b"$9223372036854775807\r\n"),
+            Err(Error::Other(_))
+        ));
+        assert!(matches!(check(b"$536870913\r\n"), Err(Error::Other(_))));
+        assert!(matches!(check(b"$536870912\r\n"), Err(Error::Incomplete)));
+    }
+
+    #[test]
+    fn deep_nesting_is_rejected() {
+        let nested = |depth: usize| {
+            let mut src = b"*1\r\n".repeat(depth);
+            src.extend_from_slice(b":1\r\n");
+            src
+        };
+
+        assert!(check(&nested(MAX_DEPTH)).is_ok());
+        assert!(matches!(check(&nested(200_000)), Err(Error::Other(_))));
+    }
+}
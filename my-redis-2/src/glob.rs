@@ -0,0 +1,152 @@
+//! Glob-style pattern matching, as used by `KEYS`.
+//!
+//! Supported syntax:
+//!
+//! * `?` matches any single character
+//! * `*` matches any run of characters, including none
+//! * `[abc]`, `[a-z]` and `[^a]` match a character class
+//! * `\x` matches `x` literally
+
+/// Returns `true` if `string` matches the glob-style `pattern`.
+pub fn matches(pattern: &str, string: &str) -> bool {
+    matches_bytes(pattern.as_bytes(), string.as_bytes())
+}
+
+/// Byte oriented version of `matches`.
+pub fn matches_bytes(pattern: &[u8], string: &[u8]) -> bool {
+    let (mut p, mut s) = (0, 0);
+
+    // Position to resume from when the last `*` has to swallow one more byte.
+    let mut backtrack = None;
+
+    while s < string.len() {
+        if p < pattern.len() {
+            match pattern[p] {
+                b'*' => {
+                    backtrack = Some((p, s));
+                    p += 1;
+                    continue;
+                }
+                b'?' => {
+                    p += 1;
+                    s += 1;
+                    continue;
+                }
+                b'[' => {
+                    if let Some((matched, next)) = class(pattern, p, string[s]) {
+                        if matched {
+                            p = next;
+                            s += 1;
+                            continue;
+                        }
+                    } else if string[s] == b'[' {
+                        // An unterminated class is matched literally
+                        p += 1;
+                        s += 1;
+                        continue;
+                    }
+                }
+                b'\\' if p + 1 < pattern.len() => {
+                    if pattern[p + 1] == string[s] {
+                        p += 2;
+                        s += 1;
+                        continue;
+                    }
+                }
+                c => {
+                    if c == string[s] {
+                        p += 1;
+                        s += 1;
+                        continue;
+                    }
+                }
+            }
+        }
+
+        // Mismatch, let the last `*` consume one more byte if there is one
+        match backtrack {
+            Some((star, from)) => {
+                p = star + 1;
+                s = from + 1;
+                backtrack = Some((star, from + 1));
+            }
+            None => return false,
+        }
+    }
+
+    // The string is consumed, only trailing `*` may remain in the pattern
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+/// Match `c` against the character class starting at `pattern[start]`.
+///
+/// Returns whether it matched and the index just past the class, or `None`
+/// if the class is not terminated.
+fn class(pattern: &[u8], start: usize, c: u8) -> Option<(bool, usize)> {
+    let mut i = start + 1;
+
+    let negate = pattern.get(i) == Some(&b'^');
+    if negate {
+        i += 1;
+    }
+
+    let mut matched = false;
+
+    loop {
+        match *pattern.get(i)? {
+            b']' => return Some((matched != negate, i + 1)),
+            b'\\' => {
+                matched |= *pattern.get(i + 1)? == c;
+                i += 2;
+            }
+            lo if pattern.get(i + 1) == Some(&b'-')
+                && pattern.get(i + 2).is_some_and(|&hi| hi != b']') =>
+            {
+                let hi = pattern[i + 2];
+                let (lo, hi) = if lo <= hi { (lo, hi) } else { (hi, lo) };
+                matched |= (lo..=hi).contains(&c);
+                i += 3;
+            }
+            other => {
+                matched |= other == c;
+                i += 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::matches;
+
+    #[test]
+    fn wildcards() {
+        assert!(matches("*", ""));
+        assert!(matches("*", "anything"));
+        assert!(matches("h?llo", "hello"));
+        assert!(!matches("h?llo", "hllo"));
+        assert!(matches("h*llo", "hllo"));
+        assert!(matches("h*llo", "heeeello"));
+        assert!(matches("user:*:name", "user:42:name"));
+        assert!(!matches("user:*:name", "user:42:age"));
+        assert!(matches("*a*b", "xaxxab"));
+    }
+
+    #[test]
+    fn classes() {
+        assert!(matches("h[ae]llo", "hello"));
+        assert!(matches("h[ae]llo", "hallo"));
+        assert!(!matches("h[ae]llo", "hillo"));
+        assert!(matches("h[^e]llo", "hallo"));
+        assert!(!matches("h[^e]llo", "hello"));
+        assert!(matches("h[a-b]llo", "hbllo"));
+        assert!(!matches("h[a-b]llo", "hcllo"));
+    }
+
+    #[test]
+    fn escapes() {
+        assert!(matches("h\\*llo", "h*llo"));
+        assert!(!matches("h\\*llo", "hello"));
+        assert!(matches("[\\]]", "]"));
+    }
+}
@@ -1,27 +1,106 @@
-use bytes::{Buf, BytesMut};
-use mini_redis::{frame::Error::Incomplete, Frame, Result};
+use bytes::{Bytes, BytesMut};
+pub use mini_redis::{Error, Result};
+use std::borrow::Cow;
 use std::io::Cursor;
-use tokio::io::{self, AsyncReadExt, AsyncWriteExt, BufWriter};
+use tokio::io::{self, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufWriter};
 use tokio::net::TcpStream;
 
-pub struct Connection {
-    stream: BufWriter<TcpStream>,
+pub mod acl;
+
+pub mod aof;
+
+pub mod client;
+
+pub mod clients;
+
+pub mod cmd;
+
+pub mod config;
+
+pub mod db;
+pub use db::Db;
+
+pub mod frame;
+pub use frame::Frame;
+
+mod glob;
+
+mod sha256;
+
+mod parse;
+pub use parse::{Parse, ParseError};
+
+pub mod replication;
+pub mod server;
+
+pub mod slowlog;
+
+pub mod snapshot;
+
+/// A byte stream the server serves a connection over, such as a TCP or Unix
+/// socket.
+pub trait Socket: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<S: AsyncRead + AsyncWrite + Unpin + Send> Socket for S {}
+
+/// Send and receive `Frame` values over a byte stream.
+///
+/// The stream defaults to a `TcpStream`, but anything readable and writable
+/// works, e.g. an in-memory `tokio::io::duplex` pipe in tests.
+pub struct Connection<S = TcpStream> {
+    stream: BufWriter<S>,
     buffer: BytesMut,
+    // RESP protocol version used to encode frames, 2 until `HELLO 3`
+    protocol: u8,
 }
 
-impl Connection {
-    pub fn new(stream: TcpStream) -> Self {
+impl<S: AsyncRead + AsyncWrite + Unpin> Connection<S> {
+    pub fn new(stream: S) -> Self {
         Connection {
             stream: BufWriter::new(stream),
             // Allocate the buffer with 4kb of capacity
             buffer: BytesMut::with_capacity(4096),
+            protocol: 2,
         }
     }
 
+    /// The RESP protocol version frames are written with.
+    pub fn protocol(&self) -> u8 {
+        self.protocol
+    }
+
+    /// Switch the RESP protocol version, as negotiated by `HELLO`.
+    ///
+    /// With protocol 2, RESP3-only frames are written as their closest RESP2
+    /// equivalent: maps, sets and pushes become flat arrays, doubles and big
+    /// numbers become bulk strings and booleans become `0`/`1` integers.
+    pub fn set_protocol(&mut self, protocol: u8) {
+        self.protocol = protocol;
+    }
+
+    /// Bytes received but not parsed into a frame yet, along with the
+    /// capacity of the input buffer.
+    pub fn input_buffer(&self) -> (usize, usize) {
+        (self.buffer.len(), self.buffer.capacity())
+    }
+
+    /// Bytes written but not flushed yet.
+    pub fn output_buffer(&self) -> usize {
+        self.stream.buffer().len()
+    }
+
     /// Read a frame from the connection.
     ///
     /// Returns `None` if EOF is reached
     pub async fn read_frame(&mut self) -> Result<Option<Frame>> {
+        Ok(self.read_frame_raw().await?.map(|(frame, _)| frame))
+    }
+
+    /// Read a frame from the connection, along with the bytes it was encoded
+    /// as.
+    ///
+    /// Returns `None` if EOF is reached
+    pub async fn read_frame_raw(&mut self) -> Result<Option<(Frame, Bytes)>> {
         loop {
             // Attempt to parse a frame from the buffered data. If
             // enough data has been buffered, the frame is
@@ -49,7 +128,22 @@ impl Connection {
         }
     }
 
-    fn parse_frame(&mut self) -> Result<Option<Frame>> {
+    /// Wait for the remote to close the connection, or for it to fail.
+    ///
+    /// Data received in the meantime is kept for the next `read_frame`, so a
+    /// client blocked by a command can still pipeline further commands.
+    pub async fn closed(&mut self) {
+        loop {
+            match self.stream.read_buf(&mut self.buffer).await {
+                Ok(0) | Err(_) => return,
+                Ok(_) => {}
+            }
+        }
+    }
+
+    fn parse_frame(&mut self) -> Result<Option<(Frame, Bytes)>> {
+        use frame::Error::Incomplete;
+
         // Create the `T: Buf` type
         let mut buf = Cursor::new(&self.buffer[..]);
 
@@ -65,11 +159,11 @@ impl Connection {
                 // Parse the frame
                 let frame = Frame::parse(&mut buf)?;
 
-                // Discard the frame from the buffer
-                self.buffer.advance(len);
+                // Take the frame out of the buffer
+                let raw = self.buffer.split_to(len).freeze();
 
                 // Return the frame to the caller.
-                Ok(Some(frame))
+                Ok(Some((frame, raw)))
             }
             // Not enough data has been buffered
             Err(Incomplete) => Ok(None),
@@ -80,46 +174,147 @@ impl Connection {
 
     /// Write a frame to the connection.
     pub async fn write_frame(&mut self, frame: &Frame) -> io::Result<()> {
+        self.write_value(frame).await?;
+        self.stream.flush().await?;
+
+        Ok(())
+    }
+
+    /// Write data which is already encoded, such as frames read with
+    /// `read_frame_raw`.
+    pub async fn write_raw(&mut self, data: &[u8]) -> io::Result<()> {
+        self.stream.write_all(data).await?;
+        self.stream.flush().await
+    }
+
+    /// Write a frame to the buffered stream, without flushing it.
+    async fn write_value(&mut self, frame: &Frame) -> io::Result<()> {
+        let resp3 = self.protocol >= 3;
+
         match frame {
             Frame::Simple(val) => {
                 self.stream.write_u8(b'+').await?;
-                self.stream.write_all(val.as_bytes()).await?;
+                self.stream.write_all(&one_line(val)).await?;
                 self.stream.write_all(b"\r\n").await?;
             }
             Frame::Error(val) => {
                 self.stream.write_u8(b'-').await?;
-                self.stream.write_all(val.as_bytes()).await?;
+                self.stream.write_all(&one_line(val)).await?;
                 self.stream.write_all(b"\r\n").await?;
             }
             Frame::Integer(val) => {
                 self.stream.write_u8(b':').await?;
                 self.write_decimal(*val).await?;
             }
+            // RESP3 has a single null type
+            Frame::Null | Frame::NullArray if resp3 => {
+                self.stream.write_all(b"_\r\n").await?;
+            }
             Frame::Null => {
                 self.stream.write_all(b"$-1\r\n").await?;
             }
-            Frame::Bulk(val) => {
-                let len = val.len();
+            Frame::Bulk(val) => self.write_blob(b'$', val).await?,
+            Frame::NullArray => {
+                self.stream.write_all(b"*-1\r\n").await?;
+            }
+            Frame::Array(val) => self.write_aggregate(b'*', val).await?,
+            Frame::Set(val) => {
+                let prefix = if resp3 { b'~' } else { b'*' };
+                self.write_aggregate(prefix, val).await?;
+            }
+            Frame::Push(val) => {
+                let prefix = if resp3 { b'>' } else { b'*' };
+                self.write_aggregate(prefix, val).await?;
+            }
+            Frame::Map(pairs) => {
+                // A RESP2 map is a flat array of keys and values
+                if resp3 {
+                    self.stream.write_u8(b'%').await?;
+                    self.write_decimal(pairs.len() as i64).await?;
+                } else {
+                    self.stream.write_u8(b'*').await?;
+                    self.write_decimal(pairs.len() as i64 * 2).await?;
+                }
 
-                self.stream.write_u8(b'$').await?;
-                self.write_decimal(len as u64).await?;
+                for (key, value) in pairs {
+                    Box::pin(self.write_value(key)).await?;
+                    Box::pin(self.write_value(value)).await?;
+                }
+            }
+            Frame::Double(val) => {
+                let val = frame::format_double(*val);
+                self.write_line_or_blob(b',', val.as_bytes()).await?;
+            }
+            Frame::BigNumber(val) => self.write_line_or_blob(b'(', val.as_bytes()).await?,
+            Frame::Boolean(val) if resp3 => {
+                let val = if *val { b"#t\r\n" } else { b"#f\r\n" };
                 self.stream.write_all(val).await?;
-                self.stream.write_all(b"\r\n").await?;
             }
-            Frame::Array(_val) => unimplemented!(),
+            Frame::Boolean(val) => {
+                self.stream.write_u8(b':').await?;
+                self.write_decimal(*val as i64).await?;
+            }
+            Frame::Verbatim(format, text) if resp3 => {
+                let mut val = Vec::with_capacity(format.len() + 1 + text.len());
+                val.extend_from_slice(format.as_bytes());
+                val.push(b':');
+                val.extend_from_slice(text);
+
+                self.write_blob(b'=', &val).await?;
+            }
+            Frame::Verbatim(_, text) => self.write_blob(b'$', text).await?,
         }
 
-        self.stream.flush().await?;
+        Ok(())
+    }
+
+    /// Write an aggregate frame: its type prefix, length and entries.
+    async fn write_aggregate(&mut self, prefix: u8, val: &[Frame]) -> io::Result<()> {
+        // Encode the frame type prefix. For an array, it is `*`.
+        self.stream.write_u8(prefix).await?;
+
+        // Encode the length of the array.
+        self.write_decimal(val.len() as i64).await?;
+
+        // Iterate and encode each entry in the array. Entries may be
+        // arrays themselves, so the recursive call has to be boxed.
+        for entry in val {
+            Box::pin(self.write_value(entry)).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Write a length-prefixed blob, such as a bulk string.
+    async fn write_blob(&mut self, prefix: u8, val: &[u8]) -> io::Result<()> {
+        self.stream.write_u8(prefix).await?;
+        self.write_decimal(val.len() as i64).await?;
+        self.stream.write_all(val).await?;
+        self.stream.write_all(b"\r\n").await?;
+
+        Ok(())
+    }
+
+    /// Write a RESP3 line type, or a bulk string with the same content when
+    /// speaking RESP2.
+    async fn write_line_or_blob(&mut self, prefix: u8, val: &[u8]) -> io::Result<()> {
+        if self.protocol < 3 {
+            return self.write_blob(b'$', val).await;
+        }
+
+        self.stream.write_u8(prefix).await?;
+        self.stream.write_all(val).await?;
+        self.stream.write_all(b"\r\n").await?;
 
         Ok(())
     }
 
     /// Write a decimal frame to the stream
-    async fn write_decimal(&mut self, val: u64) -> io::Result<()> {
+    async fn write_decimal(&mut self, val: i64) -> io::Result<()> {
         use std::io::Write;
 
         // Convert the value to a string
-        let mut buf = [0u8; 12];
+        let mut buf = [0u8; 20];
         let mut buf = Cursor::new(&mut buf[..]);
         write!(&mut buf, "{}", val)?;
 
@@ -257,3 +452,169 @@ pub mod fut {
         }
     }
 }
+
+/// `line` with its line breaks replaced by spaces, as simple strings and
+/// errors end at the first one. Errors often quote client input.
+fn one_line(line: &str) -> Cow<'_, [u8]> {
+    if !line.contains(['\r', '\n']) {
+        return Cow::Borrowed(line.as_bytes());
+    }
+
+    line.bytes()
+        .map(|b| match b {
+            b'\r' | b'\n' => b' ',
+            b => b,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+    use tokio::io::{duplex, AsyncWriteExt};
+
+    /// Write `frame` on one end of an in-memory pipe and read it back from the
+    /// other end.
+    async fn round_trip(frame: Frame) -> Frame {
+        round_trip_with(2, frame).await
+    }
+
+    /// Same as `round_trip`, with the writer speaking RESP `protocol`.
+    async fn round_trip_with(protocol: u8, frame: Frame) -> Frame {
+        let (client, server) = duplex(64);
+        let (mut tx, mut rx) = (Connection::new(client), Connection::new(server));
+        tx.set_protocol(protocol);
+
+        // The pipe is smaller than some frames, so write and read concurrently.
+        let write = async move { tx.write_frame(&frame).await.unwrap() };
+        let (_, read) = tokio::join!(write, rx.read_frame());
+
+        read.unwrap().unwrap()
+    }
+
+    #[tokio::test]
+    async fn literals_round_trip() {
+        for frame in [
+            Frame::Simple("OK".to_string()),
+            Frame::Error("ERR boom".to_string()),
+            Frame::Integer(-42),
+            Frame::Integer(i64::MIN),
+            Frame::Bulk(Bytes::from_static(b"hello\r\nworld")),
+            Frame::Null,
+            Frame::NullArray,
+        ] {
+            assert_eq!(round_trip(frame.clone()).await, frame);
+        }
+    }
+
+    #[tokio::test]
+    async fn line_breaks_stay_out_of_lines() {
+        let error = Frame::Error("ERR unknown command 'a\r\n+OK'".to_string());
+        assert_eq!(
+            round_trip(error).await,
+            Frame::Error("ERR unknown command 'a  +OK'".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn arrays_round_trip() {
+        let nested = Frame::Array(vec![
+            Frame::Bulk(Bytes::from_static(b"message")),
+            Frame::Array(vec![]),
+            Frame::Array(vec![
+                Frame::Integer(1),
+                Frame::Null,
+                Frame::NullArray,
+                Frame::Array(vec![Frame::Simple("deep".to_string())]),
+            ]),
+        ]);
+
+        assert_eq!(round_trip(nested.clone()).await, nested);
+        assert_eq!(round_trip(Frame::array()).await, Frame::array());
+    }
+
+    #[tokio::test]
+    async fn frames_split_across_reads() {
+        let (mut client, server) = duplex(64);
+        let mut rx = Connection::new(server);
+
+        let write = async move {
+            for chunk in [&b"*2\r\n$3\r\nfo"[..], b"o\r\n*-", b"1\r\n:7\r\n"] {
+                client.write_all(chunk).await.unwrap();
+                tokio::task::yield_now().await;
+            }
+        };
+        let read = async {
+            (
+                rx.read_frame().await.unwrap(),
+                rx.read_frame().await.unwrap(),
+                rx.read_frame().await.unwrap(),
+            )
+        };
+        let (_, frames) = tokio::join!(write, read);
+
+        let foo = Frame::Bulk(Bytes::from_static(b"foo"));
+        assert_eq!(frames.0, Some(Frame::Array(vec![foo, Frame::NullArray])));
+        assert_eq!(frames.1, Some(Frame::Integer(7)));
+        // The writer hung up after a complete frame, which is a clean EOF.
+        assert_eq!(frames.2, None);
+    }
+
+    #[tokio::test]
+    async fn resp3_round_trip() {
+        let bulk = |s: &'static str| Frame::Bulk(Bytes::from_static(s.as_bytes()));
+
+        for frame in [
+            Frame::Map(vec![
+                (bulk("proto"), Frame::Integer(3)),
+                (bulk("modules"), Frame::array()),
+            ]),
+            Frame::Set(vec![bulk("a"), bulk("b")]),
+            Frame::Double(1.5),
+            Frame::Double(f64::NEG_INFINITY),
+            Frame::Boolean(true),
+            Frame::Boolean(false),
+            Frame::BigNumber("3492890328409238509324850943850943825024385".to_string()),
+            Frame::Verbatim("txt".to_string(), Bytes::from_static(b"Some string")),
+            Frame::Push(vec![bulk("message"), bulk("news"), bulk("hi")]),
+        ] {
+            assert_eq!(round_trip_with(3, frame.clone()).await, frame);
+        }
+
+        // RESP3 has a single null type
+        assert_eq!(round_trip_with(3, Frame::NullArray).await, Frame::Null);
+    }
+
+    #[tokio::test]
+    async fn resp3_types_downgrade_to_resp2() {
+        let bulk = |s: &'static str| Frame::Bulk(Bytes::from_static(s.as_bytes()));
+
+        let map = Frame::Map(vec![(bulk("a"), Frame::Integer(1))]);
+        assert_eq!(
+            round_trip(map).await,
+            Frame::Array(vec![bulk("a"), Frame::Integer(1)])
+        );
+
+        let push = Frame::Push(vec![bulk("message"), Frame::Set(vec![bulk("x")])]);
+        assert_eq!(
+            round_trip(push).await,
+            Frame::Array(vec![bulk("message"), Frame::Array(vec![bulk("x")])])
+        );
+
+        assert_eq!(round_trip(Frame::Double(2.5)).await, bulk("2.5"));
+        assert_eq!(round_trip(Frame::Boolean(true)).await, Frame::Integer(1));
+        assert_eq!(
+            round_trip(Frame::BigNumber("12".to_string())).await,
+            bulk("12")
+        );
+        assert_eq!(
+            round_trip(Frame::Verbatim(
+                "txt".to_string(),
+                Bytes::from_static(b"hi")
+            ))
+            .await,
+            bulk("hi")
+        );
+    }
+}
@@ -0,0 +1,705 @@
+//! Leader/follower replication.
+//!
+//! A leader streams the write commands it applies to its followers, encoded
+//! as they are in the append-only file. Every byte of the stream has an
+//! offset, counted from the start of the stream, which is named by a random
+//! replication ID. The last bytes of the stream are kept in a backlog.
+//!
+//! A follower connects with `PSYNC replid offset`, `offset` being the number
+//! of bytes of the stream it applied so far, `PSYNC ? -1` the first time. If
+//! the backlog still holds the bytes from `offset` on, the leader replies
+//! `+CONTINUE replid` and sends them. Otherwise it replies
+//! `+FULLRESYNC replid offset`, then a snapshot of its keyspace as a bulk
+//! string. Either way, the stream follows. A `SELECT` is streamed whenever a
+//! command applies to another database than the previous one.
+//!
+//! Followers are set up by `REPLICAOF host port`. They only accept write
+//! commands from their leader and reconnect when the link breaks, resuming
+//! the stream where they left it if possible. A follower authenticates with
+//! the `masteruser` and `masterauth` settings, if set, before `PSYNC`.
+//!
+//! A follower which falls `FOLLOWER_BUFFER` chunks of the stream behind is
+//! disconnected, and syncs again once it reconnects.
+
+use crate::client::command;
+use crate::{aof, cmd, db, snapshot, Connection, Db, Frame, Socket};
+
+use bytes::Bytes;
+use std::collections::VecDeque;
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio::{select, time};
+
+/// Bytes of the stream kept for followers to catch up after a disconnection.
+const BACKLOG_SIZE: usize = 1024 * 1024;
+
+/// Chunks of the stream buffered for a follower before it is disconnected as
+/// too slow.
+const FOLLOWER_BUFFER: usize = 64 * 1024;
+
+/// Time between attempts to reconnect to the leader.
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+/// The replication stream of a server and the leader it follows, if any, see
+/// `Db::replication`.
+#[derive(Debug)]
+pub struct Replication {
+    /// Names the stream, offsets of different streams being unrelated.
+    replid: String,
+
+    /// The ID of the stream followed before this server was promoted, along
+    /// with the offset at which it was promoted. Followers of the same leader
+    /// share the stream up to that offset.
+    prev_replid: Option<(String, u64)>,
+
+    /// Offset of the end of the stream.
+    offset: u64,
+
+    /// Index of the database last selected in the stream, unset if the next
+    /// command has to select one. A follower applies the commands of its
+    /// leader to this database.
+    selected: Option<usize>,
+
+    /// The last bytes of the stream, at most `BACKLOG_SIZE`.
+    backlog: VecDeque<u8>,
+
+    /// Every new byte of the stream is sent to each follower.
+    followers: Vec<mpsc::Sender<Bytes>>,
+
+    /// The leader followed, if any.
+    leader: Option<Leader>,
+
+    /// Whether write commands are added to the stream, which they are once a
+    /// follower connected or this server followed a leader. It stays set, as
+    /// followers reconnect.
+    streaming: bool,
+}
+
+#[derive(Debug)]
+struct Leader {
+    host: String,
+    port: u16,
+
+    /// The task following the leader, aborted once this server stops
+    /// following it.
+    task: JoinHandle<()>,
+}
+
+/// What a follower is sent first, see `serve`.
+enum Sync {
+    /// The keyspace, ending at the given offset of the stream.
+    Full(Vec<u8>, u64),
+
+    /// The bytes of the stream the follower missed.
+    Continue(Vec<u8>),
+}
+
+impl Default for Replication {
+    fn default() -> Replication {
+        Replication {
+            replid: new_replid(),
+            prev_replid: None,
+            offset: 0,
+            selected: None,
+            backlog: VecDeque::new(),
+            followers: vec![],
+            streaming: false,
+            leader: None,
+        }
+    }
+}
+
+impl Replication {
+    /// Stream a write command just applied by this server to the database
+    /// `db`, as encoded by `aof::encode`.
+    ///
+    /// A follower only applies the commands of its leader, whose stream it
+    /// takes over as is, see `sync_with`.
+    pub(crate) fn feed(&mut self, db: usize, data: &[u8]) {
+        if self.leader.is_some() {
+            return;
+        }
+
+        if self.selected != Some(db) {
+            self.append(Bytes::from(aof::select(db)));
+            self.selected = Some(db);
+        }
+        self.append(Bytes::copy_from_slice(data));
+    }
+
+    /// Returns `true` if this server follows a leader, and thus only accepts
+    /// writes from it.
+    pub(crate) fn is_follower(&self) -> bool {
+        self.leader.is_some()
+    }
+
+    /// Add `data` to the stream, sending it to the followers.
+    fn append(&mut self, data: Bytes) {
+        self.offset += data.len() as u64;
+
+        self.backlog.extend(&data[..]);
+        let excess = self.backlog.len().saturating_sub(BACKLOG_SIZE);
+        self.backlog.drain(..excess);
+
+        // Followers which went away dropped their receiver. Those too far
+        // behind are dropped, which closes their link.
+        self.followers
+            .retain(|follower| follower.try_send(data.clone()).is_ok());
+    }
+
+    /// The bytes of the stream `replid` from `offset` on, if the backlog still
+    /// holds them.
+    fn backlog_from(&self, replid: &str, offset: u64) -> Option<Vec<u8>> {
+        let same_stream = replid == self.replid
+            || matches!(&self.prev_replid, Some((prev, end)) if prev == replid && offset <= *end);
+        let start = self.offset - self.backlog.len() as u64;
+
+        if !same_stream || offset < start || offset > self.offset {
+            return None;
+        }
+
+        Some(
+            self.backlog
+                .range((offset - start) as usize..)
+                .copied()
+                .collect(),
+        )
+    }
+
+    /// Whether write commands are added to the stream.
+    pub(crate) fn is_streaming(&self) -> bool {
+        self.streaming
+    }
+
+    /// Close the links with the followers, which reconnect and sync again.
+    pub(crate) fn disconnect_followers(&mut self) {
+        self.followers.clear();
+    }
+
+    /// Take over the stream `replid` at `offset`, after a full sync.
+    fn reset(&mut self, replid: String, offset: u64) {
+        self.replid = replid;
+        self.prev_replid = None;
+        self.offset = offset;
+        self.selected = None;
+        self.backlog.clear();
+
+        // Followers of this server resync, as its keyspace was replaced.
+        self.disconnect_followers();
+    }
+}
+
+/// Follow the leader at `host:port`, replacing the keyspace with its own.
+/// The server stops accepting writes from clients.
+pub fn follow(db: &Db, host: String, port: u16) {
+    // Write commands applied once promoted are streamed to followers.
+    let mut logging = db.logging_mut();
+    *logging = true;
+
+    let mut replication = db.replication();
+    replication.streaming = true;
+
+    if let Some(leader) = replication.leader.take() {
+        leader.task.abort();
+    }
+
+    let task = tokio::spawn(run_follower(db.clone(), host.clone(), port));
+    replication.leader = Some(Leader { host, port, task });
+}
+
+/// Stop following the leader, if any. The keyspace is kept and the server
+/// accepts writes again.
+pub fn promote(db: &Db) {
+    let mut replication = db.replication();
+
+    if let Some(leader) = replication.leader.take() {
+        leader.task.abort();
+
+        // Writes now diverge from the stream of the leader. Followers which
+        // applied no more of it than this server can still continue.
+        let prev = std::mem::replace(&mut replication.replid, new_replid());
+        replication.prev_replid = Some((prev, replication.offset));
+    }
+}
+
+/// The leader followed, if any.
+pub fn leader(db: &Db) -> Option<(String, u16)> {
+    let replication = db.replication();
+    let leader = replication.leader.as_ref()?;
+    Some((leader.host.clone(), leader.port))
+}
+
+/// The fields of the `Replication` section of `INFO`.
+pub(crate) fn info(db: &Db) -> Vec<(&'static str, String)> {
+    let replication = db.replication();
+
+    let mut fields = vec![];
+    match &replication.leader {
+        Some(leader) => {
+            fields.push(("role", "slave".to_string()));
+            fields.push(("master_host", leader.host.clone()));
+            fields.push(("master_port", leader.port.to_string()));
+        }
+        None => fields.push(("role", "master".to_string())),
+    }
+    let followers = replication.followers.iter().filter(|f| !f.is_closed());
+    fields.push(("connected_slaves", followers.count().to_string()));
+    fields.push(("master_replid", replication.replid.clone()));
+    fields.push(("master_repl_offset", replication.offset.to_string()));
+
+    fields
+}
+
+/// Serve the follower connected to `dst`, which applied the stream `replid`
+/// up to `offset`.
+///
+/// This only returns once the follower disconnects, or is disconnected, see
+/// `Replication::disconnect_followers`.
+pub(crate) async fn serve(
+    db: &Db,
+    replid: &str,
+    offset: Option<u64>,
+    dst: &mut Connection<impl Socket>,
+) -> crate::Result<()> {
+    let (tx, mut rx) = mpsc::channel(FOLLOWER_BUFFER);
+
+    let (sync, replid) = {
+        // Write commands hold the logging lock while they run, so the
+        // follower gets every command either within the keyspace or in the
+        // stream.
+        let mut logging = db.logging_mut();
+        *logging = true;
+
+        let mut replication = db.replication();
+        replication.streaming = true;
+
+        let sync = match offset.and_then(|offset| replication.backlog_from(replid, offset)) {
+            Some(missing) => Sync::Continue(missing),
+            None => {
+                // The stream following the keyspace starts by selecting a
+                // database, as the follower starts from the first one.
+                replication.selected = None;
+                Sync::Full(snapshot::dump(db), replication.offset)
+            }
+        };
+
+        replication.followers.push(tx);
+        (sync, replication.replid.clone())
+    };
+
+    match sync {
+        Sync::Full(keyspace, offset) => {
+            let reply = format!("FULLRESYNC {} {}", replid, offset);
+            dst.write_frame(&Frame::Simple(reply)).await?;
+            dst.write_frame(&Frame::Bulk(Bytes::from(keyspace))).await?;
+        }
+        Sync::Continue(missing) => {
+            dst.write_frame(&Frame::Simple(format!("CONTINUE {}", replid)))
+                .await?;
+            dst.write_raw(&missing).await?;
+        }
+    }
+
+    loop {
+        select! {
+            data = rx.recv() => match data {
+                Some(data) => dst.write_raw(&data).await?,
+                // The follower fell too far behind, the keyspace of this
+                // server was replaced, or it shuts down. Closing the
+                // connection makes the follower sync again.
+                None => return Err("replication link closed".into()),
+            },
+            _ = dst.closed() => return Ok(()),
+        }
+    }
+}
+
+/// Routine of the task following a leader, which reconnects whenever the
+/// link breaks.
+async fn run_follower(db: Db, host: String, port: u16) {
+    loop {
+        match sync_with(&db, &host, port).await {
+            Ok(()) => eprintln!("leader {}:{} closed the replication link", host, port),
+            Err(err) => eprintln!("replication link with {}:{} failed: {}", host, port, err),
+        }
+
+        time::sleep(RECONNECT_DELAY).await;
+    }
+}
+
+/// Connect to the leader, sync with it and apply its stream until the link
+/// breaks.
+async fn sync_with(db: &Db, host: &str, port: u16) -> crate::Result<()> {
+    let (user, password) = {
+        let config = db.config();
+        (config.masteruser.clone(), config.masterauth.clone())
+    };
+
+    let socket = TcpStream::connect((host, port)).await?;
+    let mut leader = Connection::new(socket);
+
+    if !password.is_empty() {
+        let auth = match user.is_empty() {
+            true => command(&["AUTH", &password]),
+            false => command(&["AUTH", &user, &password]),
+        };
+        call(&mut leader, &auth).await?;
+    }
+    call(&mut leader, &command(&["PING"])).await?;
+
+    let (replid, offset) = {
+        let replication = db.replication();
+        (replication.replid.clone(), replication.offset.to_string())
+    };
+    leader
+        .write_frame(&command(&["PSYNC", &replid, &offset]))
+        .await?;
+
+    let reply = match leader.read_frame().await? {
+        Some(Frame::Simple(reply)) => reply,
+        Some(Frame::Error(err)) => return Err(err.into()),
+        _ => return Err("unexpected reply to PSYNC".into()),
+    };
+    let words: Vec<_> = reply.split(' ').collect();
+
+    match &words[..] {
+        ["FULLRESYNC", replid, offset] => {
+            let offset = offset.parse().map_err(|_| "invalid FULLRESYNC offset")?;
+            let keyspace = match leader.read_frame().await? {
+                Some(Frame::Bulk(keyspace)) => keyspace,
+                _ => return Err("expected the keyspace of the leader".into()),
+            };
+
+            load(db, replid, offset, &keyspace)?;
+        }
+        // The leader may have been promoted since, and renamed the stream.
+        ["CONTINUE", replid] => db.replication().replid = replid.to_string(),
+        _ => return Err(format!("unexpected reply to PSYNC: {}", reply).into()),
+    }
+
+    // Commands of a transaction are applied once its `EXEC` is received. They
+    // only count towards the offset then, so a transaction cut short by a
+    // disconnection is streamed again in full.
+    let mut transaction: Option<(Vec<Frame>, Vec<Bytes>)> = None;
+
+    while let Some((frame, raw)) = leader.read_frame_raw().await? {
+        match (&cmd::name(&frame)[..], &mut transaction) {
+            (b"multi", _) => transaction = Some((vec![], vec![raw])),
+            (b"exec", Some(_)) => {
+                let (commands, mut raws) = transaction.take().unwrap();
+                raws.push(raw);
+
+                for command in commands {
+                    apply(db, command);
+                }
+                for raw in raws {
+                    db.replication().append(raw);
+                }
+            }
+            (_, Some((commands, raws))) => {
+                commands.push(frame);
+                raws.push(raw);
+            }
+            (_, None) => {
+                apply(db, frame);
+                db.replication().append(raw);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Send `command` to the leader, failing if it replies with an error.
+async fn call(leader: &mut Connection, command: &Frame) -> crate::Result<()> {
+    leader.write_frame(command).await?;
+
+    match leader.read_frame().await? {
+        Some(Frame::Error(err)) => Err(err.into()),
+        Some(_) => Ok(()),
+        None => Err("connection closed".into()),
+    }
+}
+
+/// Replace the keyspace with the snapshot `keyspace` of the leader, which
+/// ends at `offset` of the stream `replid`.
+fn load(db: &Db, replid: &str, offset: u64, keyspace: &[u8]) -> crate::Result<()> {
+    // No write command runs while the keyspace is replaced.
+    let _logging = db.logging_mut();
+    let mut aof = db.aof();
+
+    db.clear_all();
+    snapshot::restore(db, keyspace)?;
+    db.replication().reset(replid.to_string(), offset);
+
+    // The log does not hold the new keyspace yet.
+    if let Some(aof) = aof.as_mut() {
+        aof.rewrite(db);
+    }
+
+    Ok(())
+}
+
+/// Apply a command of the leader, to the database last selected by its
+/// stream.
+fn apply(db: &Db, command: Frame) {
+    if let Some(index) = cmd::select_index(&command) {
+        db.replication().selected = Some(index);
+        return;
+    }
+
+    let index = db.replication().selected.unwrap_or(0);
+    let db = match db.select(index) {
+        Some(db) => db,
+        None => {
+            eprintln!("replicated command selects a missing database {}", index);
+            return;
+        }
+    };
+
+    // The follower and the leader started from the same keyspace, so this
+    // only fails if the leader replied with an error as well.
+    if let Frame::Error(err) = cmd::apply_replicated(command, &db) {
+        eprintln!("replicated command failed: {}", err);
+    }
+}
+
+/// A random 40 characters hexadecimal ID.
+fn new_replid() -> String {
+    (0..5)
+        .map(|_| format!("{:08x}", db::random() as u32))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::Policy;
+    use crate::{acl, server};
+
+    #[test]
+    fn backlog_covers_recent_offsets() {
+        let mut replication = Replication::default();
+        let replid = replication.replid.clone();
+
+        // The first command selects its database, the others share it.
+        replication.feed(0, b"");
+        let start = replication.offset;
+        assert_eq!(replication.backlog_from(&replid, 0), Some(aof::select(0)));
+
+        replication.feed(0, b"abc");
+        replication.feed(0, b"de");
+
+        assert_eq!(
+            replication.backlog_from(&replid, start),
+            Some(b"abcde".to_vec())
+        );
+        assert_eq!(
+            replication.backlog_from(&replid, start + 3),
+            Some(b"de".to_vec())
+        );
+        assert_eq!(replication.backlog_from(&replid, start + 5), Some(vec![]));
+        assert_eq!(replication.backlog_from(&replid, start + 6), None);
+        assert_eq!(replication.backlog_from("unknown", 0), None);
+
+        // The start of the stream no longer fits in the backlog.
+        replication.feed(0, &vec![0; BACKLOG_SIZE]);
+        assert_eq!(replication.backlog_from(&replid, start), None);
+        assert_eq!(
+            replication.backlog_from(&replid, start + 5).unwrap().len(),
+            BACKLOG_SIZE
+        );
+    }
+
+    #[test]
+    fn followers_too_far_behind_are_dropped() {
+        let mut replication = Replication::default();
+        let (slow, _slow_rx) = mpsc::channel(1);
+        let (fast, mut fast_rx) = mpsc::channel(1);
+        replication.followers = vec![slow, fast.clone()];
+
+        replication.append(Bytes::from("a"));
+        assert_eq!(fast_rx.try_recv().unwrap(), "a");
+        replication.append(Bytes::from("b"));
+
+        assert_eq!(replication.followers.len(), 1);
+        assert!(replication.followers[0].same_channel(&fast));
+    }
+
+    #[test]
+    fn previous_stream_continues_up_to_the_promotion() {
+        let mut replication = Replication::default();
+        let prev = replication.replid.clone();
+
+        replication.selected = Some(0);
+        replication.feed(0, b"abc");
+        replication.prev_replid = Some((prev.clone(), 2));
+        replication.replid = new_replid();
+
+        assert_eq!(replication.backlog_from(&prev, 1), Some(b"bc".to_vec()));
+        assert_eq!(replication.backlog_from(&prev, 3), None);
+    }
+
+    fn run(db: &Db, args: &[&str]) -> Frame {
+        cmd::apply(command(args), db)
+    }
+
+    /// Wait for the follower to catch up with the leader.
+    async fn synced(leader: &Db, follower: &Db) {
+        for _ in 0..500 {
+            let stream = |db: &Db| {
+                let replication = db.replication();
+                (replication.replid.clone(), replication.offset)
+            };
+            if follower.replication().leader.is_some() && stream(follower) == stream(leader) {
+                return;
+            }
+            time::sleep(Duration::from_millis(10)).await;
+        }
+
+        panic!("the follower did not catch up");
+    }
+
+    #[tokio::test]
+    async fn follower_syncs_then_streams() {
+        let leader = Db::new();
+        let addr = server::spawn(&leader).await;
+        run(&leader, &["SET", "a", "1"]);
+        run(&leader, &["RPUSH", "list", "x", "y"]);
+
+        let follower = Db::new();
+        follower.set("stale".into(), Bytes::from("1"), None);
+        follow(&follower, "127.0.0.1".into(), addr.port());
+
+        // The full sync replaces the keyspace.
+        synced(&leader, &follower).await;
+        assert_eq!(follower.get("stale"), Ok(None));
+        assert_eq!(follower.get("a"), Ok(Some(Bytes::from("1"))));
+
+        run(&leader, &["INCR", "a"]);
+        run(&leader, &["LPOP", "list"]);
+        synced(&leader, &follower).await;
+        assert_eq!(follower.get("a"), Ok(Some(Bytes::from("2"))));
+        assert_eq!(
+            run(&follower, &["LRANGE", "list", "0", "-1"]),
+            Frame::Array(vec![Frame::Bulk(Bytes::from("y"))])
+        );
+
+        // Reconnecting resumes the stream, keeping the keyspace.
+        follower.set("marker".into(), Bytes::from("1"), None);
+        follow(&follower, "127.0.0.1".into(), addr.port());
+        run(&leader, &["SET", "b", "1"]);
+        synced(&leader, &follower).await;
+        assert_eq!(follower.get("b"), Ok(Some(Bytes::from("1"))));
+        assert_eq!(follower.get("marker"), Ok(Some(Bytes::from("1"))));
+
+        promote(&follower);
+        assert_eq!(
+            run(&follower, &["SET", "c", "1"]),
+            Frame::Simple("OK".into())
+        );
+    }
+
+    #[tokio::test]
+    async fn follower_authenticates() {
+        let leader = Db::new();
+        let addr = server::spawn(&leader).await;
+        acl::require_pass(&leader, "secret");
+        run(&leader, &["SET", "a", "1"]);
+
+        let follower = Db::new();
+        follower.config().masterauth = "secret".into();
+        follow(&follower, "127.0.0.1".into(), addr.port());
+
+        synced(&leader, &follower).await;
+        assert_eq!(follower.get("a"), Ok(Some(Bytes::from("1"))));
+    }
+
+    #[tokio::test]
+    async fn dropped_followers_are_disconnected() {
+        let leader = Db::new();
+        let addr = server::spawn(&leader).await;
+
+        let mut follower = Connection::new(TcpStream::connect(addr).await.unwrap());
+        follower
+            .write_frame(&command(&["PSYNC", "?", "-1"]))
+            .await
+            .unwrap();
+        assert!(matches!(
+            follower.read_frame().await.unwrap(),
+            Some(Frame::Simple(reply)) if reply.starts_with("FULLRESYNC ")
+        ));
+        follower.read_frame().await.unwrap();
+
+        leader.replication().disconnect_followers();
+        assert_eq!(follower.read_frame().await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn followers_ignore_the_memory_limit() {
+        let leader = Db::new();
+        let addr = server::spawn(&leader).await;
+        run(&leader, &["SET", "a", "1"]);
+
+        let follower = Db::new();
+        follow(&follower, "127.0.0.1".into(), addr.port());
+        synced(&leader, &follower).await;
+
+        // The follower applies the writes of the leader whatever its limit.
+        follower.set_max_memory(follower.used_memory() / 2, Policy::NoEviction);
+        run(&leader, &["SET", "b", "2"]);
+        synced(&leader, &follower).await;
+        assert_eq!(follower.get("b"), Ok(Some(Bytes::from("2"))));
+
+        follower.set_max_memory(follower.used_memory() / 2, Policy::AllKeysLru);
+        run(&leader, &["SET", "c", "3"]);
+        synced(&leader, &follower).await;
+        for key in ["a", "b", "c"] {
+            assert!(follower.get(key).unwrap().is_some(), "{} was evicted", key);
+        }
+    }
+
+    #[tokio::test]
+    async fn followers_are_read_only() {
+        let leader = Db::new();
+        let addr = server::spawn(&leader).await;
+        let follower = Db::new();
+        let follower_addr = server::spawn(&follower).await;
+
+        let mut client = Connection::new(TcpStream::connect(follower_addr).await.unwrap());
+        let port = addr.port().to_string();
+        client
+            .write_frame(&command(&["REPLICAOF", "127.0.0.1", &port]))
+            .await
+            .unwrap();
+        assert_eq!(
+            client.read_frame().await.unwrap(),
+            Some(Frame::Simple("OK".into()))
+        );
+
+        for args in [&["SET", "a", "1"][..], &["GET", "a"]] {
+            client.write_frame(&command(args)).await.unwrap();
+        }
+        assert_eq!(
+            client.read_frame().await.unwrap(),
+            Some(Frame::Error(cmd::READ_ONLY.into()))
+        );
+        assert_eq!(client.read_frame().await.unwrap(), Some(Frame::Null));
+
+        client
+            .write_frame(&command(&["REPLICAOF", "NO", "ONE"]))
+            .await
+            .unwrap();
+        client.read_frame().await.unwrap();
+        client
+            .write_frame(&command(&["SET", "a", "1"]))
+            .await
+            .unwrap();
+        assert_eq!(
+            client.read_frame().await.unwrap(),
+            Some(Frame::Simple("OK".into()))
+        );
+    }
+}
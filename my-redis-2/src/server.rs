@@ -0,0 +1,533 @@
+//! Accepting connections and running their commands until shutdown.
+//!
+//! `run` accepts connections on TCP listeners and a Unix socket until the
+//! `shutdown` future completes. It then stops accepting, lets every connection finish the
+//! command it is running, up to a deadline, and flushes persistence to disk.
+
+use crate::clients::Client;
+use crate::cmd::{self, Session};
+use crate::{aof, snapshot, Connection, Db, Socket};
+
+use futures::future::{self, BoxFuture, FutureExt};
+use std::fs;
+use std::future::Future;
+use std::io;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream, UnixListener, UnixStream};
+use tokio::sync::{broadcast, mpsc, OwnedSemaphorePermit, Semaphore};
+use tokio::time;
+
+/// Server settings, see `run`.
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Most connections served at once. Further connections wait to be
+    /// accepted until one closes.
+    pub max_connections: usize,
+
+    /// How long connections get to finish their command on shutdown.
+    pub shutdown_timeout: Duration,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            max_connections: 10_000,
+            shutdown_timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+/// The sockets `run` accepts connections on.
+#[derive(Debug, Default)]
+pub struct Listeners {
+    /// One listener per bound address.
+    pub tcp: Vec<TcpListener>,
+
+    /// The Unix socket, whose file is removed on shutdown.
+    pub unix: Option<UnixListener>,
+}
+
+impl From<TcpListener> for Listeners {
+    fn from(listener: TcpListener) -> Listeners {
+        Listeners {
+            tcp: vec![listener],
+            unix: None,
+        }
+    }
+}
+
+/// A connection accepted by one of the `Listeners`.
+enum Accepted {
+    Tcp(TcpStream),
+    Unix(UnixStream),
+}
+
+/// Accepts connections and spawns a `Handler` for each.
+#[derive(Debug)]
+struct Listener {
+    listeners: Listeners,
+
+    /// Path of the Unix socket, if any.
+    unix_path: Option<PathBuf>,
+
+    db: Db,
+
+    /// Limits the number of connections served at once. A permit is acquired
+    /// before accepting a connection and released when its handler drops.
+    limit_connections: Arc<Semaphore>,
+
+    /// Broadcasts the shutdown signal to every connection handler.
+    notify_shutdown: broadcast::Sender<()>,
+
+    /// Cloned into every handler. Once all the clones are dropped, the
+    /// receiving half returns `None`, meaning every connection is done.
+    shutdown_complete_tx: mpsc::Sender<()>,
+}
+
+/// Reads the commands of a connection and runs them.
+struct Handler<S> {
+    db: Db,
+
+    connection: Connection<S>,
+
+    /// The client as listed by `CLIENT LIST`, registered for as long as the
+    /// handler runs.
+    client: Arc<Client>,
+
+    /// State carried across the commands of the connection, such as a
+    /// transaction in progress.
+    session: Session,
+
+    shutdown: Shutdown,
+
+    /// Dropped along with the handler, see `Listener::shutdown_complete_tx`.
+    _shutdown_complete: mpsc::Sender<()>,
+}
+
+/// Listens for the shutdown signal.
+#[derive(Debug)]
+struct Shutdown {
+    /// True once the signal was received.
+    is_shutdown: bool,
+
+    notify: broadcast::Receiver<()>,
+}
+
+/// Serve the connections of `listeners` until `shutdown` completes, then
+/// shut down gracefully.
+///
+/// Connections finish the command they are running, if any, before they are
+/// closed. Links with followers are closed right away. Connections still
+/// busy after `config.shutdown_timeout`, such as clients blocked on a list,
+/// are dropped. The append-only file is then flushed and a last snapshot
+/// written if saves are scheduled.
+///
+/// # Panics
+///
+/// Panics if there is no listener at all.
+pub async fn run(listeners: impl Into<Listeners>, db: Db, config: Config, shutdown: impl Future) {
+    let listeners = listeners.into();
+    assert!(
+        !listeners.tcp.is_empty() || listeners.unix.is_some(),
+        "the server needs a listener"
+    );
+
+    let (notify_shutdown, _) = broadcast::channel(1);
+    let (shutdown_complete_tx, mut shutdown_complete_rx) = mpsc::channel(1);
+
+    let unix_path = listeners.unix.as_ref().and_then(|listener| {
+        let addr = listener.local_addr().ok()?;
+        addr.as_pathname().map(|path| path.to_path_buf())
+    });
+
+    let mut server = Listener {
+        listeners,
+        unix_path,
+        db,
+        limit_connections: Arc::new(Semaphore::new(config.max_connections)),
+        notify_shutdown,
+        shutdown_complete_tx,
+    };
+
+    tokio::select! {
+        res = server.run() => {
+            // Accepting failed too many times in a row, shut down.
+            if let Err(err) = res {
+                eprintln!("failed to accept: {}", err);
+            }
+        }
+        _ = shutdown => {
+            println!("shutting down");
+        }
+    }
+
+    let Listener {
+        listeners,
+        unix_path,
+        db,
+        notify_shutdown,
+        shutdown_complete_tx,
+        ..
+    } = server;
+
+    // Stop accepting, and let no one connect to a dead socket file.
+    drop(listeners);
+    if let Some(path) = unix_path {
+        let _ = fs::remove_file(path);
+    }
+
+    // Dropping the sender notifies every handler subscribed to it, and the
+    // listener's own completion sender must go for the receiver to finish.
+    drop(notify_shutdown);
+    drop(shutdown_complete_tx);
+
+    // Follower links never wait for a command, they are closed right away.
+    db.replication().disconnect_followers();
+
+    if time::timeout(config.shutdown_timeout, shutdown_complete_rx.recv())
+        .await
+        .is_err()
+    {
+        eprintln!(
+            "connections still busy after {:?}, shutting down anyway",
+            config.shutdown_timeout
+        );
+    }
+
+    if let Err(err) = aof::sync(&db) {
+        eprintln!("append only file fsync failed: {}", err);
+    }
+
+    if let Err(err) = snapshot::save_on_shutdown(&db).await {
+        eprintln!("saving on shutdown failed: {}", err);
+    }
+}
+
+/// Serve `db` on a free local port for the rest of the test, returning the
+/// address to connect to.
+#[cfg(test)]
+pub(crate) async fn spawn(db: &Db) -> std::net::SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    tokio::spawn(run(
+        listener,
+        db.clone(),
+        Config::default(),
+        future::pending::<()>(),
+    ));
+    addr
+}
+
+impl Listener {
+    /// Accept connections, spawning a task to process each.
+    ///
+    /// Returns an error once accepting failed repeatedly, see `accept`.
+    async fn run(&mut self) -> crate::Result<()> {
+        loop {
+            // Wait for a connection slot to free up first. The permit is
+            // given back when the handler drops it.
+            let permit = self
+                .limit_connections
+                .clone()
+                .acquire_owned()
+                .await
+                .unwrap();
+
+            match self.accept().await? {
+                Accepted::Tcp(socket) => {
+                    let addr = match socket.peer_addr() {
+                        Ok(addr) => addr.to_string(),
+                        Err(_) => "?".to_string(),
+                    };
+                    self.spawn(socket, addr, permit);
+                }
+                Accepted::Unix(socket) => {
+                    // Unix clients have no address of their own, they are
+                    // listed by the path of the socket.
+                    let addr = match &self.unix_path {
+                        Some(path) => format!("{}:0", path.display()),
+                        None => "?".to_string(),
+                    };
+                    self.spawn(socket, addr, permit);
+                }
+            }
+        }
+    }
+
+    /// Spawn a task running the commands of the connection over `socket`,
+    /// holding `permit` until it is done.
+    fn spawn<S: Socket + 'static>(&self, socket: S, addr: String, permit: OwnedSemaphorePermit) {
+        let client = self.db.clients().connect(addr);
+
+        let mut handler = Handler {
+            db: self.db.clone(),
+            connection: Connection::new(socket),
+            client: client.clone(),
+            session: Session::new(client),
+            shutdown: Shutdown::new(self.notify_shutdown.subscribe()),
+            _shutdown_complete: self.shutdown_complete_tx.clone(),
+        };
+
+        tokio::spawn(async move {
+            if let Err(err) = handler.run().await {
+                eprintln!("connection error: {}", err);
+            }
+            handler.db.clients().disconnect(&handler.client);
+            drop(permit);
+        });
+    }
+
+    /// Accept a connection on any of the listeners.
+    ///
+    /// Errors, such as running out of file descriptors, are retried after
+    /// waiting 1 second, then twice as long after each failure. Returns the
+    /// error once the wait would exceed 64 seconds.
+    async fn accept(&mut self) -> crate::Result<Accepted> {
+        let mut backoff = 1;
+
+        loop {
+            match self.accept_any().await {
+                Ok(socket) => return Ok(socket),
+                Err(err) => {
+                    if backoff > 64 {
+                        return Err(err.into());
+                    }
+                    eprintln!("failed to accept, retrying in {}s: {}", backoff, err);
+                }
+            }
+
+            time::sleep(Duration::from_secs(backoff)).await;
+            backoff *= 2;
+        }
+    }
+
+    /// Wait for the first connection on any of the listeners.
+    async fn accept_any(&self) -> io::Result<Accepted> {
+        let tcp = self.listeners.tcp.iter().map(|listener| {
+            async move {
+                let (socket, _) = listener.accept().await?;
+
+                // Replies are flushed one by one. Without this, the replies
+                // to pipelined commands wait on the client's delayed
+                // acknowledgements.
+                let _ = socket.set_nodelay(true);
+                Ok(Accepted::Tcp(socket))
+            }
+            .boxed()
+        });
+        let unix = self.listeners.unix.iter().map(|listener| {
+            async move {
+                let (socket, _) = listener.accept().await?;
+                Ok(Accepted::Unix(socket))
+            }
+            .boxed()
+        });
+
+        let accepts: Vec<BoxFuture<'_, io::Result<Accepted>>> = tcp.chain(unix).collect();
+        future::select_all(accepts).await.0
+    }
+}
+
+impl<S: Socket> Handler<S> {
+    /// Run the commands of the connection until it closes, the server shuts
+    /// down or the client is killed.
+    ///
+    /// The shutdown signal is only checked between commands, so a command
+    /// which started always finishes and gets its reply. Killing the client
+    /// interrupts its command, such as `MONITOR` or a blocking pop.
+    async fn run(&mut self) -> crate::Result<()> {
+        while !self.shutdown.is_shutdown() {
+            let frame = tokio::select! {
+                res = self.connection.read_frame() => res?,
+                _ = self.shutdown.recv() => return Ok(()),
+                _ = self.client.killed() => return Ok(()),
+            };
+
+            // `None` means the peer closed the connection.
+            let frame = match frame {
+                Some(frame) => frame,
+                None => return Ok(()),
+            };
+
+            let name = cmd::name(&frame);
+            self.client.started(
+                &String::from_utf8_lossy(&name),
+                self.session.flags(&name),
+                self.connection.input_buffer(),
+                self.connection.output_buffer(),
+            );
+
+            tokio::select! {
+                res = cmd::run(frame, &self.db, &mut self.connection, &mut self.session) => res?,
+                _ = self.client.killed() => return Ok(()),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Shutdown {
+    fn new(notify: broadcast::Receiver<()>) -> Shutdown {
+        Shutdown {
+            is_shutdown: false,
+            notify,
+        }
+    }
+
+    fn is_shutdown(&self) -> bool {
+        self.is_shutdown
+    }
+
+    /// Wait for the shutdown signal, returning right away if it was already
+    /// received.
+    async fn recv(&mut self) {
+        if self.is_shutdown {
+            return;
+        }
+
+        // Only the sender dropping is ever received.
+        let _ = self.notify.recv().await;
+
+        self.is_shutdown = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Frame;
+    use bytes::Bytes;
+    use std::net::SocketAddr;
+    use tokio::sync::oneshot;
+    use tokio::task::JoinHandle;
+
+    /// Run a server on a free port until the returned sender fires.
+    async fn start(config: Config) -> (SocketAddr, oneshot::Sender<()>, JoinHandle<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (tx, rx) = oneshot::channel();
+
+        let server = tokio::spawn(run(listener, Db::new(), config, rx));
+        (addr, tx, server)
+    }
+
+    async fn connect(addr: SocketAddr) -> Connection {
+        Connection::new(TcpStream::connect(addr).await.unwrap())
+    }
+
+    fn command(args: &[&str]) -> Frame {
+        Frame::Array(
+            args.iter()
+                .map(|arg| Frame::Bulk(Bytes::copy_from_slice(arg.as_bytes())))
+                .collect(),
+        )
+    }
+
+    #[tokio::test]
+    async fn shutdown_closes_idle_connections() {
+        let (addr, shutdown, server) = start(Config::default()).await;
+
+        let mut client = connect(addr).await;
+        client
+            .write_frame(&command(&["SET", "a", "1"]))
+            .await
+            .unwrap();
+        assert_eq!(
+            client.read_frame().await.unwrap(),
+            Some(Frame::Simple("OK".into()))
+        );
+
+        shutdown.send(()).unwrap();
+        server.await.unwrap();
+
+        assert_eq!(client.read_frame().await.unwrap(), None);
+        assert!(TcpStream::connect(addr).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn busy_connections_are_dropped_after_the_timeout() {
+        let config = Config {
+            shutdown_timeout: Duration::from_millis(50),
+            ..Config::default()
+        };
+        let (addr, shutdown, server) = start(config).await;
+
+        let mut client = connect(addr).await;
+        client
+            .write_frame(&command(&["BLPOP", "list", "0"]))
+            .await
+            .unwrap();
+        time::sleep(Duration::from_millis(50)).await;
+
+        shutdown.send(()).unwrap();
+        time::timeout(Duration::from_secs(5), server)
+            .await
+            .expect("server did not shut down")
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn tcp_and_unix_socket_clients_are_served_together() {
+        let path = std::env::temp_dir().join(format!("my-redis-2-{}.sock", std::process::id()));
+        let tcp = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = tcp.local_addr().unwrap();
+        let listeners = Listeners {
+            tcp: vec![tcp],
+            unix: Some(UnixListener::bind(&path).unwrap()),
+        };
+        let (shutdown, rx) = oneshot::channel();
+        let server = tokio::spawn(run(listeners, Db::new(), Config::default(), rx));
+
+        let mut tcp = connect(addr).await;
+        let mut unix = Connection::new(UnixStream::connect(&path).await.unwrap());
+
+        tcp.write_frame(&command(&["SET", "a", "1"])).await.unwrap();
+        tcp.read_frame().await.unwrap();
+        unix.write_frame(&command(&["GET", "a"])).await.unwrap();
+        assert_eq!(
+            unix.read_frame().await.unwrap(),
+            Some(Frame::Bulk(Bytes::from("1")))
+        );
+
+        // Unix clients are listed by the path of the socket.
+        unix.write_frame(&command(&["CLIENT", "LIST"]))
+            .await
+            .unwrap();
+        let list = match unix.read_frame().await.unwrap() {
+            Some(Frame::Bulk(list)) => String::from_utf8(list.to_vec()).unwrap(),
+            frame => panic!("unexpected reply {:?}", frame),
+        };
+        assert!(list.contains(&format!("addr={}:0 ", path.display())));
+
+        shutdown.send(()).unwrap();
+        server.await.unwrap();
+        assert!(!path.exists());
+    }
+
+    #[tokio::test]
+    async fn connections_wait_for_a_free_slot() {
+        let config = Config {
+            max_connections: 1,
+            ..Config::default()
+        };
+        let (addr, _shutdown, _server) = start(config).await;
+
+        let mut first = connect(addr).await;
+        first.write_frame(&command(&["PING"])).await.unwrap();
+        assert!(first.read_frame().await.unwrap().is_some());
+
+        // Connecting succeeds, but the connection is not served yet.
+        let mut second = connect(addr).await;
+        second.write_frame(&command(&["PING"])).await.unwrap();
+        let pending = time::timeout(Duration::from_millis(100), second.read_frame()).await;
+        assert!(pending.is_err());
+
+        drop(first);
+        let reply = time::timeout(Duration::from_secs(5), second.read_frame()).await;
+        assert!(reply.unwrap().unwrap().is_some());
+    }
+}
@@ -0,0 +1,254 @@
+//! Command timing: the slow log and per-command statistics.
+//!
+//! Every command is timed from dispatch until its reply is ready, writing the
+//! reply excluded. Commands which wait, such as a blocking pop or `SUBSCRIBE`,
+//! are counted but not timed, as the time spent waiting says nothing about
+//! the server.
+//!
+//! Commands taking longer than a threshold are kept in the slow log, listed by
+//! `SLOWLOG GET`, and the calls and time spent per command are listed by
+//! `INFO commandstats`.
+
+use crate::clients::Client;
+use crate::db::unix_time;
+use crate::{Db, Frame};
+
+use bytes::Bytes;
+use std::collections::{BTreeMap, VecDeque};
+use std::time::Duration;
+
+/// Most arguments kept per entry, the last one kept standing for the rest.
+const MAX_ARGS: usize = 32;
+
+/// Most bytes kept per argument.
+const MAX_ARG_LEN: usize = 128;
+
+/// The slow log and statistics, see `Db::slowlog`.
+#[derive(Debug)]
+pub struct SlowLog {
+    /// Commands taking at least this long are logged, none if unset.
+    log_slower_than: Option<Duration>,
+
+    /// Most entries kept, the oldest are dropped first.
+    max_len: usize,
+
+    /// Most recent first.
+    entries: VecDeque<Entry>,
+
+    /// ID of the next entry, IDs are never reused.
+    next_id: u64,
+
+    /// Calls and cumulative time, by command name.
+    commands: BTreeMap<String, CommandStats>,
+}
+
+/// A command logged as slow.
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub id: u64,
+
+    /// Unix time at which the command was logged.
+    pub time: Duration,
+
+    pub duration: Duration,
+
+    /// The command and its arguments, shortened past `MAX_ARGS` and
+    /// `MAX_ARG_LEN`.
+    pub args: Vec<Bytes>,
+
+    /// Address and name of the client which sent the command, empty if it
+    /// did not come from a connection.
+    pub addr: String,
+    pub name: String,
+}
+
+/// What `INFO commandstats` lists for a command.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CommandStats {
+    pub calls: u64,
+
+    /// Time spent running the command, over every call.
+    pub usec: u64,
+}
+
+impl Default for SlowLog {
+    fn default() -> SlowLog {
+        SlowLog {
+            log_slower_than: Some(Duration::from_millis(10)),
+            max_len: 128,
+            entries: VecDeque::new(),
+            next_id: 0,
+            commands: BTreeMap::new(),
+        }
+    }
+}
+
+/// Log the commands of `db` taking at least `log_slower_than`, none if unset,
+/// keeping the last `max_len`.
+pub fn configure(db: &Db, log_slower_than: Option<Duration>, max_len: usize) {
+    let mut slowlog = db.slowlog();
+
+    slowlog.log_slower_than = log_slower_than;
+    slowlog.max_len = max_len;
+    slowlog.entries.truncate(max_len);
+}
+
+impl SlowLog {
+    /// Count a call to the command `name`, sent by `client`, which took
+    /// `duration`, unset if it waited. The call is logged if it was slow.
+    pub(crate) fn record(
+        &mut self,
+        name: &str,
+        duration: Option<Duration>,
+        command: Frame,
+        client: Option<&Client>,
+    ) {
+        let stats = match self.commands.get_mut(name) {
+            Some(stats) => stats,
+            None => self.commands.entry(name.to_string()).or_default(),
+        };
+        stats.calls += 1;
+
+        let duration = match duration {
+            Some(duration) => duration,
+            None => return,
+        };
+        stats.usec += duration.as_micros() as u64;
+
+        match self.log_slower_than {
+            Some(threshold) if duration >= threshold && self.max_len > 0 => {}
+            _ => return,
+        }
+
+        let entry = Entry {
+            id: self.next_id,
+            time: unix_time(),
+            duration,
+            args: shorten(command),
+            addr: client.map_or_else(String::new, |client| client.addr().to_string()),
+            name: client.map_or_else(String::new, Client::name),
+        };
+        self.next_id += 1;
+
+        self.entries.push_front(entry);
+        self.entries.truncate(self.max_len);
+    }
+
+    /// The `count` most recent entries, most recent first.
+    pub fn get(&self, count: usize) -> Vec<Entry> {
+        self.entries.iter().take(count).cloned().collect()
+    }
+
+    /// Number of entries.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns `true` if no command was logged since the last reset.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Drop every entry.
+    pub fn reset(&mut self) {
+        self.entries.clear();
+    }
+
+    /// Calls and time spent per command name, sorted by name.
+    pub fn commands(&self) -> impl Iterator<Item = (&str, CommandStats)> {
+        self.commands
+            .iter()
+            .map(|(name, stats)| (name.as_str(), *stats))
+    }
+}
+
+/// The arguments of `command`, at most `MAX_ARGS` of at most `MAX_ARG_LEN`
+/// bytes each, the way `SLOWLOG GET` lists them.
+fn shorten(command: Frame) -> Vec<Bytes> {
+    let args = match command {
+        Frame::Array(args) => args,
+        _ => return vec![],
+    };
+    let total = args.len();
+
+    let mut shortened: Vec<Bytes> = args
+        .into_iter()
+        .take(MAX_ARGS)
+        .map(|arg| {
+            let arg = match arg {
+                Frame::Bulk(data) => data,
+                frame => Bytes::from(frame.to_string()),
+            };
+            if arg.len() <= MAX_ARG_LEN {
+                return arg;
+            }
+
+            let more = format!("... ({} more bytes)", arg.len() - MAX_ARG_LEN);
+            [&arg[..MAX_ARG_LEN], more.as_bytes()].concat().into()
+        })
+        .collect();
+
+    if total > MAX_ARGS {
+        let more = format!("... ({} more arguments)", total - MAX_ARGS + 1);
+        shortened[MAX_ARGS - 1] = Bytes::from(more);
+    }
+
+    shortened
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::command;
+
+    #[test]
+    fn slow_commands_are_logged_most_recent_first() {
+        let mut slowlog = SlowLog {
+            max_len: 2,
+            ..SlowLog::default()
+        };
+        let fast = Some(Duration::from_millis(1));
+        let slow = Some(Duration::from_millis(20));
+
+        for (key, duration) in [("a", slow), ("b", fast), ("c", slow), ("d", slow)] {
+            slowlog.record("get", duration, command(&["GET", key]), None);
+        }
+        slowlog.record("blpop", None, command(&["BLPOP", "list", "0"]), None);
+
+        let entries = slowlog.get(10);
+        assert_eq!(entries.len(), 2);
+        assert_eq!((entries[0].id, &entries[0].args[1][..]), (2, &b"d"[..]));
+        assert_eq!((entries[1].id, &entries[1].args[1][..]), (1, &b"c"[..]));
+
+        let commands: Vec<_> = slowlog.commands().collect();
+        assert_eq!(
+            commands,
+            [
+                ("blpop", CommandStats { calls: 1, usec: 0 }),
+                (
+                    "get",
+                    CommandStats {
+                        calls: 4,
+                        usec: 61_000
+                    }
+                ),
+            ]
+        );
+
+        slowlog.reset();
+        assert!(slowlog.is_empty());
+    }
+
+    #[test]
+    fn long_commands_are_shortened() {
+        let value = "x".repeat(200);
+        let mut args = vec!["RPUSH", "list", &value];
+        args.extend(["y"; 40]);
+
+        let args = shorten(command(&args));
+        assert_eq!(args.len(), MAX_ARGS);
+        assert_eq!(args[2].len(), MAX_ARG_LEN + "... (72 more bytes)".len());
+        assert!(args[2].ends_with(b"... (72 more bytes)"));
+        assert_eq!(&args[31][..], b"... (12 more arguments)");
+    }
+}
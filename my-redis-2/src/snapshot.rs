@@ -0,0 +1,886 @@
+//! Point-in-time snapshots of the keyspace.
+//!
+//! A snapshot is a compact binary dump of every key, written by `SAVE`,
+//! `BGSAVE` and the "N changes in M seconds" schedule, and loaded at boot.
+//!
+//! The file layout is:
+//!
+//! ```text
+//! "MRDB" version:u32
+//! ( SELECT index ( [EXPIRES unix-ms:u64] type:u8 key value )* )*
+//! EOF crc32:u32
+//! ```
+//!
+//! Only the databases holding keys are written.
+//!
+//! Integers are little endian, keys and values are prefixed by their length
+//! as a LEB128 varint. Collection values are their item count as a varint,
+//! then the items; sorted set scores are the bits of an `f64`. Streams are
+//! their entries, last ID and consumer groups, IDs being two `u64`. The
+//! checksum covers every byte before it.
+
+use crate::db::{unix_time, Group, Pending, Stream, StreamId, Value, WeakDb, ZSet};
+use crate::Db;
+
+use bytes::Bytes;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+use tokio::task::{self, JoinHandle};
+use tokio::time;
+
+const MAGIC: &[u8] = b"MRDB";
+
+/// Format version of snapshot files. Files of any other version are rejected.
+const VERSION: u32 = 1;
+
+/// Opcode preceding the entries of a database, followed by its index.
+const SELECT: u8 = 0xfe;
+
+/// Opcode preceding an entry with a time to live.
+const EXPIRES: u8 = 0xfc;
+
+/// Opcode ending the entries, followed by the checksum.
+const EOF: u8 = 0xff;
+
+/// Entry types.
+const STRING: u8 = 0;
+const LIST: u8 = 1;
+const HASH: u8 = 2;
+const SET: u8 = 3;
+const ZSET: u8 = 4;
+const STREAM: u8 = 5;
+
+/// The entries of a database, along with their time to live when encoding
+/// and the Unix time they expire at when decoding.
+type Entries = Vec<(String, Value, Option<Duration>)>;
+
+/// Saves once `changes` writes happened in the last `after`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SaveRule {
+    pub after: Duration,
+    pub changes: u64,
+}
+
+/// Parses a schedule such as `"3600 1 300 100"`, a list of
+/// `seconds changes` pairs. An empty string disables scheduled saves.
+pub fn parse_rules(s: &str) -> Result<Vec<SaveRule>, String> {
+    let numbers = s
+        .split_whitespace()
+        .map(u64::from_str)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| format!("invalid save schedule `{}`", s))?;
+
+    if numbers.len() % 2 != 0 {
+        return Err(format!("invalid save schedule `{}`", s));
+    }
+
+    Ok(numbers
+        .chunks(2)
+        .map(|pair| SaveRule {
+            after: Duration::from_secs(pair[0]),
+            changes: pair[1],
+        })
+        .collect())
+}
+
+/// Snapshot settings and progress, see `Db::snapshot`.
+#[derive(Debug)]
+pub struct Snapshot {
+    path: PathBuf,
+
+    rules: Vec<SaveRule>,
+
+    /// True while a `BGSAVE` is running.
+    saving: bool,
+
+    /// The task running the last `BGSAVE`, waited for on shutdown.
+    background: Option<JoinHandle<()>>,
+
+    /// Unix time of the last successful save, or of startup.
+    last_save: Duration,
+
+    /// True once the background task enforcing `rules` is running.
+    scheduled: bool,
+}
+
+impl Default for Snapshot {
+    fn default() -> Snapshot {
+        Snapshot {
+            path: PathBuf::from("dump.rdb"),
+            rules: vec![],
+            saving: false,
+            background: None,
+            last_save: unix_time(),
+            scheduled: false,
+        }
+    }
+}
+
+/// Write snapshots of `db` to `path`, following the `rules` schedule.
+pub fn configure(db: &Db, path: impl AsRef<Path>, rules: Vec<SaveRule>) {
+    let mut snapshot = db.snapshot();
+
+    snapshot.path = path.as_ref().to_path_buf();
+    snapshot.rules = rules;
+
+    if !snapshot.scheduled {
+        snapshot.scheduled = true;
+        tokio::spawn(run_schedule(db.downgrade()));
+    }
+}
+
+/// Load the configured snapshot file into `db`, if it exists.
+///
+/// Keys whose time to live elapsed since the snapshot was taken are skipped.
+pub fn load(db: &Db) -> crate::Result<()> {
+    let path = db.snapshot().path.clone();
+
+    let data = match fs::read(&path) {
+        Ok(data) => data,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err.into()),
+    };
+
+    restore(db, &data)
+}
+
+/// A snapshot of `db`, as it would be written to disk.
+pub(crate) fn dump(db: &Db) -> Vec<u8> {
+    encode(db.dump(), unix_time())
+}
+
+/// Load the snapshot `data` into `db`, see `load`.
+pub(crate) fn restore(db: &Db, data: &[u8]) -> crate::Result<()> {
+    let now = unix_time();
+
+    for (index, entries) in decode(data, db.databases())?.into_iter().enumerate() {
+        let db = db.select(index).expect("database index in range");
+
+        for (key, value, expires_at) in entries {
+            let ttl = match expires_at {
+                Some(when) if when <= now => continue,
+                Some(when) => Some(when - now),
+                None => None,
+            };
+
+            db.insert(key, value, ttl);
+        }
+    }
+
+    Ok(())
+}
+
+/// Write a snapshot right away, blocking the caller until it is on disk.
+pub fn save(db: &Db) -> crate::Result<()> {
+    let path = {
+        let snapshot = db.snapshot();
+
+        if snapshot.saving {
+            return Err("Background save already in progress".into());
+        }
+
+        snapshot.path.clone()
+    };
+
+    let changes = db.changes();
+    write(&path, &encode(db.dump(), unix_time()))?;
+    saved(db, changes);
+
+    Ok(())
+}
+
+/// Write a last snapshot before shutting down, if saves are scheduled.
+///
+/// A background save in progress may miss the latest writes, it is waited
+/// for first.
+pub async fn save_on_shutdown(db: &Db) -> crate::Result<()> {
+    let path = loop {
+        let background = {
+            let mut snapshot = db.snapshot();
+
+            if snapshot.rules.is_empty() {
+                return Ok(());
+            }
+
+            // Keep the schedule from starting another save meanwhile.
+            if !snapshot.saving {
+                snapshot.saving = true;
+                break snapshot.path.clone();
+            }
+
+            snapshot.background.take()
+        };
+
+        match background {
+            Some(background) => {
+                let _ = background.await;
+            }
+            // Started, but its task is not recorded yet.
+            None => task::yield_now().await,
+        }
+    };
+
+    let changes = db.changes();
+    let written = write(&path, &encode(db.dump(), unix_time()));
+    db.snapshot().saving = false;
+    written?;
+    saved(db, changes);
+
+    Ok(())
+}
+
+/// Write a snapshot from a blocking task, without holding up the caller.
+///
+/// Returns `false` if a background save is already in progress.
+pub(crate) fn bgsave(db: &Db) -> bool {
+    let path = {
+        let mut snapshot = db.snapshot();
+
+        if snapshot.saving {
+            return false;
+        }
+
+        snapshot.saving = true;
+        snapshot.path.clone()
+    };
+
+    // Copying the keyspace is quick, only the encoding and disk writes are
+    // moved off the caller.
+    let changes = db.changes();
+    let entries = db.dump();
+    let now = unix_time();
+
+    let background = {
+        let db = db.clone();
+
+        tokio::spawn(async move {
+            let result = task::spawn_blocking(move || write(&path, &encode(entries, now))).await;
+
+            db.snapshot().saving = false;
+
+            match result {
+                Ok(Ok(())) => saved(&db, changes),
+                Ok(Err(err)) => eprintln!("background save failed: {}", err),
+                Err(err) => eprintln!("background save failed: {}", err),
+            }
+        })
+    };
+    db.snapshot().background = Some(background);
+
+    true
+}
+
+/// Unix time of the last successful save.
+pub(crate) fn last_save(db: &Db) -> Duration {
+    db.snapshot().last_save
+}
+
+/// Record a successful save of a keyspace which had seen `changes` writes.
+fn saved(db: &Db, changes: u64) {
+    db.saved_changes(changes);
+    db.snapshot().last_save = unix_time();
+}
+
+/// Start a background save whenever one of the rules is met.
+async fn run_schedule(db: WeakDb) {
+    let mut interval = time::interval(Duration::from_secs(1));
+
+    loop {
+        interval.tick().await;
+
+        let db = match db.upgrade() {
+            Some(db) => db,
+            None => return,
+        };
+
+        let due = {
+            let snapshot = db.snapshot();
+            let elapsed = unix_time().saturating_sub(snapshot.last_save);
+            let changes = db.changes();
+
+            !snapshot.saving
+                && snapshot
+                    .rules
+                    .iter()
+                    .any(|rule| changes >= rule.changes && elapsed >= rule.after)
+        };
+
+        if due {
+            bgsave(&db);
+        }
+    }
+}
+
+/// Write `data` to `path` through a temporary file, so a crash never leaves a
+/// partial snapshot behind.
+fn write(path: &Path, data: &[u8]) -> std::io::Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+
+    fs::write(&tmp, data)?;
+    fs::File::open(&tmp)?.sync_all()?;
+    fs::rename(&tmp, path)
+}
+
+/// Encode `databases`, the entries of each database, whose times to live are
+/// relative to the Unix time `now`.
+fn encode(databases: Vec<Entries>, now: Duration) -> Vec<u8> {
+    let mut buf = MAGIC.to_vec();
+    buf.extend_from_slice(&VERSION.to_le_bytes());
+
+    for (index, entries) in databases.into_iter().enumerate() {
+        if entries.is_empty() {
+            continue;
+        }
+
+        buf.push(SELECT);
+        put_len(&mut buf, index);
+
+        for (key, value, ttl) in entries {
+            if let Some(ttl) = ttl {
+                buf.push(EXPIRES);
+                buf.extend_from_slice(&((now + ttl).as_millis() as u64).to_le_bytes());
+            }
+
+            put_value(&mut buf, key.as_bytes(), &value);
+        }
+    }
+
+    buf.push(EOF);
+    let checksum = crc32(&buf);
+    buf.extend_from_slice(&checksum.to_le_bytes());
+
+    buf
+}
+
+/// Decode a snapshot into the entries of each of the `databases`, along with
+/// the Unix time each of them expires at.
+fn decode(data: &[u8], databases: usize) -> crate::Result<Vec<Entries>> {
+    if data.len() < MAGIC.len() + 4 + 1 + 4 || !data.starts_with(MAGIC) {
+        return Err("invalid snapshot file".into());
+    }
+
+    let (body, checksum) = data.split_at(data.len() - 4);
+    if crc32(body) != u32::from_le_bytes(checksum.try_into()?) {
+        return Err("snapshot checksum mismatch".into());
+    }
+
+    let mut src = Reader {
+        data: &body[MAGIC.len()..],
+    };
+
+    let version = u32::from_le_bytes(src.take(4)?.try_into()?);
+    if version != VERSION {
+        return Err(format!("unsupported snapshot version {}", version).into());
+    }
+
+    let mut decoded: Vec<Entries> = vec![vec![]; databases];
+    let mut index = None;
+    let mut expires_at = None;
+
+    loop {
+        match src.byte()? {
+            SELECT => match src.len()? {
+                selected if selected < databases => index = Some(selected),
+                _ => return Err("snapshot database index out of range".into()),
+            },
+            EXPIRES => {
+                let ms = u64::from_le_bytes(src.take(8)?.try_into()?);
+                expires_at = Some(Duration::from_millis(ms));
+            }
+            EOF => break,
+            kind @ (STRING | LIST | HASH | SET | ZSET | STREAM) => {
+                let index = index.ok_or("snapshot entry outside of a database")?;
+                let key = String::from_utf8(src.blob()?.to_vec())?;
+                let value = src.value(kind)?;
+                decoded[index].push((key, value, expires_at.take()));
+            }
+            other => return Err(format!("invalid snapshot entry type {}", other).into()),
+        }
+    }
+
+    Ok(decoded)
+}
+
+/// Append the type of `value`, then `key` and `value`.
+fn put_value(buf: &mut Vec<u8>, key: &[u8], value: &Value) {
+    let kind = match value {
+        Value::String(_) => STRING,
+        Value::List(_) => LIST,
+        Value::Hash(_) => HASH,
+        Value::Set(_) => SET,
+        Value::ZSet(_) => ZSET,
+        Value::Stream(_) => STREAM,
+    };
+
+    buf.push(kind);
+    put_blob(buf, key);
+
+    match value {
+        Value::String(value) => put_blob(buf, value),
+        Value::List(list) => {
+            put_len(buf, list.len());
+            list.iter().for_each(|value| put_blob(buf, value));
+        }
+        Value::Hash(hash) => {
+            put_len(buf, hash.len());
+            for (field, value) in hash {
+                put_blob(buf, field);
+                put_blob(buf, value);
+            }
+        }
+        Value::Set(set) => {
+            put_len(buf, set.len());
+            set.iter().for_each(|member| put_blob(buf, member));
+        }
+        Value::ZSet(zset) => {
+            put_len(buf, zset.len());
+            for (member, score) in zset.iter() {
+                put_blob(buf, member);
+                buf.extend_from_slice(&score.to_bits().to_le_bytes());
+            }
+        }
+        Value::Stream(stream) => {
+            put_len(buf, stream.entries.len());
+            for (id, fields) in &stream.entries {
+                put_id(buf, *id);
+                put_len(buf, fields.len());
+                for (field, value) in fields {
+                    put_blob(buf, field);
+                    put_blob(buf, value);
+                }
+            }
+            put_id(buf, stream.last_id);
+
+            put_len(buf, stream.groups.len());
+            for (name, group) in &stream.groups {
+                put_blob(buf, name.as_bytes());
+                put_id(buf, group.last_delivered);
+
+                put_len(buf, group.pending.len());
+                for (id, pending) in &group.pending {
+                    put_id(buf, *id);
+                    put_blob(buf, pending.consumer.as_bytes());
+                    buf.extend_from_slice(&pending.delivered_at.to_le_bytes());
+                    buf.extend_from_slice(&pending.deliveries.to_le_bytes());
+                }
+            }
+        }
+    }
+}
+
+fn put_id(buf: &mut Vec<u8>, id: StreamId) {
+    buf.extend_from_slice(&id.ms.to_le_bytes());
+    buf.extend_from_slice(&id.seq.to_le_bytes());
+}
+
+/// Append `data` prefixed by its length.
+fn put_blob(buf: &mut Vec<u8>, data: &[u8]) {
+    put_len(buf, data.len());
+    buf.extend_from_slice(data);
+}
+
+/// Append a length as a varint.
+fn put_len(buf: &mut Vec<u8>, len: usize) {
+    let mut len = len as u64;
+
+    // LEB128: 7 bits per byte, the high bit flags that more bytes follow.
+    loop {
+        let byte = (len & 0x7f) as u8;
+        len >>= 7;
+
+        if len == 0 {
+            buf.push(byte);
+            break;
+        }
+        buf.push(byte | 0x80);
+    }
+}
+
+/// Cursor over the body of a snapshot.
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> crate::Result<&'a [u8]> {
+        if self.data.len() < n {
+            return Err("snapshot file ends early".into());
+        }
+
+        let (head, tail) = self.data.split_at(n);
+        self.data = tail;
+        Ok(head)
+    }
+
+    fn byte(&mut self) -> crate::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    /// Read a blob written by `put_blob`.
+    fn blob(&mut self) -> crate::Result<&'a [u8]> {
+        let len = self.len()?;
+        self.take(len)
+    }
+
+    fn bytes(&mut self) -> crate::Result<Bytes> {
+        Ok(Bytes::copy_from_slice(self.blob()?))
+    }
+
+    fn string(&mut self) -> crate::Result<String> {
+        Ok(String::from_utf8(self.blob()?.to_vec())?)
+    }
+
+    fn u64(&mut self) -> crate::Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into()?))
+    }
+
+    fn id(&mut self) -> crate::Result<StreamId> {
+        Ok(StreamId {
+            ms: self.u64()?,
+            seq: self.u64()?,
+        })
+    }
+
+    /// Read a length written by `put_len`.
+    fn len(&mut self) -> crate::Result<usize> {
+        let mut len = 0u64;
+
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            len |= ((byte & 0x7f) as u64) << shift;
+
+            if byte & 0x80 == 0 {
+                return Ok(len.try_into()?);
+            }
+        }
+
+        Err("invalid snapshot length".into())
+    }
+
+    /// Read the item count of a collection. As each item takes at least a
+    /// byte, a count past the bytes left is rejected before any allocation.
+    fn count(&mut self) -> crate::Result<usize> {
+        match self.len()? {
+            len if len <= self.data.len() => Ok(len),
+            _ => Err("snapshot file ends early".into()),
+        }
+    }
+
+    /// Read a value of type `kind` written by `put_value`.
+    fn value(&mut self, kind: u8) -> crate::Result<Value> {
+        if kind == STRING {
+            return Ok(Value::String(self.bytes()?));
+        }
+
+        let len = self.count()?;
+
+        Ok(match kind {
+            LIST => Value::List((0..len).map(|_| self.bytes()).collect::<Result<_, _>>()?),
+            HASH => Value::Hash(
+                (0..len)
+                    .map(|_| Ok((self.bytes()?, self.bytes()?)))
+                    .collect::<crate::Result<_>>()?,
+            ),
+            SET => Value::Set((0..len).map(|_| self.bytes()).collect::<Result<_, _>>()?),
+            ZSET => {
+                let mut zset = ZSet::default();
+                for _ in 0..len {
+                    let member = self.bytes()?;
+                    let score = f64::from_bits(self.u64()?);
+                    zset.insert(member, score);
+                }
+                Value::ZSet(zset)
+            }
+            _ => Value::Stream(self.stream(len)?),
+        })
+    }
+
+    /// Read a stream of `len` entries written by `put_value`.
+    fn stream(&mut self, len: usize) -> crate::Result<Stream> {
+        let mut stream = Stream::default();
+
+        for _ in 0..len {
+            let id = self.id()?;
+            let fields = (0..self.count()?)
+                .map(|_| Ok((self.bytes()?, self.bytes()?)))
+                .collect::<crate::Result<_>>()?;
+            stream.entries.insert(id, fields);
+        }
+        stream.last_id = self.id()?;
+
+        for _ in 0..self.count()? {
+            let name = self.string()?;
+            let mut group = Group {
+                last_delivered: self.id()?,
+                ..Group::default()
+            };
+
+            for _ in 0..self.count()? {
+                let id = self.id()?;
+                let pending = Pending {
+                    consumer: self.string()?,
+                    delivered_at: self.u64()?,
+                    deliveries: self.u64()?,
+                };
+                group.pending.insert(id, pending);
+            }
+
+            stream.groups.insert(name, group);
+        }
+
+        Ok(stream)
+    }
+}
+
+/// Lookup table of the CRC-32 (IEEE) checksum.
+const CRC_TABLE: [u32; 256] = crc_table();
+
+const fn crc_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                0xedb8_8320 ^ (crc >> 1)
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+
+        table[i] = crc;
+        i += 1;
+    }
+
+    table
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0, |crc, &byte| {
+        CRC_TABLE[((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn parses_rules() {
+        let rule = |after, changes| SaveRule {
+            after: Duration::from_secs(after),
+            changes,
+        };
+
+        assert_eq!(
+            parse_rules("900 1 60 10000"),
+            Ok(vec![rule(900, 1), rule(60, 10000)])
+        );
+        assert_eq!(parse_rules(""), Ok(vec![]));
+        assert!(parse_rules("900").is_err());
+        assert!(parse_rules("900 x").is_err());
+    }
+
+    #[tokio::test]
+    async fn shutdown_waits_for_the_background_save() {
+        let path =
+            std::env::temp_dir().join(format!("my-redis-2-{}-shutdown.rdb", std::process::id()));
+
+        let db = Db::new();
+        configure(&db, &path, parse_rules("3600 1").unwrap());
+        db.set("a".to_string(), Bytes::from("1"), None);
+        assert!(bgsave(&db));
+
+        // Missed by the background save, which copied the keyspace already.
+        db.set("b".to_string(), Bytes::from("2"), None);
+        save_on_shutdown(&db).await.unwrap();
+        assert!(!db.snapshot().saving);
+
+        let loaded = Db::new();
+        configure(&loaded, &path, vec![]);
+        load(&loaded).unwrap();
+        assert_eq!(loaded.get("b"), Ok(Some(Bytes::from("2"))));
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn encoding_round_trips() {
+        let now = Duration::from_secs(1_000_000);
+        let long = Bytes::from(vec![b'x'; 300]);
+        let entries = vec![
+            ("a".to_string(), Value::String(Bytes::from("1")), None),
+            (
+                "long".to_string(),
+                Value::String(long.clone()),
+                Some(Duration::from_secs(5)),
+            ),
+        ];
+
+        let decoded = decode(&encode(vec![vec![], entries], now), 2).unwrap();
+        assert_eq!(
+            decoded,
+            vec![
+                vec![],
+                vec![
+                    ("a".to_string(), Value::String(Bytes::from("1")), None),
+                    (
+                        "long".to_string(),
+                        Value::String(long),
+                        Some(now + Duration::from_secs(5))
+                    ),
+                ]
+            ]
+        );
+    }
+
+    #[test]
+    fn collections_round_trip() {
+        let id = |ms, seq| StreamId { ms, seq };
+        let mut stream = Stream {
+            last_id: id(7, 0),
+            ..Stream::default()
+        };
+        stream
+            .entries
+            .insert(id(5, 1), vec![(Bytes::from("f"), Bytes::from("v"))]);
+        let mut group = Group {
+            last_delivered: id(5, 1),
+            ..Group::default()
+        };
+        group.pending.insert(
+            id(5, 1),
+            Pending {
+                consumer: "alice".to_string(),
+                delivered_at: 1_000,
+                deliveries: 2,
+            },
+        );
+        stream.groups.insert("g".to_string(), group);
+
+        let mut zset = ZSet::default();
+        zset.insert(Bytes::from("a"), 1.5);
+        zset.insert(Bytes::from("b"), f64::NEG_INFINITY);
+
+        let entries = vec![
+            (
+                "list".to_string(),
+                Value::List([Bytes::from("a"), Bytes::from("b")].into()),
+                None,
+            ),
+            (
+                "hash".to_string(),
+                Value::Hash([(Bytes::from("f"), Bytes::from("v"))].into()),
+                None,
+            ),
+            (
+                "set".to_string(),
+                Value::Set([Bytes::from("a"), Bytes::from("b")].into()),
+                None,
+            ),
+            ("zset".to_string(), Value::ZSet(zset), None),
+            ("stream".to_string(), Value::Stream(stream), None),
+        ];
+
+        assert_eq!(
+            decode(&encode(vec![entries.clone()], Duration::ZERO), 1).unwrap(),
+            vec![entries]
+        );
+    }
+
+    #[test]
+    fn corruption_is_detected() {
+        let mut data = encode(
+            vec![vec![(
+                "a".to_string(),
+                Value::String(Bytes::from("1")),
+                None,
+            )]],
+            Duration::ZERO,
+        );
+
+        let last = data.len() - 6;
+        data[last] ^= 1;
+        assert!(decode(&data, 1).is_err());
+
+        assert!(decode(b"MRDB", 1).is_err());
+    }
+
+    #[test]
+    fn lengths_are_checked_before_allocating() {
+        // A valid checksum is appended to `body`.
+        let snapshot = |body: &[u8]| {
+            let mut data = MAGIC.to_vec();
+            data.extend_from_slice(&VERSION.to_le_bytes());
+            data.extend_from_slice(body);
+            data.push(EOF);
+            let checksum = crc32(&data);
+            data.extend_from_slice(&checksum.to_le_bytes());
+            data
+        };
+
+        let mut select = vec![SELECT];
+        put_len(&mut select, usize::MAX);
+        assert!(decode(&snapshot(&select), 16).is_err());
+        assert!(decode(&snapshot(&[SELECT, 16]), 16).is_err());
+        assert_eq!(decode(&snapshot(&[SELECT, 15]), 16).unwrap().len(), 16);
+
+        let mut list = vec![SELECT, 0, LIST, 1, b'k'];
+        put_len(&mut list, 1 << 40);
+        assert!(decode(&snapshot(&list), 16).is_err());
+
+        // Entries belong to the database selected before them.
+        let entry = [STRING, 1, b'k', 1, b'v'];
+        assert!(decode(&snapshot(&entry), 16).is_err());
+    }
+
+    #[tokio::test]
+    async fn save_and_load() {
+        let path = std::env::temp_dir().join(format!("my-redis-2-{}-dump.rdb", std::process::id()));
+
+        let db = Db::new();
+        configure(&db, &path, vec![]);
+        db.set("a".to_string(), Bytes::from("1"), None);
+        db.set(
+            "b".to_string(),
+            Bytes::from("2"),
+            Some(Duration::from_secs(60)),
+        );
+        db.set(
+            "gone".to_string(),
+            Bytes::from("3"),
+            Some(Duration::from_millis(1)),
+        );
+        let other = db.select(3).unwrap();
+        other.set("a".to_string(), Bytes::from("other"), None);
+        db.record_change();
+        save(&db).unwrap();
+        assert_eq!(db.changes(), 0);
+
+        time::sleep(Duration::from_millis(5)).await;
+
+        let loaded = Db::new();
+        configure(&loaded, &path, vec![]);
+        load(&loaded).unwrap();
+        assert_eq!(loaded.get("a"), Ok(Some(Bytes::from("1"))));
+        assert!(loaded.ttl("b").unwrap().unwrap() > Duration::from_secs(55));
+        assert_eq!(loaded.get("gone"), Ok(None));
+        let other = loaded.select(3).unwrap();
+        assert_eq!(other.get("a"), Ok(Some(Bytes::from("other"))));
+        assert_eq!(other.key_counts(), (1, 0));
+
+        fs::remove_file(&path).unwrap();
+    }
+}